@@ -0,0 +1,24 @@
+// Builds the command-line binaries bundled with the app and copies them to
+// where Tauri expects sidecars: src-tauri/binaries/<name>-<target triple>.
+import { execFileSync } from "node:child_process";
+import { copyFileSync, mkdirSync } from "node:fs";
+import { join } from "node:path";
+
+const SIDECARS = ["bonsai-mcp", "bonsai"];
+
+const release = !process.argv.includes("--debug");
+const triple = /host: (\S+)/.exec(execFileSync("rustc", ["-vV"], { encoding: "utf8" }))[1];
+const ext = process.platform === "win32" ? ".exe" : "";
+const manifest = join("src-tauri", "Cargo.toml");
+
+for (const name of SIDECARS) {
+  const args = ["build", "--manifest-path", manifest, "--bin", name];
+  if (release) args.push("--release");
+  execFileSync("cargo", args, { stdio: "inherit" });
+}
+
+mkdirSync(join("src-tauri", "binaries"), { recursive: true });
+for (const name of SIDECARS) {
+  const built = join("src-tauri", "target", release ? "release" : "debug", name + ext);
+  copyFileSync(built, join("src-tauri", "binaries", `${name}-${triple}${ext}`));
+}
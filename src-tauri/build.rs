@@ -1,3 +1,113 @@
+// Every command in `generate_handler!` in src/lib.rs must be declared here,
+// which makes it a permission that capabilities have to grant.
+const COMMANDS: &[&str] = &[
+    "get_config",
+    "set_config",
+    "ai_chat",
+    "get_settings",
+    "update_settings",
+    "reset_settings",
+    "list_workspaces",
+    "create_workspace",
+    "open_workspace",
+    "switch_workspace",
+    "rename_workspace",
+    "close_workspace",
+    "set_workspace_data_dir",
+    "create_leaf",
+    "read_leaf",
+    "delete_leaf",
+    "update_leaf",
+    "list_leaves",
+    "search_leaves",
+    "upload_file",
+    "get_file",
+    "create_sage",
+    "read_sage",
+    "delete_sage",
+    "update_sage",
+    "list_sages",
+    "search_sages",
+    "sql_create_entity",
+    "sql_read_entity",
+    "sql_update_entity",
+    "sql_list_entities",
+    "sql_delete_entity",
+    "get_leaf_tags",
+    "list_trash",
+    "restore_leaf",
+    "get_leaf_children",
+    "open_leaf_doc",
+    "apply_leaf_doc_update",
+    "reload_leaf_doc",
+    "get_leaf_doc_state_vector",
+    "get_leaf_doc_diff",
+    "close_leaf_doc",
+    "start_collab_server",
+    "stop_collab_server",
+    "get_collab_status",
+    "set_leaf_shared",
+    "list_shared_leaves",
+    "import_markdown",
+    "import_notion",
+    "publish_leaves",
+    "start_markdown_mirror",
+    "stop_markdown_mirror",
+    "sync_markdown_mirror",
+    "start_sync",
+    "stop_sync",
+    "sync_now",
+    "create_backup",
+    "verify_backup",
+    "restore_backup",
+    "get_backup_schedule",
+    "set_backup_schedule",
+    "get_database_status",
+    "unlock_database",
+    "enable_database_encryption",
+    "change_database_passphrase",
+    "export_plaintext_database",
+    "get_secret_status",
+    "get_secret",
+    "set_secret",
+    "delete_secret",
+    "test_secret",
+    "unlock_secrets",
+    "set_secrets_passphrase",
+    "set_secrets_backend",
+    "get_api_status",
+    "regenerate_api_token",
+    "take_pending_deep_links",
+    "take_startup_errors",
+    "set_entity_change_filter",
+    "replay_entity_changes",
+    "open_leaf_window",
+    "get_leaf_lock",
+    "acquire_leaf_lock",
+    "release_leaf_lock",
+    "get_or_create_daily_note",
+    "get_journal_calendar",
+    "list_templates",
+    "create_leaf_from_template",
+    "list_tasks",
+    "toggle_task",
+    "create_reminder",
+    "snooze_reminder",
+    "list_reminders",
+    "cancel_reminder",
+    "get_leaf_stats",
+    "get_writing_activity",
+    "list_blocks",
+    "get_block",
+    "update_block",
+    "get_block_references",
+    "resolve_transclusions",
+];
+
 fn main() {
-    tauri_build::build()
+    tauri_build::try_build(
+        tauri_build::Attributes::new()
+            .app_manifest(tauri_build::AppManifest::new().commands(COMMANDS)),
+    )
+    .expect("failed to run tauri-build")
 }
@@ -1 +0,0 @@
-DROP TABLE config
\ No newline at end of file
@@ -1,4 +0,0 @@
-CREATE TABLE config (
-    key TEXT NOT NULL PRIMARY KEY,
-    value TEXT NOT NULL
-);
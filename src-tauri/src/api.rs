@@ -0,0 +1,636 @@
+use crate::db::{Entity, Leaf, Sage, SqlDatabase, TimeStamped};
+use crate::filesystem::Database;
+use crate::mcp;
+use crate::settings::{McpAccess, SettingsStore};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::Error as SqlxError;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+// Opt-in REST API on 127.0.0.1 for scripts and launchers. Every route except
+// the OpenAPI description needs `Authorization: Bearer <token>`.
+
+const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+const DEFAULT_LIMIT: i32 = 20;
+// Time requests in flight get to finish when the server stops
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+// Compares in constant time so the token can't be guessed byte by byte.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// -------------------------------------------------------
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<SqlxError> for ApiError {
+    fn from(e: SqlxError) -> Self {
+        match e {
+            SqlxError::RowNotFound => ApiError(StatusCode::NOT_FOUND, "Not found".to_string()),
+            e => ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(e: io::Error) -> Self {
+        let status = match e.kind() {
+            io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError(status, e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError(StatusCode::BAD_REQUEST, e.to_string())
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+type McpSessions = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Value>>>>;
+
+#[derive(Clone)]
+struct ApiState {
+    app: AppHandle,
+    token: Arc<str>,
+    // Open MCP event streams by session id
+    mcp_sessions: McpSessions,
+}
+
+impl ApiState {
+    // The database stays closed while an encrypted workspace is locked
+    fn db(&self) -> ApiResult<tauri::State<'_, SqlDatabase>> {
+        self.app.try_state::<SqlDatabase>().ok_or_else(|| {
+            ApiError(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Database is locked".to_string(),
+            )
+        })
+    }
+
+    // MCP routes answer only while MCP is turned on in the settings
+    fn mcp_access(&self) -> ApiResult<McpAccess> {
+        let settings = self.app.state::<SettingsStore>().get().mcp;
+        if !settings.enabled {
+            return Err(ApiError(StatusCode::NOT_FOUND, "MCP is turned off".to_string()));
+        }
+        Ok(settings.access)
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Leaves,
+    Sages,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    #[serde(rename = "type")]
+    kind: Option<Kind>,
+    limit: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct McpSessionQuery {
+    session_id: String,
+}
+
+async fn authorize(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if same_token(given, &state.token) => next.run(request).await,
+        _ => ApiError(StatusCode::UNAUTHORIZED, "Missing or invalid token".to_string())
+            .into_response(),
+    }
+}
+
+fn to_values<T: Entity>(entities: Vec<T>) -> ApiResult<Json<Vec<Value>>> {
+    let values = entities
+        .into_iter()
+        .map(serde_json::to_value)
+        .collect::<Result<_, _>>()?;
+    Ok(Json(values))
+}
+
+async fn create<T: Entity + TimeStamped>(db: &SqlDatabase, body: Value) -> ApiResult<Response> {
+    let entity: T = serde_json::from_value(body)?;
+    let id = db.create(entity).await?;
+    Ok((StatusCode::CREATED, Json(json!({ "id": id }))).into_response())
+}
+
+async fn read<T: Entity>(db: &SqlDatabase, id: &str) -> ApiResult<Json<Value>> {
+    let entity = db.read::<T>(id).await?.ok_or(SqlxError::RowNotFound)?;
+    Ok(Json(serde_json::to_value(entity)?))
+}
+
+// Fields left out of the body keep their current values.
+async fn update<T: Entity + TimeStamped>(
+    db: &SqlDatabase,
+    id: String,
+    mut body: Value,
+) -> ApiResult<StatusCode> {
+    db.read::<T>(&id).await?.ok_or(SqlxError::RowNotFound)?;
+    let fields = body.as_object_mut().ok_or_else(|| {
+        ApiError(StatusCode::BAD_REQUEST, "Expected a JSON object".to_string())
+    })?;
+    fields.insert("id".to_string(), Value::String(id));
+    let entity: T = serde_json::from_value(body)?;
+    db.update(entity).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete<T: Entity>(db: &SqlDatabase, id: &str) -> ApiResult<StatusCode> {
+    db.read::<T>(id).await?.ok_or(SqlxError::RowNotFound)?;
+    db.delete::<T>(id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// -------------------------------------------------------
+
+async fn list_entities(
+    State(state): State<ApiState>,
+    Path(kind): Path<Kind>,
+) -> ApiResult<Json<Vec<Value>>> {
+    let db = state.db()?;
+    match kind {
+        Kind::Leaves => to_values(db.list::<Leaf>().await?),
+        Kind::Sages => to_values(db.list::<Sage>().await?),
+    }
+}
+
+async fn create_entity(
+    State(state): State<ApiState>,
+    Path(kind): Path<Kind>,
+    Json(body): Json<Value>,
+) -> ApiResult<Response> {
+    let db = state.db()?;
+    match kind {
+        Kind::Leaves => create::<Leaf>(&db, body).await,
+        Kind::Sages => create::<Sage>(&db, body).await,
+    }
+}
+
+async fn read_entity(
+    State(state): State<ApiState>,
+    Path((kind, id)): Path<(Kind, String)>,
+) -> ApiResult<Json<Value>> {
+    let db = state.db()?;
+    match kind {
+        Kind::Leaves => read::<Leaf>(&db, &id).await,
+        Kind::Sages => read::<Sage>(&db, &id).await,
+    }
+}
+
+async fn update_entity(
+    State(state): State<ApiState>,
+    Path((kind, id)): Path<(Kind, String)>,
+    Json(body): Json<Value>,
+) -> ApiResult<StatusCode> {
+    let db = state.db()?;
+    match kind {
+        Kind::Leaves => update::<Leaf>(&db, id, body).await,
+        Kind::Sages => update::<Sage>(&db, id, body).await,
+    }
+}
+
+async fn delete_entity(
+    State(state): State<ApiState>,
+    Path((kind, id)): Path<(Kind, String)>,
+) -> ApiResult<StatusCode> {
+    let db = state.db()?;
+    match kind {
+        Kind::Leaves => delete::<Leaf>(&db, &id).await,
+        Kind::Sages => delete::<Sage>(&db, &id).await,
+    }
+}
+
+async fn search(
+    State(state): State<ApiState>,
+    Query(query): Query<SearchQuery>,
+) -> ApiResult<Json<Vec<Value>>> {
+    let db = state.db()?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    match query.kind.unwrap_or(Kind::Leaves) {
+        Kind::Leaves => to_values(db.search::<Leaf>(&query.q, limit).await?),
+        Kind::Sages => to_values(db.search::<Sage>(&query.q, limit).await?),
+    }
+}
+
+async fn semantic_search(
+    State(state): State<ApiState>,
+    Query(query): Query<SearchQuery>,
+) -> ApiResult<Json<Vec<Value>>> {
+    let db = state.db()?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    match query.kind.unwrap_or(Kind::Leaves) {
+        Kind::Leaves => to_values(db.find_similar_entities::<Leaf>(&query.q, limit).await?),
+        Kind::Sages => to_values(db.find_similar_entities::<Sage>(&query.q, limit).await?),
+    }
+}
+
+// Upload names are plain file names inside the uploads folder.
+fn check_file_name(name: &str) -> ApiResult<()> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            "Invalid file name".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn upload(
+    State(state): State<ApiState>,
+    Query(query): Query<UploadQuery>,
+    body: Bytes,
+) -> ApiResult<Response> {
+    check_file_name(&query.name)?;
+    let path = state.app.state::<Database>().upload_file(&query.name, &body)?;
+    Ok((StatusCode::CREATED, Json(json!({ "path": path }))).into_response())
+}
+
+async fn download(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> ApiResult<Response> {
+    check_file_name(&name)?;
+    let data = state.app.state::<Database>().get_file(&name)?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], data).into_response())
+}
+
+// -------------------------------------------------------
+// MCP over HTTP: `POST /mcp` answers in the response body, while the older
+// SSE transport opens `GET /mcp/sse` and posts to the endpoint it announces.
+
+async fn mcp_post(State(state): State<ApiState>, Json(message): Json<Value>) -> ApiResult<Response> {
+    let access = state.mcp_access()?;
+    let db = state.db()?;
+    Ok(match mcp::handle(&db, access, message).await {
+        Some(response) => Json(response).into_response(),
+        None => StatusCode::ACCEPTED.into_response(),
+    })
+}
+
+// Forgets the session once its event stream is dropped.
+struct McpSession {
+    id: String,
+    sessions: McpSessions,
+    rx: mpsc::UnboundedReceiver<Value>,
+}
+
+impl Drop for McpSession {
+    fn drop(&mut self) {
+        self.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+async fn mcp_sse(
+    State(state): State<ApiState>,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    state.mcp_access()?;
+    let id = Uuid::new_v4().simple().to_string();
+    let (tx, rx) = mpsc::unbounded_channel();
+    state.mcp_sessions.lock().unwrap().insert(id.clone(), tx);
+
+    let endpoint = Event::default()
+        .event("endpoint")
+        .data(format!("/mcp/messages?sessionId={}", id));
+    let session = McpSession {
+        id,
+        sessions: state.mcp_sessions.clone(),
+        rx,
+    };
+    let messages = stream::unfold(session, |mut session| async move {
+        let message = session.rx.recv().await?;
+        let event = Event::default().event("message").data(message.to_string());
+        Some((Ok(event), session))
+    });
+    Ok(Sse::new(stream::once(async { Ok(endpoint) }).chain(messages))
+        .keep_alive(KeepAlive::default()))
+}
+
+async fn mcp_message(
+    State(state): State<ApiState>,
+    Query(query): Query<McpSessionQuery>,
+    Json(message): Json<Value>,
+) -> ApiResult<StatusCode> {
+    let access = state.mcp_access()?;
+    let tx = state
+        .mcp_sessions
+        .lock()
+        .unwrap()
+        .get(&query.session_id)
+        .cloned()
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "Unknown session".to_string()))?;
+    let db = state.db()?;
+    if let Some(response) = mcp::handle(&db, access, message).await {
+        let _ = tx.send(response);
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn openapi_json(State(state): State<ApiState>) -> Json<Value> {
+    let port = state
+        .app
+        .state::<ApiServer>()
+        .port()
+        .unwrap_or_default();
+    Json(openapi(port))
+}
+
+fn router(state: ApiState) -> Router {
+    let protected = Router::new()
+        .route("/v1/search", get(search))
+        .route("/v1/semantic-search", get(semantic_search))
+        .route(
+            "/v1/uploads",
+            post(upload).layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES)),
+        )
+        .route("/v1/uploads/:name", get(download))
+        .route("/v1/:kind", get(list_entities).post(create_entity))
+        .route(
+            "/v1/:kind/:id",
+            get(read_entity).patch(update_entity).delete(delete_entity),
+        )
+        .route("/mcp", post(mcp_post))
+        .route("/mcp/sse", get(mcp_sse))
+        .route("/mcp/messages", post(mcp_message))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize));
+    Router::new()
+        .route("/v1/openapi.json", get(openapi_json))
+        .merge(protected)
+        .with_state(state)
+}
+
+// -------------------------------------------------------
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiStatus {
+    pub running: bool,
+    pub url: Option<String>,
+    // Masked; the full token is only shown when it is generated
+    pub token: Option<String>,
+}
+
+struct ApiTask {
+    port: u16,
+    mcp_sessions: McpSessions,
+    shutdown: oneshot::Sender<()>,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+// Managed state owning the HTTP server, if it is enabled.
+#[derive(Default)]
+pub struct ApiServer {
+    running: Mutex<Option<ApiTask>>,
+    // Held while starting or stopping, so a restart can't overlap another
+    lifecycle: tokio::sync::Mutex<()>,
+}
+
+impl ApiServer {
+    pub fn port(&self) -> Option<u16> {
+        self.running.lock().unwrap().as_ref().map(|task| task.port)
+    }
+
+    // Replaces a running server once it has let go of its port.
+    pub async fn start(&self, app: AppHandle, port: u16, token: String) -> io::Result<()> {
+        let _lifecycle = self.lifecycle.lock().await;
+        self.shut_down().await;
+
+        // Only this machine can connect
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        let mcp_sessions = McpSessions::default();
+        let state = ApiState {
+            app,
+            token: token.into(),
+            mcp_sessions: mcp_sessions.clone(),
+        };
+        let (shutdown, signal) = oneshot::channel();
+        let task = tauri::async_runtime::spawn(async move {
+            let result = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => {
+                    axum::serve(listener, router(state))
+                        .with_graceful_shutdown(async {
+                            let _ = signal.await;
+                        })
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                eprintln!("HTTP API stopped: {}", e);
+            }
+        });
+
+        *self.running.lock().unwrap() = Some(ApiTask {
+            port,
+            mcp_sessions,
+            shutdown,
+            task,
+        });
+        Ok(())
+    }
+
+    pub async fn stop(&self) {
+        let _lifecycle = self.lifecycle.lock().await;
+        self.shut_down().await;
+    }
+
+    // Stops accepting connections, ends the MCP event streams and waits for
+    // the requests in flight. Connections still open after the timeout are
+    // dropped; either way the port is free on return.
+    async fn shut_down(&self) {
+        let Some(running) = self.running.lock().unwrap().take() else {
+            return;
+        };
+        let _ = running.shutdown.send(());
+        running.mcp_sessions.lock().unwrap().clear();
+        let mut task = running.task;
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut task).await.is_err() {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+// -------------------------------------------------------
+
+fn entity_schema(kind: &str) -> Value {
+    let text_field = if kind == "leaf" { "content" } else { "description" };
+    json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "string" },
+            "name": { "type": "string" },
+            text_field: { "type": "string" },
+            "createdAt": { "type": "string", "format": "date-time", "readOnly": true },
+            "modifiedAt": { "type": "string", "format": "date-time", "readOnly": true }
+        }
+    })
+}
+
+// OpenAPI 3 description of the routes above.
+pub fn openapi(port: u16) -> Value {
+    let error = json!({ "$ref": "#/components/responses/Error" });
+    let kind = json!({
+        "name": "kind", "in": "path", "required": true,
+        "schema": { "type": "string", "enum": ["leaves", "sages"] }
+    });
+    let id = json!({ "name": "id", "in": "path", "required": true, "schema": { "type": "string" } });
+    let search_parameters = json!([
+        { "name": "q", "in": "query", "required": true, "schema": { "type": "string" } },
+        { "name": "type", "in": "query", "schema": { "type": "string", "enum": ["leaves", "sages"], "default": "leaves" } },
+        { "name": "limit", "in": "query", "schema": { "type": "integer", "default": DEFAULT_LIMIT } }
+    ]);
+    let entity = json!({ "oneOf": [
+        { "$ref": "#/components/schemas/Leaf" },
+        { "$ref": "#/components/schemas/Sage" }
+    ]});
+    let entities = json!({ "type": "array", "items": entity });
+    let content = |schema: &Value| json!({ "application/json": { "schema": schema } });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "Bonsai", "version": env!("CARGO_PKG_VERSION") },
+        "servers": [{ "url": format!("http://127.0.0.1:{}", port) }],
+        "security": [{ "bearer": [] }],
+        "paths": {
+            "/v1/{kind}": {
+                "parameters": [kind],
+                "get": {
+                    "summary": "List leaves or sages",
+                    "responses": { "200": { "description": "All entities", "content": content(&entities) }, "default": error }
+                },
+                "post": {
+                    "summary": "Create a leaf or sage",
+                    "requestBody": { "required": true, "content": content(&entity) },
+                    "responses": {
+                        "201": { "description": "Created", "content": content(&json!({ "type": "object", "properties": { "id": { "type": "string" } } })) },
+                        "default": error
+                    }
+                }
+            },
+            "/v1/{kind}/{id}": {
+                "parameters": [kind, id],
+                "get": {
+                    "summary": "Read a leaf or sage",
+                    "responses": { "200": { "description": "The entity", "content": content(&entity) }, "default": error }
+                },
+                "patch": {
+                    "summary": "Update a leaf or sage; omitted fields are kept",
+                    "requestBody": { "required": true, "content": content(&entity) },
+                    "responses": { "204": { "description": "Updated" }, "default": error }
+                },
+                "delete": {
+                    "summary": "Delete a leaf or sage",
+                    "responses": { "204": { "description": "Deleted" }, "default": error }
+                }
+            },
+            "/v1/search": {
+                "get": {
+                    "summary": "Find entities containing the query text",
+                    "parameters": search_parameters,
+                    "responses": { "200": { "description": "Matches, newest first", "content": content(&entities) }, "default": error }
+                }
+            },
+            "/v1/semantic-search": {
+                "get": {
+                    "summary": "Find entities similar in meaning to the query",
+                    "parameters": search_parameters,
+                    "responses": { "200": { "description": "Matches, closest first", "content": content(&entities) }, "default": error }
+                }
+            },
+            "/v1/uploads": {
+                "post": {
+                    "summary": "Store a file in the uploads folder",
+                    "parameters": [{ "name": "name", "in": "query", "required": true, "schema": { "type": "string" } }],
+                    "requestBody": { "required": true, "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } } },
+                    "responses": {
+                        "201": { "description": "Stored", "content": content(&json!({ "type": "object", "properties": { "path": { "type": "string" } } })) },
+                        "default": error
+                    }
+                }
+            },
+            "/v1/uploads/{name}": {
+                "get": {
+                    "summary": "Fetch an uploaded file",
+                    "parameters": [{ "name": "name", "in": "path", "required": true, "schema": { "type": "string" } }],
+                    "responses": {
+                        "200": { "description": "File contents", "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } } },
+                        "default": error
+                    }
+                }
+            },
+            "/v1/openapi.json": {
+                "get": {
+                    "summary": "This description",
+                    "security": [],
+                    "responses": { "200": { "description": "OpenAPI document" } }
+                }
+            }
+        },
+        "components": {
+            "securitySchemes": { "bearer": { "type": "http", "scheme": "bearer" } },
+            "schemas": {
+                "Leaf": entity_schema("leaf"),
+                "Sage": entity_schema("sage"),
+                "Error": { "type": "object", "properties": { "error": { "type": "string" } } }
+            },
+            "responses": {
+                "Error": { "description": "Error", "content": content(&json!({ "$ref": "#/components/schemas/Error" })) }
+            }
+        }
+    })
+}
@@ -0,0 +1,521 @@
+use crate::crypto;
+use crate::db::{SqlDatabase, COUNTED_TABLES};
+use crate::filesystem::Database;
+use crate::html;
+use crate::settings::{BackupSchedule, SettingsStore};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::ZipArchive;
+
+pub const EXTENSION: &str = "bonsai-backup";
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const DATABASE_FILE: &str = "database.db";
+const SCHEDULED_PREFIX: &str = "bonsai-";
+const PRE_RESTORE_PREFIX: &str = "pre-restore-";
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupManifest {
+    pub version: u32,
+    pub created_at: String,
+    pub encrypted: bool,
+    // Data folder the backup was taken from, used to relocate asset URLs
+    pub root_dir: String,
+    pub row_counts: BTreeMap<String, i64>,
+    pub files: Vec<String>,
+}
+
+fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// A temporary folder that is removed again when dropped.
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new() -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("bonsai-backup-{}", Uuid::new_v4().simple()));
+        let mut builder = fs::DirBuilder::new();
+        // Database snapshots in here are plaintext; keep other users out
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&dir)?;
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Folders below the data folder that hold backups rather than workspace
+// files: `backups/`, and the scheduled backup folder when it is inside. An
+// empty path stands for the data folder itself.
+fn backup_dirs(root: &Path, schedule: &BackupSchedule) -> Vec<String> {
+    let mut dirs = vec!["backups".to_string()];
+    if let Some(dir) = schedule.dir.as_deref().and_then(|dir| dir.strip_prefix(root).ok()) {
+        dirs.push(dir.to_string_lossy().replace('\\', "/").trim_end_matches('/').to_string());
+    }
+    dirs
+}
+
+// Everything in the data folder except the live database files, which are
+// captured through a snapshot instead, and the automatic backups themselves.
+fn workspace_files(root: &Path, backup_dirs: &[String]) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    if root.is_dir() {
+        collect_files(root, root, &mut files)?;
+    }
+    files.retain(|path| !is_database_file(path) && !is_in_backup_dir(path, backup_dirs));
+    files.sort();
+    Ok(files)
+}
+
+fn is_database_file(path: &str) -> bool {
+    path.starts_with("database.db")
+}
+
+fn is_in_backup_dir(path: &str, backup_dirs: &[String]) -> bool {
+    backup_dirs.iter().any(|dir| match dir.as_str() {
+        // Only the archives when backups go into the data folder itself
+        "" => !path.contains('/') && path.ends_with(EXTENSION),
+        dir => path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/')),
+    })
+}
+
+// Where a file listed in a manifest goes below the data folder. Anything
+// but a plain relative path is refused, so a crafted backup can't write
+// outside the folder or over the live database.
+fn entry_path(file: &str) -> io::Result<&Path> {
+    let path = Path::new(file);
+    let plain = path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !plain || is_database_file(file) {
+        return Err(invalid_data(format!("Backup contains an invalid path {}", file)));
+    }
+    Ok(path)
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            files.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+async fn snapshot_row_counts(path: &Path, tables: &[&str]) -> io::Result<BTreeMap<String, i64>> {
+    let snapshot = SqlDatabase::open_read_only(path).await.map_err(to_io_error)?;
+    let result = async {
+        snapshot.integrity_check().await?;
+        snapshot.row_counts(tables).await
+    }
+    .await;
+    snapshot.close().await;
+    result.map_err(|e| invalid_data(format!("Database snapshot is damaged: {}", e)))
+}
+
+// -------------------------------------------------------
+
+// Writes the database and every workspace file into a zip archive at
+// `path`, encrypted with `passphrase` when one is given. Backups of an
+// encrypted database are always encrypted, by default with its passphrase.
+// Files are streamed through, so the archive is never held in memory.
+pub async fn create_backup(
+    sql_db: &SqlDatabase,
+    db: &Database,
+    schedule: &BackupSchedule,
+    path: &Path,
+    passphrase: Option<&str>,
+) -> io::Result<BackupManifest> {
+    let passphrase = passphrase
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .or_else(|| sql_db.passphrase());
+    let passphrase = passphrase.as_deref();
+    let scratch = ScratchDir::new()?;
+    let snapshot = scratch.0.join(DATABASE_FILE);
+    sql_db.backup_to(&snapshot).await.map_err(to_io_error)?;
+
+    let root = db.root_dir();
+    let manifest = BackupManifest {
+        version: FORMAT_VERSION,
+        created_at: Utc::now().to_rfc3339(),
+        encrypted: passphrase.is_some(),
+        root_dir: root.to_string_lossy().into_owned(),
+        row_counts: snapshot_row_counts(&snapshot, COUNTED_TABLES).await?,
+        files: workspace_files(&root, &backup_dirs(&root, schedule))?,
+    };
+
+    // Write beside the target first so a failed backup never replaces a good
+    // one. Encrypted archives are zipped in the scratch folder, then sealed.
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("partial");
+    let archive = match passphrase {
+        Some(_) => scratch.0.join("archive.zip"),
+        None => partial.clone(),
+    };
+    let result = (|| {
+        let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(&archive)?));
+        let options = SimpleFileOptions::default().large_file(true);
+        zip.start_file(MANIFEST_FILE, options)?;
+        zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+        zip.start_file(DATABASE_FILE, options)?;
+        io::copy(&mut File::open(&snapshot)?, &mut zip)?;
+        for file in &manifest.files {
+            zip.start_file(file.as_str(), options)?;
+            io::copy(&mut File::open(root.join(file))?, &mut zip)?;
+        }
+        zip.finish()?.flush()?;
+
+        if let Some(passphrase) = passphrase {
+            let mut reader = BufReader::new(File::open(&archive)?);
+            let mut writer = BufWriter::new(File::create(&partial)?);
+            crypto::encrypt_stream(passphrase, &mut reader, &mut writer)?;
+        }
+        fs::rename(&partial, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result?;
+
+    Ok(manifest)
+}
+
+// Opens a backup, decrypting it into `scratch` first when it is encrypted.
+fn open_backup(
+    path: &Path,
+    passphrase: Option<&str>,
+    scratch: &ScratchDir,
+) -> io::Result<(BackupManifest, ZipArchive<File>)> {
+    let mut file = File::open(path)?;
+    let mut header = Vec::new();
+    (&mut file).take(64).read_to_end(&mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let encrypted = crypto::is_encrypted_stream(&header) || crypto::is_encrypted(&header);
+    let file = if encrypted {
+        let passphrase = passphrase.filter(|p| !p.is_empty()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "This backup is encrypted and needs its passphrase",
+            )
+        })?;
+        let decrypted = scratch.0.join("archive.zip");
+        if crypto::is_encrypted_stream(&header) {
+            let mut writer = BufWriter::new(File::create(&decrypted)?);
+            crypto::decrypt_stream(passphrase, &mut BufReader::new(file), &mut writer)?;
+        } else {
+            // Backups from before archives were encrypted in chunks
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            fs::write(&decrypted, crypto::decrypt(passphrase, &data)?)?;
+        }
+        File::open(&decrypted)?
+    } else {
+        file
+    };
+
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| invalid_data(format!("Not a backup archive: {}", e)))?;
+    let manifest: BackupManifest = serde_json::from_reader(archive.by_name(MANIFEST_FILE)?)?;
+    if manifest.version > FORMAT_VERSION {
+        return Err(invalid_data(
+            "This backup was made by a newer version of Bonsai".to_string(),
+        ));
+    }
+    Ok((manifest, archive))
+}
+
+// Reads every entry (which checks its CRC), then opens the database snapshot
+// and compares its row counts with the ones recorded at backup time.
+async fn check_backup(
+    manifest: &BackupManifest,
+    archive: &mut ZipArchive<File>,
+    scratch: &ScratchDir,
+) -> io::Result<PathBuf> {
+    for file in &manifest.files {
+        entry_path(file)?;
+        let mut entry = archive
+            .by_name(file)
+            .map_err(|_| invalid_data(format!("Backup is missing {}", file)))?;
+        io::copy(&mut entry, &mut io::sink())?;
+    }
+
+    let snapshot = scratch.0.join(DATABASE_FILE);
+    io::copy(&mut archive.by_name(DATABASE_FILE)?, &mut File::create(&snapshot)?)?;
+
+    // Only tables known to this version are checked
+    let tables: Vec<&str> = COUNTED_TABLES
+        .iter()
+        .copied()
+        .filter(|table| manifest.row_counts.contains_key(*table))
+        .collect();
+    let counts = snapshot_row_counts(&snapshot, &tables).await?;
+    for (table, count) in &counts {
+        let expected = manifest.row_counts[table];
+        if *count != expected {
+            return Err(invalid_data(format!(
+                "Backup table {} has {} rows, expected {}",
+                table, count, expected
+            )));
+        }
+    }
+    Ok(snapshot)
+}
+
+pub async fn verify_backup(path: &Path, passphrase: Option<&str>) -> io::Result<BackupManifest> {
+    let scratch = ScratchDir::new()?;
+    let (manifest, mut archive) = open_backup(path, passphrase, &scratch)?;
+    check_backup(&manifest, &mut archive, &scratch).await?;
+    Ok(manifest)
+}
+
+// Replaces the workspace with the contents of a backup. The backup is fully
+// verified first, and the current workspace is saved as a pre-restore backup.
+pub async fn restore_backup(
+    sql_db: &SqlDatabase,
+    db: &Database,
+    schedule: &BackupSchedule,
+    path: &Path,
+    passphrase: Option<&str>,
+) -> io::Result<BackupManifest> {
+    let scratch = ScratchDir::new()?;
+    let (manifest, mut archive) = open_backup(path, passphrase, &scratch)?;
+    let snapshot = check_backup(&manifest, &mut archive, &scratch).await?;
+
+    let safety = backup_dir(db, schedule).join(format!(
+        "{}{}.{}",
+        PRE_RESTORE_PREFIX,
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        EXTENSION
+    ));
+    create_backup(sql_db, db, schedule, &safety, None).await?;
+
+    sql_db.restore_from(&snapshot).await.map_err(to_io_error)?;
+
+    // Backups are left as they are, including any an older version put in
+    // the archive
+    let root = db.root_dir();
+    let backup_dirs = backup_dirs(&root, schedule);
+    for file in workspace_files(&root, &backup_dirs)? {
+        fs::remove_file(root.join(file))?;
+    }
+    for file in manifest.files.iter().filter(|file| !is_in_backup_dir(file, &backup_dirs)) {
+        let target = root.join(entry_path(file)?);
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        io::copy(&mut archive.by_name(file)?, &mut File::create(target)?)?;
+    }
+
+    // Uploads are linked by absolute path, so point them at this data folder
+    let old_uploads = Path::new(&manifest.root_dir).join("uploads");
+    let new_uploads = root.join("uploads");
+    if old_uploads != new_uploads {
+        sql_db
+            .replace_in_leaf_content(
+                &html::encode_uri_component(&old_uploads.to_string_lossy()),
+                &html::encode_uri_component(&new_uploads.to_string_lossy()),
+            )
+            .await
+            .map_err(to_io_error)?;
+    }
+
+    Ok(manifest)
+}
+
+// -------------------------------------------------------
+
+fn backup_dir(db: &Database, schedule: &BackupSchedule) -> PathBuf {
+    schedule
+        .dir
+        .clone()
+        .unwrap_or_else(|| db.root_dir().join("backups"))
+}
+
+// Automatic backups in `dir`, oldest first. Names embed the creation time.
+fn scheduled_backups(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with(SCHEDULED_PREFIX) && name.ends_with(EXTENSION) {
+                backups.push(path);
+            }
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+// Scheduled backups have no passphrase of their own, since none is stored;
+// those of an encrypted database use the database passphrase.
+async fn run_scheduled_backup(app: &AppHandle) -> io::Result<()> {
+    let db = app.state::<Database>();
+    // An encrypted database is not managed until it has been unlocked
+    let Some(sql_db) = app.try_state::<SqlDatabase>() else {
+        return Ok(());
+    };
+    let schedule = app.state::<SettingsStore>().get().backup;
+    if !schedule.enabled {
+        return Ok(());
+    }
+
+    let dir = backup_dir(&db, &schedule);
+    let backups = scheduled_backups(&dir)?;
+    let interval = Duration::from_secs(u64::from(schedule.interval_hours.max(1)) * 60 * 60);
+    let due = backups
+        .last()
+        .and_then(|last| fs::metadata(last).ok()?.modified().ok())
+        .is_none_or(|modified| modified.elapsed().unwrap_or_default() >= interval);
+    if !due {
+        return Ok(());
+    }
+
+    let path = dir.join(format!(
+        "{}{}.{}",
+        SCHEDULED_PREFIX,
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        EXTENSION
+    ));
+    create_backup(&sql_db, &db, &schedule, &path, None).await?;
+
+    let backups = scheduled_backups(&dir)?;
+    let keep = schedule.keep.max(1) as usize;
+    if backups.len() > keep {
+        for old in &backups[..backups.len() - keep] {
+            fs::remove_file(old)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn start_scheduler(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = run_scheduled_backup(&app).await {
+                eprintln!("Automatic backup failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_the_database_and_backup_folders() {
+        let root = Path::new("/data");
+        let schedule = BackupSchedule {
+            dir: Some(PathBuf::from("/data/archive/")),
+            ..BackupSchedule::default()
+        };
+        let dirs = backup_dirs(root, &schedule);
+        assert_eq!(dirs, ["backups", "archive"]);
+        for path in ["backups/a.bonsai-backup", "archive/b.bonsai-backup", "archive/x/c"] {
+            assert!(is_in_backup_dir(path, &dirs), "{}", path);
+        }
+        for path in ["archived/a", "archive", "uploads/backups/a", "a.bonsai-backup"] {
+            assert!(!is_in_backup_dir(path, &dirs), "{}", path);
+        }
+
+        // Backups kept in the data folder itself, or outside it
+        let schedule = BackupSchedule {
+            dir: Some(PathBuf::from("/data")),
+            ..BackupSchedule::default()
+        };
+        let dirs = backup_dirs(root, &schedule);
+        assert!(is_in_backup_dir("bonsai-1.bonsai-backup", &dirs));
+        assert!(!is_in_backup_dir("uploads/a.bonsai-backup", &dirs));
+        let schedule = BackupSchedule {
+            dir: Some(PathBuf::from("/elsewhere")),
+            ..BackupSchedule::default()
+        };
+        assert_eq!(backup_dirs(root, &schedule), ["backups"]);
+
+        assert!(entry_path("database.db-wal").is_err());
+        assert!(entry_path("../outside").is_err());
+        assert!(entry_path("/etc/passwd").is_err());
+        assert!(entry_path("uploads/a.png").is_ok());
+    }
+
+    #[tokio::test]
+    async fn encrypted_backups_restore_files_and_keep_backups() {
+        let dir = ScratchDir::new().unwrap();
+        let root = dir.0.join("workspace");
+        let sql_db = SqlDatabase::open(root.clone(), None).await.unwrap();
+        let db = Database::new(root.clone()).unwrap();
+        let schedule = BackupSchedule {
+            dir: Some(root.join("archive")),
+            ..BackupSchedule::default()
+        };
+        let write = |file: &str, text: &str| {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write("notes/a.txt", "original");
+        write("uploads/b.png", &"png".repeat(50_000));
+        write("archive/bonsai-1.bonsai-backup", "older backup");
+        write("backups/bonsai-2.bonsai-backup", "older backup");
+
+        let path = dir.0.join("out.bonsai-backup");
+        let manifest = create_backup(&sql_db, &db, &schedule, &path, Some("secret")).await.unwrap();
+        assert_eq!(manifest.files, ["notes/a.txt", "uploads/b.png"]);
+        assert!(crypto::is_encrypted_stream(&fs::read(&path).unwrap()));
+        assert!(!path.with_extension("partial").exists());
+
+        assert!(verify_backup(&path, None).await.is_err());
+        assert!(verify_backup(&path, Some("wrong")).await.is_err());
+        verify_backup(&path, Some("secret")).await.unwrap();
+
+        write("notes/a.txt", "changed");
+        write("notes/new.txt", "added");
+        restore_backup(&sql_db, &db, &schedule, &path, Some("secret")).await.unwrap();
+        assert_eq!(fs::read_to_string(root.join("notes/a.txt")).unwrap(), "original");
+        assert!(!root.join("notes/new.txt").exists());
+        assert_eq!(fs::read_to_string(root.join("uploads/b.png")).unwrap(), "png".repeat(50_000));
+        assert!(root.join("archive/bonsai-1.bonsai-backup").exists());
+        assert!(root.join("backups/bonsai-2.bonsai-backup").exists());
+        // The state before the restore was saved next to them
+        let saved = fs::read_dir(root.join("archive"))
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with(PRE_RESTORE_PREFIX)
+            })
+            .count();
+        assert_eq!(saved, 1);
+        sql_db.close().await;
+    }
+}
@@ -0,0 +1,40 @@
+// MCP server over stdio, bundled with the app as a sidecar. Serves the
+// workspace the app has open, or the one passed as `--workspace <id>`.
+// Agents launch it themselves, so it reads the database directly.
+
+use app_lib::{headless, mcp};
+use std::io;
+
+async fn run() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut workspace = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--workspace" => workspace = args.next(),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown argument {}", other),
+                ))
+            }
+        }
+    }
+
+    let open = headless::open(workspace.as_deref()).await?;
+    if !open.settings.mcp.enabled {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "MCP is turned off in Bonsai's settings",
+        ));
+    }
+    eprintln!("bonsai-mcp: serving workspace {}", open.info.name);
+    mcp::serve_stdio(&open.db, open.settings.mcp.access).await
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("bonsai-mcp: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,440 @@
+// Command-line access to a workspace without the app. Serves the workspace
+// the app has open, or the one passed as `--workspace <id>`. The app may be
+// running at the same time: SQLite's locking keeps writes apart, and `edit`
+// refuses to save over a leaf that changed while the editor was open.
+
+use app_lib::db::{Entity, Leaf, Sage, SqlDatabase, TimeStamped};
+use app_lib::headless::{self, OpenWorkspace};
+use app_lib::{backup, importer, markdown, mirror, notion, publish};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+const USAGE: &str = "\
+Usage: bonsai [--workspace <id>] [--json] <command> [args]
+
+Commands:
+  list [--sages]                    List leaves, or sages
+  show <id> [--html]                Print a leaf as Markdown, or as HTML
+  new <name>                        Create a leaf from Markdown on stdin
+  edit <id>                         Edit a leaf as Markdown in $EDITOR
+  search <query> [--limit <n>]      Find leaves by title or text
+  semantic-search <query> [--limit <n>]
+                                    Find leaves and sages by meaning
+  export <path>                     Write every leaf as Markdown files into a
+                                    folder, or into one .html or .epub file
+  import <path> [--notion]          Import a Markdown vault or Notion export
+  backup <path>                     Write a backup archive
+  reindex                           Recompute every embedding";
+
+const DEFAULT_LIMIT: i32 = 20;
+
+struct Args {
+    workspace: Option<String>,
+    json: bool,
+    command: String,
+    positional: Vec<String>,
+    flags: HashSet<String>,
+    limit: i32,
+}
+
+fn invalid_input(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn parse_args() -> io::Result<Args> {
+    let mut args = env::args().skip(1);
+    let mut workspace = None;
+    let mut json = false;
+    let mut limit = DEFAULT_LIMIT;
+    let mut words = Vec::new();
+    let mut flags = HashSet::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--workspace" => workspace = args.next(),
+            "--json" => json = true,
+            "--limit" => {
+                limit = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| invalid_input("--limit needs a positive number"))?
+            }
+            "-h" | "--help" => words.insert(0, "help".to_string()),
+            flag if flag.starts_with("--") => {
+                flags.insert(flag.trim_start_matches("--").to_string());
+            }
+            _ => words.push(arg),
+        }
+    }
+    if words.is_empty() {
+        words.push("help".to_string());
+    }
+    let command = words.remove(0);
+    Ok(Args {
+        workspace,
+        json,
+        command,
+        positional: words,
+        flags,
+        limit,
+    })
+}
+
+impl Args {
+    fn arg(&self, name: &str) -> io::Result<&str> {
+        self.positional
+            .first()
+            .map(String::as_str)
+            .ok_or_else(|| invalid_input(format!("{} needs a {}\n\n{}", self.command, name, USAGE)))
+    }
+
+    fn check_flags(&self, allowed: &[&str]) -> io::Result<()> {
+        match self.flags.iter().find(|flag| !allowed.contains(&flag.as_str())) {
+            Some(flag) => Err(invalid_input(format!("Unknown option --{}", flag))),
+            None => Ok(()),
+        }
+    }
+}
+
+// Prints `value` in JSON mode, and `text` otherwise.
+fn output(args: &Args, value: Value, text: impl FnOnce() -> String) {
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default());
+    } else {
+        let text = text();
+        if !text.is_empty() {
+            println!("{}", text);
+        }
+    }
+}
+
+async fn read_leaf(db: &SqlDatabase, id: &str) -> io::Result<Leaf> {
+    db.read::<Leaf>(id)
+        .await
+        .map_err(to_io_error)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Leaf {} not found", id)))
+}
+
+fn leaf_summary(leaf: &Leaf) -> Value {
+    json!({
+        "id": leaf.id(),
+        "name": leaf.name(),
+        "createdAt": leaf.created_at(),
+        "modifiedAt": leaf.modified_at(),
+    })
+}
+
+fn snippet(html: &str) -> String {
+    let text = markdown::from_html(html);
+    let line = text.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    line.chars().take(100).collect()
+}
+
+// -------------------------------------------------------
+
+async fn list(args: &Args, db: &SqlDatabase) -> io::Result<()> {
+    args.check_flags(&["sages"])?;
+    if args.flags.contains("sages") {
+        let sages = db.list::<Sage>().await.map_err(to_io_error)?;
+        let value = sages
+            .iter()
+            .map(|sage| json!({ "id": sage.id(), "name": sage.name(), "description": sage.description() }))
+            .collect();
+        output(args, value, || {
+            sages
+                .iter()
+                .map(|sage| format!("{}  {}", sage.id(), sage.name()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        });
+        return Ok(());
+    }
+    let leaves = db.list::<Leaf>().await.map_err(to_io_error)?;
+    output(args, leaves.iter().map(leaf_summary).collect(), || {
+        leaves
+            .iter()
+            .map(|leaf| format!("{}  {}", leaf.id(), leaf.name()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    Ok(())
+}
+
+async fn show(args: &Args, db: &SqlDatabase) -> io::Result<()> {
+    args.check_flags(&["html"])?;
+    let leaf = read_leaf(db, args.arg("leaf id")?).await?;
+    let content = if args.flags.contains("html") {
+        leaf.content().to_string()
+    } else {
+        markdown::from_html(leaf.content())
+    };
+    let mut value = leaf_summary(&leaf);
+    value["content"] = json!(content);
+    output(args, value, || format!("# {}\n\n{}", leaf.name(), content.trim_end()));
+    Ok(())
+}
+
+async fn new(args: &Args, db: &SqlDatabase) -> io::Result<()> {
+    args.check_flags(&[])?;
+    let name = args.positional.join(" ");
+    if name.trim().is_empty() {
+        return Err(invalid_input(format!("new needs a name\n\n{}", USAGE)));
+    }
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text)?;
+    let content = markdown::to_html(&text, |_| None, |_| None);
+    let leaf = Leaf::new(uuid::Uuid::new_v4().to_string(), name, content);
+    let id = db.create(leaf).await.map_err(to_io_error)?;
+    output(args, json!({ "id": id }), || id.clone());
+    Ok(())
+}
+
+// The command line of the user's editor, split into program and arguments.
+fn editor() -> Vec<String> {
+    let configured = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty());
+    match configured {
+        Some(editor) => editor.split_whitespace().map(str::to_string).collect(),
+        None if cfg!(windows) => vec!["notepad".to_string()],
+        None => vec!["vi".to_string()],
+    }
+}
+
+async fn edit(args: &Args, db: &SqlDatabase) -> io::Result<()> {
+    args.check_flags(&[])?;
+    let leaf = read_leaf(db, args.arg("leaf id")?).await?;
+    let original = mirror::render_file(&leaf);
+    // The leaf is in plain text here, so the name is random and only the
+    // user can read the file (0600); it is removed when dropped
+    let mut file = tempfile::Builder::new()
+        .prefix("bonsai-")
+        .suffix(".md")
+        .tempfile()?;
+    file.write_all(original.as_bytes())?;
+    file.flush()?;
+
+    let editor = editor();
+    let status = Command::new(&editor[0]).args(&editor[1..]).arg(file.path()).status();
+    let edited = fs::read_to_string(file.path());
+    drop(file);
+    if !status?.success() {
+        return Err(io::Error::other(format!("{} exited with an error", editor[0])));
+    }
+    let edited = edited?;
+    if edited == original {
+        output(args, json!({ "id": leaf.id(), "changed": false }), || "No changes".to_string());
+        return Ok(());
+    }
+
+    // The app may have saved the leaf while the editor was open
+    let current = read_leaf(db, leaf.id()).await?;
+    if current.modified_at() != leaf.modified_at() {
+        return Err(io::Error::other(format!(
+            "Leaf {} changed while it was being edited; nothing was saved",
+            leaf.id()
+        )));
+    }
+
+    let (front_matter, body) = markdown::split_front_matter(&edited);
+    let name = front_matter
+        .map(markdown::parse_front_matter)
+        .and_then(|front_matter| front_matter.title)
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or_else(|| leaf.name().to_string());
+    let content = markdown::to_html(body, |_| None, |_| None);
+    db.update(Leaf::new(leaf.id().to_string(), name, content))
+        .await
+        .map_err(to_io_error)?;
+    output(args, json!({ "id": leaf.id(), "changed": true }), || {
+        format!("Saved {}", leaf.id())
+    });
+    Ok(())
+}
+
+async fn search(args: &Args, db: &SqlDatabase) -> io::Result<()> {
+    args.check_flags(&[])?;
+    let query = args.positional.join(" ");
+    if query.trim().is_empty() {
+        return Err(invalid_input(format!("search needs a query\n\n{}", USAGE)));
+    }
+    let leaves = db
+        .search::<Leaf>(&query, args.limit)
+        .await
+        .map_err(to_io_error)?;
+    output(args, leaves.iter().map(leaf_summary).collect(), || {
+        leaves
+            .iter()
+            .map(|leaf| format!("{}  {}\n    {}", leaf.id(), leaf.name(), snippet(leaf.content())))
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    Ok(())
+}
+
+async fn semantic_search(args: &Args, db: &SqlDatabase) -> io::Result<()> {
+    args.check_flags(&[])?;
+    let query = args.positional.join(" ");
+    if query.trim().is_empty() {
+        return Err(invalid_input(format!("semantic-search needs a query\n\n{}", USAGE)));
+    }
+    let similar = db
+        .find_similar(&query, args.limit)
+        .await
+        .map_err(to_io_error)?;
+    let mut results = Vec::new();
+    for (id, object_type, distance) in similar {
+        let name = match object_type.as_str() {
+            "leaf" => db.read::<Leaf>(&id).await.map_err(to_io_error)?.map(|leaf| leaf.name().to_string()),
+            "sage" => db.read::<Sage>(&id).await.map_err(to_io_error)?.map(|sage| sage.name().to_string()),
+            _ => None,
+        };
+        if let Some(name) = name {
+            results.push(json!({ "id": id, "type": object_type, "name": name, "distance": distance }));
+        }
+    }
+    let text = || {
+        results
+            .iter()
+            .map(|result| {
+                format!(
+                    "{}  {} {} ({:.3})",
+                    result["id"].as_str().unwrap_or_default(),
+                    result["type"].as_str().unwrap_or_default(),
+                    result["name"].as_str().unwrap_or_default(),
+                    result["distance"].as_f64().unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    output(args, Value::Array(results.clone()), text);
+    Ok(())
+}
+
+async fn export(args: &Args, db: &SqlDatabase, uploads: &Path) -> io::Result<()> {
+    args.check_flags(&[])?;
+    let path = PathBuf::from(args.arg("path")?);
+    let leaves = db.list::<Leaf>().await.map_err(to_io_error)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => publish::publish_html(&leaves, "Bonsai", uploads, &path)?,
+        Some("epub") => publish::publish_epub(&leaves, "Bonsai", "", uploads, &path)?,
+        _ => {
+            fs::create_dir_all(&path)?;
+            let mut taken: HashSet<String> = fs::read_dir(&path)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_lowercase())
+                .collect();
+            for leaf in &leaves {
+                let file = mirror::unique_path(leaf.name(), &taken, "");
+                fs::write(path.join(&file), mirror::render_file(leaf))?;
+                taken.insert(file.to_lowercase());
+            }
+        }
+    }
+    output(
+        args,
+        json!({ "path": path, "leaves": leaves.len() }),
+        || format!("Exported {} leaves to {}", leaves.len(), path.display()),
+    );
+    Ok(())
+}
+
+async fn import(args: &Args, open: &OpenWorkspace) -> io::Result<()> {
+    args.check_flags(&["notion"])?;
+    let source = Path::new(args.arg("path")?);
+    let report = if args.flags.contains("notion") {
+        notion::import_notion(&open.db, &open.files, source).await?
+    } else {
+        importer::import_markdown(&open.db, &open.files, source).await?
+    };
+    let value = serde_json::to_value(&report)?;
+    output(args, value, || {
+        let mut lines = vec![format!("Imported {} files", report.imported.len())];
+        for failed in &report.failed {
+            lines.push(format!("Failed: {}", serde_json::to_string(failed).unwrap_or_default()));
+        }
+        lines.join("\n")
+    });
+    Ok(())
+}
+
+async fn create_backup(args: &Args, open: &OpenWorkspace) -> io::Result<()> {
+    args.check_flags(&[])?;
+    let path = Path::new(args.arg("path")?);
+    // Archives are encrypted when a passphrase is set, like from the app
+    let passphrase = env::var("BONSAI_BACKUP_PASSPHRASE").ok();
+    let schedule = &open.settings.backup;
+    let manifest =
+        backup::create_backup(&open.db, &open.files, schedule, path, passphrase.as_deref()).await?;
+    let value = serde_json::to_value(&manifest)?;
+    output(args, value, || {
+        format!("Backed up {} files to {}", manifest.files.len(), path.display())
+    });
+    Ok(())
+}
+
+async fn reindex(args: &Args, db: &SqlDatabase) -> io::Result<()> {
+    args.check_flags(&[])?;
+    let leaves = db.list::<Leaf>().await.map_err(to_io_error)?;
+    for leaf in &leaves {
+        db.store_embedding(leaf.id().to_string(), Leaf::get_object_type(), &leaf.get_embedding_text())
+            .await
+            .map_err(to_io_error)?;
+    }
+    let sages = db.list::<Sage>().await.map_err(to_io_error)?;
+    for sage in &sages {
+        db.store_embedding(sage.id().to_string(), Sage::get_object_type(), &sage.get_embedding_text())
+            .await
+            .map_err(to_io_error)?;
+    }
+    output(
+        args,
+        json!({ "leaves": leaves.len(), "sages": sages.len() }),
+        || format!("Reindexed {} leaves and {} sages", leaves.len(), sages.len()),
+    );
+    Ok(())
+}
+
+// -------------------------------------------------------
+
+async fn run() -> io::Result<()> {
+    let args = parse_args()?;
+    if args.command == "help" {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let open = headless::open(args.workspace.as_deref()).await?;
+    let db = &open.db;
+    match args.command.as_str() {
+        "list" => list(&args, db).await,
+        "show" => show(&args, db).await,
+        "new" => new(&args, db).await,
+        "edit" => edit(&args, db).await,
+        "search" => search(&args, db).await,
+        "semantic-search" => semantic_search(&args, db).await,
+        "export" => export(&args, db, &open.files.root_dir().join("uploads")).await,
+        "import" => import(&args, &open).await,
+        "backup" => create_backup(&args, &open).await,
+        "reindex" => reindex(&args, db).await,
+        other => Err(invalid_input(format!("Unknown command {}\n\n{}", other, USAGE))),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("bonsai: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::db::{Leaf, SqlDatabase};
+use crate::html::{self, Node};
+use serde::Serialize;
+use sqlx::Error as SqlxError;
+
+// Leaf content split into its top-level blocks, indexed into the `blocks`
+// table whenever a leaf is written. Blocks are addressed by the id the
+// BlockID extension gives paragraphs and headings.
+//
+// A leaf embeds a block of another by reference with
+//
+//   <div data-type="transclusion" data-leaf-id=".." data-block-id=".."></div>
+//
+// which `resolve_transclusions` fills with the block's current HTML. Only
+// paragraphs and headings have ids, so an embedded block never embeds
+// another one in turn.
+
+pub const TRANSCLUSION_TYPE: &str = "transclusion";
+// Elements that may take a block's place, the ones BlockID tags
+const ADDRESSABLE: &[&str] = &["p", "h1", "h2", "h3", "h4", "h5", "h6"];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Block {
+    pub leaf_id: String,
+    pub position: i64,
+    // None for blocks BlockID does not tag, e.g. lists and tables
+    pub id: Option<String>,
+    // Editor node type, e.g. `paragraph`, `heading` or `taskList`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: String,
+    pub html: String,
+}
+
+// A leaf embedding a block.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockReference {
+    pub leaf_id: String,
+    pub leaf_name: String,
+}
+
+// Where a transclusion points, as leaf id and block id.
+pub type Transclusion = (String, String);
+
+fn kind(node: &Node) -> String {
+    let data_type = node.attr("data-type");
+    match (node.name().unwrap_or_default(), data_type) {
+        (_, Some(data_type)) => data_type.to_string(),
+        ("p", _) => "paragraph".to_string(),
+        (name, _) if ADDRESSABLE.contains(&name) => "heading".to_string(),
+        ("ul", _) => "bulletList".to_string(),
+        ("ol", _) => "orderedList".to_string(),
+        ("pre", _) => "codeBlock".to_string(),
+        ("hr", _) => "horizontalRule".to_string(),
+        ("img", _) => "image".to_string(),
+        (name, _) => name.to_string(),
+    }
+}
+
+fn transclusion(node: &Node) -> Option<Transclusion> {
+    if node.name() != Some("div") || node.attr("data-type") != Some(TRANSCLUSION_TYPE) {
+        return None;
+    }
+    Some((
+        node.attr("data-leaf-id")?.to_string(),
+        node.attr("data-block-id")?.to_string(),
+    ))
+}
+
+// The top-level blocks of `content`, in order. Whitespace between them is
+// not a block.
+pub fn extract(leaf_id: &str, content: &str) -> Vec<Block> {
+    html::parse(content)
+        .iter()
+        .filter(|node| !matches!(node, Node::Text(text) if text.trim().is_empty()))
+        .enumerate()
+        .map(|(position, node)| Block {
+            leaf_id: leaf_id.to_string(),
+            position: position as i64,
+            id: node.attr("blockid").map(str::to_string),
+            kind: match node {
+                Node::Text(_) => "text".to_string(),
+                element => kind(element),
+            },
+            text: node.text().split_whitespace().collect::<Vec<_>>().join(" "),
+            html: html::render(std::slice::from_ref(node)),
+        })
+        .collect()
+}
+
+fn collect_transclusions(nodes: &[Node], out: &mut Vec<Transclusion>) {
+    for node in nodes {
+        match transclusion(node) {
+            Some(target) => out.push(target),
+            None => collect_transclusions(node.children(), out),
+        }
+    }
+}
+
+// Every block `content` embeds, wherever it sits, e.g. inside columns.
+pub fn transclusions(content: &str) -> Vec<Transclusion> {
+    let mut targets = Vec::new();
+    collect_transclusions(&html::parse(content), &mut targets);
+    targets
+}
+
+// `content` with the block `block_id` replaced by `replacement`, which must
+// be a single paragraph or heading. It keeps the block's id.
+pub fn replace_block(content: &str, block_id: &str, replacement: &str) -> Result<String, String> {
+    let mut nodes = html::parse(content);
+    let index = nodes
+        .iter()
+        .position(|node| node.attr("blockid") == Some(block_id))
+        .ok_or_else(|| format!("Block {} not found", block_id))?;
+
+    let mut replacement: Vec<Node> = html::parse(replacement)
+        .into_iter()
+        .filter(|node| !matches!(node, Node::Text(text) if text.trim().is_empty()))
+        .collect();
+    let node = match replacement.as_mut_slice() {
+        [node @ Node::Element { .. }]
+            if ADDRESSABLE.contains(&node.name().unwrap_or_default()) =>
+        {
+            node
+        }
+        _ => return Err("A block can only be replaced by a single paragraph or heading".to_string()),
+    };
+    if let Node::Element { attrs, .. } = node {
+        attrs.retain(|(key, _)| !key.eq_ignore_ascii_case("blockid"));
+        attrs.push(("blockid".to_string(), block_id.to_string()));
+    }
+    nodes[index] = replacement.remove(0);
+    Ok(html::render(&nodes))
+}
+
+pub async fn update_block(
+    db: &SqlDatabase,
+    leaf_id: &str,
+    block_id: &str,
+    replacement: &str,
+) -> Result<Block, String> {
+    let leaf = db
+        .read::<Leaf>(leaf_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Leaf {} not found", leaf_id))?;
+    let content = replace_block(leaf.content(), block_id, replacement)?;
+    db.update(Leaf::new(leaf_id.to_string(), leaf.name().to_string(), content))
+        .await
+        .map_err(|e| e.to_string())?;
+    db.block(leaf_id, block_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| SqlxError::RowNotFound.to_string())
+}
+
+// -------------------------------------------------------
+
+fn fill_transclusions(nodes: &mut [Node], blocks: &[(Transclusion, Option<Node>)]) {
+    for node in nodes.iter_mut() {
+        let Some(target) = transclusion(node) else {
+            if let Node::Element { children, .. } = node {
+                fill_transclusions(children, blocks);
+            }
+            continue;
+        };
+        let Node::Element { attrs, children, .. } = node else {
+            continue;
+        };
+        let block = blocks
+            .iter()
+            .find(|(t, _)| *t == target)
+            .and_then(|(_, block)| block.clone());
+        attrs.retain(|(key, _)| key != "data-missing");
+        match block {
+            Some(block) => *children = vec![block],
+            // Deleted since, or the leaf is gone
+            None => {
+                children.clear();
+                attrs.push(("data-missing".to_string(), "true".to_string()));
+            }
+        }
+    }
+}
+
+// `content` with each transclusion holding the current HTML of its block.
+pub async fn resolve_transclusions(db: &SqlDatabase, content: &str) -> Result<String, SqlxError> {
+    let targets = transclusions(content);
+    if targets.is_empty() {
+        return Ok(content.to_string());
+    }
+    let mut blocks = Vec::with_capacity(targets.len());
+    for target in targets {
+        let block = db.block(&target.0, &target.1).await?;
+        let node = block.and_then(|block| html::parse(&block.html).into_iter().next());
+        blocks.push((target, node));
+    }
+    let mut nodes = html::parse(content);
+    fill_transclusions(&mut nodes, &blocks);
+    Ok(html::render(&nodes))
+}
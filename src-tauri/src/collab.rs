@@ -0,0 +1,862 @@
+use crate::crdt::{self, LeafDocReset, LeafDocUpdate, LeafDocs, UPDATE_EVENT};
+use crate::crypto::{Role, SealedChannel, Spake2};
+use crate::db::SqlDatabase;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use uuid::Uuid;
+
+// Shares chosen leaves with people on the same network. Peers connect over a
+// WebSocket and run a SPAKE2 exchange keyed by the pairing code shown in the
+// app, then join leaves and exchange the same Yjs updates as the editor
+// windows. Every update goes through `LeafDocs`, so it is saved like a local
+// edit.
+//
+// Messages are JSON with a `type` field; updates and shares are base64. The
+// exchange is one `handshake` text frame each way, made with the code's
+// letters and digits in upper case. Everything after it, starting with the
+// peer's `hello`, is sealed in binary frames with the keys it produced, so
+// the code never crosses the network and the traffic can't be read or
+// changed without it.
+
+pub const DEFAULT_PORT: u16 = 7321;
+pub const PRESENCE_EVENT: &str = "collab-presence";
+// A peer must say hello within this time
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+// Slows down guessing of the pairing code
+const REJECT_DELAY: Duration = Duration::from_secs(1);
+// Wrong codes after which an address may not pair again until the server
+// restarts with a new code
+const MAX_FAILED_PAIRINGS: u32 = 5;
+// Connections that have not paired yet, in all and from one address
+const MAX_UNPAIRED: usize = 16;
+const MAX_UNPAIRED_PER_ADDRESS: usize = 2;
+const WRONG_CODE: &str = "Wrong pairing code";
+// Pairing codes leave out letters and digits that look alike
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 8;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum ClientMessage {
+    Handshake { share: String },
+    Hello { name: String },
+    Join { leaf_id: String },
+    Leave,
+    Update { leaf_id: String, generation: u64, update: String },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum ServerMessage {
+    Handshake { share: String },
+    Welcome { peer_id: String, leaves: Vec<SharedLeaf> },
+    Shared { leaves: Vec<SharedLeaf> },
+    // Full state of a joined leaf. `seed` is the leaf's HTML when nobody had
+    // it open; the peer loads it into its editor to fill the document. Sent
+    // again with a new generation when the document was started over, and
+    // the peer replaces its own.
+    Sync { leaf_id: String, update: String, seed: Option<String>, generation: u64 },
+    // `peer_id` is unset for edits made in the app itself
+    Update { leaf_id: String, update: String, peer_id: Option<String>, generation: u64 },
+    Presence { peers: Vec<Peer> },
+    Error { message: String },
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedLeaf {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Peer {
+    pub peer_id: String,
+    pub name: String,
+    pub address: String,
+    // The leaf the peer is viewing
+    pub leaf_id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollabStatus {
+    pub running: bool,
+    pub port: Option<u16>,
+    pub code: Option<String>,
+    // Addresses peers can reach this machine on
+    pub addresses: Vec<String>,
+    pub peers: Vec<Peer>,
+}
+
+// Eight symbols out of 32, shown as two groups of four: 40 random bits.
+fn pairing_code() -> String {
+    let random = Uuid::new_v4().as_u128();
+    let symbols: String = (0..CODE_LENGTH)
+        .map(|i| CODE_ALPHABET[(random >> (i * 5)) as usize % CODE_ALPHABET.len()] as char)
+        .collect();
+    format!("{}-{}", &symbols[..4], &symbols[4..])
+}
+
+// Codes are accepted in any case, with or without the dash.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// The address used to reach the rest of the network. Connecting a UDP socket
+// only picks a route; nothing is sent.
+fn lan_address() -> Option<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then(|| ip.to_string())
+}
+
+fn encode(message: &ServerMessage) -> String {
+    serde_json::to_string(message).unwrap_or_default()
+}
+
+async fn shared_leaves(db: &SqlDatabase) -> Vec<SharedLeaf> {
+    db.list_shared()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, name)| SharedLeaf { id, name })
+        .collect()
+}
+
+// -------------------------------------------------------
+
+type Socket = WebSocketStream<tokio::net::TcpStream>;
+
+struct Connection {
+    peer: Peer,
+    // Messages as JSON, sealed by the connection's task
+    tx: mpsc::UnboundedSender<String>,
+}
+
+// Pairing attempts from one address.
+#[derive(Default)]
+struct Attempts {
+    pending: usize,
+    failures: u32,
+}
+
+// A connection that has not paired yet. Dropping it ends the attempt.
+struct PairingAttempt<'a> {
+    hub: &'a Hub,
+    address: IpAddr,
+}
+
+impl Drop for PairingAttempt<'_> {
+    fn drop(&mut self) {
+        if let Some(attempts) = self.hub.attempts.lock().unwrap().get_mut(&self.address) {
+            attempts.pending -= 1;
+        }
+    }
+}
+
+// State shared by the connections of a running server.
+struct Hub {
+    code: String,
+    // By address, so a host guessing codes only locks itself out
+    attempts: Mutex<HashMap<IpAddr, Attempts>>,
+    unpaired: Semaphore,
+    connections: Mutex<HashMap<String, Connection>>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Hub {
+    fn new(code: String) -> Hub {
+        let (shutdown, _) = watch::channel(false);
+        Hub {
+            code,
+            attempts: Mutex::new(HashMap::new()),
+            unpaired: Semaphore::new(MAX_UNPAIRED),
+            connections: Mutex::new(HashMap::new()),
+            shutdown,
+        }
+    }
+
+    fn begin_pairing(&self, address: IpAddr) -> Result<PairingAttempt<'_>, String> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let attempts = attempts.entry(address).or_default();
+        if attempts.failures >= MAX_FAILED_PAIRINGS {
+            return Err(format!("Too many wrong pairing codes from {}", address));
+        }
+        if attempts.pending >= MAX_UNPAIRED_PER_ADDRESS {
+            return Err(format!("Too many connections waiting to pair from {}", address));
+        }
+        attempts.pending += 1;
+        Ok(PairingAttempt { hub: self, address })
+    }
+
+    fn pairing_failed(&self, address: IpAddr) {
+        self.attempts.lock().unwrap().entry(address).or_default().failures += 1;
+    }
+
+    fn paired(&self, address: IpAddr) {
+        if let Some(attempts) = self.attempts.lock().unwrap().get_mut(&address) {
+            attempts.failures = 0;
+        }
+    }
+
+    fn peers(&self) -> Vec<Peer> {
+        let mut peers: Vec<Peer> = self
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|c| c.peer.clone())
+            .collect();
+        peers.sort_by(|a, b| a.name.cmp(&b.name).then(a.peer_id.cmp(&b.peer_id)));
+        peers
+    }
+
+    fn send(&self, peer_id: &str, message: &ServerMessage) {
+        if let Some(connection) = self.connections.lock().unwrap().get(peer_id) {
+            let _ = connection.tx.send(encode(message));
+        }
+    }
+
+    fn broadcast(&self, message: &ServerMessage) {
+        let message = encode(message);
+        for connection in self.connections.lock().unwrap().values() {
+            let _ = connection.tx.send(message.clone());
+        }
+    }
+
+    // Sends an update to everyone viewing the leaf except its author.
+    fn relay(&self, leaf_id: &str, generation: u64, update: &[u8], from: Option<&str>) {
+        let message = encode(&ServerMessage::Update {
+            leaf_id: leaf_id.to_string(),
+            update: STANDARD.encode(update),
+            peer_id: from.map(str::to_string),
+            generation,
+        });
+        for (peer_id, connection) in self.connections.lock().unwrap().iter() {
+            if connection.peer.leaf_id.as_deref() == Some(leaf_id) && Some(peer_id.as_str()) != from {
+                let _ = connection.tx.send(message.clone());
+            }
+        }
+    }
+
+    fn set_leaf(&self, peer_id: &str, leaf_id: Option<String>) {
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(peer_id) {
+            connection.peer.leaf_id = leaf_id;
+        }
+    }
+
+    fn viewers(&self, leaf_id: &str) -> Vec<String> {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, c)| c.peer.leaf_id.as_deref() == Some(leaf_id))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect()
+    }
+
+    fn leaf(&self, peer_id: &str) -> Option<String> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .and_then(|c| c.peer.leaf_id.clone())
+    }
+
+    fn presence_changed<R: Runtime>(&self, app: &AppHandle<R>) {
+        let peers = self.peers();
+        let _ = app.emit(PRESENCE_EVENT, &peers);
+        self.broadcast(&ServerMessage::Presence { peers });
+    }
+}
+
+// Resolves once the server is told to stop, even if that happened before.
+async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stopped| *stopped).await;
+}
+
+async fn leave<R: Runtime>(app: &AppHandle<R>, hub: &Hub, peer_id: &str) {
+    if let Some(leaf_id) = hub.leaf(peer_id) {
+        hub.set_leaf(peer_id, None);
+        let db = app.state::<SqlDatabase>();
+        let docs = app.state::<LeafDocs>();
+        if let Err(e) = docs.close(&db, &leaf_id).await {
+            eprintln!("Failed to close shared leaf {}: {}", leaf_id, e);
+        }
+    }
+}
+
+async fn handle_message<R: Runtime>(
+    app: &AppHandle<R>,
+    hub: &Hub,
+    peer_id: &str,
+    message: ClientMessage,
+) -> Result<(), String> {
+    let db = app.state::<SqlDatabase>();
+    let docs = app.state::<LeafDocs>();
+    match message {
+        ClientMessage::Handshake { .. } | ClientMessage::Hello { .. } => {
+            Err("Already paired".to_string())
+        }
+        ClientMessage::Join { leaf_id } => {
+            if !db.is_shared(&leaf_id).await.map_err(|e| e.to_string())? {
+                return Err(format!("Leaf {} is not shared", leaf_id));
+            }
+            if hub.leaf(peer_id).as_deref() == Some(leaf_id.as_str()) {
+                return Ok(());
+            }
+            leave(app, hub, peer_id).await;
+            let session = docs.open(&db, &leaf_id).await.map_err(|e| e.to_string())?;
+            hub.set_leaf(peer_id, Some(leaf_id.clone()));
+            hub.send(
+                peer_id,
+                &ServerMessage::Sync {
+                    leaf_id,
+                    update: STANDARD.encode(&session.update),
+                    seed: session.seed,
+                    generation: session.generation,
+                },
+            );
+            hub.presence_changed(app);
+            Ok(())
+        }
+        ClientMessage::Leave => {
+            leave(app, hub, peer_id).await;
+            hub.presence_changed(app);
+            Ok(())
+        }
+        ClientMessage::Update { leaf_id, generation, update } => {
+            if hub.leaf(peer_id).as_deref() != Some(leaf_id.as_str()) {
+                return Err(format!("Join leaf {} before editing it", leaf_id));
+            }
+            // The leaf may have been unshared since it was joined
+            if !db.is_shared(&leaf_id).await.map_err(|e| e.to_string())? {
+                leave(app, hub, peer_id).await;
+                hub.presence_changed(app);
+                return Err(format!("Leaf {} is no longer shared", leaf_id));
+            }
+            let update = STANDARD.decode(update).map_err(|e| e.to_string())?;
+            let reset = docs
+                .apply_update(&db, &leaf_id, generation, &update)
+                .await
+                .map_err(|e| e.to_string())?;
+            if let Some(reset) = reset {
+                crdt::announce_reset(app, reset).await;
+                return Ok(());
+            }
+            hub.relay(&leaf_id, generation, &update, Some(peer_id));
+            let _ = app.emit(
+                UPDATE_EVENT,
+                LeafDocUpdate {
+                    leaf_id,
+                    update,
+                    origin: Some(format!("collab:{}", peer_id)),
+                    generation,
+                },
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn send_sealed(
+    sink: &mut SplitSink<Socket, Message>,
+    channel: &mut SealedChannel,
+    text: &str,
+) -> Result<(), String> {
+    let frame = channel.seal(text.as_bytes()).map_err(|e| e.to_string())?;
+    sink.send(Message::Binary(frame)).await.map_err(|e| e.to_string())
+}
+
+// Runs the key exchange and reads the peer's hello, which only opens when
+// both sides used the same code. None when the peer left before trying.
+async fn pair(
+    code: &str,
+    sink: &mut SplitSink<Socket, Message>,
+    stream: &mut SplitStream<Socket>,
+) -> Result<Option<(SealedChannel, String)>, String> {
+    let share = match stream.next().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+            Ok(ClientMessage::Handshake { share }) => share,
+            _ => return Err("Expected a handshake".to_string()),
+        },
+        _ => return Ok(None),
+    };
+    let share = STANDARD.decode(share).map_err(|e| e.to_string())?;
+    let (exchange, reply) = Spake2::start(Role::Server, &normalize_code(code));
+    let mut channel = exchange.finish(&share).map_err(|e| e.to_string())?;
+    let handshake = ServerMessage::Handshake {
+        share: STANDARD.encode(reply),
+    };
+    sink.send(Message::Text(encode(&handshake)))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let hello = match stream.next().await {
+        Some(Ok(Message::Binary(frame))) => channel
+            .open(&frame)
+            .ok()
+            .and_then(|text| serde_json::from_slice(&text).ok()),
+        Some(Ok(_)) => None,
+        _ => return Ok(None),
+    };
+    match hello {
+        Some(ClientMessage::Hello { name }) => Ok(Some((channel, name))),
+        _ => Err(WRONG_CODE.to_string()),
+    }
+}
+
+async fn handle_connection<R: Runtime>(
+    app: AppHandle<R>,
+    hub: Arc<Hub>,
+    stream: tokio::net::TcpStream,
+    address: SocketAddr,
+) -> Result<(), String> {
+    let attempt = hub.begin_pairing(address.ip())?;
+    let unpaired = hub
+        .unpaired
+        .try_acquire()
+        .map_err(|_| format!("Too many connections waiting to pair; dropped {}", address))?;
+    let socket = tokio_tungstenite::accept_async(stream)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (mut sink, mut stream) = socket.split();
+
+    let paired = tokio::time::timeout(HELLO_TIMEOUT, pair(&hub.code, &mut sink, &mut stream))
+        .await
+        .map_err(|_| "Timed out waiting for hello".to_string())?;
+    let (mut channel, name) = match paired {
+        Ok(Some(paired)) => paired,
+        Ok(None) => return Ok(()),
+        Err(message) => {
+            hub.pairing_failed(address.ip());
+            tokio::time::sleep(REJECT_DELAY).await;
+            let error = ServerMessage::Error {
+                message: message.clone(),
+            };
+            let _ = sink.send(Message::Text(encode(&error))).await;
+            let _ = sink.close().await;
+            return Err(format!("{} failed to pair: {}", address, message));
+        }
+    };
+    hub.paired(address.ip());
+    drop(attempt);
+    drop(unpaired);
+
+    let db = app.state::<SqlDatabase>();
+    let peer_id = Uuid::new_v4().to_string();
+    let welcome = ServerMessage::Welcome {
+        peer_id: peer_id.clone(),
+        leaves: shared_leaves(&db).await,
+    };
+    send_sealed(&mut sink, &mut channel, &encode(&welcome)).await?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    hub.connections.lock().unwrap().insert(
+        peer_id.clone(),
+        Connection {
+            peer: Peer {
+                peer_id: peer_id.clone(),
+                name,
+                address: address.ip().to_string(),
+                leaf_id: None,
+            },
+            tx,
+        },
+    );
+    hub.presence_changed(&app);
+
+    let mut shutdown = hub.shutdown.subscribe();
+    loop {
+        tokio::select! {
+            incoming = stream.next() => match incoming {
+                Some(Ok(Message::Binary(frame))) => {
+                    // A frame that doesn't open was changed on the way, and
+                    // nothing after it can be trusted
+                    let Ok(text) = channel.open(&frame) else {
+                        break;
+                    };
+                    let result = match serde_json::from_slice(&text) {
+                        Ok(message) => handle_message(&app, &hub, &peer_id, message).await,
+                        Err(e) => Err(format!("Invalid message: {}", e)),
+                    };
+                    if let Err(message) = result {
+                        hub.send(&peer_id, &ServerMessage::Error { message });
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            outgoing = rx.recv() => match outgoing {
+                Some(text) => {
+                    if send_sealed(&mut sink, &mut channel, &text).await.is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            _ = stopped(&mut shutdown) => {
+                let _ = sink.close().await;
+                break;
+            }
+        }
+    }
+
+    leave(&app, &hub, &peer_id).await;
+    hub.connections.lock().unwrap().remove(&peer_id);
+    hub.presence_changed(&app);
+    Ok(())
+}
+
+// -------------------------------------------------------
+
+struct ServerTask {
+    port: u16,
+    hub: Arc<Hub>,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+// Managed state owning the server, if one is running.
+#[derive(Default)]
+pub struct CollabServer {
+    running: Mutex<Option<ServerTask>>,
+    // Held while starting or stopping, so a restart can't overlap another
+    lifecycle: tokio::sync::Mutex<()>,
+}
+
+impl CollabServer {
+    // Listens on every interface. Port 0 picks a free port, which the
+    // returned status reports.
+    pub async fn start<R: Runtime>(&self, app: AppHandle<R>, port: u16) -> io::Result<CollabStatus> {
+        let _lifecycle = self.lifecycle.lock().await;
+        self.shut_down().await;
+
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let hub = Arc::new(Hub::new(pairing_code()));
+
+        let task_hub = hub.clone();
+        let mut shutdown = hub.shutdown.subscribe();
+        let task = tauri::async_runtime::spawn(async move {
+            let listener = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Collaboration server failed to start: {}", e);
+                    return;
+                }
+            };
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = stopped(&mut shutdown) => break,
+                };
+                let (stream, address) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        eprintln!("Collaboration server failed to accept: {}", e);
+                        continue;
+                    }
+                };
+                let app = app.clone();
+                let hub = task_hub.clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = handle_connection(app, hub, stream, address).await {
+                        eprintln!("Collaboration connection closed: {}", e);
+                    }
+                });
+            }
+        });
+
+        *self.running.lock().unwrap() = Some(ServerTask { port, hub, task });
+        Ok(self.status())
+    }
+
+    // Stops listening and disconnects every peer. The port is free once
+    // this returns.
+    pub async fn stop(&self) {
+        let _lifecycle = self.lifecycle.lock().await;
+        self.shut_down().await;
+    }
+
+    async fn shut_down(&self) {
+        let Some(running) = self.running.lock().unwrap().take() else {
+            return;
+        };
+        running.hub.shutdown.send_replace(true);
+        let _ = running.task.await;
+    }
+
+    pub fn status(&self) -> CollabStatus {
+        match self.running.lock().unwrap().as_ref() {
+            Some(running) => CollabStatus {
+                running: true,
+                port: Some(running.port),
+                code: Some(running.hub.code.clone()),
+                addresses: lan_address().into_iter().collect(),
+                peers: running.hub.peers(),
+            },
+            None => CollabStatus {
+                running: false,
+                port: None,
+                code: None,
+                addresses: Vec::new(),
+                peers: Vec::new(),
+            },
+        }
+    }
+
+    // Passes an edit made in one of the app's windows on to peers.
+    pub fn relay(&self, leaf_id: &str, generation: u64, update: &[u8]) {
+        if let Some(running) = self.running.lock().unwrap().as_ref() {
+            running.hub.relay(leaf_id, generation, update, None);
+        }
+    }
+
+    // Sends the restarted document to the peers viewing the leaf.
+    pub async fn reset(&self, docs: &LeafDocs, reset: &LeafDocReset) {
+        let hub = match self.running.lock().unwrap().as_ref() {
+            Some(running) => running.hub.clone(),
+            None => return,
+        };
+        for peer_id in hub.viewers(&reset.leaf_id) {
+            let Ok(session) = docs.reload(&reset.leaf_id).await else {
+                return;
+            };
+            let sync = ServerMessage::Sync {
+                leaf_id: reset.leaf_id.clone(),
+                update: STANDARD.encode(&session.update),
+                seed: session.seed,
+                generation: session.generation,
+            };
+            hub.send(&peer_id, &sync);
+        }
+    }
+
+    // Tells peers which leaves are shared after the list changed.
+    pub async fn shares_changed(&self, db: &SqlDatabase) {
+        let hub = match self.running.lock().unwrap().as_ref() {
+            Some(running) => running.hub.clone(),
+            None => return,
+        };
+        let leaves = shared_leaves(db).await;
+        hub.broadcast(&ServerMessage::Shared { leaves });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::FRAGMENT;
+    use crate::db::{Leaf, TimeStamped};
+    use chrono::Utc;
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+    use yrs::types::xml::{XmlElementPrelim, XmlFragment, XmlTextPrelim};
+    use yrs::updates::decoder::Decode;
+    use yrs::{Doc, ReadTxn, Transact, Update};
+
+    const ID: &str = "0d6c1a52-8f3b-4e0e-9a51-2b7d6f4c8e13";
+    const WAIT: Duration = Duration::from_secs(5);
+
+    // A workspace folder, removed when dropped.
+    struct Workspace(PathBuf);
+
+    impl Workspace {
+        // The workspace and its database, which has the shared leaf `ID`.
+        async fn new() -> (Workspace, SqlDatabase) {
+            let root = std::env::temp_dir().join(format!("bonsai-collab-test-{}", Uuid::new_v4()));
+            let db = SqlDatabase::open(root.clone(), None).await.unwrap();
+            let mut leaf = Leaf::new(ID.to_string(), "Plan".to_string(), "<p>Shared</p>".to_string());
+            let now = Utc::now().to_rfc3339();
+            leaf.set_created_at(now.clone());
+            leaf.set_modified_at(now);
+            db.put_deferred(leaf).await.unwrap();
+            db.set_shared(ID, true).await.unwrap();
+            (Workspace(root), db)
+        }
+    }
+
+    impl Drop for Workspace {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    struct Client {
+        socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+        channel: SealedChannel,
+    }
+
+    impl Client {
+        // Runs the key exchange with `code` and says hello. Returns the
+        // server's answer: the welcome, or an error when the codes differ.
+        async fn hello(port: u16, code: &str, name: &str) -> (Client, Value) {
+            let url = format!("ws://127.0.0.1:{}", port);
+            let mut socket = tokio_tungstenite::connect_async(url).await.unwrap().0;
+            let (exchange, share) = Spake2::start(Role::Client, &normalize_code(code));
+            let handshake = json!({ "type": "handshake", "share": STANDARD.encode(share) });
+            socket.send(Message::Text(handshake.to_string())).await.unwrap();
+            let reply = match socket.next().await {
+                Some(Ok(Message::Text(text))) => serde_json::from_str::<Value>(&text).unwrap(),
+                other => panic!("Expected a handshake: {:?}", other),
+            };
+            let share = STANDARD.decode(reply["share"].as_str().unwrap()).unwrap();
+            let channel = exchange.finish(&share).unwrap();
+
+            let mut client = Client { socket, channel };
+            client.send(json!({ "type": "hello", "name": name })).await;
+            let answer = client
+                .expect(|message| message["type"] == "welcome" || message["type"] == "error")
+                .await;
+            (client, answer)
+        }
+
+        async fn send(&mut self, message: Value) {
+            let frame = self.channel.seal(message.to_string().as_bytes()).unwrap();
+            self.socket.send(Message::Binary(frame)).await.unwrap();
+        }
+
+        // Skips messages until one satisfies `wanted`.
+        async fn expect(&mut self, wanted: impl Fn(&Value) -> bool) -> Value {
+            tokio::time::timeout(WAIT, async {
+                loop {
+                    // Only errors before pairing are sent in the clear
+                    let text = match self.socket.next().await {
+                        Some(Ok(Message::Text(text))) => text.into_bytes(),
+                        Some(Ok(Message::Binary(frame))) => self.channel.open(&frame).unwrap(),
+                        Some(Ok(_)) => continue,
+                        other => panic!("Connection ended: {:?}", other),
+                    };
+                    let message: Value = serde_json::from_slice(&text).unwrap();
+                    if wanted(&message) {
+                        return message;
+                    }
+                }
+            })
+            .await
+            .expect("Timed out waiting for a message")
+        }
+
+        async fn expect_type(&mut self, kind: &str) -> Value {
+            self.expect(|message| message["type"] == kind).await
+        }
+
+        async fn pair(port: u16, code: &str, name: &str) -> (Client, String) {
+            let (client, welcome) = Client::hello(port, code, name).await;
+            assert_eq!(welcome["type"], "welcome");
+            assert_eq!(welcome["leaves"][0]["id"], ID);
+            let peer_id = welcome["peerId"].as_str().unwrap().to_string();
+            (client, peer_id)
+        }
+    }
+
+    // An update adding a paragraph to the document a peer was sent.
+    fn add_paragraph(sync: &[u8], text: &str) -> Vec<u8> {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment(FRAGMENT);
+        let before = {
+            let mut txn = doc.transact_mut();
+            txn.apply_update(Update::decode_v1(sync).unwrap()).unwrap();
+            txn.state_vector()
+        };
+        {
+            let mut txn = doc.transact_mut();
+            let paragraph = fragment.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+            paragraph.push_back(&mut txn, XmlTextPrelim::new(text));
+        }
+        let txn = doc.transact();
+        txn.encode_diff_v1(&before)
+    }
+
+    #[tokio::test]
+    async fn pairs_joins_and_relays_updates() {
+        let (_workspace, db) = Workspace::new().await;
+        let app = tauri::test::mock_app();
+        app.manage(db);
+        app.manage(LeafDocs::default());
+        let server = CollabServer::default();
+        let status = server.start(app.handle().clone(), 0).await.unwrap();
+        let port = status.port.unwrap();
+        let code = status.code.unwrap();
+
+        let (_, error) = Client::hello(port, "ABCD-2345", "Eve").await;
+        assert_eq!(error["message"], WRONG_CODE);
+
+        // Codes are typed in any case, with or without the dash
+        let typed = code.to_lowercase().replace('-', "");
+        let (mut alice, alice_id) = Client::pair(port, &typed, "Alice").await;
+        let (mut bob, _) = Client::pair(port, &code, "Bob").await;
+
+        alice.send(json!({ "type": "join", "leafId": ID })).await;
+        let sync = alice.expect_type("sync").await;
+        assert_eq!(sync["seed"], "<p>Shared</p>");
+        let generation = sync["generation"].as_u64().unwrap();
+        bob.send(json!({ "type": "join", "leafId": ID })).await;
+        let sync = bob.expect_type("sync").await;
+        assert_eq!(sync["seed"], Value::Null);
+        assert_eq!(sync["generation"], generation);
+
+        let presence = bob
+            .expect(|message| {
+                message["type"] == "presence"
+                    && message["peers"]
+                        .as_array()
+                        .is_some_and(|peers| peers.len() == 2 && peers.iter().all(|p| p["leafId"] == ID))
+            })
+            .await;
+        assert_eq!(presence["peers"][0]["name"], "Alice");
+        assert_eq!(presence["peers"][1]["name"], "Bob");
+
+        let state = STANDARD.decode(sync["update"].as_str().unwrap()).unwrap();
+        let update = STANDARD.encode(add_paragraph(&state, "Hello"));
+        alice
+            .send(json!({ "type": "update", "leafId": ID, "generation": generation, "update": update }))
+            .await;
+        let relayed = bob.expect_type("update").await;
+        assert_eq!(relayed["update"], update);
+        assert_eq!(relayed["peerId"], alice_id.as_str());
+        assert_eq!(relayed["generation"], generation);
+
+        server.stop().await;
+    }
+
+    #[test]
+    fn limits_pairing_attempts_per_address() {
+        let hub = Hub::new(pairing_code());
+        let eve = IpAddr::from([192, 168, 1, 66]);
+        let alice = IpAddr::from([192, 168, 1, 10]);
+        for _ in 0..MAX_FAILED_PAIRINGS {
+            let _attempt = hub.begin_pairing(eve).unwrap();
+            hub.pairing_failed(eve);
+        }
+        assert!(hub.begin_pairing(eve).is_err());
+
+        // Others still pair, but each only so many at a time
+        let attempts: Vec<_> = (0..MAX_UNPAIRED_PER_ADDRESS)
+            .map(|_| hub.begin_pairing(alice).unwrap())
+            .collect();
+        assert!(hub.begin_pairing(alice).is_err());
+        drop(attempts);
+        assert!(hub.begin_pairing(alice).is_ok());
+    }
+}
@@ -0,0 +1,484 @@
+use crate::collab::CollabServer;
+use crate::db::{Leaf, SqlDatabase};
+use crate::events::{self, ChangeKind, EntityKind};
+use crate::html;
+use serde::{Deserialize, Serialize};
+use sqlx::Error as SqlxError;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tokio::sync::broadcast;
+use yrs::types::text::YChange;
+use yrs::types::xml::{Xml, XmlFragment, XmlOut};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{Any, Doc, Out, ReadTxn, StateVector, Text, Transact, Update, XmlElementRef};
+
+// Name of the fragment the Tiptap collaboration extension binds to
+pub const FRAGMENT: &str = "default";
+pub const UPDATE_EVENT: &str = "leaf-doc-update";
+pub const RESET_EVENT: &str = "leaf-doc-reset";
+
+// Broadcast after an update is applied so the other windows editing the leaf
+// can apply it too. `origin` is the label of the window that sent it.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafDocUpdate {
+    pub leaf_id: String,
+    pub update: Vec<u8>,
+    pub origin: Option<String>,
+    pub generation: u64,
+}
+
+// Broadcast when a leaf's content was changed outside the editors, e.g. by
+// the API or the Markdown mirror. Its document was started again from the
+// new content; windows drop theirs and call `reload_leaf_doc`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafDocReset {
+    pub leaf_id: String,
+    pub generation: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafDocSession {
+    // Full document state to apply before binding the editor
+    pub update: Vec<u8>,
+    // Set when the document was just created: the opening window loads this
+    // HTML into the editor, which fills the document for everyone.
+    pub seed: Option<String>,
+    // Updates must carry it; ones made on an earlier document are refused
+    pub generation: u64,
+}
+
+fn decode_error(e: impl std::fmt::Display) -> SqlxError {
+    SqlxError::Decode(format!("Invalid document update: {}", e).into())
+}
+
+fn stale_error() -> SqlxError {
+    SqlxError::Protocol("The document was reloaded; apply leaf-doc-reset first".to_string())
+}
+
+fn empty_doc() -> Doc {
+    let doc = Doc::new();
+    doc.get_or_insert_xml_fragment(FRAGMENT);
+    doc
+}
+
+struct LiveDoc {
+    doc: Doc,
+    // Windows that have the leaf open
+    sessions: usize,
+    generation: u64,
+    // The leaf's modification time as of the last update saved from `doc`
+    version: String,
+    // Content to fill a document that was started again; handed to the
+    // first window that asks for it
+    seed: Option<String>,
+}
+
+impl LiveDoc {
+    fn session(&mut self) -> LeafDocSession {
+        let update = self
+            .doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        LeafDocSession {
+            update,
+            seed: self.seed.take(),
+            generation: self.generation,
+        }
+    }
+}
+
+// Documents of the leaves currently open in an editor. Every update is saved
+// straight away; the leaf's embedding is refreshed once the last window
+// closes it.
+#[derive(Default)]
+pub struct LeafDocs {
+    docs: tokio::sync::Mutex<HashMap<String, LiveDoc>>,
+    generations: AtomicU64,
+}
+
+impl LeafDocs {
+    fn next_generation(&self) -> u64 {
+        self.generations.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    // Throws away the document of a leaf whose content was changed without
+    // it and starts again from the leaf as stored.
+    fn restart(&self, live: &mut LiveDoc, leaf: &Leaf) -> LeafDocReset {
+        live.doc = empty_doc();
+        live.generation = self.next_generation();
+        live.version = leaf.modified_at().to_string();
+        live.seed = Some(leaf.content().to_string());
+        LeafDocReset {
+            leaf_id: leaf.id().to_string(),
+            generation: live.generation,
+        }
+    }
+
+    pub async fn open(&self, db: &SqlDatabase, leaf_id: &str) -> Result<LeafDocSession, SqlxError> {
+        let mut docs = self.docs.lock().await;
+        if let Some(live) = docs.get_mut(leaf_id) {
+            live.sessions += 1;
+            return Ok(live.session());
+        }
+
+        let leaf = db.read::<Leaf>(leaf_id).await?.ok_or(SqlxError::RowNotFound)?;
+        let doc = empty_doc();
+        // A stored document older than the leaf was edited without it, e.g. by
+        // an import or the Markdown mirror, so start again from the HTML
+        let stored = db
+            .read_leaf_document(leaf_id)
+            .await?
+            .filter(|(_, modified_at)| modified_at.as_str() >= leaf.modified_at());
+        let seed = match stored {
+            Some((state, _)) => {
+                let update = Update::decode_v1(&state).map_err(decode_error)?;
+                doc.transact_mut().apply_update(update).map_err(decode_error)?;
+                None
+            }
+            None => Some(leaf.content().to_string()),
+        };
+        let update = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        let generation = self.next_generation();
+        let live = LiveDoc {
+            doc,
+            sessions: 1,
+            generation,
+            version: leaf.modified_at().to_string(),
+            seed: None,
+        };
+        docs.insert(leaf_id.to_string(), live);
+        Ok(LeafDocSession { update, seed, generation })
+    }
+
+    // The current document of a leaf that is already open, after a reset.
+    pub async fn reload(&self, leaf_id: &str) -> Result<LeafDocSession, SqlxError> {
+        let mut docs = self.docs.lock().await;
+        let live = docs.get_mut(leaf_id).ok_or(SqlxError::RowNotFound)?;
+        Ok(live.session())
+    }
+
+    // Applies an update from an editor and saves the document along with its
+    // HTML rendering as the leaf's content. When the leaf was changed by
+    // something else since the last save, the update is dropped and the
+    // document started again; the returned reset must be announced.
+    pub async fn apply_update(
+        &self,
+        db: &SqlDatabase,
+        leaf_id: &str,
+        generation: u64,
+        update: &[u8],
+    ) -> Result<Option<LeafDocReset>, SqlxError> {
+        let mut docs = self.docs.lock().await;
+        let live = docs.get_mut(leaf_id).ok_or(SqlxError::RowNotFound)?;
+        if generation != live.generation {
+            return Err(stale_error());
+        }
+        let update = Update::decode_v1(update).map_err(decode_error)?;
+        let (state, content) = {
+            let mut txn = live.doc.transact_mut();
+            txn.apply_update(update).map_err(decode_error)?;
+            let fragment = txn.get_xml_fragment(FRAGMENT).ok_or(SqlxError::RowNotFound)?;
+            let content = render_children(&txn, &fragment);
+            (txn.encode_state_as_update_v1(&StateVector::default()), content)
+        };
+        match db
+            .write_leaf_document(leaf_id, &state, &content, &live.version)
+            .await?
+        {
+            Some(version) => {
+                live.version = version;
+                Ok(None)
+            }
+            None => {
+                let leaf = db.read::<Leaf>(leaf_id).await?.ok_or(SqlxError::RowNotFound)?;
+                Ok(Some(self.restart(live, &leaf)))
+            }
+        }
+    }
+
+    // Starts the leaf's document again if its content changed since the
+    // document last saved it.
+    pub async fn reset_if_changed(
+        &self,
+        db: &SqlDatabase,
+        leaf_id: &str,
+    ) -> Result<Option<LeafDocReset>, SqlxError> {
+        let mut docs = self.docs.lock().await;
+        let Some(live) = docs.get_mut(leaf_id) else {
+            return Ok(None);
+        };
+        match db.read::<Leaf>(leaf_id).await? {
+            Some(leaf) if leaf.modified_at() != live.version => Ok(Some(self.restart(live, &leaf))),
+            _ => Ok(None),
+        }
+    }
+
+    async fn open_ids(&self) -> Vec<String> {
+        self.docs.lock().await.keys().cloned().collect()
+    }
+
+    pub async fn state_vector(&self, leaf_id: &str) -> Result<Vec<u8>, SqlxError> {
+        let docs = self.docs.lock().await;
+        let live = docs.get(leaf_id).ok_or(SqlxError::RowNotFound)?;
+        let sv = live.doc.transact().state_vector().encode_v1();
+        Ok(sv)
+    }
+
+    // Everything the holder of `state_vector` is missing.
+    pub async fn diff(&self, leaf_id: &str, state_vector: &[u8]) -> Result<Vec<u8>, SqlxError> {
+        let docs = self.docs.lock().await;
+        let live = docs.get(leaf_id).ok_or(SqlxError::RowNotFound)?;
+        let sv = StateVector::decode_v1(state_vector).map_err(decode_error)?;
+        let diff = live.doc.transact().encode_diff_v1(&sv);
+        Ok(diff)
+    }
+
+    pub async fn close(&self, db: &SqlDatabase, leaf_id: &str) -> Result<(), SqlxError> {
+        let mut docs = self.docs.lock().await;
+        let Some(live) = docs.get_mut(leaf_id) else {
+            return Ok(());
+        };
+        live.sessions = live.sessions.saturating_sub(1);
+        if live.sessions > 0 {
+            return Ok(());
+        }
+        docs.remove(leaf_id);
+        drop(docs);
+
+        if let Some(leaf) = db.read::<Leaf>(leaf_id).await? {
+            let text = format!("{}\n{}", leaf.name(), leaf.content());
+            db.store_embedding(leaf_id.to_string(), "leaf", &text).await?;
+        }
+        Ok(())
+    }
+}
+
+// Tells the windows and peers editing a leaf that its document was started
+// again.
+pub async fn announce_reset<R: Runtime>(app: &AppHandle<R>, reset: LeafDocReset) {
+    let docs = app.state::<LeafDocs>();
+    app.state::<CollabServer>().reset(&docs, &reset).await;
+    let _ = app.emit(RESET_EVENT, reset);
+}
+
+// Writes that bypass `LeafDocs` (the API, MCP, tasks, the mirror, sync)
+// would be overwritten by the next editor update, so documents of leaves
+// changed that way are started again from the new content.
+pub fn watch(app: AppHandle) {
+    let mut changes = events::subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let ids = match changes.recv().await {
+                Ok(change) => {
+                    let content_changed = change.entity == EntityKind::Leaf
+                        && change.change == ChangeKind::Updated
+                        && change.fields.iter().any(|field| field == "content");
+                    if !content_changed {
+                        continue;
+                    }
+                    vec![change.id]
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    app.state::<LeafDocs>().open_ids().await
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Some(db) = app.try_state::<SqlDatabase>() else {
+                continue;
+            };
+            let docs = app.state::<LeafDocs>();
+            for id in ids {
+                match docs.reset_if_changed(&db, &id).await {
+                    Ok(Some(reset)) => announce_reset(&app, reset).await,
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Failed to reload document of leaf {}: {}", id, e),
+                }
+            }
+        }
+    });
+}
+
+// -------------------------------------------------------
+// Rendering the document as the HTML Tiptap would produce
+
+fn render_children<T: ReadTxn, F: XmlFragment>(txn: &T, fragment: &F) -> String {
+    let mut out = String::new();
+    for i in 0..fragment.len(txn) {
+        match fragment.get(txn, i) {
+            Some(XmlOut::Element(element)) => render_element(txn, &element, &mut out),
+            Some(XmlOut::Fragment(fragment)) => out.push_str(&render_children(txn, &fragment)),
+            Some(XmlOut::Text(text)) => render_text(txn, &text.diff(txn, YChange::identity), &mut out),
+            None => {}
+        }
+    }
+    out
+}
+
+fn attribute<T: ReadTxn>(txn: &T, element: &XmlElementRef, name: &str) -> Option<String> {
+    element
+        .get_attribute(txn, name)
+        .map(|value| value.to_string(txn))
+        .filter(|value| !value.is_empty() && value != "null" && value != "undefined")
+}
+
+fn render_element<T: ReadTxn>(txn: &T, element: &XmlElementRef, out: &mut String) {
+    let attr = |name: &str| attribute(txn, element, name);
+    let block_id = || {
+        attr("blockId")
+            .map(|id| vec![("blockid".to_string(), id)])
+            .unwrap_or_default()
+    };
+    let children = || render_children(txn, element);
+    let wrap = |out: &mut String, name: &str, attrs: &[(String, String)], inner: String| {
+        out.push_str(&html::render_start_tag(name, attrs, false));
+        out.push_str(&inner);
+        out.push_str(&format!("</{}>", name));
+    };
+
+    match element.tag().as_ref() {
+        "paragraph" => wrap(out, "p", &block_id(), children()),
+        "heading" => {
+            let level = attr("level")
+                .and_then(|level| level.parse::<f64>().ok())
+                .map(|level| level.clamp(1.0, 6.0) as u8)
+                .unwrap_or(1);
+            wrap(out, &format!("h{}", level), &block_id(), children())
+        }
+        "blockquote" => wrap(out, "blockquote", &[], children()),
+        "bulletList" => wrap(out, "ul", &[], children()),
+        "orderedList" => {
+            let attrs: Vec<_> = attr("start")
+                .filter(|start| start != "1")
+                .map(|start| ("start".to_string(), start))
+                .into_iter()
+                .collect();
+            wrap(out, "ol", &attrs, children())
+        }
+        "listItem" => wrap(out, "li", &[], children()),
+        "codeBlock" => {
+            let attrs: Vec<_> = attr("language")
+                .map(|language| ("class".to_string(), format!("language-{}", language)))
+                .into_iter()
+                .collect();
+            out.push_str("<pre>");
+            wrap(out, "code", &attrs, children());
+            out.push_str("</pre>");
+        }
+        "horizontalRule" => out.push_str("<hr>"),
+        "hardBreak" => out.push_str("<br>"),
+        "image" => {
+            let attrs: Vec<_> = ["src", "alt", "title"]
+                .iter()
+                .filter_map(|name| attr(name).map(|value| (name.to_string(), value)))
+                .collect();
+            out.push_str(&html::render_start_tag("img", &attrs, false));
+        }
+        "taskList" => wrap(
+            out,
+            "ul",
+            &[("data-type".to_string(), "taskList".to_string())],
+            children(),
+        ),
+        "taskItem" => {
+            let checked = attr("checked").is_some_and(|checked| checked == "true");
+            let attrs = [
+                ("data-type".to_string(), "taskItem".to_string()),
+                ("data-checked".to_string(), checked.to_string()),
+            ];
+            out.push_str(&html::render_start_tag("li", &attrs, false));
+            out.push_str("<label><input type=\"checkbox\"");
+            if checked {
+                out.push_str(" checked=\"checked\"");
+            }
+            out.push_str("><span></span></label>");
+            wrap(out, "div", &[], children());
+            out.push_str("</li>");
+        }
+        "table" => {
+            out.push_str("<table><tbody>");
+            out.push_str(&children());
+            out.push_str("</tbody></table>");
+        }
+        "tableRow" => wrap(out, "tr", &[], children()),
+        "tableCell" | "tableHeader" => {
+            let name = if element.tag().as_ref() == "tableHeader" { "th" } else { "td" };
+            let attrs: Vec<_> = ["colspan", "rowspan"]
+                .iter()
+                .filter_map(|key| attr(key).map(|value| (key.to_string(), value)))
+                .collect();
+            wrap(out, name, &attrs, children())
+        }
+        "tableOfContentNode" => wrap(
+            out,
+            "div",
+            &[("data-type".to_string(), "table-of-content".to_string())],
+            String::new(),
+        ),
+        // Nodes from extensions we don't know keep their content
+        other => wrap(
+            out,
+            "div",
+            &[("data-type".to_string(), other.to_string())],
+            children(),
+        ),
+    }
+}
+
+// Start and end tags for a Tiptap mark.
+fn mark_tags(name: &str, value: &Any) -> (String, String) {
+    let tag = match name {
+        "bold" => "strong",
+        "italic" => "em",
+        "strike" => "s",
+        "underline" => "u",
+        "code" => "code",
+        "highlight" => "mark",
+        "subscript" => "sub",
+        "superscript" => "sup",
+        "link" => {
+            let href = match value {
+                Any::Map(attrs) => match attrs.get("href") {
+                    Some(Any::String(href)) => href.to_string(),
+                    _ => String::new(),
+                },
+                _ => String::new(),
+            };
+            let attrs = [("href".to_string(), href)];
+            return (html::render_start_tag("a", &attrs, false), "</a>".to_string());
+        }
+        _ => "span",
+    };
+    (format!("<{}>", tag), format!("</{}>", tag))
+}
+
+fn render_text<T: ReadTxn>(txn: &T, chunks: &[yrs::types::text::Diff<YChange>], out: &mut String) {
+    for chunk in chunks {
+        let text = match &chunk.insert {
+            Out::Any(Any::String(text)) => text.to_string(),
+            other => other.clone().to_string(txn),
+        };
+        let mut marks: Vec<(String, String)> = Vec::new();
+        if let Some(attrs) = chunk.attributes.as_deref() {
+            let mut names: Vec<_> = attrs.iter().collect();
+            names.sort_by(|a, b| a.0.cmp(b.0));
+            marks = names
+                .into_iter()
+                .map(|(name, value)| mark_tags(name, value))
+                .collect();
+        }
+        for (start, _) in &marks {
+            out.push_str(start);
+        }
+        out.push_str(&html::escape(&text));
+        for (_, end) in marks.iter().rev() {
+            out.push_str(end);
+        }
+    }
+}
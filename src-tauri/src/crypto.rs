@@ -0,0 +1,475 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::sync::OnceLock;
+
+// Passphrase-encrypted files start with this header, followed by the Argon2
+// salt, the AES-GCM nonce and the ciphertext.
+const MAGIC: &[u8] = b"BONSAI-ENC\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+// Streams start with their own header, then the Argon2 salt and a nonce
+// prefix, followed by chunks sealed one by one (the STREAM construction):
+// each nonce is the prefix, the chunk's number and whether it is the last.
+// Every chunk but the last holds `CHUNK_LEN` bytes, so a stream cut short
+// or put together from other chunks fails to decrypt.
+const STREAM_MAGIC: &[u8] = b"BONSAI-ENC\x02";
+const NONCE_PREFIX_LEN: usize = 7;
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn is_encrypted_stream(data: &[u8]) -> bool {
+    data.starts_with(STREAM_MAGIC)
+}
+
+// Stretches a passphrase into a 256-bit key with Argon2id.
+pub fn derive_key(passphrase: &str, salt: &[u8]) -> io::Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    Ok(key)
+}
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+pub fn encrypt(passphrase: &str, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let salt = random_bytes::<SALT_LEN>();
+    let nonce = random_bytes::<NONCE_LEN>();
+    let key = derive_key(passphrase, &salt)?;
+
+    let ciphertext = Aes256Gcm::new(&key.into())
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| io::Error::other("Encryption failed"))?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+pub fn decrypt(passphrase: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let header = MAGIC.len() + SALT_LEN + NONCE_LEN;
+    if !is_encrypted(data) || data.len() < header {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Data is not encrypted",
+        ));
+    }
+    let salt = &data[MAGIC.len()..MAGIC.len() + SALT_LEN];
+    let nonce = &data[MAGIC.len() + SALT_LEN..header];
+    let key = derive_key(passphrase, salt)?;
+
+    Aes256Gcm::new(&key.into())
+        .decrypt(Nonce::from_slice(nonce), &data[header..])
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Wrong passphrase or corrupted data",
+            )
+        })
+}
+
+fn chunk_nonce(prefix: &[u8], counter: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LEN - 1] = u8::from(last);
+    nonce
+}
+
+// Fills `buf` as far as the reader goes; short only at the end.
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+pub fn encrypt_stream(
+    passphrase: &str,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    let salt = random_bytes::<SALT_LEN>();
+    let prefix = random_bytes::<NONCE_PREFIX_LEN>();
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?.into());
+    writer.write_all(STREAM_MAGIC)?;
+    writer.write_all(&salt)?;
+    writer.write_all(&prefix)?;
+
+    let mut chunk = vec![0u8; CHUNK_LEN];
+    let mut counter: u32 = 0;
+    loop {
+        let len = read_chunk(reader, &mut chunk)?;
+        // A full chunk may be the last one only if the reader is done; an
+        // empty last chunk follows it then
+        let last = len < CHUNK_LEN;
+        let nonce = chunk_nonce(&prefix, counter, last);
+        let sealed = cipher
+            .encrypt(Nonce::from_slice(&nonce), &chunk[..len])
+            .map_err(|_| io::Error::other("Encryption failed"))?;
+        writer.write_all(&sealed)?;
+        if last {
+            return writer.flush();
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("Too much data to encrypt"))?;
+    }
+}
+
+pub fn decrypt_stream(
+    passphrase: &str,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    let mut header = [0u8; STREAM_MAGIC.len() + SALT_LEN + NONCE_PREFIX_LEN];
+    if read_chunk(reader, &mut header)? < header.len() || !is_encrypted_stream(&header) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Data is not encrypted"));
+    }
+    let salt = &header[STREAM_MAGIC.len()..STREAM_MAGIC.len() + SALT_LEN];
+    let prefix = &header[STREAM_MAGIC.len() + SALT_LEN..];
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, salt)?.into());
+
+    let mut chunk = vec![0u8; CHUNK_LEN + TAG_LEN];
+    let mut counter: u32 = 0;
+    loop {
+        let len = read_chunk(reader, &mut chunk)?;
+        let last = len < chunk.len();
+        let nonce = chunk_nonce(prefix, counter, last);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), &chunk[..len])
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Wrong passphrase or corrupted data",
+                )
+            })?;
+        writer.write_all(&plaintext)?;
+        if last {
+            return writer.flush();
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Encrypted data is too long"))?;
+    }
+}
+
+// -------------------------------------------------------
+
+// SPAKE2 (RFC 9382) in the 2048-bit MODP group of RFC 3526. Both sides turn a
+// short shared code into the same session keys. Someone without the code
+// gets one guess per exchange, and recorded traffic does not help them.
+
+const MODP_PRIME: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF",
+);
+const SPAKE2_CONTEXT: &[u8] = b"bonsai-spake2-v1";
+// Group elements are sent as big-endian numbers of this many bytes
+const SHARE_LEN: usize = 256;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+// The prime `p`, the order `q` of the subgroup generated by `g`, and the
+// blinding elements `m` and `n`.
+struct Group {
+    p: BigUint,
+    q: BigUint,
+    g: BigUint,
+    m: BigUint,
+    n: BigUint,
+}
+
+fn group() -> &'static Group {
+    static GROUP: OnceLock<Group> = OnceLock::new();
+    GROUP.get_or_init(|| {
+        let p = BigUint::parse_bytes(MODP_PRIME.as_bytes(), 16).unwrap();
+        let q = (&p - 1u32) >> 1;
+        // Hashed into the group, so nobody knows their logarithms
+        let m = hash_to_group(&p, b"M");
+        let n = hash_to_group(&p, b"N");
+        Group {
+            p,
+            q,
+            g: BigUint::from(2u32),
+            m,
+            n,
+        }
+    })
+}
+
+// Squaring lands in the subgroup of order `q`.
+fn hash_to_group(p: &BigUint, label: &[u8]) -> BigUint {
+    let mut bytes = Vec::new();
+    for counter in 0u32..9 {
+        let block = Sha256::new()
+            .chain_update(SPAKE2_CONTEXT)
+            .chain_update(label)
+            .chain_update(counter.to_be_bytes())
+            .finalize();
+        bytes.extend_from_slice(&block);
+    }
+    BigUint::from_bytes_be(&bytes).modpow(&BigUint::from(2u32), p)
+}
+
+fn element_bytes(value: &BigUint) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut out = vec![0; SHARE_LEN.saturating_sub(bytes.len())];
+    out.extend_from_slice(&bytes);
+    out
+}
+
+fn invalid_exchange() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid key exchange")
+}
+
+pub struct Spake2 {
+    role: Role,
+    // The code as an exponent
+    w: BigUint,
+    secret: BigUint,
+    share: Vec<u8>,
+}
+
+impl Spake2 {
+    // Starts an exchange. The returned share goes to the other side.
+    pub fn start(role: Role, code: &str) -> (Spake2, Vec<u8>) {
+        let group = group();
+        let code_hash = Sha256::new()
+            .chain_update(SPAKE2_CONTEXT)
+            .chain_update(code.as_bytes())
+            .finalize();
+        let w = BigUint::from_bytes_be(&code_hash) % &group.q;
+        let secret = BigUint::from_bytes_be(&random_bytes::<32>()) % &group.q + 1u32;
+        let blind = match role {
+            Role::Client => &group.m,
+            Role::Server => &group.n,
+        };
+        let share = group.g.modpow(&secret, &group.p) * blind.modpow(&w, &group.p) % &group.p;
+        let share = element_bytes(&share);
+        let exchange = Spake2 {
+            role,
+            w,
+            secret,
+            share: share.clone(),
+        };
+        (exchange, share)
+    }
+
+    // The channel keyed from both shares. A wrong code on either side isn't
+    // noticed here; the first frame sealed by the other side fails to open.
+    pub fn finish(self, their_share: &[u8]) -> io::Result<SealedChannel> {
+        let group = group();
+        let one = BigUint::from(1u32);
+        let theirs = BigUint::from_bytes_be(their_share);
+        if their_share.len() != SHARE_LEN
+            || theirs <= one
+            || theirs >= &group.p - 1u32
+            || theirs.modpow(&group.q, &group.p) != one
+        {
+            return Err(invalid_exchange());
+        }
+        let their_blind = match self.role {
+            Role::Client => &group.n,
+            Role::Server => &group.m,
+        };
+        // Their blinding to the power of -w, since its order is `q`
+        let unblind = their_blind.modpow(&(&group.q - &self.w), &group.p);
+        let shared = (theirs * unblind % &group.p).modpow(&self.secret, &group.p);
+
+        let (client_share, server_share) = match self.role {
+            Role::Client => (&self.share[..], their_share),
+            Role::Server => (their_share, &self.share[..]),
+        };
+        let mut transcript = Sha256::new();
+        for part in [
+            SPAKE2_CONTEXT,
+            client_share,
+            server_share,
+            &element_bytes(&shared),
+            &element_bytes(&self.w),
+        ] {
+            transcript.update((part.len() as u64).to_le_bytes());
+            transcript.update(part);
+        }
+        let transcript = transcript.finalize();
+        let key = |direction: &[u8]| -> [u8; 32] {
+            Sha256::new()
+                .chain_update(transcript)
+                .chain_update(direction)
+                .finalize()
+                .into()
+        };
+        let (client_key, server_key) = (key(b"client"), key(b"server"));
+        Ok(match self.role {
+            Role::Client => SealedChannel::new(&client_key, &server_key),
+            Role::Server => SealedChannel::new(&server_key, &client_key),
+        })
+    }
+}
+
+// Frames sealed with one key per direction. Nonces count the frames so far,
+// so a frame that is replayed, reordered or dropped makes the next one fail.
+pub struct SealedChannel {
+    send: Aes256Gcm,
+    receive: Aes256Gcm,
+    sent: u64,
+    received: u64,
+}
+
+fn frame_nonce(counter: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[NONCE_LEN - 8..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+impl SealedChannel {
+    fn new(send_key: &[u8; 32], receive_key: &[u8; 32]) -> Self {
+        Self {
+            send: Aes256Gcm::new(send_key.into()),
+            receive: Aes256Gcm::new(receive_key.into()),
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let frame = self
+            .send
+            .encrypt(Nonce::from_slice(&frame_nonce(self.sent)), plaintext)
+            .map_err(|_| io::Error::other("Encryption failed"))?;
+        self.sent += 1;
+        Ok(frame)
+    }
+
+    pub fn open(&mut self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        let plaintext = self
+            .receive
+            .decrypt(Nonce::from_slice(&frame_nonce(self.received)), frame)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Frame failed to open"))?;
+        self.received += 1;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt_all(plaintext: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        encrypt_stream("secret", &mut &plaintext[..], &mut sealed).unwrap();
+        sealed
+    }
+
+    fn decrypt_all(passphrase: &str, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        decrypt_stream(passphrase, &mut &sealed[..], &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn streams_round_trip_in_chunks() {
+        for len in [0, 1, CHUNK_LEN - 1, CHUNK_LEN, 2 * CHUNK_LEN + 5] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let sealed = encrypt_all(&plaintext);
+            assert!(is_encrypted_stream(&sealed) && !is_encrypted(&sealed));
+            assert_eq!(decrypt_all("secret", &sealed).unwrap(), plaintext, "{}", len);
+        }
+        assert!(decrypt_all("wrong", &encrypt_all(b"data")).is_err());
+        assert!(decrypt_all("secret", b"plain data").is_err());
+    }
+
+    #[test]
+    fn streams_cut_short_or_changed_fail() {
+        let plaintext = vec![7u8; 2 * CHUNK_LEN + 5];
+        let sealed = encrypt_all(&plaintext);
+        let header = STREAM_MAGIC.len() + SALT_LEN + NONCE_PREFIX_LEN;
+        // Whole chunks dropped from the end
+        let first_chunk = header + CHUNK_LEN + TAG_LEN;
+        assert!(decrypt_all("secret", &sealed[..first_chunk]).is_err());
+        assert!(decrypt_all("secret", &sealed[..sealed.len() - 1]).is_err());
+
+        let mut changed = sealed.clone();
+        changed[header + 10] ^= 1;
+        assert!(decrypt_all("secret", &changed).is_err());
+    }
+
+    fn exchange(client_code: &str, server_code: &str) -> (SealedChannel, SealedChannel) {
+        let (client, client_share) = Spake2::start(Role::Client, client_code);
+        let (server, server_share) = Spake2::start(Role::Server, server_code);
+        (client.finish(&server_share).unwrap(), server.finish(&client_share).unwrap())
+    }
+
+    #[test]
+    fn same_codes_agree_on_keys() {
+        let (mut client, mut server) = exchange("ABCD2345", "ABCD2345");
+        for text in ["hello", "again"] {
+            let frame = client.seal(text.as_bytes()).unwrap();
+            assert_eq!(server.open(&frame).unwrap(), text.as_bytes());
+        }
+        let frame = server.seal(b"welcome").unwrap();
+        assert_eq!(client.open(&frame).unwrap(), b"welcome");
+    }
+
+    #[test]
+    fn different_codes_fail_to_open() {
+        let (mut client, mut server) = exchange("ABCD2345", "ABCD2346");
+        let frame = client.seal(b"hello").unwrap();
+        assert!(server.open(&frame).is_err());
+    }
+
+    #[test]
+    fn replayed_or_changed_frames_fail_to_open() {
+        let (mut client, mut server) = exchange("ABCD2345", "ABCD2345");
+        let first = client.seal(b"first").unwrap();
+        server.open(&first).unwrap();
+        assert!(server.open(&first).is_err());
+
+        let mut second = client.seal(b"second").unwrap();
+        second[0] ^= 1;
+        assert!(server.open(&second).is_err());
+    }
+
+    #[test]
+    fn refuses_shares_outside_the_group() {
+        let p = &group().p;
+        for share in [BigUint::from(0u32), BigUint::from(1u32), p - 1u32, p.clone()] {
+            let (server, _) = Spake2::start(Role::Server, "ABCD2345");
+            assert!(server.finish(&element_bytes(&share)).is_err());
+        }
+        let (server, _) = Spake2::start(Role::Server, "ABCD2345");
+        assert!(server.finish(&[1; 16]).is_err());
+    }
+}
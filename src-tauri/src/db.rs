@@ -1,16 +1,65 @@
-use crate::ollama::get_ollama_embedding;
+use crate::blocks::{self, Block, BlockReference};
+use crate::events::{self, ChangeKind, EntityKind};
+use crate::ollama::get_embedding;
+use crate::reminders::Reminder;
+use crate::sanitize;
+use crate::stats::{self, LeafStats, WritingDay};
+use crate::tasks::{self, Task, TaskFilter, TaskStatus};
 use chrono::Utc;
+use libsqlite3_sys::{
+    sqlite3, sqlite3_backup_finish, sqlite3_backup_init, sqlite3_backup_step, sqlite3_close,
+    sqlite3_errmsg, sqlite3_errstr, sqlite3_open_v2, SQLITE_BUSY, SQLITE_DONE, SQLITE_LOCKED,
+    SQLITE_OK, SQLITE_OPEN_CREATE, SQLITE_OPEN_READWRITE,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sqlite_vec::sqlite3_vec_init;
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqlitePool},
-    Error as SqlxError, Row,
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool},
+    Connection, Error as SqlxError, Row,
 };
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{c_int, CStr, CString};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
 use uuid::Uuid;
 
 pub struct SqlDatabase {
-    pool: SqlitePool,
+    // Replaced when the database file is swapped, e.g. on enabling encryption
+    // or opening another workspace
+    path: RwLock<PathBuf>,
+    pool: RwLock<SqlitePool>,
+    key: RwLock<Option<String>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseStatus {
+    pub encrypted: bool,
+    pub unlocked: bool,
+}
+
+// A leaf in the trash, e.g. one whose mirrored file was deleted.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedLeaf {
+    pub leaf_id: String,
+    pub name: String,
+    pub reason: String,
+    pub deleted_at: String,
+}
+
+// A reminder kept with its leaf in the trash.
+#[derive(Serialize, Deserialize)]
+struct TrashedReminder {
+    id: String,
+    block_id: Option<String>,
+    note: String,
+    due_at: String,
+    fired_at: Option<String>,
+    created_at: String,
 }
 
 fn generate_uuid() -> String {
@@ -21,14 +70,18 @@ fn generate_uuid() -> String {
 pub trait Entity: Serialize + DeserializeOwned {
     const TABLE_NAME: &'static str;
     const CREATE_TABLE: &'static str;
+    // Columns matched by `SqlDatabase::search`
+    const SEARCH_COLUMNS: &'static [&'static str] = &[];
     fn get_id(&self) -> &str;
     fn from_row(row: sqlx::sqlite::SqliteRow) -> Result<Self, SqlxError>;
     fn to_params(&self) -> Vec<(String, String)>;
     fn get_embedding_text(&self) -> String;
     fn get_object_type() -> &'static str;
+    // Cleans fields holding HTML before they are stored
+    fn sanitize(&mut self) {}
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Leaf {
     #[serde(default = "generate_uuid")]
@@ -43,6 +96,34 @@ pub struct Leaf {
     modified_at: String,
 }
 
+impl Leaf {
+    pub fn new(id: String, name: String, content: String) -> Self {
+        Self {
+            id,
+            name,
+            content,
+            created_at: String::new(),
+            modified_at: String::new(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn modified_at(&self) -> &str {
+        &self.modified_at
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Sage {
@@ -58,11 +139,66 @@ pub struct Sage {
     modified_at: String,
 }
 
+impl Sage {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+// Starting content for new leaves, with `{{...}}` variables filled in when a
+// leaf is created from it.
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct Config {
-    openai_api_key: String,
-    theme: String,
+pub struct Template {
+    #[serde(default = "generate_uuid")]
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    created_at: String,
+    #[serde(default)]
+    modified_at: String,
+}
+
+impl Template {
+    pub fn new(id: String, name: String, description: String, content: String) -> Self {
+        Self {
+            id,
+            name,
+            description,
+            content,
+            created_at: String::new(),
+            modified_at: String::new(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -74,13 +210,192 @@ pub struct Embedding {
     embedding: Vec<f32>,
 }
 
+const CREATE_LEAF_TAGS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS leaf_tags (
+        leaf_id TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (leaf_id, tag)
+    )";
+
+// Page hierarchy: each leaf has at most one parent, ordered among siblings.
+const CREATE_LEAF_PARENTS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS leaf_parents (
+        leaf_id TEXT PRIMARY KEY,
+        parent_id TEXT NOT NULL,
+        position INTEGER NOT NULL
+    )";
+
+// CRDT state of leaves edited collaboratively. The leaf's `content` is kept
+// as the HTML rendering of this document.
+const CREATE_LEAF_DOCUMENTS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS leaf_documents (
+        leaf_id TEXT PRIMARY KEY,
+        state BLOB NOT NULL,
+        modified_at TEXT NOT NULL
+    )";
+
+// Leaves offered to peers by the LAN collaboration server.
+const CREATE_COLLAB_SHARES_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS collab_shares (
+        leaf_id TEXT PRIMARY KEY,
+        shared_at TEXT NOT NULL
+    )";
+
+// Daily notes: the leaf written for each date (`YYYY-MM-DD`).
+const CREATE_JOURNAL_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS journal (
+        date TEXT PRIMARY KEY,
+        leaf_id TEXT NOT NULL UNIQUE
+    )";
+
+// Checklist items found in leaf content, rebuilt whenever a leaf is written.
+const CREATE_TASKS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS tasks (
+        leaf_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        block_id TEXT,
+        text TEXT NOT NULL,
+        checked INTEGER NOT NULL,
+        due_date TEXT,
+        PRIMARY KEY (leaf_id, position)
+    )";
+
+// Top-level blocks of each leaf's content. `id` is the block's BlockID
+// attribute, which only paragraphs and headings have.
+const CREATE_BLOCKS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS blocks (
+        leaf_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        id TEXT,
+        type TEXT NOT NULL,
+        text TEXT NOT NULL,
+        html TEXT NOT NULL,
+        PRIMARY KEY (leaf_id, position)
+    )";
+
+// Blocks each leaf embeds by reference.
+const CREATE_TRANSCLUSIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS transclusions (
+        leaf_id TEXT NOT NULL,
+        source_leaf_id TEXT NOT NULL,
+        source_block_id TEXT NOT NULL,
+        PRIMARY KEY (leaf_id, source_leaf_id, source_block_id)
+    )";
+
+// Metrics of each leaf's current content. `headings` is a JSON outline.
+const CREATE_LEAF_STATS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS leaf_stats (
+        leaf_id TEXT PRIMARY KEY,
+        word_count INTEGER NOT NULL,
+        character_count INTEGER NOT NULL,
+        reading_minutes INTEGER NOT NULL,
+        headings TEXT NOT NULL,
+        readability REAL,
+        modified_at TEXT NOT NULL
+    )";
+
+// Words added and removed across all leaves per local date.
+const CREATE_WRITING_ACTIVITY_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS writing_activity (
+        date TEXT PRIMARY KEY,
+        words_added INTEGER NOT NULL,
+        words_removed INTEGER NOT NULL
+    )";
+
+// Reminders about a leaf, or a block in it. Times are RFC 3339 in UTC.
+const CREATE_REMINDERS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS reminders (
+        id TEXT PRIMARY KEY,
+        leaf_id TEXT NOT NULL,
+        block_id TEXT,
+        note TEXT NOT NULL,
+        due_at TEXT NOT NULL,
+        fired_at TEXT,
+        created_at TEXT NOT NULL
+    )";
+
+// Leaves deleted by a background process rather than the user, with what
+// `delete` drops so they can be restored under their id. `tags`, `children`
+// and `reminders` are JSON arrays; `journal_date` and `shared_at` are set
+// for daily notes and shared leaves.
+const CREATE_TRASH_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS trash (
+        leaf_id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        content TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        modified_at TEXT NOT NULL,
+        tags TEXT NOT NULL,
+        parent_id TEXT,
+        position INTEGER,
+        children TEXT NOT NULL,
+        journal_date TEXT,
+        shared_at TEXT,
+        reminders TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        deleted_at TEXT NOT NULL
+    )";
+
+// Entities stored without an embedding, to be embedded by
+// `refresh_embeddings`.
+const CREATE_STALE_EMBEDDINGS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS stale_embeddings (
+        object_id TEXT NOT NULL,
+        object_type TEXT NOT NULL,
+        PRIMARY KEY (object_id, object_type)
+    )";
+
+// Tables whose row counts are recorded in backups and checked on restore.
+pub const COUNTED_TABLES: &[&str] = &[
+    "leaves",
+    "sages",
+    "embedding_metadata",
+    "leaf_tags",
+    "leaf_parents",
+    "leaf_documents",
+    "collab_shares",
+    "journal",
+    "templates",
+    "tasks",
+    "reminders",
+    "leaf_stats",
+    "writing_activity",
+    "blocks",
+    "transclusions",
+    "trash",
+    "stale_embeddings",
+];
+
 pub trait TimeStamped {
+    fn created_at(&self) -> &str;
+    fn modified_at(&self) -> &str;
     fn set_created_at(&mut self, timestamp: String);
     fn set_modified_at(&mut self, timestamp: String);
 }
 
 // Implement TimeStamped for Leaf
 impl TimeStamped for Leaf {
+    fn created_at(&self) -> &str {
+        &self.created_at
+    }
+    fn modified_at(&self) -> &str {
+        &self.modified_at
+    }
+    fn set_created_at(&mut self, timestamp: String) {
+        self.created_at = timestamp;
+    }
+    fn set_modified_at(&mut self, timestamp: String) {
+        self.modified_at = timestamp;
+    }
+}
+
+impl TimeStamped for Template {
+    fn created_at(&self) -> &str {
+        &self.created_at
+    }
+    fn modified_at(&self) -> &str {
+        &self.modified_at
+    }
     fn set_created_at(&mut self, timestamp: String) {
         self.created_at = timestamp;
     }
@@ -91,6 +406,12 @@ impl TimeStamped for Leaf {
 
 // Implement TimeStamped for Sage
 impl TimeStamped for Sage {
+    fn created_at(&self) -> &str {
+        &self.created_at
+    }
+    fn modified_at(&self) -> &str {
+        &self.modified_at
+    }
     fn set_created_at(&mut self, timestamp: String) {
         self.created_at = timestamp;
     }
@@ -109,6 +430,7 @@ impl Entity for Leaf {
             created_at TEXT NOT NULL,
             modified_at TEXT NOT NULL
         )";
+    const SEARCH_COLUMNS: &'static [&'static str] = &["name", "content"];
 
     fn get_id(&self) -> &str {
         &self.id
@@ -140,6 +462,12 @@ impl Entity for Leaf {
     fn get_object_type() -> &'static str {
         "leaf"
     }
+
+    fn sanitize(&mut self) {
+        if !self.content.is_empty() {
+            self.content = sanitize::clean(&self.content);
+        }
+    }
 }
 
 impl Entity for Sage {
@@ -152,6 +480,7 @@ impl Entity for Sage {
             created_at TEXT NOT NULL,
             modified_at TEXT NOT NULL
         )";
+    const SEARCH_COLUMNS: &'static [&'static str] = &["name", "description"];
 
     fn get_id(&self) -> &str {
         &self.id
@@ -185,6 +514,59 @@ impl Entity for Sage {
     }
 }
 
+impl Entity for Template {
+    const TABLE_NAME: &'static str = "templates";
+    const CREATE_TABLE: &'static str = "
+        CREATE TABLE IF NOT EXISTS templates (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            modified_at TEXT NOT NULL
+        )";
+    const SEARCH_COLUMNS: &'static [&'static str] = &["name", "description"];
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn from_row(row: sqlx::sqlite::SqliteRow) -> Result<Self, SqlxError> {
+        Ok(Self {
+            id: row.get("id"),
+            name: row.get("name"),
+            description: row.get("description"),
+            content: row.get("content"),
+            created_at: row.get("created_at"),
+            modified_at: row.get("modified_at"),
+        })
+    }
+
+    fn to_params(&self) -> Vec<(String, String)> {
+        vec![
+            ("name".into(), self.name.clone()),
+            ("description".into(), self.description.clone()),
+            ("content".into(), self.content.clone()),
+            ("created_at".into(), self.created_at.clone()),
+            ("modified_at".into(), self.modified_at.clone()),
+        ]
+    }
+
+    fn get_embedding_text(&self) -> String {
+        format!("{}\n{}", self.name, self.description)
+    }
+
+    fn get_object_type() -> &'static str {
+        "template"
+    }
+
+    fn sanitize(&mut self) {
+        if !self.content.is_empty() {
+            self.content = sanitize::clean(&self.content);
+        }
+    }
+}
+
 impl Entity for Embedding {
     const TABLE_NAME: &'static str = "embeddings";
     const CREATE_TABLE: &'static str = "
@@ -237,8 +619,14 @@ impl Entity for Embedding {
 }
 
 impl SqlDatabase {
-    pub async fn new(dir: PathBuf) -> Result<Self, SqlxError> {
-        let db_path = dir.join("bonsai/database.db");
+    pub async fn new(root_dir: PathBuf) -> Result<Self, SqlxError> {
+        Self::open(root_dir, None).await
+    }
+
+    // Opens the database of the workspace in `root_dir`, unlocking it with
+    // `key` when it is encrypted.
+    pub async fn open(root_dir: PathBuf, key: Option<String>) -> Result<Self, SqlxError> {
+        let db_path = Self::database_path(&root_dir);
         std::fs::create_dir_all(db_path.parent().unwrap())?;
 
         // Initialize the sqlite3_vec extension
@@ -248,76 +636,149 @@ impl SqlDatabase {
             )));
         }
 
-        let options = SqliteConnectOptions::new()
-            .filename(&db_path)
-            .create_if_missing(true)
-            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
-
-        let pool = SqlitePool::connect_with(options).await?;
+        let pool = SqlitePool::connect_with(connect_options(&db_path, key.as_deref()))
+            .await
+            .map_err(|e| match e {
+                // SQLCipher reports a wrong key as a file that is not a database
+                SqlxError::Database(ref db_error)
+                    if key.is_some() && db_error.code().as_deref() == Some("26") =>
+                {
+                    SqlxError::Protocol("Wrong passphrase".to_string())
+                }
+                e => e,
+            })?;
 
         // Initialize tables
         sqlx::query(Leaf::CREATE_TABLE).execute(&pool).await?;
         sqlx::query(Sage::CREATE_TABLE).execute(&pool).await?;
+        sqlx::query(Template::CREATE_TABLE).execute(&pool).await?;
         sqlx::query(Embedding::CREATE_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_LEAF_TAGS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_LEAF_PARENTS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_LEAF_DOCUMENTS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_COLLAB_SHARES_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_JOURNAL_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_REMINDERS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_TRASH_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_STALE_EMBEDDINGS_TABLE).execute(&pool).await?;
+
+        // Leaves written before their content was indexed are indexed once
+        let mut indexed = true;
+        for table in ["tasks", "leaf_stats", "blocks", "transclusions"] {
+            indexed &= table_exists(&pool, table).await?;
+        }
+        sqlx::query(CREATE_TASKS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_BLOCKS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_TRANSCLUSIONS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_LEAF_STATS_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_WRITING_ACTIVITY_TABLE).execute(&pool).await?;
+        if !indexed {
+            let mut tx = pool.begin().await?;
+            let rows = sqlx::query("SELECT id, content, modified_at FROM leaves")
+                .fetch_all(&mut *tx)
+                .await?;
+            for row in rows {
+                let id: String = row.get("id");
+                let content: String = row.get("content");
+                let modified_at: String = row.get("modified_at");
+                index_content(&mut tx, &id, &content, &modified_at, false).await?;
+            }
+            tx.commit().await?;
+        }
+
+        Ok(Self {
+            path: RwLock::new(db_path),
+            pool: RwLock::new(pool),
+            key: RwLock::new(key),
+        })
+    }
+
+    pub fn database_path(root_dir: &Path) -> PathBuf {
+        root_dir.join("database.db")
+    }
+
+    // Plain SQLite files start with a fixed header while SQLCipher files
+    // start with their salt, so any other header means the file is encrypted.
+    pub fn is_encrypted(root_dir: &Path) -> bool {
+        let mut header = [0u8; 16];
+        match File::open(Self::database_path(root_dir)).and_then(|mut f| f.read_exact(&mut header)) {
+            Ok(()) => &header != b"SQLite format 3\0",
+            Err(_) => false,
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.read().unwrap().clone()
+    }
+
+    fn pool(&self) -> SqlitePool {
+        self.pool.read().unwrap().clone()
+    }
+
+    fn key(&self) -> Option<String> {
+        self.key.read().unwrap().clone()
+    }
+
+    // What an encrypted database was unlocked with.
+    pub fn passphrase(&self) -> Option<String> {
+        self.key()
+    }
 
-        Ok(Self { pool })
+    pub fn uses_encryption(&self) -> bool {
+        self.key.read().unwrap().is_some()
     }
 
     pub async fn create<T: Entity + TimeStamped>(
         &self,
-        mut entity: T,
+        entity: T,
     ) -> Result<String, SqlxError> {
-        let now = Utc::now().to_rfc3339();
-        entity.set_created_at(now.clone());
-        entity.set_modified_at(now);
-
-        let params = entity.to_params();
-        let columns = format!(
-            "id, {}",
-            params
-                .iter()
-                .map(|(name, _)| name.as_str())
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
-        let placeholders = format!(
-            "?, {}",
-            std::iter::repeat("?")
-                .take(params.len())
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
-
-        let sql = format!(
-            "INSERT INTO {} ({}) VALUES ({})",
-            T::TABLE_NAME,
-            columns,
-            placeholders
-        );
+        let mut ids = self.create_batch(vec![entity]).await?;
+        Ok(ids.remove(0))
+    }
 
-        let mut query = sqlx::query(&sql);
-        query = query.bind(entity.get_id());
-        for (_, value) in params {
-            query = query.bind(value);
+    // Creates all entities in a single transaction. Embeddings are computed
+    // up front so a failing embedding model leaves nothing half-written.
+    pub async fn create_batch<T: Entity + TimeStamped>(
+        &self,
+        mut entities: Vec<T>,
+    ) -> Result<Vec<String>, SqlxError> {
+        let now = Utc::now().to_rfc3339();
+        let mut embeddings = Vec::with_capacity(entities.len());
+        for entity in entities.iter_mut() {
+            entity.sanitize();
+            // Keep timestamps supplied by importers, stamp everything else
+            if entity.created_at().is_empty() {
+                entity.set_created_at(now.clone());
+                entity.set_modified_at(now.clone());
+            }
+            embeddings.push(compute_embedding(&entity.get_embedding_text()).await?);
         }
 
-        query.execute(&self.pool).await?;
-
-        self.store_embedding(
-            entity.get_id().to_string(),
-            T::get_object_type(),
-            &entity.get_embedding_text(),
-        )
-        .await?;
+        let mut tx = self.pool().begin().await?;
+        for (entity, embedding) in entities.iter().zip(embeddings) {
+            insert_entity(&mut tx, entity).await?;
+            write_embedding(&mut tx, entity.get_id(), T::get_object_type(), &embedding).await?;
+            if let Some(content) = leaf_content(entity) {
+                index_content(&mut tx, entity.get_id(), &content, entity.modified_at(), false)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
 
-        Ok(entity.get_id().to_string())
+        for entity in &entities {
+            publish_change(entity, ChangeKind::Created, None);
+        }
+        Ok(entities
+            .iter()
+            .map(|entity| entity.get_id().to_string())
+            .collect())
     }
 
     pub async fn read<T: Entity>(&self, id: &str) -> Result<Option<T>, SqlxError> {
         let sql = format!("SELECT * FROM {} WHERE id = ?", T::TABLE_NAME);
         let row = sqlx::query(&sql)
             .bind(id)
-            .fetch_optional(&self.pool)
+            .fetch_optional(&self.pool())
             .await?;
 
         match row {
@@ -328,7 +789,7 @@ impl SqlDatabase {
 
     pub async fn list<T: Entity>(&self) -> Result<Vec<T>, SqlxError> {
         let sql = format!("SELECT * FROM {}", T::TABLE_NAME);
-        let rows = sqlx::query(&sql).fetch_all(&self.pool).await?;
+        let rows = sqlx::query(&sql).fetch_all(&self.pool()).await?;
 
         let mut entities = Vec::with_capacity(rows.len());
         for row in rows {
@@ -338,18 +799,58 @@ impl SqlDatabase {
         Ok(entities)
     }
 
+    // Entities with `query` in one of their search columns, most recently
+    // modified first.
+    pub async fn search<T: Entity>(&self, query: &str, limit: i32) -> Result<Vec<T>, SqlxError> {
+        if T::SEARCH_COLUMNS.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pattern = format!(
+            "%{}%",
+            query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let conditions = T::SEARCH_COLUMNS
+            .iter()
+            .map(|column| format!("{} LIKE ? ESCAPE '\\'", column))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        let sql = format!(
+            "SELECT * FROM {} WHERE {} ORDER BY modified_at DESC LIMIT ?",
+            T::TABLE_NAME,
+            conditions
+        );
+        let mut q = sqlx::query(&sql);
+        for _ in T::SEARCH_COLUMNS {
+            q = q.bind(&pattern);
+        }
+        let rows = q.bind(limit).fetch_all(&self.pool()).await?;
+        rows.into_iter().map(T::from_row).collect()
+    }
+
     pub async fn update<T: Entity + TimeStamped>(&self, mut entity: T) -> Result<(), SqlxError> {
+        // Empty fields are left alone, but a field sanitizing emptied was
+        // given and is written empty
+        let given: HashSet<String> = entity
+            .to_params()
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, _)| name)
+            .collect();
+        entity.sanitize();
         let now = Utc::now().to_rfc3339();
         entity.set_modified_at(now);
 
         let params = entity.to_params();
-        let non_empty_params: Vec<_> = params
+        let written_params: Vec<_> = params
             .into_iter()
-            .filter(|(_, value)| !value.is_empty())
+            .filter(|(name, value)| !value.is_empty() || given.contains(name))
             .collect();
 
-        if !non_empty_params.is_empty() {
-            let set_clause = non_empty_params
+        if !written_params.is_empty() {
+            let set_clause = written_params
                 .iter()
                 .map(|(name, _)| format!("{} = ?", name))
                 .collect::<Vec<_>>()
@@ -358,14 +859,21 @@ impl SqlDatabase {
             let sql = format!("UPDATE {} SET {} WHERE id = ?", T::TABLE_NAME, set_clause);
 
             let mut query = sqlx::query(&sql);
-            // Bind all non-empty values
-            for (_, value) in &non_empty_params {
+            // Bind the values being written
+            for (_, value) in &written_params {
                 query = query.bind(value);
             }
             // Bind the ID last for the WHERE clause
             query = query.bind(entity.get_id());
 
-            query.execute(&self.pool).await?;
+            // The row and what is derived from its content change together
+            let mut tx = self.pool().begin().await?;
+            query.execute(&mut *tx).await?;
+            if let Some(content) = leaf_content(&entity).filter(|_| given.contains("content")) {
+                index_content(&mut tx, entity.get_id(), &content, entity.modified_at(), true)
+                    .await?;
+            }
+            tx.commit().await?;
 
             self.store_embedding(
                 entity.get_id().to_string(),
@@ -373,132 +881,750 @@ impl SqlDatabase {
                 &entity.get_embedding_text(),
             )
             .await?;
+
+            let fields: Vec<&str> = written_params.iter().map(|(name, _)| name.as_str()).collect();
+            publish_change(&entity, ChangeKind::Updated, Some(&fields));
         }
 
         Ok(())
     }
 
-    pub async fn delete<T: Entity>(&self, id: &str) -> Result<(), SqlxError> {
-        // First delete from embedding_metadata to remove the reference
-        let sql = "DELETE FROM embedding_metadata WHERE object_id = ? AND object_type = ?";
-        sqlx::query(sql)
-            .bind(id)
-            .bind(T::get_object_type())
-            .execute(&self.pool)
-            .await?;
-
-        // Then delete from the embeddings virtual table using the rowid
-        let sql = "DELETE FROM embeddings WHERE rowid IN (SELECT rowid FROM embedding_metadata WHERE object_id = ? AND object_type = ?)";
-        sqlx::query(sql)
-            .bind(id)
-            .bind(T::get_object_type())
-            .execute(&self.pool)
-            .await?;
-
-        // Finally delete from the main entity table
-        let sql = format!("DELETE FROM {} WHERE id = ?", T::TABLE_NAME);
-        sqlx::query(&sql).bind(id).execute(&self.pool).await?;
+    // Stores `entity` exactly as given, replacing any row with the same id.
+    // Unlike `update`, empty fields and timestamps are written as they are,
+    // which is what merging changes from another device needs.
+    pub async fn put<T: Entity + TimeStamped>(&self, mut entity: T) -> Result<(), SqlxError> {
+        entity.sanitize();
+        let embedding = compute_embedding(&entity.get_embedding_text()).await?;
+        self.write_put(entity, Some(embedding)).await
+    }
 
-        Ok(())
+    // Like `put`, but leaves the embedding to `refresh_embeddings`, so
+    // storing does not depend on the embedding model being reachable.
+    pub async fn put_deferred<T: Entity + TimeStamped>(&self, mut entity: T) -> Result<(), SqlxError> {
+        entity.sanitize();
+        self.write_put(entity, None).await
     }
 
-    pub async fn store_embedding(
+    async fn write_put<T: Entity + TimeStamped>(
         &self,
-        object_id: String,
-        object_type: &str,
-        text: &str,
+        entity: T,
+        embedding: Option<Vec<u8>>,
     ) -> Result<(), SqlxError> {
-        let embedding = get_ollama_embedding(text)
-            .await
-            .map_err(|e| SqlxError::Protocol(e.to_string()))?;
-
-        let embedding_bytes: Vec<u8> = unsafe {
-            std::slice::from_raw_parts(
-                embedding.as_ptr() as *const u8,
-                embedding.len() * std::mem::size_of::<f32>(),
-            )
-            .to_vec()
-        };
-
-        // First, delete any existing embedding for this object
-        let delete_embeddings = "DELETE FROM embeddings WHERE rowid IN (SELECT rowid FROM embedding_metadata WHERE object_id = ? AND object_type = ?)";
-        sqlx::query(delete_embeddings)
-            .bind(object_id.clone())
-            .bind(object_type)
-            .execute(&self.pool)
+        let mut tx = self.pool().begin().await?;
+        let sql = format!("DELETE FROM {} WHERE id = ?", T::TABLE_NAME);
+        sqlx::query(&sql)
+            .bind(entity.get_id())
+            .execute(&mut *tx)
             .await?;
+        insert_entity(&mut tx, &entity).await?;
+        match embedding {
+            Some(embedding) => {
+                write_embedding(&mut tx, entity.get_id(), T::get_object_type(), &embedding).await?
+            }
+            None => mark_embedding_stale(&mut tx, entity.get_id(), T::get_object_type()).await?,
+        }
+        if let Some(content) = leaf_content(&entity) {
+            index_content(&mut tx, entity.get_id(), &content, entity.modified_at(), false).await?;
+        }
+        tx.commit().await?;
 
-        let delete_embedding_metadata =
-            "DELETE FROM embedding_metadata WHERE object_id = ? AND object_type = ?";
-        sqlx::query(delete_embedding_metadata)
-            .bind(object_id.clone())
-            .bind(object_type)
-            .execute(&self.pool)
-            .await?;
+        publish_change(&entity, ChangeKind::Updated, None);
+        Ok(())
+    }
 
-        // Then insert the new embedding
-        let insert_sql = "INSERT INTO embeddings(embedding) VALUES (?)";
-        let result = sqlx::query(insert_sql)
-            .bind(&embedding_bytes[..]) // Bind the slice directly
-            .execute(&self.pool)
-            .await;
-
-        let row = match result {
-            Ok(r) => r,
-            Err(e) => {
-                println!("Error inserting embedding: {:?}", e);
-                return Ok(()); // Ignore the error and continue
-            }
-        };
+    pub async fn delete<T: Entity>(&self, id: &str) -> Result<(), SqlxError> {
+        let mut tx = self.pool().begin().await?;
+        let deleted = delete_entity::<T>(&mut tx, id).await?;
+        tx.commit().await?;
 
-        let last_id = row.last_insert_rowid();
+        if deleted {
+            if let Some(kind) = EntityKind::from_object_type(T::get_object_type()) {
+                events::publish(kind, id, ChangeKind::Deleted, None, &[]);
+            }
+        }
+        Ok(())
+    }
 
-        let metadata_sql =
-            "INSERT INTO embedding_metadata (rowid, object_id, object_type) VALUES (?, ?, ?)";
-        sqlx::query(metadata_sql)
-            .bind(last_id)
-            .bind(object_id)
-            .bind(object_type)
-            .execute(&self.pool)
+    pub async fn set_tags(&self, leaf_id: &str, tags: &[String]) -> Result<(), SqlxError> {
+        let mut tx = self.pool().begin().await?;
+        sqlx::query("DELETE FROM leaf_tags WHERE leaf_id = ?")
+            .bind(leaf_id)
+            .execute(&mut *tx)
             .await?;
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO leaf_tags (leaf_id, tag) VALUES (?, ?)")
+                .bind(leaf_id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
 
+    pub async fn set_parent(
+        &self,
+        leaf_id: &str,
+        parent_id: &str,
+        position: i64,
+    ) -> Result<(), SqlxError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO leaf_parents (leaf_id, parent_id, position) VALUES (?, ?, ?)",
+        )
+        .bind(leaf_id)
+        .bind(parent_id)
+        .bind(position)
+        .execute(&self.pool())
+        .await?;
         Ok(())
     }
 
-    pub async fn find_similar(
-        &self,
-        text: &str,
-        limit: i32,
-    ) -> Result<Vec<(String, String, f32)>, SqlxError> {
-        let query_embedding = get_ollama_embedding(text)
-            .await
-            .map_err(|e| SqlxError::Protocol(e.to_string()))?;
+    pub async fn list_children(&self, parent_id: &str) -> Result<Vec<String>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT leaf_id FROM leaf_parents WHERE parent_id = ? ORDER BY position, leaf_id",
+        )
+        .bind(parent_id)
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(rows.into_iter().map(|row| row.get("leaf_id")).collect())
+    }
 
-        let embedding_bytes: Vec<u8> = unsafe {
-            std::slice::from_raw_parts(
-                query_embedding.as_ptr() as *const u8,
-                query_embedding.len() * std::mem::size_of::<f32>(),
-            )
-            .to_vec()
-        };
+    pub async fn list_tags(&self, leaf_id: &str) -> Result<Vec<String>, SqlxError> {
+        let rows = sqlx::query("SELECT tag FROM leaf_tags WHERE leaf_id = ? ORDER BY tag")
+            .bind(leaf_id)
+            .fetch_all(&self.pool())
+            .await?;
+        Ok(rows.into_iter().map(|row| row.get("tag")).collect())
+    }
 
-        let sql = "
-            SELECT m.object_id, m.object_type, e.distance
-            FROM embeddings e
-            JOIN embedding_metadata m ON e.rowid = m.rowid
-            WHERE e.embedding MATCH ?
-            ORDER BY e.distance
-            LIMIT ?";
+    pub async fn set_shared(&self, leaf_id: &str, shared: bool) -> Result<(), SqlxError> {
+        if shared {
+            sqlx::query("INSERT OR IGNORE INTO collab_shares (leaf_id, shared_at) VALUES (?, ?)")
+                .bind(leaf_id)
+                .bind(Utc::now().to_rfc3339())
+                .execute(&self.pool())
+                .await?;
+        } else {
+            sqlx::query("DELETE FROM collab_shares WHERE leaf_id = ?")
+                .bind(leaf_id)
+                .execute(&self.pool())
+                .await?;
+        }
+        Ok(())
+    }
 
-        let rows = sqlx::query(sql)
-            .bind(embedding_bytes)
-            .bind(limit)
-            .fetch_all(&self.pool)
+    pub async fn is_shared(&self, leaf_id: &str) -> Result<bool, SqlxError> {
+        let row = sqlx::query("SELECT 1 FROM collab_shares WHERE leaf_id = ?")
+            .bind(leaf_id)
+            .fetch_optional(&self.pool())
             .await?;
+        Ok(row.is_some())
+    }
 
+    // Shared leaves as (id, name), in the order they were shared.
+    pub async fn list_shared(&self) -> Result<Vec<(String, String)>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT l.id, l.name FROM collab_shares s JOIN leaves l ON l.id = s.leaf_id ORDER BY s.shared_at",
+        )
+        .fetch_all(&self.pool())
+        .await?;
         Ok(rows
             .into_iter()
-            .map(|row| {
+            .map(|row| (row.get("id"), row.get("name")))
+            .collect())
+    }
+
+    // -------------------------------------------------------
+
+    // Deletes a leaf, keeping a copy with its tags, place in the hierarchy,
+    // journal date, share and reminders in the trash.
+    pub async fn trash_leaf(&self, id: &str, reason: &str) -> Result<(), SqlxError> {
+        let mut tx = self.pool().begin().await?;
+        let Some(row) = sqlx::query("SELECT * FROM leaves WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(());
+        };
+        let leaf = Leaf::from_row(row)?;
+        let tags: Vec<String> = sqlx::query("SELECT tag FROM leaf_tags WHERE leaf_id = ? ORDER BY tag")
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|row| row.get("tag"))
+            .collect();
+        let children: Vec<String> = sqlx::query(
+            "SELECT leaf_id FROM leaf_parents WHERE parent_id = ? ORDER BY position, leaf_id",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.get("leaf_id"))
+        .collect();
+        let parent = sqlx::query("SELECT parent_id, position FROM leaf_parents WHERE leaf_id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let journal_date: Option<String> = sqlx::query("SELECT date FROM journal WHERE leaf_id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| row.get("date"));
+        let shared_at: Option<String> = sqlx::query("SELECT shared_at FROM collab_shares WHERE leaf_id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| row.get("shared_at"));
+        let reminders: Vec<TrashedReminder> = sqlx::query(
+            "SELECT id, block_id, note, due_at, fired_at, created_at FROM reminders WHERE leaf_id = ?",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| TrashedReminder {
+            id: row.get("id"),
+            block_id: row.get("block_id"),
+            note: row.get("note"),
+            due_at: row.get("due_at"),
+            fired_at: row.get("fired_at"),
+            created_at: row.get("created_at"),
+        })
+        .collect();
+
+        sqlx::query(
+            "INSERT OR REPLACE INTO trash
+             (leaf_id, name, content, created_at, modified_at, tags, parent_id, position, children,
+              journal_date, shared_at, reminders, reason, deleted_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(leaf.name())
+        .bind(leaf.content())
+        .bind(leaf.created_at())
+        .bind(leaf.modified_at())
+        .bind(serde_json::to_string(&tags).unwrap_or_else(|_| "[]".to_string()))
+        .bind(parent.as_ref().map(|row| row.get::<String, _>("parent_id")))
+        .bind(parent.as_ref().map(|row| row.get::<i64, _>("position")))
+        .bind(serde_json::to_string(&children).unwrap_or_else(|_| "[]".to_string()))
+        .bind(journal_date)
+        .bind(shared_at)
+        .bind(serde_json::to_string(&reminders).unwrap_or_else(|_| "[]".to_string()))
+        .bind(reason)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+        delete_entity::<Leaf>(&mut tx, id).await?;
+        tx.commit().await?;
+
+        events::publish(EntityKind::Leaf, id, ChangeKind::Deleted, None, &[]);
+        Ok(())
+    }
+
+    // Most recently trashed first.
+    pub async fn list_trash(&self) -> Result<Vec<TrashedLeaf>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT leaf_id, name, reason, deleted_at FROM trash ORDER BY deleted_at DESC",
+        )
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| TrashedLeaf {
+                leaf_id: row.get("leaf_id"),
+                name: row.get("name"),
+                reason: row.get("reason"),
+                deleted_at: row.get("deleted_at"),
+            })
+            .collect())
+    }
+
+    // Brings a trashed leaf back under its old id, so links to it work
+    // again. Returns false when it is not in the trash. A journal date or
+    // reminder taken over by another leaf in the meantime stays with it.
+    pub async fn restore_leaf(&self, id: &str) -> Result<bool, SqlxError> {
+        let Some(row) = sqlx::query("SELECT * FROM trash WHERE leaf_id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool())
+            .await?
+        else {
+            return Ok(false);
+        };
+        let mut leaf = Leaf::new(id.to_string(), row.get("name"), row.get("content"));
+        leaf.sanitize();
+        leaf.set_created_at(row.get("created_at"));
+        leaf.set_modified_at(row.get("modified_at"));
+        // Computed first, the model is not asked inside the transaction. When
+        // it can't be reached the leaf is restored all the same and embedded
+        // by `refresh_embeddings` later.
+        let embedding = compute_embedding(&leaf.get_embedding_text()).await.ok();
+
+        let mut tx = self.pool().begin().await?;
+        // Restored by another call while the embedding was computed
+        let trashed = sqlx::query("SELECT 1 FROM trash WHERE leaf_id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if trashed.is_none() {
+            return Ok(false);
+        }
+        insert_entity(&mut tx, &leaf).await?;
+        match embedding {
+            Some(embedding) => write_embedding(&mut tx, id, Leaf::get_object_type(), &embedding).await?,
+            None => mark_embedding_stale(&mut tx, id, Leaf::get_object_type()).await?,
+        }
+        index_content(&mut tx, id, leaf.content(), leaf.modified_at(), false).await?;
+
+        let tags: Vec<String> = serde_json::from_str(row.get::<&str, _>("tags")).unwrap_or_default();
+        for tag in &tags {
+            sqlx::query("INSERT OR IGNORE INTO leaf_tags (leaf_id, tag) VALUES (?, ?)")
+                .bind(id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let parent_id: Option<String> = row.get("parent_id");
+        if let Some(parent_id) = parent_id {
+            sqlx::query(
+                "INSERT OR REPLACE INTO leaf_parents (leaf_id, parent_id, position)
+                 SELECT ?, id, ? FROM leaves WHERE id = ?",
+            )
+            .bind(id)
+            .bind(row.get::<Option<i64>, _>("position").unwrap_or(0))
+            .bind(&parent_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        // Children moved to the top level on delete; take back those still there
+        let children: Vec<String> = serde_json::from_str(row.get::<&str, _>("children")).unwrap_or_default();
+        for (position, child) in children.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO leaf_parents (leaf_id, parent_id, position)
+                 SELECT id, ?, ? FROM leaves
+                 WHERE id = ? AND id NOT IN (SELECT leaf_id FROM leaf_parents)",
+            )
+            .bind(id)
+            .bind(position as i64)
+            .bind(child)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let journal_date: Option<String> = row.get("journal_date");
+        if let Some(date) = journal_date {
+            sqlx::query("INSERT OR IGNORE INTO journal (date, leaf_id) VALUES (?, ?)")
+                .bind(date)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let shared_at: Option<String> = row.get("shared_at");
+        if let Some(shared_at) = shared_at {
+            sqlx::query("INSERT OR IGNORE INTO collab_shares (leaf_id, shared_at) VALUES (?, ?)")
+                .bind(id)
+                .bind(shared_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let reminders: Vec<TrashedReminder> =
+            serde_json::from_str(row.get::<&str, _>("reminders")).unwrap_or_default();
+        for reminder in reminders {
+            sqlx::query(
+                "INSERT OR IGNORE INTO reminders (id, leaf_id, block_id, note, due_at, fired_at, created_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(reminder.id)
+            .bind(id)
+            .bind(reminder.block_id)
+            .bind(reminder.note)
+            .bind(reminder.due_at)
+            .bind(reminder.fired_at)
+            .bind(reminder.created_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM trash WHERE leaf_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        publish_change(&leaf, ChangeKind::Created, None);
+        Ok(true)
+    }
+
+    // -------------------------------------------------------
+
+    pub async fn journal_entry(&self, date: &str) -> Result<Option<String>, SqlxError> {
+        let row = sqlx::query("SELECT leaf_id FROM journal WHERE date = ?")
+            .bind(date)
+            .fetch_optional(&self.pool())
+            .await?;
+        Ok(row.map(|row| row.get("leaf_id")))
+    }
+
+    // The latest entry before `date`, as its date and leaf id.
+    pub async fn previous_journal_entry(
+        &self,
+        date: &str,
+    ) -> Result<Option<(String, String)>, SqlxError> {
+        let row = sqlx::query(
+            "SELECT date, leaf_id FROM journal WHERE date < ? ORDER BY date DESC LIMIT 1",
+        )
+        .bind(date)
+        .fetch_optional(&self.pool())
+        .await?;
+        Ok(row.map(|row| (row.get("date"), row.get("leaf_id"))))
+    }
+
+    pub async fn add_journal_entry(&self, date: &str, leaf_id: &str) -> Result<(), SqlxError> {
+        sqlx::query("INSERT OR REPLACE INTO journal (date, leaf_id) VALUES (?, ?)")
+            .bind(date)
+            .bind(leaf_id)
+            .execute(&self.pool())
+            .await?;
+        Ok(())
+    }
+
+    // Entries dated from `from` to `to` inclusive, oldest first.
+    pub async fn journal_entries(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<(String, Leaf)>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT j.date, l.* FROM journal j JOIN leaves l ON l.id = j.leaf_id
+             WHERE j.date BETWEEN ? AND ? ORDER BY j.date",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool())
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                let date: String = row.get("date");
+                Leaf::from_row(row).map(|leaf| (date, leaf))
+            })
+            .collect()
+    }
+
+    // -------------------------------------------------------
+
+    // Tasks across leaves, those due soonest first and undated ones last.
+    pub async fn list_tasks(&self, filter: &TaskFilter) -> Result<Vec<Task>, SqlxError> {
+        let mut sql = String::from(
+            "SELECT t.*, l.name AS leaf_name FROM tasks t JOIN leaves l ON l.id = t.leaf_id WHERE 1 = 1",
+        );
+        let mut params: Vec<String> = Vec::new();
+        if let Some(status) = filter.status {
+            sql.push_str(match status {
+                TaskStatus::Open => " AND t.checked = 0",
+                TaskStatus::Done => " AND t.checked = 1",
+            });
+        }
+        if let Some(from) = &filter.due_from {
+            sql.push_str(" AND t.due_date >= ?");
+            params.push(from.clone());
+        }
+        if let Some(to) = &filter.due_to {
+            sql.push_str(" AND t.due_date <= ?");
+            params.push(to.clone());
+        }
+        if let Some(leaf_id) = &filter.leaf_id {
+            sql.push_str(" AND t.leaf_id = ?");
+            params.push(leaf_id.clone());
+        }
+        sql.push_str(" ORDER BY t.due_date IS NULL, t.due_date, l.modified_at DESC, t.position");
+
+        let mut query = sqlx::query(&sql);
+        for param in &params {
+            query = query.bind(param);
+        }
+        let rows = query.fetch_all(&self.pool()).await?;
+        Ok(rows.into_iter().map(task_from_row).collect())
+    }
+
+    pub async fn task(&self, leaf_id: &str, position: i64) -> Result<Option<Task>, SqlxError> {
+        let row = sqlx::query(
+            "SELECT t.*, l.name AS leaf_name FROM tasks t JOIN leaves l ON l.id = t.leaf_id
+             WHERE t.leaf_id = ? AND t.position = ?",
+        )
+        .bind(leaf_id)
+        .bind(position)
+        .fetch_optional(&self.pool())
+        .await?;
+        Ok(row.map(task_from_row))
+    }
+
+    // -------------------------------------------------------
+
+    pub async fn add_reminder(
+        &self,
+        id: &str,
+        leaf_id: &str,
+        block_id: Option<&str>,
+        note: &str,
+        due_at: &str,
+        created_at: &str,
+    ) -> Result<(), SqlxError> {
+        sqlx::query(
+            "INSERT INTO reminders (id, leaf_id, block_id, note, due_at, created_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(leaf_id)
+        .bind(block_id)
+        .bind(note)
+        .bind(due_at)
+        .bind(created_at)
+        .execute(&self.pool())
+        .await?;
+        Ok(())
+    }
+
+    pub async fn reminder(&self, id: &str) -> Result<Option<Reminder>, SqlxError> {
+        let row = sqlx::query(
+            "SELECT r.*, l.name AS leaf_name FROM reminders r JOIN leaves l ON l.id = r.leaf_id
+             WHERE r.id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool())
+        .await?;
+        Ok(row.map(reminder_from_row))
+    }
+
+    // Pending reminders, soonest first, then fired ones if asked for, most
+    // recent first.
+    pub async fn list_reminders(
+        &self,
+        leaf_id: Option<&str>,
+        include_fired: bool,
+    ) -> Result<Vec<Reminder>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT r.*, l.name AS leaf_name FROM reminders r JOIN leaves l ON l.id = r.leaf_id
+             WHERE (? IS NULL OR r.leaf_id = ?) AND (? OR r.fired_at IS NULL)
+             ORDER BY r.fired_at IS NOT NULL, r.fired_at DESC, r.due_at",
+        )
+        .bind(leaf_id)
+        .bind(leaf_id)
+        .bind(include_fired)
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(rows.into_iter().map(reminder_from_row).collect())
+    }
+
+    // Pending reminders due at or before `now`, oldest first.
+    pub async fn due_reminders(&self, now: &str) -> Result<Vec<Reminder>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT r.*, l.name AS leaf_name FROM reminders r JOIN leaves l ON l.id = r.leaf_id
+             WHERE r.fired_at IS NULL AND r.due_at <= ? ORDER BY r.due_at",
+        )
+        .bind(now)
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(rows.into_iter().map(reminder_from_row).collect())
+    }
+
+    pub async fn next_reminder_due(&self) -> Result<Option<String>, SqlxError> {
+        let row = sqlx::query("SELECT MIN(due_at) AS due_at FROM reminders WHERE fired_at IS NULL")
+            .fetch_one(&self.pool())
+            .await?;
+        Ok(row.get("due_at"))
+    }
+
+    pub async fn mark_reminder_fired(&self, id: &str, fired_at: &str) -> Result<(), SqlxError> {
+        sqlx::query("UPDATE reminders SET fired_at = ? WHERE id = ?")
+            .bind(fired_at)
+            .bind(id)
+            .execute(&self.pool())
+            .await?;
+        Ok(())
+    }
+
+    // Sets a new time and makes the reminder pending again. False when there
+    // is no such reminder.
+    pub async fn reschedule_reminder(&self, id: &str, due_at: &str) -> Result<bool, SqlxError> {
+        let result = sqlx::query("UPDATE reminders SET due_at = ?, fired_at = NULL WHERE id = ?")
+            .bind(due_at)
+            .bind(id)
+            .execute(&self.pool())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_reminder(&self, id: &str) -> Result<bool, SqlxError> {
+        let result = sqlx::query("DELETE FROM reminders WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // -------------------------------------------------------
+
+    pub async fn list_blocks(&self, leaf_id: &str) -> Result<Vec<Block>, SqlxError> {
+        let rows = sqlx::query("SELECT * FROM blocks WHERE leaf_id = ? ORDER BY position")
+            .bind(leaf_id)
+            .fetch_all(&self.pool())
+            .await?;
+        Ok(rows.into_iter().map(block_from_row).collect())
+    }
+
+    pub async fn block(&self, leaf_id: &str, block_id: &str) -> Result<Option<Block>, SqlxError> {
+        let row = sqlx::query("SELECT * FROM blocks WHERE leaf_id = ? AND id = ? ORDER BY position")
+            .bind(leaf_id)
+            .bind(block_id)
+            .fetch_optional(&self.pool())
+            .await?;
+        Ok(row.map(block_from_row))
+    }
+
+    // Leaves embedding the block, by name.
+    pub async fn block_references(
+        &self,
+        leaf_id: &str,
+        block_id: &str,
+    ) -> Result<Vec<BlockReference>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT t.leaf_id, l.name AS leaf_name FROM transclusions t
+             JOIN leaves l ON l.id = t.leaf_id
+             WHERE t.source_leaf_id = ? AND t.source_block_id = ? ORDER BY l.name",
+        )
+        .bind(leaf_id)
+        .bind(block_id)
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| BlockReference {
+                leaf_id: row.get("leaf_id"),
+                leaf_name: row.get("leaf_name"),
+            })
+            .collect())
+    }
+
+    // -------------------------------------------------------
+
+    pub async fn leaf_stats(&self, leaf_id: &str) -> Result<Option<LeafStats>, SqlxError> {
+        let row = sqlx::query("SELECT * FROM leaf_stats WHERE leaf_id = ?")
+            .bind(leaf_id)
+            .fetch_optional(&self.pool())
+            .await?;
+        Ok(row.map(|row| {
+            let headings: String = row.get("headings");
+            LeafStats {
+                leaf_id: row.get("leaf_id"),
+                word_count: row.get("word_count"),
+                character_count: row.get("character_count"),
+                reading_minutes: row.get("reading_minutes"),
+                headings: serde_json::from_str(&headings).unwrap_or_default(),
+                readability: row.get("readability"),
+                modified_at: row.get("modified_at"),
+            }
+        }))
+    }
+
+    // Days from `from` to `to` inclusive with any writing, oldest first.
+    pub async fn writing_days(&self, from: &str, to: &str) -> Result<Vec<WritingDay>, SqlxError> {
+        let rows = sqlx::query(
+            "SELECT * FROM writing_activity WHERE date BETWEEN ? AND ? ORDER BY date",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| WritingDay {
+                date: row.get("date"),
+                words_added: row.get("words_added"),
+                words_removed: row.get("words_removed"),
+            })
+            .collect())
+    }
+
+    // Every date words were added on, oldest first.
+    pub async fn writing_dates(&self) -> Result<Vec<String>, SqlxError> {
+        let rows = sqlx::query("SELECT date FROM writing_activity WHERE words_added > 0 ORDER BY date")
+            .fetch_all(&self.pool())
+            .await?;
+        Ok(rows.into_iter().map(|row| row.get("date")).collect())
+    }
+
+    // Computes the embeddings `put_deferred` left out. Stops at the first
+    // failure, e.g. while the embedding model is unreachable, leaving the
+    // rest for the next call.
+    pub async fn refresh_embeddings(&self) -> Result<usize, SqlxError> {
+        let rows = sqlx::query("SELECT object_id, object_type FROM stale_embeddings")
+            .fetch_all(&self.pool())
+            .await?;
+        let mut refreshed = 0;
+        for row in rows {
+            let id: String = row.get("object_id");
+            let object_type: String = row.get("object_type");
+            let text = match object_type.as_str() {
+                "leaf" => self.read::<Leaf>(&id).await?.map(|e| e.get_embedding_text()),
+                "sage" => self.read::<Sage>(&id).await?.map(|e| e.get_embedding_text()),
+                "template" => self.read::<Template>(&id).await?.map(|e| e.get_embedding_text()),
+                _ => None,
+            };
+            match text {
+                Some(text) => {
+                    self.store_embedding(id, &object_type, &text).await?;
+                    refreshed += 1;
+                }
+                // Deleted since
+                None => {
+                    sqlx::query("DELETE FROM stale_embeddings WHERE object_id = ? AND object_type = ?")
+                        .bind(&id)
+                        .bind(&object_type)
+                        .execute(&self.pool())
+                        .await?;
+                }
+            }
+        }
+        Ok(refreshed)
+    }
+
+    pub async fn store_embedding(
+        &self,
+        object_id: String,
+        object_type: &str,
+        text: &str,
+    ) -> Result<(), SqlxError> {
+        let embedding = compute_embedding(text).await?;
+        let mut conn = self.pool().acquire().await?;
+        write_embedding(&mut conn, &object_id, object_type, &embedding).await
+    }
+
+    pub async fn find_similar(
+        &self,
+        text: &str,
+        limit: i32,
+    ) -> Result<Vec<(String, String, f32)>, SqlxError> {
+        let embedding_bytes = compute_embedding(text).await?;
+
+        let sql = "
+            SELECT m.object_id, m.object_type, e.distance
+            FROM embeddings e
+            JOIN embedding_metadata m ON e.rowid = m.rowid
+            WHERE e.embedding MATCH ? AND k = ?
+            ORDER BY e.distance";
+
+        // sqlite-vec needs the neighbour count in the MATCH clause itself
+        // when the query has a join
+        let rows = sqlx::query(sql)
+            .bind(embedding_bytes)
+            .bind(limit)
+            .fetch_all(&self.pool())
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
                 (
                     row.get("object_id"),
                     row.get("object_type"),
@@ -526,4 +1652,699 @@ impl SqlDatabase {
 
         Ok(results)
     }
+
+    // -------------------------------------------------------
+
+    // Opens an existing database file, such as a snapshot taken by `backup_to`.
+    pub async fn open_read_only(path: &Path) -> Result<Self, SqlxError> {
+        let options = SqliteConnectOptions::new().filename(path).read_only(true);
+        let pool = SqlitePool::connect_with(options).await?;
+        Ok(Self {
+            path: RwLock::new(path.to_path_buf()),
+            pool: RwLock::new(pool),
+            key: RwLock::new(None),
+        })
+    }
+
+    pub async fn close(&self) {
+        self.pool().close().await;
+    }
+
+    // Takes over another open database, closing this one. Commands hold on
+    // to the managed instance, so switching workspaces swaps its contents.
+    pub async fn replace_with(&self, other: SqlDatabase) {
+        let previous = std::mem::replace(&mut *self.pool.write().unwrap(), other.pool());
+        *self.path.write().unwrap() = other.path();
+        *self.key.write().unwrap() = other.key();
+        previous.close().await;
+    }
+
+    // Copies a consistent snapshot of the live database to `path` using
+    // SQLite's online backup API, without stopping other connections.
+    // Snapshots of an encrypted database are exported in plaintext, so
+    // callers must not leave them on disk unencrypted.
+    pub async fn backup_to(&self, path: &Path) -> Result<(), SqlxError> {
+        let mut conn = self.pool().acquire().await?;
+        if self.key().is_some() {
+            return export_database(&mut conn, path, "").await;
+        }
+        let mut handle = conn.lock_handle().await?;
+        let target = open_raw(path)?;
+        let result = unsafe { copy_database(handle.as_raw_handle().as_ptr(), target) };
+        unsafe { sqlite3_close(target) };
+        result
+    }
+
+    // Replaces the contents of the live database with the plaintext
+    // database at `path`.
+    pub async fn restore_from(&self, path: &Path) -> Result<(), SqlxError> {
+        if let Some(key) = self.key() {
+            let encrypted = self.path().with_extension("db.restoring");
+            // Attached databases inherit the open flags, so allow creating
+            // the target file
+            let options = SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true);
+            let mut source = SqliteConnection::connect_with(&options).await?;
+            let result = export_database(&mut source, &encrypted, &key).await;
+            source.close().await?;
+            result?;
+            return self.replace_file(&encrypted, Some(key)).await;
+        }
+        let mut conn = self.pool().acquire().await?;
+        let mut handle = conn.lock_handle().await?;
+        let source = open_raw(path)?;
+        let result = unsafe { copy_database(source, handle.as_raw_handle().as_ptr()) };
+        unsafe { sqlite3_close(source) };
+        result
+    }
+
+    // -------------------------------------------------------
+
+    // Swaps the database file for `replacement` and reconnects. Writes that
+    // land while the old pool drains are lost, so callers keep this short.
+    async fn replace_file(&self, replacement: &Path, key: Option<String>) -> Result<(), SqlxError> {
+        let path = self.path();
+        self.pool().close().await;
+        for suffix in ["-wal", "-shm"] {
+            let mut sidecar = path.clone().into_os_string();
+            sidecar.push(suffix);
+            let _ = std::fs::remove_file(sidecar);
+        }
+        std::fs::rename(replacement, &path)?;
+
+        let pool = SqlitePool::connect_with(connect_options(&path, key.as_deref())).await?;
+        *self.pool.write().unwrap() = pool;
+        *self.key.write().unwrap() = key;
+        Ok(())
+    }
+
+    pub async fn enable_encryption(&self, key: &str) -> Result<(), SqlxError> {
+        if self.key().is_some() {
+            return Err(SqlxError::Protocol("Database is already encrypted".to_string()));
+        }
+        self.rekey(key).await
+    }
+
+    pub async fn change_key(&self, current_key: &str, new_key: &str) -> Result<(), SqlxError> {
+        if self.key().as_deref() != Some(current_key) {
+            return Err(SqlxError::Protocol("Current passphrase is incorrect".to_string()));
+        }
+        self.rekey(new_key).await
+    }
+
+    // Re-encrypts into a fresh file rather than with `PRAGMA rekey`, which
+    // would only update the connection that runs it.
+    async fn rekey(&self, key: &str) -> Result<(), SqlxError> {
+        if key.is_empty() {
+            return Err(SqlxError::Protocol("Passphrase must not be empty".to_string()));
+        }
+        let encrypted = self.path().with_extension("db.rekeying");
+        {
+            let mut conn = self.pool().acquire().await?;
+            export_database(&mut conn, &encrypted, key).await?;
+        }
+        self.replace_file(&encrypted, Some(key.to_string())).await
+    }
+
+    pub async fn export_plaintext(&self, path: &Path) -> Result<(), SqlxError> {
+        let mut conn = self.pool().acquire().await?;
+        export_database(&mut conn, path, "").await
+    }
+
+    pub async fn row_counts(&self, tables: &[&str]) -> Result<BTreeMap<String, i64>, SqlxError> {
+        let mut counts = BTreeMap::new();
+        for table in tables {
+            let sql = format!("SELECT COUNT(*) FROM {}", table);
+            let count: i64 = sqlx::query_scalar(&sql).fetch_one(&self.pool()).await?;
+            counts.insert(table.to_string(), count);
+        }
+        Ok(counts)
+    }
+
+    pub async fn integrity_check(&self) -> Result<(), SqlxError> {
+        let result: String = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_one(&self.pool())
+            .await?;
+        if result == "ok" {
+            Ok(())
+        } else {
+            Err(SqlxError::Protocol(result))
+        }
+    }
+
+    // Rewrites a string in every leaf, e.g. the uploads folder in asset URLs
+    // after a workspace moves to another machine.
+    pub async fn replace_in_leaf_content(&self, from: &str, to: &str) -> Result<u64, SqlxError> {
+        let mut tx = self.pool().begin().await?;
+        // Documents of rewritten leaves are rebuilt from the new HTML
+        sqlx::query(
+            "DELETE FROM leaf_documents WHERE leaf_id IN (SELECT id FROM leaves WHERE instr(content, ?) > 0)",
+        )
+        .bind(from)
+        .execute(&mut *tx)
+        .await?;
+        let result = sqlx::query(
+            "UPDATE leaves SET content = replace(content, ?, ?) WHERE instr(content, ?) > 0",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(from)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    // -------------------------------------------------------
+
+    // Stored CRDT state of a leaf and when it was last written.
+    pub async fn read_leaf_document(
+        &self,
+        leaf_id: &str,
+    ) -> Result<Option<(Vec<u8>, String)>, SqlxError> {
+        let row = sqlx::query("SELECT state, modified_at FROM leaf_documents WHERE leaf_id = ?")
+            .bind(leaf_id)
+            .fetch_optional(&self.pool())
+            .await?;
+        Ok(row.map(|row| (row.get("state"), row.get("modified_at"))))
+    }
+
+    // Stores a document and its rendering as the leaf's content, stamping
+    // both with the same time, which is returned.
+    // Only written while the leaf's modification time is still `expected`;
+    // returns None when something else changed the leaf since.
+    pub async fn write_leaf_document(
+        &self,
+        leaf_id: &str,
+        state: &[u8],
+        content: &str,
+        expected: &str,
+    ) -> Result<Option<String>, SqlxError> {
+        let content = &sanitize::clean(content);
+        let now = Utc::now().to_rfc3339();
+        let mut tx = self.pool().begin().await?;
+        let result = sqlx::query(
+            "UPDATE leaves SET content = ?, modified_at = ? WHERE id = ? AND modified_at = ?",
+        )
+        .bind(content)
+        .bind(&now)
+        .bind(leaf_id)
+        .bind(expected)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            let exists = sqlx::query("SELECT 1 FROM leaves WHERE id = ?")
+                .bind(leaf_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .is_some();
+            return if exists { Ok(None) } else { Err(SqlxError::RowNotFound) };
+        }
+        sqlx::query(
+            "INSERT OR REPLACE INTO leaf_documents (leaf_id, state, modified_at) VALUES (?, ?, ?)",
+        )
+        .bind(leaf_id)
+        .bind(state)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+        index_content(&mut tx, leaf_id, content, &now, true).await?;
+        tx.commit().await?;
+        events::publish(
+            EntityKind::Leaf,
+            leaf_id,
+            ChangeKind::Updated,
+            Some(now.clone()),
+            &["content", "modified_at"],
+        );
+        Ok(Some(now))
+    }
+}
+
+// SQLCipher needs the key before any other statement; sqlx always issues
+// the `key` pragma first on a new connection.
+fn connect_options(path: &Path, key: Option<&str>) -> SqliteConnectOptions {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
+        // The app and the command-line tools can have the database open at
+        // the same time, so wait for the other's write lock instead of failing
+        .busy_timeout(Duration::from_secs(10));
+    match key {
+        Some(key) => options.pragma("key", format!("'{}'", key.replace('\'', "''"))),
+        None => options,
+    }
+}
+
+// Copies the database behind `conn` into a new file with `sqlcipher_export`,
+// encrypted with `key`, or in plaintext when `key` is empty.
+async fn export_database(
+    conn: &mut SqliteConnection,
+    target: &Path,
+    key: &str,
+) -> Result<(), SqlxError> {
+    let _ = std::fs::remove_file(target);
+    sqlx::query("ATTACH DATABASE ? AS export KEY ?")
+        .bind(target.to_string_lossy())
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+    let result = sqlx::query("SELECT sqlcipher_export('export')")
+        .execute(&mut *conn)
+        .await;
+    sqlx::query("DETACH DATABASE export")
+        .execute(&mut *conn)
+        .await?;
+    result?;
+    Ok(())
+}
+
+fn sqlite_error(code: c_int) -> SqlxError {
+    let message = unsafe { CStr::from_ptr(sqlite3_errstr(code)) };
+    SqlxError::Protocol(message.to_string_lossy().into_owned())
+}
+
+fn open_raw(path: &Path) -> Result<*mut sqlite3, SqlxError> {
+    let path = CString::new(path.to_string_lossy().as_bytes())
+        .map_err(|e| SqlxError::Protocol(e.to_string()))?;
+    let mut db = std::ptr::null_mut();
+    let code = unsafe {
+        sqlite3_open_v2(
+            path.as_ptr(),
+            &mut db,
+            SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE,
+            std::ptr::null(),
+        )
+    };
+    if code != SQLITE_OK {
+        unsafe { sqlite3_close(db) };
+        return Err(sqlite_error(code));
+    }
+    Ok(db)
+}
+
+// Copies every page of `source` into `target`, retrying while another
+// connection holds a lock.
+unsafe fn copy_database(source: *mut sqlite3, target: *mut sqlite3) -> Result<(), SqlxError> {
+    let main = c"main".as_ptr();
+    let backup = sqlite3_backup_init(target, main, source, main);
+    if backup.is_null() {
+        let message = CStr::from_ptr(sqlite3_errmsg(target));
+        return Err(SqlxError::Protocol(message.to_string_lossy().into_owned()));
+    }
+
+    let mut code = sqlite3_backup_step(backup, -1);
+    for _ in 0..100 {
+        if code != SQLITE_BUSY && code != SQLITE_LOCKED {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        code = sqlite3_backup_step(backup, -1);
+    }
+
+    let finish = sqlite3_backup_finish(backup);
+    if code != SQLITE_DONE {
+        return Err(sqlite_error(code));
+    }
+    if finish != SQLITE_OK {
+        return Err(sqlite_error(finish));
+    }
+    Ok(())
+}
+
+async fn compute_embedding(text: &str) -> Result<Vec<u8>, SqlxError> {
+    let embedding = get_embedding(text)
+        .await
+        .map_err(|e| SqlxError::Protocol(e.to_string()))?;
+
+    let embedding_bytes: Vec<u8> = unsafe {
+        std::slice::from_raw_parts(
+            embedding.as_ptr() as *const u8,
+            embedding.len() * std::mem::size_of::<f32>(),
+        )
+        .to_vec()
+    };
+
+    Ok(embedding_bytes)
+}
+
+// Reports a change to a leaf or sage. `fields` are the columns written, or
+// all of them.
+fn publish_change<T: Entity + TimeStamped>(entity: &T, change: ChangeKind, fields: Option<&[&str]>) {
+    let Some(kind) = EntityKind::from_object_type(T::get_object_type()) else {
+        return;
+    };
+    let params = entity.to_params();
+    let all: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+    events::publish(
+        kind,
+        entity.get_id(),
+        change,
+        Some(entity.modified_at().to_string()),
+        fields.unwrap_or(&all),
+    );
+}
+
+// The content of `entity` if it is a leaf.
+fn leaf_content<T: Entity>(entity: &T) -> Option<String> {
+    if T::get_object_type() != Leaf::get_object_type() {
+        return None;
+    }
+    entity
+        .to_params()
+        .into_iter()
+        .find(|(name, _)| name == "content")
+        .map(|(_, content)| content)
+}
+
+async fn table_exists(pool: &SqlitePool, name: &str) -> Result<bool, SqlxError> {
+    let row = sqlx::query("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+// Rebuilds what is derived from a leaf's content. With `record_activity` the
+// change in word count counts as today's writing; imports and changes synced
+// from other devices don't.
+async fn index_content(
+    conn: &mut SqliteConnection,
+    leaf_id: &str,
+    content: &str,
+    modified_at: &str,
+    record_activity: bool,
+) -> Result<(), SqlxError> {
+    write_tasks(conn, leaf_id, content).await?;
+    write_blocks(conn, leaf_id, content).await?;
+    write_stats(conn, leaf_id, content, modified_at, record_activity).await
+}
+
+async fn write_stats(
+    conn: &mut SqliteConnection,
+    leaf_id: &str,
+    content: &str,
+    modified_at: &str,
+    record_activity: bool,
+) -> Result<(), SqlxError> {
+    let stats = stats::compute(leaf_id, content, modified_at);
+    if record_activity {
+        let previous: i64 = sqlx::query("SELECT word_count FROM leaf_stats WHERE leaf_id = ?")
+            .bind(leaf_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .map_or(0, |row| row.get("word_count"));
+        let delta = stats.word_count - previous;
+        if delta != 0 {
+            sqlx::query(
+                "INSERT INTO writing_activity (date, words_added, words_removed) VALUES (?, ?, ?)
+                 ON CONFLICT(date) DO UPDATE SET
+                     words_added = words_added + excluded.words_added,
+                     words_removed = words_removed + excluded.words_removed",
+            )
+            .bind(stats::today())
+            .bind(delta.max(0))
+            .bind((-delta).max(0))
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+    sqlx::query(
+        "INSERT OR REPLACE INTO leaf_stats
+             (leaf_id, word_count, character_count, reading_minutes, headings, readability, modified_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(leaf_id)
+    .bind(stats.word_count)
+    .bind(stats.character_count)
+    .bind(stats.reading_minutes)
+    .bind(serde_json::to_string(&stats.headings).unwrap_or_else(|_| "[]".to_string()))
+    .bind(stats.readability)
+    .bind(&stats.modified_at)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn write_blocks(
+    conn: &mut SqliteConnection,
+    leaf_id: &str,
+    content: &str,
+) -> Result<(), SqlxError> {
+    sqlx::query("DELETE FROM blocks WHERE leaf_id = ?")
+        .bind(leaf_id)
+        .execute(&mut *conn)
+        .await?;
+    for block in blocks::extract(leaf_id, content) {
+        sqlx::query(
+            "INSERT INTO blocks (leaf_id, position, id, type, text, html) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(leaf_id)
+        .bind(block.position)
+        .bind(block.id)
+        .bind(block.kind)
+        .bind(block.text)
+        .bind(block.html)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM transclusions WHERE leaf_id = ?")
+        .bind(leaf_id)
+        .execute(&mut *conn)
+        .await?;
+    for (source_leaf_id, source_block_id) in blocks::transclusions(content) {
+        sqlx::query(
+            "INSERT OR IGNORE INTO transclusions (leaf_id, source_leaf_id, source_block_id)
+             VALUES (?, ?, ?)",
+        )
+        .bind(leaf_id)
+        .bind(source_leaf_id)
+        .bind(source_block_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+fn block_from_row(row: sqlx::sqlite::SqliteRow) -> Block {
+    Block {
+        leaf_id: row.get("leaf_id"),
+        position: row.get("position"),
+        id: row.get("id"),
+        kind: row.get("type"),
+        text: row.get("text"),
+        html: row.get("html"),
+    }
+}
+
+// Replaces the indexed tasks of a leaf with those in `content`.
+async fn write_tasks(
+    conn: &mut SqliteConnection,
+    leaf_id: &str,
+    content: &str,
+) -> Result<(), SqlxError> {
+    sqlx::query("DELETE FROM tasks WHERE leaf_id = ?")
+        .bind(leaf_id)
+        .execute(&mut *conn)
+        .await?;
+    for task in tasks::extract(content) {
+        sqlx::query(
+            "INSERT INTO tasks (leaf_id, position, block_id, text, checked, due_date)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(leaf_id)
+        .bind(task.position)
+        .bind(task.block_id)
+        .bind(task.text)
+        .bind(task.checked)
+        .bind(task.due_date)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+fn task_from_row(row: sqlx::sqlite::SqliteRow) -> Task {
+    Task {
+        leaf_id: row.get("leaf_id"),
+        leaf_name: row.get("leaf_name"),
+        position: row.get("position"),
+        block_id: row.get("block_id"),
+        text: row.get("text"),
+        checked: row.get("checked"),
+        due_date: row.get("due_date"),
+    }
+}
+
+fn reminder_from_row(row: sqlx::sqlite::SqliteRow) -> Reminder {
+    Reminder {
+        id: row.get("id"),
+        leaf_id: row.get("leaf_id"),
+        leaf_name: row.get("leaf_name"),
+        block_id: row.get("block_id"),
+        note: row.get("note"),
+        due_at: row.get("due_at"),
+        fired_at: row.get("fired_at"),
+        created_at: row.get("created_at"),
+    }
+}
+
+// Deletes an entity and the rows kept about it. Returns whether it existed.
+async fn delete_entity<T: Entity>(conn: &mut SqliteConnection, id: &str) -> Result<bool, SqlxError> {
+    sqlx::query("DELETE FROM stale_embeddings WHERE object_id = ? AND object_type = ?")
+        .bind(id)
+        .bind(T::get_object_type())
+        .execute(&mut *conn)
+        .await?;
+
+    // The embeddings are found through their metadata, so they go first
+    sqlx::query("DELETE FROM embeddings WHERE rowid IN (SELECT rowid FROM embedding_metadata WHERE object_id = ? AND object_type = ?)")
+        .bind(id)
+        .bind(T::get_object_type())
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM embedding_metadata WHERE object_id = ? AND object_type = ?")
+        .bind(id)
+        .bind(T::get_object_type())
+        .execute(&mut *conn)
+        .await?;
+
+    if T::get_object_type() == Leaf::get_object_type() {
+        // Children of a deleted leaf move up to the top level. Embeds of its
+        // blocks elsewhere stay, and show as missing.
+        let leaf_tables = [
+            "DELETE FROM leaf_tags WHERE leaf_id = ?",
+            "DELETE FROM leaf_parents WHERE leaf_id = ?1 OR parent_id = ?1",
+            "DELETE FROM leaf_documents WHERE leaf_id = ?",
+            "DELETE FROM collab_shares WHERE leaf_id = ?",
+            "DELETE FROM journal WHERE leaf_id = ?",
+            "DELETE FROM tasks WHERE leaf_id = ?",
+            "DELETE FROM reminders WHERE leaf_id = ?",
+            "DELETE FROM leaf_stats WHERE leaf_id = ?",
+            "DELETE FROM blocks WHERE leaf_id = ?",
+            "DELETE FROM transclusions WHERE leaf_id = ?",
+        ];
+        for sql in leaf_tables {
+            sqlx::query(sql).bind(id).execute(&mut *conn).await?;
+        }
+    }
+
+    let sql = format!("DELETE FROM {} WHERE id = ?", T::TABLE_NAME);
+    let result = sqlx::query(&sql).bind(id).execute(&mut *conn).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+async fn insert_entity<T: Entity>(
+    conn: &mut SqliteConnection,
+    entity: &T,
+) -> Result<(), SqlxError> {
+    let params = entity.to_params();
+    let columns = format!(
+        "id, {}",
+        params
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let placeholders = format!(
+        "?, {}",
+        std::iter::repeat("?")
+            .take(params.len())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        T::TABLE_NAME,
+        columns,
+        placeholders
+    );
+
+    let mut query = sqlx::query(&sql);
+    query = query.bind(entity.get_id());
+    for (_, value) in params {
+        query = query.bind(value);
+    }
+
+    query.execute(&mut *conn).await?;
+    Ok(())
+}
+
+// Drops the embedding of an object whose text changed without computing a
+// new one, so searches don't match the old text.
+async fn mark_embedding_stale(
+    conn: &mut SqliteConnection,
+    object_id: &str,
+    object_type: &str,
+) -> Result<(), SqlxError> {
+    sqlx::query("DELETE FROM embeddings WHERE rowid IN (SELECT rowid FROM embedding_metadata WHERE object_id = ? AND object_type = ?)")
+        .bind(object_id)
+        .bind(object_type)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM embedding_metadata WHERE object_id = ? AND object_type = ?")
+        .bind(object_id)
+        .bind(object_type)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("INSERT OR IGNORE INTO stale_embeddings (object_id, object_type) VALUES (?, ?)")
+        .bind(object_id)
+        .bind(object_type)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn write_embedding(
+    conn: &mut SqliteConnection,
+    object_id: &str,
+    object_type: &str,
+    embedding_bytes: &[u8],
+) -> Result<(), SqlxError> {
+    sqlx::query("DELETE FROM stale_embeddings WHERE object_id = ? AND object_type = ?")
+        .bind(object_id)
+        .bind(object_type)
+        .execute(&mut *conn)
+        .await?;
+
+    // First, delete any existing embedding for this object
+    let delete_embeddings = "DELETE FROM embeddings WHERE rowid IN (SELECT rowid FROM embedding_metadata WHERE object_id = ? AND object_type = ?)";
+    sqlx::query(delete_embeddings)
+        .bind(object_id)
+        .bind(object_type)
+        .execute(&mut *conn)
+        .await?;
+
+    let delete_embedding_metadata =
+        "DELETE FROM embedding_metadata WHERE object_id = ? AND object_type = ?";
+    sqlx::query(delete_embedding_metadata)
+        .bind(object_id)
+        .bind(object_type)
+        .execute(&mut *conn)
+        .await?;
+
+    // Then insert the new embedding
+    let insert_sql = "INSERT INTO embeddings(embedding) VALUES (?)";
+    let row = sqlx::query(insert_sql)
+        .bind(embedding_bytes) // Bind the slice directly
+        .execute(&mut *conn)
+        .await?;
+
+    let last_id = row.last_insert_rowid();
+
+    let metadata_sql =
+        "INSERT INTO embedding_metadata (rowid, object_id, object_type) VALUES (?, ?, ?)";
+    sqlx::query(metadata_sql)
+        .bind(last_id)
+        .bind(object_id)
+        .bind(object_type)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
 }
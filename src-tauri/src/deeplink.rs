@@ -0,0 +1,276 @@
+use crate::db::{Leaf, SqlDatabase};
+use crate::markdown;
+use crate::sanitize;
+use crate::windows::MAIN_WINDOW;
+use serde::Serialize;
+use sqlx::Error as SqlxError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+use url::Url;
+
+// `bonsai://` links from other apps. They are parsed and checked here, then
+// handed to the main window, which opens the leaf or runs the search. Links
+// never change the workspace by themselves: a `new` link reaches the window
+// as a draft, and the leaf is only created once the user confirms it.
+
+pub const SCHEME: &str = "bonsai";
+pub const EVENT: &str = "deep-link";
+
+const MAX_ID_LEN: usize = 64;
+const MAX_TITLE_LEN: usize = 500;
+const MAX_CONTENT_LEN: usize = 100_000;
+const MAX_QUERY_LEN: usize = 1_000;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum DeepLink {
+    // `bonsai://leaf/<id>`, or `bonsai://leaf/<id>#<blockId>` to scroll to
+    // the block with that BlockID attribute
+    #[serde(rename_all = "camelCase")]
+    Leaf {
+        leaf_id: String,
+        block_id: Option<String>,
+    },
+    // `bonsai://new?title=..&content=..`, with the content in Markdown. The
+    // window gets it as a draft with the content as editor HTML.
+    New { title: String, content: String },
+    // `bonsai://search?q=..`
+    Search { query: String },
+}
+
+// Leaf and block ids are generated by us, so anything beyond these
+// characters did not come from a real link.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn parse(link: &str) -> Result<DeepLink, String> {
+    let url = Url::parse(link).map_err(|e| format!("Invalid link {}: {}", link, e))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("Not a {}:// link: {}", SCHEME, link));
+    }
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    // In `bonsai://leaf/<id>` the action is the host and the id the path
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    match (url.host_str().unwrap_or_default(), segments.as_slice()) {
+        ("leaf", [id]) => {
+            if !valid_id(id) {
+                return Err(format!("Invalid leaf id in {}", link));
+            }
+            let block_id = url.fragment().filter(|fragment| !fragment.is_empty());
+            if block_id.is_some_and(|block_id| !valid_id(block_id)) {
+                return Err(format!("Invalid block id in {}", link));
+            }
+            Ok(DeepLink::Leaf {
+                leaf_id: id.to_string(),
+                block_id: block_id.map(str::to_string),
+            })
+        }
+        ("new", []) => {
+            let title = query("title").unwrap_or_default().trim().to_string();
+            let content = query("content").unwrap_or_default();
+            if title.chars().count() > MAX_TITLE_LEN || content.len() > MAX_CONTENT_LEN {
+                return Err("The new leaf's title or content is too long".to_string());
+            }
+            Ok(DeepLink::New { title, content })
+        }
+        ("search", []) => {
+            let query = query("q").unwrap_or_default().trim().to_string();
+            if query.is_empty() || query.chars().count() > MAX_QUERY_LEN {
+                return Err(format!("Missing or overlong search in {}", link));
+            }
+            Ok(DeepLink::Search { query })
+        }
+        _ => Err(format!("Unknown link {}", link)),
+    }
+}
+
+// Checks the link against the database; a block that no longer exists opens
+// the whole leaf. A `new` link's Markdown becomes the draft's HTML.
+pub async fn resolve(db: &SqlDatabase, link: DeepLink) -> Result<DeepLink, SqlxError> {
+    match link {
+        DeepLink::Leaf { leaf_id, block_id } => {
+            let leaf = db.read::<Leaf>(&leaf_id).await?.ok_or(SqlxError::RowNotFound)?;
+            let block_id = block_id.filter(|block_id| {
+                leaf.content().contains(&format!("blockid=\"{}\"", block_id))
+            });
+            Ok(DeepLink::Leaf { leaf_id, block_id })
+        }
+        DeepLink::New { title, content } => {
+            let content = sanitize::clean(&markdown::to_html(&content, |_| None, |_| None));
+            Ok(DeepLink::New { title, content })
+        }
+        search => Ok(search),
+    }
+}
+
+async fn resolve_all(db: &SqlDatabase, links: Vec<DeepLink>) -> Vec<DeepLink> {
+    let mut resolved = Vec::new();
+    for link in links {
+        match resolve(db, link).await {
+            Ok(link) => resolved.push(link),
+            Err(e) => eprintln!("Could not open deep link: {}", e),
+        }
+    }
+    resolved
+}
+
+// Links that arrive before the window is listening, e.g. the one the app was
+// launched with, wait here until it asks for them.
+#[derive(Default)]
+pub struct PendingLinks {
+    links: Mutex<Vec<DeepLink>>,
+    listening: AtomicBool,
+}
+
+impl PendingLinks {
+    // Called by the window once its listener is in place. Links stay queued
+    // while the database is locked.
+    pub async fn take(&self, db: Option<&SqlDatabase>) -> Vec<DeepLink> {
+        let Some(db) = db else {
+            return Vec::new();
+        };
+        self.listening.store(true, Ordering::SeqCst);
+        let links = std::mem::take(&mut *self.links.lock().unwrap());
+        resolve_all(db, links).await
+    }
+}
+
+// Brings the main window to the front, e.g. when a second launch hands its
+// links over to this one.
+pub fn focus_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+}
+
+pub fn open_urls(app: &AppHandle, urls: Vec<Url>) {
+    let links: Vec<DeepLink> = urls
+        .iter()
+        .filter_map(|url| {
+            parse(url.as_str())
+                .map_err(|e| eprintln!("Ignoring deep link: {}", e))
+                .ok()
+        })
+        .collect();
+    if links.is_empty() {
+        return;
+    }
+    focus_main_window(app);
+
+    let pending = app.state::<PendingLinks>();
+    let db = app.try_state::<SqlDatabase>();
+    if db.is_none() || !pending.listening.load(Ordering::SeqCst) {
+        pending.links.lock().unwrap().extend(links);
+        return;
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let db = app.state::<SqlDatabase>();
+        for link in resolve_all(&db, links).await {
+            let _ = app.emit_to(MAIN_WINDOW, EVENT, link);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parses_leaf_links() {
+        assert_eq!(
+            parse("bonsai://leaf/abc-123#b_1"),
+            Ok(DeepLink::Leaf {
+                leaf_id: "abc-123".to_string(),
+                block_id: Some("b_1".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("bonsai://leaf/abc/#"),
+            Ok(DeepLink::Leaf {
+                leaf_id: "abc".to_string(),
+                block_id: None,
+            })
+        );
+        assert!(parse("bonsai://leaf/a%20b").is_err());
+        assert!(parse("bonsai://leaf/abc#x'y").is_err());
+        assert!(parse("bonsai://leaf/a/b").is_err());
+        assert!(parse("bonsai://leaf").is_err());
+        assert!(parse(&format!("bonsai://leaf/{}", "a".repeat(MAX_ID_LEN + 1))).is_err());
+    }
+
+    #[test]
+    fn parses_new_and_search_links() {
+        assert_eq!(
+            parse("bonsai://new?title=%20Groceries%20&content=-%20milk%0A-%20eggs"),
+            Ok(DeepLink::New {
+                title: "Groceries".to_string(),
+                content: "- milk\n- eggs".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("bonsai://new"),
+            Ok(DeepLink::New {
+                title: String::new(),
+                content: String::new(),
+            })
+        );
+        let long_title = "t".repeat(MAX_TITLE_LEN + 1);
+        assert!(parse(&format!("bonsai://new?title={}", long_title)).is_err());
+
+        assert_eq!(
+            parse("bonsai://search?q=rust+notes"),
+            Ok(DeepLink::Search {
+                query: "rust notes".to_string(),
+            })
+        );
+        assert!(parse("bonsai://search?q=%20").is_err());
+        assert!(parse("bonsai://search").is_err());
+    }
+
+    #[test]
+    fn rejects_other_links() {
+        assert!(parse("https://leaf/abc").is_err());
+        assert!(parse("bonsai://delete/abc").is_err());
+        assert!(parse("not a link").is_err());
+    }
+
+    #[tokio::test]
+    async fn new_links_become_drafts() {
+        let name = format!("bonsai-deeplink-{}", uuid::Uuid::new_v4());
+        let root = TempDir(std::env::temp_dir().join(name));
+        let db = SqlDatabase::open(root.0.clone(), None).await.unwrap();
+        let link = parse("bonsai://new?title=Draft&content=**hi**%3Cscript%3Ex%3C/script%3E");
+        let draft = resolve(&db, link.unwrap()).await.unwrap();
+        let DeepLink::New { title, content } = draft else {
+            panic!("expected a draft, got {:?}", draft);
+        };
+        assert_eq!(title, "Draft");
+        assert!(content.contains("<strong>hi</strong>"));
+        assert!(!content.contains("script"));
+        assert!(db.list::<Leaf>().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::broadcast;
+
+// Notifications for every change made through `SqlDatabase` or the file
+// `Database`, so windows and list views can update without refetching.
+// Changes are numbered in order; a window that missed some, e.g. while the
+// machine slept, replays the ones after the last number it saw.
+
+pub const EVENT: &str = "entity-changed";
+// Changes kept for replay. Windows further behind refetch everything.
+const HISTORY: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EntityKind {
+    Leaf,
+    Sage,
+    Template,
+    // Leaves, sages and uploads in the workspace folder, keyed by file name
+    FileLeaf,
+    FileSage,
+    Upload,
+}
+
+impl EntityKind {
+    // The kind for an `Entity::get_object_type`, if changes to it are
+    // reported.
+    pub fn from_object_type(object_type: &str) -> Option<Self> {
+        match object_type {
+            "leaf" => Some(EntityKind::Leaf),
+            "sage" => Some(EntityKind::Sage),
+            "template" => Some(EntityKind::Template),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityChange {
+    pub sequence: u64,
+    pub entity: EntityKind,
+    pub id: String,
+    pub change: ChangeKind,
+    // Modification time after the change; none once deleted
+    pub version: Option<String>,
+    // Fields written, by their camelCase names
+    pub fields: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Replay {
+    pub changes: Vec<EntityChange>,
+    // False when changes after `since` were dropped from the history, in
+    // which case the window should reload its data
+    pub complete: bool,
+    pub latest: u64,
+}
+
+struct History {
+    latest: u64,
+    changes: VecDeque<EntityChange>,
+}
+
+struct ChangeBus {
+    history: Mutex<History>,
+    sender: broadcast::Sender<EntityChange>,
+}
+
+fn bus() -> &'static ChangeBus {
+    static BUS: OnceLock<ChangeBus> = OnceLock::new();
+    BUS.get_or_init(|| ChangeBus {
+        history: Mutex::new(History {
+            latest: 0,
+            changes: VecDeque::new(),
+        }),
+        sender: broadcast::channel(HISTORY).0,
+    })
+}
+
+fn camel_case(name: &str) -> String {
+    let mut out = String::new();
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+pub fn publish(
+    entity: EntityKind,
+    id: &str,
+    change: ChangeKind,
+    version: Option<String>,
+    fields: &[&str],
+) {
+    let bus = bus();
+    let mut history = bus.history.lock().unwrap();
+    history.latest += 1;
+    let change = EntityChange {
+        sequence: history.latest,
+        entity,
+        id: id.to_string(),
+        change,
+        version,
+        fields: fields.iter().map(|field| camel_case(field)).collect(),
+    };
+    if history.changes.len() == HISTORY {
+        history.changes.pop_front();
+    }
+    history.changes.push_back(change.clone());
+    // Sent under the lock so subscribers see changes in sequence order.
+    // Nobody listening (e.g. in the command-line tools) is not an error.
+    let _ = bus.sender.send(change);
+}
+
+pub fn subscribe() -> broadcast::Receiver<EntityChange> {
+    bus().sender.subscribe()
+}
+
+// Changes after `since`, limited to `entities` when any are given.
+pub fn replay(since: u64, entities: &[EntityKind]) -> Replay {
+    let history = bus().history.lock().unwrap();
+    let oldest = history.changes.front().map_or(history.latest + 1, |c| c.sequence);
+    Replay {
+        changes: history
+            .changes
+            .iter()
+            .filter(|c| c.sequence > since)
+            .filter(|c| entities.is_empty() || entities.contains(&c.entity))
+            .cloned()
+            .collect(),
+        complete: since + 1 >= oldest,
+        latest: history.latest,
+    }
+}
+
+// -------------------------------------------------------
+
+// Entities each window wants to hear about, by window label. Windows that
+// never set a filter receive every change.
+#[derive(Default)]
+pub struct ChangeFilters(Mutex<HashMap<String, Vec<EntityKind>>>);
+
+impl ChangeFilters {
+    pub fn set(&self, window: &str, entities: Option<Vec<EntityKind>>) {
+        let mut filters = self.0.lock().unwrap();
+        match entities {
+            Some(entities) => filters.insert(window.to_string(), entities),
+            None => filters.remove(window),
+        };
+    }
+
+    fn wants(&self, window: &str, entity: EntityKind) -> bool {
+        match self.0.lock().unwrap().get(window) {
+            Some(entities) => entities.contains(&entity),
+            None => true,
+        }
+    }
+}
+
+// Sends each change to the windows whose filter matches it.
+pub fn forward(app: AppHandle) {
+    let mut changes = subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let change = match changes.recv().await {
+                Ok(change) => change,
+                // Windows notice the gap in sequence numbers and replay
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let filters = app.state::<ChangeFilters>();
+            for label in app.webview_windows().keys() {
+                if filters.wants(label, change.entity) {
+                    let _ = app.emit_to(label.as_str(), EVENT, change.clone());
+                }
+            }
+        }
+    });
+}
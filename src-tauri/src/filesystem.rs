@@ -1,11 +1,15 @@
+use crate::events::{self, ChangeKind, EntityKind};
+use crate::sanitize;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::PathBuf;
+use std::sync::RwLock;
 
 pub struct Database {
-    root_dir: PathBuf,
+    // Replaced when another workspace is opened
+    root_dir: RwLock<PathBuf>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -26,11 +30,23 @@ pub struct Sage {
     modified_at: String,
 }
 
-#[derive(Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Config {
-    openai_api_key: String,
-    theme: String,
+// Same URL `convertFileSrc` produces in the webview for a local file.
+pub fn asset_url(path: &str) -> String {
+    let encoded = crate::html::encode_uri_component(path);
+    if cfg!(windows) {
+        format!("http://asset.localhost/{}", encoded)
+    } else {
+        format!("asset://localhost/{}", encoded)
+    }
+}
+
+// Inverse of `asset_url`: the local file an asset URL points at.
+pub fn asset_path(url: &str) -> Option<PathBuf> {
+    let encoded = url
+        .strip_prefix("asset://localhost/")
+        .or_else(|| url.strip_prefix("http://asset.localhost/"))
+        .or_else(|| url.strip_prefix("https://asset.localhost/"))?;
+    Some(PathBuf::from(crate::html::decode_uri_component(encoded)))
 }
 
 fn iso8601(st: &std::time::SystemTime) -> String {
@@ -38,22 +54,41 @@ fn iso8601(st: &std::time::SystemTime) -> String {
     format!("{}", dt.format("%+"))
 }
 
+// Files carry no version of their own, so changes are stamped with the time
+// they were made.
+fn publish_change(entity: EntityKind, name: &str, change: ChangeKind, fields: &[&str]) {
+    let now = iso8601(&std::time::SystemTime::now());
+    events::publish(entity, name, change, Some(now), fields);
+}
+
 impl Database {
-    pub fn new(dir: PathBuf) -> io::Result<Self> {
-        let root_dir = dir.join("bonsai");
+    pub fn new(root_dir: PathBuf) -> io::Result<Self> {
         fs::create_dir_all(&root_dir)?;
-        Ok(Self { root_dir })
+        Ok(Self {
+            root_dir: RwLock::new(root_dir),
+        })
+    }
+
+    pub fn root_dir(&self) -> PathBuf {
+        self.root_dir.read().unwrap().clone()
+    }
+
+    pub fn set_root_dir(&self, root_dir: PathBuf) -> io::Result<()> {
+        fs::create_dir_all(&root_dir)?;
+        *self.root_dir.write().unwrap() = root_dir;
+        Ok(())
     }
 
     pub fn create_leaf(&self, name: &str, content: &str) -> io::Result<()> {
-        let full_path = self.root_dir.join(name);
+        let full_path = self.root_dir().join(name);
         let mut file = File::create(full_path)?;
-        file.write_all(content.as_bytes())?;
+        file.write_all(sanitize::clean(content).as_bytes())?;
+        publish_change(EntityKind::FileLeaf, name, ChangeKind::Created, &["content"]);
         Ok(())
     }
 
     pub fn read_leaf(&self, name: &str) -> io::Result<Leaf> {
-        let full_path = self.root_dir.join(name);
+        let full_path = self.root_dir().join(name);
         let content = fs::read_to_string(&full_path)?;
         // TODO: Fix time for cross platform compatibility
         let now = iso8601(&std::time::SystemTime::now());
@@ -66,7 +101,7 @@ impl Database {
     }
 
     pub fn list_leaves(&self) -> io::Result<Vec<Leaf>> {
-        let entries = fs::read_dir(&self.root_dir)?;
+        let entries = fs::read_dir(self.root_dir())?;
         let mut leaves = Vec::new();
         for entry in entries {
             let entry = entry?;
@@ -89,20 +124,22 @@ impl Database {
     }
 
     pub fn delete_leaf(&self, name: &str) -> io::Result<()> {
-        let full_path = self.root_dir.join(name);
+        let full_path = self.root_dir().join(name);
         fs::remove_file(full_path)?;
+        events::publish(EntityKind::FileLeaf, name, ChangeKind::Deleted, None, &[]);
         Ok(())
     }
 
     pub fn update_leaf(&self, name: &str, content: &str) -> io::Result<()> {
-        let full_path = self.root_dir.join(name);
+        let full_path = self.root_dir().join(name);
         let mut file = File::create(full_path)?;
-        file.write_all(content.as_bytes())?;
+        file.write_all(sanitize::clean(content).as_bytes())?;
+        publish_change(EntityKind::FileLeaf, name, ChangeKind::Updated, &["content"]);
         Ok(())
     }
 
     pub fn search_leaves(&self, query: &str) -> io::Result<Vec<Leaf>> {
-        let entries = fs::read_dir(&self.root_dir)?;
+        let entries = fs::read_dir(self.root_dir())?;
         let mut leaves = Vec::new();
         let query_lowercase = query.to_lowercase();
         for entry in entries {
@@ -129,7 +166,7 @@ impl Database {
     // -------------------------------------------------------
 
     fn load_sages(&self) -> io::Result<Vec<Sage>> {
-        let sages_dir = self.root_dir.join("sages");
+        let sages_dir = self.root_dir().join("sages");
         let sages_file = sages_dir.join("sages.json");
         if sages_file.exists() {
             let sages_content = fs::read_to_string(&sages_file)?;
@@ -141,7 +178,7 @@ impl Database {
     }
 
     fn save_sages(&self, sages: &[Sage]) -> io::Result<()> {
-        let sages_dir = self.root_dir.join("sages");
+        let sages_dir = self.root_dir().join("sages");
         fs::create_dir_all(&sages_dir)?;
         let sages_file = sages_dir.join("sages.json");
         let sages_content = serde_json::to_string_pretty(sages)?;
@@ -166,7 +203,14 @@ impl Database {
             modified_at: now,
         };
         sages.push(sage);
-        self.save_sages(&sages)
+        self.save_sages(&sages)?;
+        publish_change(
+            EntityKind::FileSage,
+            name,
+            ChangeKind::Created,
+            &["name", "description", "createdAt", "modifiedAt"],
+        );
+        Ok(())
     }
 
     pub fn read_sage(&self, name: &str) -> io::Result<Option<Sage>> {
@@ -186,7 +230,9 @@ impl Database {
         if sages.len() == initial_length {
             return Err(io::Error::new(io::ErrorKind::NotFound, "Sage not found"));
         }
-        self.save_sages(&sages)
+        self.save_sages(&sages)?;
+        events::publish(EntityKind::FileSage, name, ChangeKind::Deleted, None, &[]);
+        Ok(())
     }
 
     pub fn update_sage(&self, name: &str, description: &str) -> io::Result<()> {
@@ -195,6 +241,12 @@ impl Database {
             sage.description = description.to_string();
             sage.modified_at = iso8601(&std::time::SystemTime::now());
             self.save_sages(&sages)?;
+            publish_change(
+                EntityKind::FileSage,
+                name,
+                ChangeKind::Updated,
+                &["description", "modifiedAt"],
+            );
         } else {
             return Err(io::Error::new(io::ErrorKind::NotFound, "Sage not found"));
         }
@@ -213,45 +265,20 @@ impl Database {
 
     // -------------------------------------------------------
 
-    pub fn set_config(&self, config: &Config) -> io::Result<()> {
-        let config_dir = self.root_dir.join("config");
-        fs::create_dir_all(&config_dir)?;
-        let config_path = config_dir.join("config.json");
-        let config_content = serde_json::to_string_pretty(config)?;
-        let mut file = File::create(config_path)?;
-        file.write_all(config_content.as_bytes())?;
-        Ok(())
-    }
-
-    pub fn get_config(&self) -> io::Result<Config> {
-        let config_dir = self.root_dir.join("config");
-        fs::create_dir_all(&config_dir)?;
-        let config_path = config_dir.join("config.json");
-        if config_path.exists() {
-            let config_content = fs::read_to_string(&config_path)?;
-            let config: Config = serde_json::from_str(&config_content)?;
-            Ok(config)
-        } else {
-            Ok(Config {
-                openai_api_key: "".to_string(),
-                theme: "dark".to_string(),
-            })
-        }
-    }
-
     pub fn upload_file(&self, file_name: &str, file_data: &[u8]) -> io::Result<String> {
-        let uploads_dir = self.root_dir.join("uploads");
+        let uploads_dir = self.root_dir().join("uploads");
         fs::create_dir_all(&uploads_dir)?;
         let file_path = uploads_dir.join(&file_name);
 
         let mut file = fs::File::create(&file_path)?;
         file.write_all(file_data)?;
+        publish_change(EntityKind::Upload, file_name, ChangeKind::Created, &[]);
 
         Ok(file_path.to_str().unwrap().to_string())
     }
 
     pub fn get_file(&self, file_name: &str) -> io::Result<Vec<u8>> {
-        let uploads_dir = self.root_dir.join("uploads");
+        let uploads_dir = self.root_dir().join("uploads");
         let file_path = uploads_dir.join(file_name);
 
         let file_data = fs::read(&file_path)?;
@@ -0,0 +1,73 @@
+use crate::db::SqlDatabase;
+use crate::filesystem::Database;
+use crate::ollama;
+use crate::secrets::{self, SecretStore};
+use crate::settings::{Settings, SettingsStore};
+use crate::workspace::{WorkspaceInfo, Workspaces};
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+// Opens a workspace without the app, for the command-line tools. Folders
+// are the ones Tauri resolves for the app's identifier. Nothing is created
+// or upgraded here; that is left to the app.
+
+const IDENTIFIER: &str = "bons.ai";
+// Passphrase for encrypted workspaces
+pub const PASSPHRASE_VAR: &str = "BONSAI_PASSPHRASE";
+
+pub struct OpenWorkspace {
+    pub info: WorkspaceInfo,
+    pub db: SqlDatabase,
+    // Uploads and other workspace files
+    pub files: Database,
+    pub settings: Settings,
+}
+
+fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn app_dir(base: Option<PathBuf>) -> io::Result<PathBuf> {
+    base.map(|dir| dir.join(IDENTIFIER))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No home folder"))
+}
+
+pub fn workspaces() -> io::Result<Workspaces> {
+    Workspaces::read_only(&app_dir(dirs::config_dir())?, &app_dir(dirs::data_dir())?)
+}
+
+// Opens the workspace with `id`, or the one the app has open.
+pub async fn open(id: Option<&str>) -> io::Result<OpenWorkspace> {
+    let workspaces = workspaces()?;
+    let info = match id {
+        Some(id) => workspaces.get(id)?,
+        None => workspaces.active(),
+    };
+    if !SqlDatabase::database_path(&info.path).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Workspace \"{}\" has no database yet; open it in Bonsai first", info.name),
+        ));
+    }
+    let config_dir = info.path.join("config");
+    let secrets = SecretStore::read_only(&app_dir(dirs::config_dir())?, &config_dir)?;
+    let settings = SettingsStore::read_only(&config_dir)?.get();
+    ollama::configure(&settings.ai, secrets.get(secrets::OPENAI_API_KEY).ok().flatten());
+
+    let key = if SqlDatabase::is_encrypted(&info.path) {
+        Some(env::var(PASSPHRASE_VAR).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Workspace is encrypted; set {} to its passphrase", PASSPHRASE_VAR),
+            )
+        })?)
+    } else {
+        None
+    };
+    let db = SqlDatabase::open(info.path.clone(), key)
+        .await
+        .map_err(to_io_error)?;
+    let files = Database::new(info.path.clone())?;
+    Ok(OpenWorkspace { info, db, files, settings })
+}
@@ -0,0 +1,748 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+// A small, forgiving HTML tokenizer for the markup produced by the editor.
+// It does not build a tree; callers walk the token stream and use the byte
+// spans to rewrite the original string in place when they need to.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    End {
+        name: String,
+    },
+    Text(String),
+    Comment(String),
+}
+
+pub const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source",
+    "track", "wbr",
+];
+
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+pub const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "h1", "h2", "h3", "h4", "h5", "h6", "ul", "ol", "li", "blockquote", "pre", "table",
+    "tr", "td", "th", "div", "figure", "figcaption", "hr", "br",
+];
+
+impl Token {
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        match self {
+            Token::Start { attrs, .. } => attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn is_start(&self, tag: &str) -> bool {
+        matches!(self, Token::Start { name, .. } if name == tag)
+    }
+
+    pub fn is_end(&self, tag: &str) -> bool {
+        matches!(self, Token::End { name } if name == tag)
+    }
+}
+
+pub fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+pub fn tokenize(html: &str) -> Vec<(Token, Range<usize>)> {
+    let bytes = html.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut text_start = 0;
+
+    while pos < bytes.len() {
+        if bytes[pos] != b'<' {
+            pos += 1;
+            continue;
+        }
+
+        let rest = &html[pos..];
+        let parsed = if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|i| i + 3).unwrap_or(rest.len());
+            let body = &rest[4..end.saturating_sub(3).max(4)];
+            Some((Token::Comment(body.to_string()), end))
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            let end = rest.find('>').map(|i| i + 1).unwrap_or(rest.len());
+            Some((Token::Comment(rest[2..end.saturating_sub(1).max(2)].to_string()), end))
+        } else if rest.starts_with("</") {
+            parse_end_tag(rest)
+        } else {
+            parse_start_tag(rest)
+        };
+
+        let Some((token, len)) = parsed else {
+            pos += 1;
+            continue;
+        };
+
+        if text_start < pos {
+            tokens.push((
+                Token::Text(unescape(&html[text_start..pos])),
+                text_start..pos,
+            ));
+        }
+
+        let raw_text = match &token {
+            Token::Start {
+                name, self_closing, ..
+            } if !self_closing && RAW_TEXT_ELEMENTS.contains(&name.as_str()) => Some(name.clone()),
+            _ => None,
+        };
+
+        tokens.push((token, pos..pos + len));
+        pos += len;
+        text_start = pos;
+
+        // Raw text elements swallow everything up to their closing tag.
+        if let Some(name) = raw_text {
+            let closing = format!("</{}", name);
+            let lower = html[pos..].to_ascii_lowercase();
+            let end = lower.find(&closing).map(|i| pos + i).unwrap_or(html.len());
+            if end > pos {
+                tokens.push((Token::Text(html[pos..end].to_string()), pos..end));
+            }
+            pos = end;
+            text_start = end;
+        }
+    }
+
+    if text_start < html.len() {
+        tokens.push((
+            Token::Text(unescape(&html[text_start..])),
+            text_start..html.len(),
+        ));
+    }
+
+    tokens
+}
+
+fn parse_end_tag(rest: &str) -> Option<(Token, usize)> {
+    let end = rest.find('>')?;
+    let name = rest[2..end].trim().to_ascii_lowercase();
+    if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((Token::End { name }, end + 1))
+}
+
+fn parse_start_tag(rest: &str) -> Option<(Token, usize)> {
+    let bytes = rest.as_bytes();
+    if bytes.len() < 2 || !bytes[1].is_ascii_alphabetic() {
+        return None;
+    }
+
+    let mut i = 1;
+    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' && bytes[i] != b'/'
+    {
+        i += 1;
+    }
+    let name = rest[1..i].to_ascii_lowercase();
+    let mut attrs = Vec::new();
+    let mut self_closing = false;
+
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= bytes.len() {
+            return None;
+        }
+        match bytes[i] {
+            b'>' => {
+                i += 1;
+                break;
+            }
+            b'/' => {
+                self_closing = true;
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let key_start = i;
+        while i < bytes.len()
+            && !bytes[i].is_ascii_whitespace()
+            && !matches!(bytes[i], b'=' | b'>' | b'/')
+        {
+            i += 1;
+        }
+        let key = rest[key_start..i].to_ascii_lowercase();
+
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let mut value = String::new();
+        if i < bytes.len() && bytes[i] == b'=' {
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i < bytes.len() && (bytes[i] == b'"' || bytes[i] == b'\'') {
+                let quote = bytes[i];
+                let value_start = i + 1;
+                i = value_start;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += 1;
+                }
+                value = unescape(&rest[value_start..i.min(rest.len())]);
+                i += 1;
+            } else {
+                let value_start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                    i += 1;
+                }
+                value = unescape(&rest[value_start..i]);
+            }
+        }
+        if !key.is_empty() {
+            attrs.push((key, value));
+        }
+    }
+
+    let self_closing = self_closing || is_void(&name);
+    Some((
+        Token::Start {
+            name,
+            attrs,
+            self_closing,
+        },
+        i,
+    ))
+}
+
+// -------------------------------------------------------
+
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';').filter(|semi| *semi <= 12) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+            }
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+pub fn render_start_tag(name: &str, attrs: &[(String, String)], self_closing: bool) -> String {
+    let mut out = format!("<{}", name);
+    for (key, value) in attrs {
+        out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+    }
+    if self_closing && !is_void(name) {
+        out.push_str(" /");
+    }
+    out.push('>');
+    out
+}
+
+// Rewrites attribute values in place. `rewrite(tag, attr, value)` returns the
+// new value, or `None` to keep the original.
+pub fn rewrite_attrs(
+    html: &str,
+    mut rewrite: impl FnMut(&str, &str, &str) -> Option<String>,
+) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut last = 0;
+    for (token, span) in tokenize(html) {
+        let Token::Start {
+            name,
+            mut attrs,
+            self_closing,
+        } = token
+        else {
+            continue;
+        };
+        let mut changed = false;
+        for (key, value) in attrs.iter_mut() {
+            if let Some(new_value) = rewrite(&name, key, value) {
+                *value = new_value;
+                changed = true;
+            }
+        }
+        if changed {
+            out.push_str(&html[last..span.start]);
+            out.push_str(&render_start_tag(&name, &attrs, self_closing));
+            last = span.end;
+        }
+    }
+    out.push_str(&html[last..]);
+    out
+}
+
+// Gives every heading an `id`, taking ids in document order from `next_id`.
+// Headings that already carry one keep it.
+pub fn rewrite_heading_ids(html: &str, mut next_id: impl FnMut() -> Option<String>) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut last = 0;
+    for (token, span) in tokenize(html) {
+        let Token::Start {
+            name,
+            mut attrs,
+            self_closing,
+        } = token
+        else {
+            continue;
+        };
+        if !(name.len() == 2 && name.starts_with('h') && (b'1'..=b'6').contains(&name.as_bytes()[1])) {
+            continue;
+        }
+        let id = next_id();
+        if attrs.iter().any(|(key, _)| key == "id") {
+            continue;
+        }
+        if let Some(id) = id {
+            attrs.push(("id".to_string(), id));
+            out.push_str(&html[last..span.start]);
+            out.push_str(&render_start_tag(&name, &attrs, self_closing));
+            last = span.end;
+        }
+    }
+    out.push_str(&html[last..]);
+    out
+}
+
+// Inner HTML of the first element matching `predicate`.
+pub fn inner_html(html: &str, predicate: impl Fn(&Token) -> bool) -> Option<&str> {
+    let tokens = tokenize(html);
+    let start = tokens.iter().position(|(token, _)| predicate(token))?;
+    let end = element_end(&tokens, start);
+    let inner_end = tokens[start + 1..]
+        .iter()
+        .find(|(token, span)| span.end == end && matches!(token, Token::End { .. }))
+        .map(|(_, span)| span.start)
+        .unwrap_or(end);
+    Some(&html[tokens[start].1.end..inner_end])
+}
+
+pub fn has_class(token: &Token, class: &str) -> bool {
+    token
+        .attr("class")
+        .is_some_and(|classes| classes.split_whitespace().any(|c| c == class))
+}
+
+// -------------------------------------------------------
+
+// A minimal element tree for callers that need structure rather than spans.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Element {
+        name: String,
+        attrs: Vec<(String, String)>,
+        children: Vec<Node>,
+    },
+    Text(String),
+}
+
+impl Node {
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        match self {
+            Node::Element { attrs, .. } => attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.as_str()),
+            Node::Text(_) => None,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Node::Element { name, .. } => Some(name),
+            Node::Text(_) => None,
+        }
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match self {
+            Node::Element { children, .. } => children,
+            Node::Text(_) => &[],
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            Node::Text(text) => text.clone(),
+            Node::Element { children, .. } => children.iter().map(Node::text).collect(),
+        }
+    }
+}
+
+// Builds a tree from a fragment. Stray closing tags are ignored and unclosed
+// elements are closed by their parent's closing tag, like a browser would.
+type OpenElement = (String, Vec<(String, String)>, Vec<Node>);
+
+pub fn parse(html: &str) -> Vec<Node> {
+    let mut stack: Vec<OpenElement> = Vec::new();
+    let mut root: Vec<Node> = Vec::new();
+
+    fn push(stack: &mut [OpenElement], root: &mut Vec<Node>, node: Node) {
+        match stack.last_mut() {
+            Some((_, _, children)) => children.push(node),
+            None => root.push(node),
+        }
+    }
+
+    for (token, _) in tokenize(html) {
+        match token {
+            Token::Start {
+                name,
+                attrs,
+                self_closing,
+            } => {
+                if self_closing {
+                    push(&mut stack, &mut root, Node::Element { name, attrs, children: Vec::new() });
+                } else {
+                    stack.push((name, attrs, Vec::new()));
+                }
+            }
+            Token::End { name } => {
+                if !stack.iter().any(|(n, _, _)| *n == name) {
+                    continue;
+                }
+                while let Some((n, attrs, children)) = stack.pop() {
+                    let done = n == name;
+                    push(&mut stack, &mut root, Node::Element { name: n, attrs, children });
+                    if done {
+                        break;
+                    }
+                }
+            }
+            Token::Text(text) => push(&mut stack, &mut root, Node::Text(text)),
+            Token::Comment(_) => {}
+        }
+    }
+    while let Some((name, attrs, children)) = stack.pop() {
+        push(&mut stack, &mut root, Node::Element { name, attrs, children });
+    }
+    root
+}
+
+// Serializes a tree back to HTML.
+pub fn render(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(&escape(text)),
+            Node::Element {
+                name,
+                attrs,
+                children,
+            } => {
+                out.push_str(&render_start_tag(name, attrs, false));
+                if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+                    children.iter().for_each(|child| out.push_str(&child.text()));
+                    out.push_str(&format!("</{}>", name));
+                } else if !is_void(name) {
+                    out.push_str(&render(children));
+                    out.push_str(&format!("</{}>", name));
+                }
+            }
+        }
+    }
+    out
+}
+
+// -------------------------------------------------------
+
+// Plain text of a fragment, with block boundaries turned into newlines.
+pub fn text_content(html: &str) -> String {
+    let mut out = String::new();
+    let mut skip_depth = 0;
+    for (token, _) in tokenize(html) {
+        match token {
+            Token::Start { name, self_closing, .. } => {
+                if name == "script" || name == "style" {
+                    skip_depth += 1;
+                } else if BLOCK_ELEMENTS.contains(&name.as_str()) && !out.ends_with('\n') && !out.is_empty() {
+                    out.push('\n');
+                }
+                if self_closing && (name == "script" || name == "style") {
+                    skip_depth -= 1;
+                }
+            }
+            Token::End { name } => {
+                if name == "script" || name == "style" {
+                    skip_depth -= 1;
+                } else if BLOCK_ELEMENTS.contains(&name.as_str()) && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            Token::Text(text) if skip_depth == 0 => out.push_str(&text),
+            _ => {}
+        }
+    }
+    out.trim().to_string()
+}
+
+// Byte range of the element that starts at `tokens[start]`, including its
+// closing tag. Unclosed elements run to the end of the token stream.
+pub fn element_end(tokens: &[(Token, Range<usize>)], start: usize) -> usize {
+    let Token::Start {
+        name, self_closing, ..
+    } = &tokens[start].0
+    else {
+        return tokens[start].1.end;
+    };
+    if *self_closing {
+        return tokens[start].1.end;
+    }
+
+    let mut depth = 0;
+    for (token, span) in &tokens[start..] {
+        match token {
+            Token::Start {
+                name: n,
+                self_closing: false,
+                ..
+            } if n == name => depth += 1,
+            Token::End { name: n } if n == name => {
+                depth -= 1;
+                if depth == 0 {
+                    return span.end;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.last().map(|(_, span)| span.end).unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Heading {
+    pub id: String,
+    pub level: u8,
+    pub item_index: usize,
+    pub text_content: String,
+}
+
+// Mirrors the editor's table of contents: every heading in document order,
+// levels normalized so the outline never skips a level, and linear indexes.
+pub fn headings(html: &str) -> Vec<Heading> {
+    let tokens = tokenize(html);
+    let mut headings = Vec::new();
+    let mut previous_levels: Vec<(u8, u8)> = Vec::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let (token, span) = &tokens[i];
+        let raw_level = match token {
+            Token::Start { name, .. } if name.len() == 2 && name.starts_with('h') => {
+                name[1..].parse::<u8>().ok().filter(|l| (1..=6).contains(l))
+            }
+            _ => None,
+        };
+        let Some(raw_level) = raw_level else {
+            i += 1;
+            continue;
+        };
+
+        let end = element_end(&tokens, i);
+        let text = text_content(&html[span.end..end]);
+        let id = token
+            .attr("id")
+            .or_else(|| token.attr("blockid"))
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| format!("heading-{}", headings.len() + 1));
+
+        while previous_levels
+            .last()
+            .is_some_and(|(raw, _)| *raw >= raw_level)
+        {
+            previous_levels.pop();
+        }
+        let level = previous_levels.last().map(|(_, l)| l + 1).unwrap_or(1);
+        previous_levels.push((raw_level, level));
+
+        headings.push(Heading {
+            id,
+            level,
+            item_index: headings.len() + 1,
+            text_content: text,
+        });
+
+        while i < tokens.len() && tokens[i].1.end <= end {
+            i += 1;
+        }
+    }
+
+    headings
+}
+
+// Percent-encoding compatible with `encodeURIComponent`.
+pub fn encode_uri_component(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'!'
+            | b'~'
+            | b'*'
+            | b'\''
+            | b'('
+            | b')' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+pub fn decode_uri_component(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            out.push(u8::from_str_radix(&text[i + 1..i + 3], 16).unwrap_or(b'%'));
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(name: &str, attrs: &[(&str, &str)], self_closing: bool) -> Token {
+        Token::Start {
+            name: name.to_string(),
+            attrs: attrs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            self_closing,
+        }
+    }
+
+    fn tokens(html: &str) -> Vec<Token> {
+        tokenize(html).into_iter().map(|(token, _)| token).collect()
+    }
+
+    #[test]
+    fn tokenizes_tags_attributes_and_text() {
+        assert_eq!(
+            tokens("<P Class=\"a\" data-x='1 2' hidden checked=yes>Hi &amp; bye</p><br/>"),
+            [
+                start("p", &[("class", "a"), ("data-x", "1 2"), ("hidden", ""), ("checked", "yes")], false),
+                Token::Text("Hi & bye".to_string()),
+                Token::End { name: "p".to_string() },
+                start("br", &[], true),
+            ]
+        );
+        // Void elements close themselves without a slash
+        assert_eq!(tokens("<img src=a.png>"), [start("img", &[("src", "a.png")], true)]);
+    }
+
+    #[test]
+    fn spans_cover_the_source() {
+        let html = "a<b>c</b>";
+        let spans: Vec<&str> = tokenize(html).into_iter().map(|(_, span)| &html[span]).collect();
+        assert_eq!(spans, ["a", "<b>", "c", "</b>"]);
+    }
+
+    #[test]
+    fn keeps_stray_brackets_and_raw_text_as_text() {
+        assert_eq!(tokens("1 < 2 <3"), [Token::Text("1 < 2 <3".to_string())]);
+        assert_eq!(
+            tokens("<script>if (a<b) x = '</p>'</script>"),
+            [
+                start("script", &[], false),
+                Token::Text("if (a<b) x = '</p>'".to_string()),
+                Token::End { name: "script".to_string() },
+            ]
+        );
+        assert_eq!(
+            tokens("<!-- note --><!DOCTYPE html>"),
+            [Token::Comment(" note ".to_string()), Token::Comment("DOCTYPE html".to_string())]
+        );
+    }
+
+    #[test]
+    fn unescapes_named_and_numeric_entities() {
+        assert_eq!(unescape("&lt;a&gt; &quot;b&quot; &apos;c&#39; &#x41;&#66;"), "<a> \"b\" 'c' AB");
+        assert_eq!(unescape("&nbsp;"), "\u{a0}");
+        // Unknown or unterminated entities stay as they are
+        assert_eq!(unescape("&copy; & &amp &#xZZ;"), "&copy; & &amp &#xZZ;");
+        assert_eq!(unescape(&escape("<a href=\"x\">'&'</a>")), "<a href=\"x\">'&'</a>");
+    }
+
+    #[test]
+    fn parses_and_renders_trees() {
+        // Unclosed elements end with their parent, or the input
+        let nodes = parse("<ul><li>One<li>Two</ul><p>Open");
+        assert_eq!(render(&nodes), "<ul><li>One<li>Two</li></li></ul><p>Open</p>");
+        assert_eq!(text_content("<h1>Title</h1><p>a <b>b</b></p><script>x</script>"), "Title\na b");
+    }
+}
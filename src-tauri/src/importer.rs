@@ -0,0 +1,389 @@
+use crate::db::{Leaf, SqlDatabase, TimeStamped};
+use crate::filesystem::{asset_url, Database};
+use crate::markdown;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use uuid::Uuid;
+
+const BATCH_SIZE: usize = 25;
+
+// A file from the import source, addressed by its `/`-separated path relative
+// to the root of the directory or archive.
+pub struct SourceFile {
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedFile {
+    pub path: String,
+    pub leaf_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedFile {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: Vec<ImportedFile>,
+    pub failed: Vec<FailedFile>,
+}
+
+// A converted note waiting to be written.
+pub struct PendingLeaf {
+    pub path: String,
+    pub leaf: Leaf,
+    pub tags: Vec<String>,
+}
+
+// Reads every file below a directory, or every entry of a `.zip` archive.
+pub fn read_source(path: &Path) -> io::Result<Vec<SourceFile>> {
+    if path.is_dir() {
+        let mut files = Vec::new();
+        read_dir_recursive(path, path, &mut files)?;
+        return Ok(files);
+    }
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut files = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if entry.is_dir() {
+            continue;
+        }
+        // Skip entries that would escape the archive root
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let path = name.to_string_lossy().replace('\\', "/");
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        files.push(SourceFile { path, data });
+    }
+    Ok(files)
+}
+
+fn read_dir_recursive(root: &Path, dir: &Path, files: &mut Vec<SourceFile>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Hidden files and folders hold app state (.obsidian, .trash, .git)
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            read_dir_recursive(root, &path, files)?;
+        } else {
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.push(SourceFile {
+                path: relative,
+                data: fs::read(&path)?,
+            });
+        }
+    }
+    Ok(())
+}
+
+// -------------------------------------------------------
+
+pub fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+pub fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// Resolves `target` relative to the directory `base`, collapsing `.` and `..`.
+pub fn join_path(base: &str, target: &str) -> String {
+    let mut parts: Vec<&str> = if target.starts_with('/') {
+        Vec::new()
+    } else {
+        base.split('/').filter(|p| !p.is_empty()).collect()
+    };
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+// Looks files up the way note apps link them: by exact relative path first,
+// then by file name anywhere in the source (Obsidian's "shortest path").
+pub struct PathIndex {
+    by_path: HashMap<String, usize>,
+    by_name: HashMap<String, usize>,
+}
+
+impl PathIndex {
+    pub fn new<'a>(paths: impl Iterator<Item = &'a str>) -> Self {
+        let mut by_path = HashMap::new();
+        let mut by_name = HashMap::new();
+        for (i, path) in paths.enumerate() {
+            by_path.insert(path.to_lowercase(), i);
+            by_name.entry(file_name(path).to_lowercase()).or_insert(i);
+        }
+        Self { by_path, by_name }
+    }
+
+    pub fn resolve(&self, from_dir: &str, target: &str) -> Option<usize> {
+        let target = target.split(['#', '?']).next().unwrap_or(target);
+        let target = &crate::html::decode_uri_component(target);
+        self.by_path
+            .get(&join_path(from_dir, target).to_lowercase())
+            .or_else(|| self.by_path.get(&join_path("", target).to_lowercase()))
+            .or_else(|| self.by_name.get(&file_name(target).to_lowercase()))
+            .copied()
+    }
+}
+
+// Copies source files into the uploads store on first use and remembers the
+// resulting asset URL.
+pub struct Attachments<'a> {
+    db: &'a Database,
+    files: &'a [SourceFile],
+    uploaded: HashMap<usize, String>,
+}
+
+impl<'a> Attachments<'a> {
+    pub fn new(db: &'a Database, files: &'a [SourceFile]) -> Self {
+        Self {
+            db,
+            files,
+            uploaded: HashMap::new(),
+        }
+    }
+
+    pub fn upload(&mut self, index: usize) -> io::Result<String> {
+        if let Some(url) = self.uploaded.get(&index) {
+            return Ok(url.clone());
+        }
+        let file = &self.files[index];
+        let unique_name = format!(
+            "{}-{}",
+            &Uuid::new_v4().simple().to_string()[..8],
+            file_name(&file.path)
+        );
+        let path = self.db.upload_file(&unique_name, &file.data)?;
+        let url = asset_url(&path);
+        self.uploaded.insert(index, url.clone());
+        Ok(url)
+    }
+}
+
+async fn record_imported(
+    sql_db: &SqlDatabase,
+    path: String,
+    leaf_id: String,
+    tags: &[String],
+    report: &mut ImportReport,
+) {
+    if !tags.is_empty() {
+        if let Err(e) = sql_db.set_tags(&leaf_id, tags).await {
+            eprintln!("Failed to store tags for {}: {}", path, e);
+        }
+    }
+    report.imported.push(ImportedFile { path, leaf_id });
+}
+
+// Writes converted leaves in batched transactions and fills in the report.
+pub async fn write_leaves(
+    sql_db: &SqlDatabase,
+    pending: Vec<PendingLeaf>,
+    report: &mut ImportReport,
+) {
+    let mut pending = pending.into_iter().peekable();
+    while pending.peek().is_some() {
+        let batch: Vec<PendingLeaf> = pending.by_ref().take(BATCH_SIZE).collect();
+        let leaves = batch.iter().map(|item| item.leaf.clone()).collect();
+
+        match sql_db.create_batch(leaves).await {
+            Ok(ids) => {
+                for (item, leaf_id) in batch.into_iter().zip(ids) {
+                    record_imported(sql_db, item.path, leaf_id, &item.tags, report).await;
+                }
+            }
+            // A single bad file fails its whole batch, so write the batch
+            // again one leaf at a time to report only that file
+            Err(_) if batch.len() > 1 => {
+                for item in batch {
+                    match sql_db.create(item.leaf).await {
+                        Ok(leaf_id) => {
+                            record_imported(sql_db, item.path, leaf_id, &item.tags, report).await
+                        }
+                        Err(e) => report.failed.push(FailedFile {
+                            path: item.path,
+                            error: e.to_string(),
+                        }),
+                    }
+                }
+            }
+            Err(e) => {
+                for item in batch {
+                    report.failed.push(FailedFile {
+                        path: item.path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------
+
+// Imports a Markdown vault (Obsidian or plain folders of `.md` files).
+pub async fn import_markdown(
+    sql_db: &SqlDatabase,
+    db: &Database,
+    source: &Path,
+) -> io::Result<ImportReport> {
+    let files = read_source(source)?;
+    let index = PathIndex::new(files.iter().map(|f| f.path.as_str()));
+    let mut report = ImportReport::default();
+
+    let notes: Vec<usize> = (0..files.len())
+        .filter(|&i| markdown::is_note_link(&files[i].path))
+        .collect();
+
+    // Ids are assigned up front so links between notes resolve regardless of
+    // the order files are converted in.
+    let ids: HashMap<usize, String> = notes
+        .iter()
+        .map(|&i| (i, Uuid::new_v4().to_string()))
+        .collect();
+
+    let attachments = RefCell::new(Attachments::new(db, &files));
+    let mut pending = Vec::with_capacity(notes.len());
+
+    for &i in &notes {
+        let file = &files[i];
+        let Ok(text) = std::str::from_utf8(&file.data) else {
+            report.failed.push(FailedFile {
+                path: file.path.clone(),
+                error: "File is not valid UTF-8".to_string(),
+            });
+            continue;
+        };
+
+        let (front_matter, body) = markdown::split_front_matter(text);
+        let front_matter = front_matter
+            .map(markdown::parse_front_matter)
+            .unwrap_or_default();
+        let dir = parent_dir(&file.path);
+
+        let content = markdown::to_html(
+            &markdown::rewrite_wikilinks(body),
+            |dest| resolve_link(&index, &ids, &attachments, dir, dest),
+            |dest| resolve_attachment(&index, &attachments, dir, dest),
+        );
+
+        let name = front_matter
+            .title
+            .unwrap_or_else(|| markdown::title_from_path(&file.path));
+        let mut leaf = Leaf::new(ids[&i].clone(), name, content);
+        if let Some(created_at) = front_matter.created_at {
+            leaf.set_modified_at(front_matter.modified_at.unwrap_or(created_at.clone()));
+            leaf.set_created_at(created_at);
+        }
+
+        pending.push(PendingLeaf {
+            path: file.path.clone(),
+            leaf,
+            tags: front_matter.tags,
+        });
+    }
+
+    write_leaves(sql_db, pending, &mut report).await;
+    Ok(report)
+}
+
+// Note links become internal leaf links; links to other files in the source
+// point at their copy in the uploads store.
+pub fn resolve_link(
+    index: &PathIndex,
+    ids: &HashMap<usize, String>,
+    attachments: &RefCell<Attachments>,
+    dir: &str,
+    dest: &str,
+) -> Option<String> {
+    if dest.contains("://") || dest.starts_with('#') || dest.starts_with("mailto:") {
+        return None;
+    }
+    let target = index.resolve(dir, dest)?;
+    match ids.get(&target) {
+        Some(id) => {
+            let fragment = dest
+                .split_once('#')
+                .map(|(_, fragment)| format!("#{}", fragment))
+                .unwrap_or_default();
+            Some(format!("/leafs/{}{}", id, fragment))
+        }
+        None => resolve_attachment(index, attachments, dir, dest),
+    }
+}
+
+pub fn resolve_attachment(
+    index: &PathIndex,
+    attachments: &RefCell<Attachments>,
+    dir: &str,
+    dest: &str,
+) -> Option<String> {
+    if dest.contains("://") || dest.starts_with("data:") {
+        return None;
+    }
+    let target = index.resolve(dir, dest)?;
+    match attachments.borrow_mut().upload(target) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            eprintln!("Failed to copy attachment {}: {}", dest, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_relative_paths() {
+        assert_eq!(join_path("notes/daily", "../img/a.png"), "notes/img/a.png");
+        assert_eq!(join_path("notes", "./a.md"), "notes/a.md");
+        assert_eq!(join_path("notes", "/top.md"), "top.md");
+        assert_eq!(join_path("", "../../a.md"), "a.md");
+        assert_eq!((parent_dir("a/b/c.md"), file_name("a/b/c.md")), ("a/b", "c.md"));
+    }
+
+    #[test]
+    fn finds_files_by_path_then_by_name() {
+        let index = PathIndex::new(["notes/Plan.md", "img/Photo 1.png", "other/Plan.md"].into_iter());
+        assert_eq!(index.resolve("notes", "Plan.md#Goals"), Some(0));
+        assert_eq!(index.resolve("other", "plan.md"), Some(2));
+        assert_eq!(index.resolve("notes", "../img/Photo%201.png"), Some(1));
+        assert_eq!(index.resolve("notes", "Photo 1.png"), Some(1));
+        assert_eq!(index.resolve("notes", "missing.md"), None);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::db::{Leaf, SqlDatabase};
+use crate::html::{self, Node};
+use crate::markdown;
+use crate::settings::JournalSettings;
+use chrono::NaiveDate;
+use serde::Serialize;
+use sqlx::Error as SqlxError;
+use std::fmt::Write;
+
+// Daily notes, one leaf per date. Ids are derived from the date, so creating
+// the same day's note twice (a double-click, two windows, another device)
+// always ends up with the one leaf.
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalDay {
+    pub date: String,
+    pub leaf_id: String,
+    pub word_count: usize,
+}
+
+pub fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT)
+        .map_err(|_| format!("Invalid date {}; expected YYYY-MM-DD", date))
+}
+
+pub fn leaf_id(date: NaiveDate) -> String {
+    format!("journal-{}", date.format(DATE_FORMAT))
+}
+
+// The note's name from the `name_template` pattern. Time fields such as `%H`
+// can't be formatted from a date, and `to_string` would panic on them.
+pub fn title(date: NaiveDate, template: &str) -> Result<String, String> {
+    let mut title = String::new();
+    write!(title, "{}", date.format(template))
+        .map_err(|_| format!("\"{}\" is not a valid daily note name", template))?;
+    Ok(title)
+}
+
+fn word_count(content: &str) -> usize {
+    html::text_content(content).split_whitespace().count()
+}
+
+fn is_task_list(node: &Node) -> bool {
+    node.attr("data-type") == Some("taskList")
+}
+
+// Moves the unchecked items of Tiptap task lists, with any items nested
+// inside them, from `nodes` to `out`. Lists left empty are removed too.
+fn take_unchecked_tasks(nodes: Vec<Node>, out: &mut Vec<Node>) -> Vec<Node> {
+    let mut kept = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let is_task = node.name() == Some("li") && node.attr("data-type") == Some("taskItem");
+        if is_task {
+            if node.attr("data-checked") == Some("true") {
+                kept.push(node);
+            } else {
+                out.push(node);
+            }
+            continue;
+        }
+        match node {
+            Node::Element { name, attrs, children } => {
+                let had_children = !children.is_empty();
+                let children = take_unchecked_tasks(children, out);
+                let node = Node::Element { name, attrs, children };
+                if !(is_task_list(&node) && had_children && node.children().is_empty()) {
+                    kept.push(node);
+                }
+            }
+            text => kept.push(text),
+        }
+    }
+    kept
+}
+
+// The task list carried over to a new entry and what stays of `content`, or
+// `None` when there is nothing to carry.
+fn carry_over(content: &str) -> Option<(String, String)> {
+    let mut tasks = Vec::new();
+    let kept = take_unchecked_tasks(html::parse(content), &mut tasks);
+    if tasks.is_empty() {
+        return None;
+    }
+    let carried = format!("<ul data-type=\"taskList\">{}</ul>", html::render(&tasks));
+    Some((carried, html::render(&kept)))
+}
+
+// The new entry, and the previous one less the tasks carried over from it.
+async fn new_note(
+    db: &SqlDatabase,
+    settings: &JournalSettings,
+    date: NaiveDate,
+) -> Result<(Leaf, Option<Leaf>), SqlxError> {
+    let day = date.format(DATE_FORMAT).to_string();
+    let title = title(date, &settings.name_template).map_err(SqlxError::Protocol)?;
+    let template = settings
+        .template
+        .replace("{{date}}", &day)
+        .replace("{{title}}", &title);
+    let mut content = markdown::to_html(&template, |_| None, |_| None);
+
+    // Unfinished tasks move over from the latest earlier entry, which is
+    // the previous day unless days were skipped. Moving rather than copying
+    // keeps each task listed once.
+    let mut previous = None;
+    if settings.carry_over_tasks {
+        if let Some((_, previous_id)) = db.previous_journal_entry(&day).await? {
+            if let Some(leaf) = db.read::<Leaf>(&previous_id).await? {
+                if let Some((carried, kept)) = carry_over(leaf.content()) {
+                    content.push_str(&carried);
+                    previous = Some(Leaf::new(previous_id, leaf.name().to_string(), kept));
+                }
+            }
+        }
+    }
+    Ok((Leaf::new(leaf_id(date), title, content), previous))
+}
+
+pub async fn get_or_create_daily_note(
+    db: &SqlDatabase,
+    settings: &JournalSettings,
+    date: NaiveDate,
+) -> Result<Leaf, SqlxError> {
+    let day = date.format(DATE_FORMAT).to_string();
+    let id = db.journal_entry(&day).await?.unwrap_or_else(|| leaf_id(date));
+    // The leaf may exist without its entry, e.g. when it came from another
+    // device through sync
+    if let Some(leaf) = db.read::<Leaf>(&id).await? {
+        db.add_journal_entry(&day, &id).await?;
+        return Ok(leaf);
+    }
+
+    let id = leaf_id(date);
+    let (note, previous) = new_note(db, settings, date).await?;
+    match db.create(note).await {
+        // Tasks only leave the previous entry once they are in the new one
+        Ok(_) => {
+            if let Some(previous) = previous {
+                db.update(previous).await?;
+            }
+        }
+        // Another call created it first, and moved the tasks
+        Err(SqlxError::Database(e)) if e.is_unique_violation() => {}
+        Err(e) => return Err(e),
+    }
+    db.add_journal_entry(&day, &id).await?;
+    db.read::<Leaf>(&id).await?.ok_or(SqlxError::RowNotFound)
+}
+
+// Dates from `from` to `to` inclusive that have an entry.
+pub async fn calendar(
+    db: &SqlDatabase,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<JournalDay>, SqlxError> {
+    let entries = db
+        .journal_entries(
+            &from.format(DATE_FORMAT).to_string(),
+            &to.format(DATE_FORMAT).to_string(),
+        )
+        .await?;
+    Ok(entries
+        .into_iter()
+        .map(|(date, leaf)| JournalDay {
+            date,
+            leaf_id: leaf.id().to_string(),
+            word_count: word_count(leaf.content()),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_refuse_time_fields_instead_of_panicking() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 9).unwrap();
+        assert_eq!(title(date, "%A, %B %-d").unwrap(), "Saturday, March 9");
+        assert!(title(date, "%Y-%m-%d %H:%M").is_err());
+    }
+
+    fn item(checked: bool, text: &str, nested: &str) -> String {
+        format!(
+            "<li data-type=\"taskItem\" data-checked=\"{}\"><p>{}</p>{}</li>",
+            checked, text, nested
+        )
+    }
+
+    fn list(items: &[String]) -> String {
+        format!("<ul data-type=\"taskList\">{}</ul>", items.concat())
+    }
+
+    #[test]
+    fn carry_over_moves_unchecked_tasks() {
+        let nested = list(&[item(true, "Nested", "")]);
+        let content = format!(
+            "<p>Notes</p>{}<blockquote>{}</blockquote>",
+            list(&[item(true, "Done", ""), item(false, "Open", &nested)]),
+            list(&[item(false, "Quoted", "")])
+        );
+        let (carried, kept) = carry_over(&content).unwrap();
+        assert_eq!(
+            carried,
+            list(&[item(false, "Open", &nested), item(false, "Quoted", "")])
+        );
+        assert_eq!(
+            kept,
+            format!("<p>Notes</p>{}<blockquote></blockquote>", list(&[item(true, "Done", "")]))
+        );
+        assert!(carry_over(&kept).is_none());
+    }
+}
@@ -1,13 +1,64 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+pub mod api;
+pub mod backup;
+pub mod blocks;
+pub mod collab;
+pub mod crdt;
+pub mod crypto;
 pub mod db;
+pub mod deeplink;
+pub mod events;
 pub mod filesystem;
+pub mod headless;
+pub mod html;
+pub mod importer;
+pub mod journal;
+pub mod markdown;
+pub mod mcp;
+pub mod mirror;
+pub mod notion;
 pub mod ollama;
+pub mod publish;
+pub mod reminders;
+pub mod sanitize;
+pub mod secrets;
+pub mod settings;
+pub mod stats;
+pub mod sync;
+pub mod tasks;
+pub mod templates;
+pub mod windows;
+pub mod workspace;
 
-use filesystem::{Config, Database, Leaf, Sage};
-use tauri::Manager;
-use db::{SqlDatabase, Leaf as SqlLeaf, Sage as SqlSage};
+use api::{ApiServer, ApiStatus};
+use backup::BackupManifest;
+use blocks::{Block, BlockReference};
+use collab::{CollabServer, CollabStatus, SharedLeaf};
+use crdt::{LeafDocSession, LeafDocUpdate, LeafDocs};
+use deeplink::{DeepLink, PendingLinks};
+use events::{ChangeFilters, EntityKind, Replay};
+use filesystem::{Database, Leaf, Sage};
+use importer::ImportReport;
+use journal::JournalDay;
+use mirror::{MarkdownMirror, SyncSummary};
+use reminders::{Reminder, ReminderScheduler};
+use secrets::{SecretBackend, SecretInfo, SecretStore, SecretStoreStatus, SecretTest};
+use settings::{BackupSchedule, LegacyConfig, Settings, SettingsStore};
+use std::path::{Path, PathBuf};
+use stats::{LeafStats, WritingActivity};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use sync::{SyncEngine, SyncReport};
+use tauri::{Emitter, Manager};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use tauri_plugin_deep_link::DeepLinkExt;
+use tasks::{Task, TaskFilter};
+use templates::TemplateInfo;
+use windows::{LeafLock, LeafWindows};
+use workspace::{WorkspaceInfo, WorkspaceList, Workspaces};
+use db::{DatabaseStatus, SqlDatabase, Leaf as SqlLeaf, Sage as SqlSage, Template, TrashedLeaf};
 
 
 // -------------------------------------------------------
@@ -27,6 +78,10 @@ async fn sql_create_entity(
             let sage: SqlSage = serde_json::from_value(entity).map_err(|e| e.to_string())?;
             db.create::<SqlSage>(sage).await.map_err(|e| e.to_string())
         },
+        "template" => {
+            let template: Template = serde_json::from_value(entity).map_err(|e| e.to_string())?;
+            db.create::<Template>(template).await.map_err(|e| e.to_string())
+        },
         _ => Err("Invalid entity type".to_string())
     }
 }
@@ -46,6 +101,10 @@ async fn sql_read_entity(
             let result = db.read::<SqlSage>(id).await.map_err(|e| e.to_string())?;
             Ok(result.map(|sage| serde_json::to_value(sage).unwrap()))
         },
+        "template" => {
+            let result = db.read::<Template>(id).await.map_err(|e| e.to_string())?;
+            Ok(result.map(|template| serde_json::to_value(template).unwrap()))
+        },
         _ => Err("Invalid entity type".to_string())
     }
 }
@@ -65,6 +124,10 @@ async fn sql_update_entity(
             let sage: SqlSage = serde_json::from_value(entity).map_err(|e| e.to_string())?;
             db.update::<SqlSage>(sage).await.map_err(|e| e.to_string())
         },
+        "template" => {
+            let template: Template = serde_json::from_value(entity).map_err(|e| e.to_string())?;
+            db.update::<Template>(template).await.map_err(|e| e.to_string())
+        },
         _ => Err("Invalid entity type".to_string())
     }
 }
@@ -83,6 +146,10 @@ async fn sql_list_entities(
             let sages = db.list::<SqlSage>().await.map_err(|e| e.to_string())?;
             Ok(sages.into_iter().map(|sage| serde_json::to_value(sage).unwrap()).collect())
         },
+        "template" => {
+            let templates = db.list::<Template>().await.map_err(|e| e.to_string())?;
+            Ok(templates.into_iter().map(|template| serde_json::to_value(template).unwrap()).collect())
+        },
         _ => Err("Invalid entity type".to_string())
     }
 }
@@ -96,10 +163,443 @@ async fn sql_delete_entity(
     match entity_type {
         "leaf" => db.delete::<SqlLeaf>(id).await.map_err(|e| e.to_string()),
         "sage" => db.delete::<SqlSage>(id).await.map_err(|e| e.to_string()),
+        "template" => db.delete::<Template>(id).await.map_err(|e| e.to_string()),
         _ => Err("Invalid entity type".to_string())
     }
 }
 
+#[tauri::command]
+async fn get_leaf_children(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: &str,
+) -> Result<Vec<String>, String> {
+    db.list_children(leaf_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_leaf_tags(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: &str,
+) -> Result<Vec<String>, String> {
+    db.list_tags(leaf_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_trash(db: tauri::State<'_, SqlDatabase>) -> Result<Vec<TrashedLeaf>, String> {
+    db.list_trash().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore_leaf(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: &str,
+) -> Result<(), String> {
+    match db.restore_leaf(leaf_id).await.map_err(|e| e.to_string())? {
+        // Its reminders came back with it
+        true => {
+            reminders::wake(&app);
+            Ok(())
+        }
+        false => Err(format!("Leaf {} is not in the trash", leaf_id)),
+    }
+}
+
+// -------------------------------------------------------
+
+// Collaborative editing: each editor window opens the leaf's document, sends
+// its changes as Yjs updates and applies the ones other windows make.
+#[tauri::command]
+async fn open_leaf_doc(
+    db: tauri::State<'_, SqlDatabase>,
+    docs: tauri::State<'_, LeafDocs>,
+    id: String,
+) -> Result<LeafDocSession, String> {
+    docs.open(&db, &id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn apply_leaf_doc_update(
+    window: tauri::Window,
+    db: tauri::State<'_, SqlDatabase>,
+    docs: tauri::State<'_, LeafDocs>,
+    collab: tauri::State<'_, CollabServer>,
+    id: String,
+    generation: u64,
+    update: Vec<u8>,
+) -> Result<(), String> {
+    let reset = docs
+        .apply_update(&db, &id, generation, &update)
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(reset) = reset {
+        crdt::announce_reset(window.app_handle(), reset).await;
+        return Ok(());
+    }
+    collab.relay(&id, generation, &update);
+    let event = LeafDocUpdate {
+        leaf_id: id,
+        update,
+        origin: Some(window.label().to_string()),
+        generation,
+    };
+    window.emit(crdt::UPDATE_EVENT, event).map_err(|e| e.to_string())
+}
+
+// Picks up the leaf's document again after a `leaf-doc-reset` event.
+#[tauri::command]
+async fn reload_leaf_doc(
+    docs: tauri::State<'_, LeafDocs>,
+    id: String,
+) -> Result<LeafDocSession, String> {
+    docs.reload(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_leaf_doc_state_vector(
+    docs: tauri::State<'_, LeafDocs>,
+    id: String,
+) -> Result<Vec<u8>, String> {
+    docs.state_vector(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_leaf_doc_diff(
+    docs: tauri::State<'_, LeafDocs>,
+    id: String,
+    state_vector: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    docs.diff(&id, &state_vector).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn close_leaf_doc(
+    db: tauri::State<'_, SqlDatabase>,
+    docs: tauri::State<'_, LeafDocs>,
+    id: String,
+) -> Result<(), String> {
+    docs.close(&db, &id).await.map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+async fn start_collab_server(
+    app: tauri::AppHandle,
+    collab: tauri::State<'_, CollabServer>,
+    port: Option<u16>,
+) -> Result<CollabStatus, String> {
+    collab
+        .start(app.clone(), port.unwrap_or(collab::DEFAULT_PORT))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_collab_server(collab: tauri::State<'_, CollabServer>) -> Result<(), String> {
+    collab.stop().await;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_collab_status(collab: tauri::State<CollabServer>) -> CollabStatus {
+    collab.status()
+}
+
+#[tauri::command]
+async fn set_leaf_shared(
+    db: tauri::State<'_, SqlDatabase>,
+    collab: tauri::State<'_, CollabServer>,
+    id: String,
+    shared: bool,
+) -> Result<(), String> {
+    db.set_shared(&id, shared).await.map_err(|e| e.to_string())?;
+    collab.shares_changed(&db).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn list_shared_leaves(
+    db: tauri::State<'_, SqlDatabase>,
+) -> Result<Vec<SharedLeaf>, String> {
+    let shared = db.list_shared().await.map_err(|e| e.to_string())?;
+    Ok(shared
+        .into_iter()
+        .map(|(id, name)| SharedLeaf { id, name })
+        .collect())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+async fn import_markdown(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    db: tauri::State<'_, Database>,
+    path: String,
+) -> Result<ImportReport, String> {
+    importer::import_markdown(&sql_db, &db, Path::new(&path))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_notion(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    db: tauri::State<'_, Database>,
+    path: String,
+) -> Result<ImportReport, String> {
+    notion::import_notion(&sql_db, &db, Path::new(&path))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+async fn publish_leaves(
+    db: tauri::State<'_, SqlDatabase>,
+    files: tauri::State<'_, Database>,
+    leaf_ids: Vec<String>,
+    format: &str,
+    path: String,
+    title: Option<String>,
+    author: Option<String>,
+) -> Result<(), String> {
+    let mut leaves = Vec::with_capacity(leaf_ids.len());
+    for id in &leaf_ids {
+        match db.read::<SqlLeaf>(id).await.map_err(|e| e.to_string())? {
+            Some(leaf) => leaves.push(leaf),
+            None => return Err(format!("Leaf {} not found", id)),
+        }
+    }
+    let title = title
+        .or_else(|| leaves.first().map(|leaf| leaf.name().to_string()))
+        .unwrap_or_default();
+
+    let uploads = files.root_dir().join("uploads");
+    match format {
+        "html" => publish::publish_html(&leaves, &title, &uploads, Path::new(&path)),
+        "epub" => publish::publish_epub(
+            &leaves,
+            &title,
+            author.as_deref().unwrap_or_default(),
+            &uploads,
+            Path::new(&path),
+        ),
+        _ => return Err("Invalid publish format".to_string()),
+    }
+    .map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+async fn start_markdown_mirror(
+    app: tauri::AppHandle,
+    mirror: tauri::State<'_, MarkdownMirror>,
+    path: String,
+) -> Result<(), String> {
+    let dir = PathBuf::from(path);
+    mirror
+        .start(app.clone(), dir.clone())
+        .map_err(|e| e.to_string())?;
+    change_settings(&app, serde_json::json!({ "mirrorDir": dir })).await?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn stop_markdown_mirror(
+    app: tauri::AppHandle,
+    mirror: tauri::State<'_, MarkdownMirror>,
+) -> Result<(), String> {
+    mirror.stop();
+    change_settings(&app, serde_json::json!({ "mirrorDir": null })).await?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn sync_markdown_mirror(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    mirror: tauri::State<'_, MarkdownMirror>,
+) -> Result<SyncSummary, String> {
+    let dir = mirror
+        .dir()
+        .ok_or_else(|| "Markdown mirror is not running".to_string())?;
+    mirror.sync(&sql_db, &dir).await.map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+async fn start_sync(
+    app: tauri::AppHandle,
+    engine: tauri::State<'_, SyncEngine>,
+    path: String,
+) -> Result<(), String> {
+    let dir = PathBuf::from(path);
+    engine
+        .start(app.clone(), dir.clone())
+        .map_err(|e| e.to_string())?;
+    change_settings(&app, serde_json::json!({ "syncDir": dir })).await?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn stop_sync(app: tauri::AppHandle, engine: tauri::State<'_, SyncEngine>) -> Result<(), String> {
+    engine.stop();
+    change_settings(&app, serde_json::json!({ "syncDir": null })).await?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn sync_now(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    db: tauri::State<'_, Database>,
+    engine: tauri::State<'_, SyncEngine>,
+) -> Result<SyncReport, String> {
+    let dir = engine
+        .dir()
+        .ok_or_else(|| "Sync is not set up".to_string())?;
+    engine
+        .sync(&sql_db, &db.root_dir(), &dir)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+async fn create_backup(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    db: tauri::State<'_, Database>,
+    settings: tauri::State<'_, SettingsStore>,
+    path: String,
+    passphrase: Option<String>,
+) -> Result<BackupManifest, String> {
+    let schedule = settings.get().backup;
+    backup::create_backup(&sql_db, &db, &schedule, Path::new(&path), passphrase.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn verify_backup(
+    path: String,
+    passphrase: Option<String>,
+) -> Result<BackupManifest, String> {
+    backup::verify_backup(Path::new(&path), passphrase.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore_backup(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    db: tauri::State<'_, Database>,
+    settings: tauri::State<'_, SettingsStore>,
+    path: String,
+    passphrase: Option<String>,
+) -> Result<BackupManifest, String> {
+    let schedule = settings.get().backup;
+    backup::restore_backup(&sql_db, &db, &schedule, Path::new(&path), passphrase.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_backup_schedule(settings: tauri::State<SettingsStore>) -> BackupSchedule {
+    settings.get().backup
+}
+
+#[tauri::command]
+async fn set_backup_schedule(app: tauri::AppHandle, schedule: BackupSchedule) -> Result<(), String> {
+    change_settings(&app, serde_json::json!({ "backup": schedule })).await?;
+    Ok(())
+}
+
+// -------------------------------------------------------
+
+// Makes an opened database available to commands and starts the background
+// work that depends on it.
+async fn open_sql_database(app: &tauri::AppHandle, sql_db: SqlDatabase) {
+    app.manage(sql_db);
+    if let Err(e) = apply_settings(app, &app.state::<SettingsStore>().get()).await {
+        eprintln!("{}", e);
+    }
+    restore_leaf_windows(app);
+    reminders::wake(app);
+}
+
+fn restore_leaf_windows(app: &tauri::AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let db = app.state::<SqlDatabase>();
+        app.state::<LeafWindows>().restore(&app, &db).await;
+    });
+}
+
+#[tauri::command]
+fn get_database_status(app: tauri::AppHandle) -> Result<DatabaseStatus, String> {
+    let root_dir = app.state::<Workspaces>().active().path;
+    Ok(match app.try_state::<SqlDatabase>() {
+        Some(sql_db) => DatabaseStatus {
+            encrypted: sql_db.uses_encryption(),
+            unlocked: true,
+        },
+        None => DatabaseStatus {
+            encrypted: SqlDatabase::is_encrypted(&root_dir),
+            unlocked: false,
+        },
+    })
+}
+
+#[tauri::command]
+async fn unlock_database(app: tauri::AppHandle, passphrase: String) -> Result<(), String> {
+    if app.try_state::<SqlDatabase>().is_some() {
+        return Ok(());
+    }
+    let root_dir = app.state::<Workspaces>().active().path;
+    let sql_db = SqlDatabase::open(root_dir, Some(passphrase))
+        .await
+        .map_err(|e| e.to_string())?;
+    open_sql_database(&app, sql_db).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn enable_database_encryption(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    passphrase: String,
+) -> Result<(), String> {
+    sql_db
+        .enable_encryption(&passphrase)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn change_database_passphrase(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    current_passphrase: String,
+    new_passphrase: String,
+) -> Result<(), String> {
+    sql_db
+        .change_key(&current_passphrase, &new_passphrase)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_plaintext_database(
+    sql_db: tauri::State<'_, SqlDatabase>,
+    path: String,
+) -> Result<(), String> {
+    sql_db
+        .export_plaintext(Path::new(&path))
+        .await
+        .map_err(|e| e.to_string())
+}
+
 // -------------------------------------------------------
 
 #[tauri::command]
@@ -193,14 +693,709 @@ fn get_file(db: tauri::State<Database>, file_name: String) -> Result<Vec<u8>, St
 
 // -------------------------------------------------------
 
+// Opens `workspace` in place of the current one. Everything is loaded before
+// managed state is touched, so a failure leaves the current workspace open.
+async fn activate_workspace(
+    app: &tauri::AppHandle,
+    workspace: WorkspaceInfo,
+    passphrase: Option<String>,
+) -> Result<WorkspaceInfo, String> {
+    let workspaces = app.state::<Workspaces>();
+    let _switching = workspaces.begin_switch().await;
+
+    let key = if SqlDatabase::is_encrypted(&workspace.path) {
+        Some(passphrase.ok_or("This workspace is encrypted; enter its passphrase to open it")?)
+    } else {
+        None
+    };
+    let sql_db = SqlDatabase::open(workspace.path.clone(), key)
+        .await
+        .map_err(|e| e.to_string())?;
+    let settings = SettingsStore::load(&workspace.path.join("config"), &app.state::<SecretStore>())
+        .map_err(|e| e.to_string())?;
+
+    app.state::<MarkdownMirror>().stop();
+    app.state::<SyncEngine>().stop();
+    app.state::<CollabServer>().stop().await;
+    // Leaf windows belong to the workspace being left
+    app.state::<LeafWindows>().close_all(app);
+    app.state::<Database>()
+        .set_root_dir(workspace.path.clone())
+        .map_err(|e| e.to_string())?;
+    app.state::<SettingsStore>().replace_with(settings);
+    workspaces
+        .set_active(&workspace.id)
+        .map_err(|e| e.to_string())?;
+    match app.try_state::<SqlDatabase>() {
+        Some(current) => {
+            current.replace_with(sql_db).await;
+            if let Err(e) = apply_settings(app, &app.state::<SettingsStore>().get()).await {
+                eprintln!("{}", e);
+            }
+            restore_leaf_windows(app);
+            reminders::wake(app);
+        }
+        None => open_sql_database(app, sql_db).await,
+    }
+
+    app.emit(settings::CHANGED_EVENT, &app.state::<SettingsStore>().get())
+        .map_err(|e| e.to_string())?;
+    app.emit(workspace::CHANGED_EVENT, &workspace)
+        .map_err(|e| e.to_string())?;
+    Ok(workspace)
+}
+
+#[tauri::command]
+fn list_workspaces(workspaces: tauri::State<Workspaces>) -> WorkspaceList {
+    workspaces.list()
+}
+
+#[tauri::command]
+async fn create_workspace(
+    app: tauri::AppHandle,
+    name: String,
+    path: Option<String>,
+) -> Result<WorkspaceInfo, String> {
+    let workspace = app
+        .state::<Workspaces>()
+        .create(&name, path.map(PathBuf::from))
+        .map_err(|e| e.to_string())?;
+    activate_workspace(&app, workspace, None).await
+}
+
 #[tauri::command]
-fn get_config(db: tauri::State<Database>) -> Result<Config, String> {
-    db.get_config().map_err(|e| e.to_string())
+async fn open_workspace(
+    app: tauri::AppHandle,
+    path: String,
+    name: Option<String>,
+    passphrase: Option<String>,
+) -> Result<WorkspaceInfo, String> {
+    let workspace = app
+        .state::<Workspaces>()
+        .add_existing(Path::new(&path), name.as_deref())
+        .map_err(|e| e.to_string())?;
+    activate_workspace(&app, workspace, passphrase).await
 }
 
 #[tauri::command]
-fn set_config(db: tauri::State<Database>, config: Config) -> Result<(), String> {
-    db.set_config(&config).map_err(|e| e.to_string())
+async fn switch_workspace(
+    app: tauri::AppHandle,
+    id: String,
+    passphrase: Option<String>,
+) -> Result<WorkspaceInfo, String> {
+    let workspaces = app.state::<Workspaces>();
+    let workspace = workspaces.get(&id).map_err(|e| e.to_string())?;
+    if workspaces.active().id == id {
+        return Ok(workspace);
+    }
+    activate_workspace(&app, workspace, passphrase).await
+}
+
+#[tauri::command]
+fn rename_workspace(
+    app: tauri::AppHandle,
+    workspaces: tauri::State<Workspaces>,
+    id: String,
+    name: String,
+) -> Result<WorkspaceInfo, String> {
+    let workspace = workspaces.rename(&id, &name).map_err(|e| e.to_string())?;
+    if workspaces.active().id == id {
+        app.emit(workspace::CHANGED_EVENT, &workspace)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(workspace)
+}
+
+#[tauri::command]
+fn close_workspace(workspaces: tauri::State<Workspaces>, id: String) -> Result<(), String> {
+    workspaces.remove(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_workspace_data_dir(
+    workspaces: tauri::State<Workspaces>,
+    path: Option<String>,
+) -> Result<(), String> {
+    workspaces
+        .set_data_dir(path.map(PathBuf::from))
+        .map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+// Points embedding calls at the chosen AI provider. The OpenAI key is only
+// readable while the secret store is unlocked.
+fn configure_ai(app: &tauri::AppHandle, settings: &Settings) {
+    let key = app
+        .state::<SecretStore>()
+        .get(secrets::OPENAI_API_KEY)
+        .ok()
+        .flatten();
+    ollama::configure(&settings.ai, key);
+}
+
+// Brings running services in line with the settings.
+async fn apply_settings(app: &tauri::AppHandle, settings: &Settings) -> Result<(), String> {
+    configure_ai(app, settings);
+
+    // The mirror and sync need the database, which may still be locked
+    if app.try_state::<SqlDatabase>().is_some() {
+        let mirror = app.state::<MarkdownMirror>();
+        if mirror.dir() != settings.mirror_dir {
+            match &settings.mirror_dir {
+                Some(dir) => mirror
+                    .start(app.clone(), dir.clone())
+                    .map_err(|e| format!("Failed to start Markdown mirror: {}", e))?,
+                None => mirror.stop(),
+            }
+        }
+        let engine = app.state::<SyncEngine>();
+        if engine.dir() != settings.sync_dir {
+            match &settings.sync_dir {
+                Some(dir) => engine
+                    .start(app.clone(), dir.clone())
+                    .map_err(|e| format!("Failed to start sync: {}", e))?,
+                None => engine.stop(),
+            }
+        }
+    }
+
+    let api = app.state::<ApiServer>();
+    if !settings.api.enabled {
+        api.stop().await;
+    } else if api.port() != Some(settings.api.port) {
+        api.start(app.clone(), settings.api.port, api_token(app)?)
+            .await
+            .map_err(|e| format!("Failed to start HTTP API: {}", e))?;
+    }
+    Ok(())
+}
+
+// The HTTP API token, created the first time the API is enabled.
+fn api_token(app: &tauri::AppHandle) -> Result<String, String> {
+    let secrets = app.state::<SecretStore>();
+    if let Some(token) = secrets.get(secrets::API_TOKEN).map_err(|e| e.to_string())? {
+        return Ok(token);
+    }
+    let token = api::generate_token();
+    secrets
+        .set(secrets::API_TOKEN, &token)
+        .map_err(|e| e.to_string())?;
+    Ok(token)
+}
+
+// Saves a settings change, applies it and tells every window about it.
+async fn change_settings(
+    app: &tauri::AppHandle,
+    changes: serde_json::Value,
+) -> Result<Settings, String> {
+    let settings = app
+        .state::<SettingsStore>()
+        .update(changes)
+        .map_err(|e| e.to_string())?;
+    app.emit(settings::CHANGED_EVENT, &settings)
+        .map_err(|e| e.to_string())?;
+    apply_settings(app, &settings).await?;
+    Ok(settings)
+}
+
+#[tauri::command]
+fn get_settings(settings: tauri::State<SettingsStore>) -> Settings {
+    settings.get()
+}
+
+#[tauri::command]
+async fn update_settings(
+    app: tauri::AppHandle,
+    changes: serde_json::Value,
+) -> Result<Settings, String> {
+    change_settings(&app, changes).await
+}
+
+#[tauri::command]
+async fn reset_settings(app: tauri::AppHandle) -> Result<Settings, String> {
+    let settings = app
+        .state::<SettingsStore>()
+        .reset()
+        .map_err(|e| e.to_string())?;
+    app.emit(settings::CHANGED_EVENT, &settings)
+        .map_err(|e| e.to_string())?;
+    apply_settings(&app, &settings).await?;
+    Ok(settings)
+}
+
+// The preferences page still reads and writes the original config shape
+#[tauri::command]
+fn get_config(
+    settings: tauri::State<SettingsStore>,
+    secrets: tauri::State<SecretStore>,
+) -> Result<LegacyConfig, String> {
+    let masked = secrets
+        .masked(secrets::OPENAI_API_KEY)
+        .map_err(|e| e.to_string())?;
+    Ok(LegacyConfig {
+        openai_api_key: masked.unwrap_or_default(),
+        theme: settings.get().theme,
+    })
+}
+
+#[tauri::command]
+async fn set_config(
+    app: tauri::AppHandle,
+    secrets: tauri::State<'_, SecretStore>,
+    config: LegacyConfig,
+) -> Result<(), String> {
+    // The page shows the masked key back; an unchanged mask keeps the key
+    // and an emptied field removes it
+    if config.openai_api_key.is_empty() {
+        secrets
+            .delete(secrets::OPENAI_API_KEY)
+            .map_err(|e| e.to_string())?;
+    } else if !secrets::is_masked(&config.openai_api_key) {
+        secrets
+            .set(secrets::OPENAI_API_KEY, &config.openai_api_key)
+            .map_err(|e| e.to_string())?;
+    }
+    change_settings(&app, serde_json::json!({ "theme": config.theme })).await?;
+    Ok(())
+}
+
+// Editor AI replies, so the OpenAI key stays in the backend
+#[tauri::command]
+async fn ai_chat(messages: Vec<ollama::ChatMessage>) -> Result<String, String> {
+    ollama::chat(&messages).await.map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+fn get_api_status(
+    api: tauri::State<ApiServer>,
+    secrets: tauri::State<SecretStore>,
+) -> Result<ApiStatus, String> {
+    let port = api.port();
+    Ok(ApiStatus {
+        running: port.is_some(),
+        url: port.map(|port| format!("http://127.0.0.1:{}/v1", port)),
+        token: secrets
+            .masked(secrets::API_TOKEN)
+            .map_err(|e| e.to_string())?,
+    })
+}
+
+// Replaces the token, invalidating the old one. The new token is returned
+// unmasked this one time so it can be copied into scripts. It is only saved
+// once the server runs with it, and the server stops if it can't be saved.
+#[tauri::command]
+async fn regenerate_api_token(
+    app: tauri::AppHandle,
+    api: tauri::State<'_, ApiServer>,
+    secrets: tauri::State<'_, SecretStore>,
+) -> Result<String, String> {
+    let token = api::generate_token();
+    if let Some(port) = api.port() {
+        api.start(app.clone(), port, token.clone())
+            .await
+            .map_err(|e| format!("Failed to restart HTTP API: {}", e))?;
+    }
+    if let Err(e) = secrets.set(secrets::API_TOKEN, &token) {
+        api.stop().await;
+        return Err(e.to_string());
+    }
+    Ok(token)
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+fn get_secret_status(secrets: tauri::State<SecretStore>) -> Result<SecretStoreStatus, String> {
+    secrets.status().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_secret(
+    secrets: tauri::State<SecretStore>,
+    name: String,
+) -> Result<Option<String>, String> {
+    secrets.masked(&name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_secret(
+    app: tauri::AppHandle,
+    secrets: tauri::State<SecretStore>,
+    name: String,
+    value: String,
+) -> Result<SecretInfo, String> {
+    let info = secrets.set(&name, &value).map_err(|e| e.to_string())?;
+    configure_ai(&app, &app.state::<SettingsStore>().get());
+    Ok(info)
+}
+
+#[tauri::command]
+fn delete_secret(
+    app: tauri::AppHandle,
+    secrets: tauri::State<SecretStore>,
+    name: String,
+) -> Result<(), String> {
+    secrets.delete(&name).map_err(|e| e.to_string())?;
+    configure_ai(&app, &app.state::<SettingsStore>().get());
+    Ok(())
+}
+
+#[tauri::command]
+async fn test_secret(
+    secrets: tauri::State<'_, SecretStore>,
+    name: String,
+) -> Result<SecretTest, String> {
+    let value = secrets.get(&name).map_err(|e| e.to_string())?;
+    Ok(secrets::test(&name, value).await)
+}
+
+#[tauri::command]
+fn unlock_secrets(
+    app: tauri::AppHandle,
+    secrets: tauri::State<SecretStore>,
+    passphrase: String,
+) -> Result<(), String> {
+    secrets.unlock(&passphrase).map_err(|e| e.to_string())?;
+    configure_ai(&app, &app.state::<SettingsStore>().get());
+    Ok(())
+}
+
+#[tauri::command]
+fn set_secrets_passphrase(
+    secrets: tauri::State<SecretStore>,
+    passphrase: Option<String>,
+) -> Result<(), String> {
+    secrets
+        .set_passphrase(passphrase.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_secrets_backend(
+    secrets: tauri::State<SecretStore>,
+    backend: SecretBackend,
+) -> Result<(), String> {
+    secrets.set_backend(backend).map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+// The daily note for `date` (YYYY-MM-DD), created from the journal settings
+// the first time.
+#[tauri::command]
+async fn get_or_create_daily_note(
+    db: tauri::State<'_, SqlDatabase>,
+    settings: tauri::State<'_, SettingsStore>,
+    date: String,
+) -> Result<SqlLeaf, String> {
+    let date = journal::parse_date(&date)?;
+    journal::get_or_create_daily_note(&db, &settings.get().journal, date)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Dates from `from` to `to` with a daily note, and how many words each has.
+#[tauri::command]
+async fn get_journal_calendar(
+    db: tauri::State<'_, SqlDatabase>,
+    from: String,
+    to: String,
+) -> Result<Vec<JournalDay>, String> {
+    let from = journal::parse_date(&from)?;
+    let to = journal::parse_date(&to)?;
+    journal::calendar(&db, from, to)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+// Built-in and workspace templates, with the prompts each one asks.
+#[tauri::command]
+async fn list_templates(db: tauri::State<'_, SqlDatabase>) -> Result<Vec<TemplateInfo>, String> {
+    templates::list(&db).await.map_err(|e| e.to_string())
+}
+
+// Creates a leaf from the template and returns its id. `values` answers the
+// template's prompts by label.
+#[tauri::command]
+async fn create_leaf_from_template(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, SqlDatabase>,
+    template_id: String,
+    title: String,
+    values: HashMap<String, String>,
+) -> Result<String, String> {
+    let template = templates::find(&db, &template_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Template {} not found", template_id))?;
+    // Only read when used; an empty or non-text clipboard renders as nothing
+    let clipboard = if templates::uses_clipboard(template.content()) {
+        app.clipboard().read_text().ok()
+    } else {
+        None
+    };
+    templates::create_leaf(&db, &template, &title, &values, clipboard.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+// Checklist items across all leaves, narrowed by `filter`.
+#[tauri::command]
+async fn list_tasks(
+    db: tauri::State<'_, SqlDatabase>,
+    filter: Option<TaskFilter>,
+) -> Result<Vec<Task>, String> {
+    let filter = filter.unwrap_or_default();
+    filter.validate()?;
+    db.list_tasks(&filter).await.map_err(|e| e.to_string())
+}
+
+// Checks or unchecks a task in its leaf. `text` is the task as listed, to
+// catch edits to the leaf in the meantime.
+#[tauri::command]
+async fn toggle_task(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+    position: i64,
+    text: String,
+    checked: bool,
+) -> Result<Task, String> {
+    tasks::toggle(&db, &leaf_id, position, &text, checked).await
+}
+
+// -------------------------------------------------------
+
+// Reminds about the leaf, or one of its blocks, at `at`: RFC 3339, or a
+// local time without an offset.
+#[tauri::command]
+async fn create_reminder(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+    block_id: Option<String>,
+    at: String,
+    note: Option<String>,
+) -> Result<Reminder, String> {
+    let reminder = reminders::create(
+        &db,
+        &leaf_id,
+        block_id.as_deref(),
+        &at,
+        note.as_deref().unwrap_or_default(),
+    )
+    .await?;
+    reminders::wake(&app);
+    Ok(reminder)
+}
+
+#[tauri::command]
+async fn snooze_reminder(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, SqlDatabase>,
+    id: String,
+    until: String,
+) -> Result<Reminder, String> {
+    let reminder = reminders::snooze(&db, &id, &until).await?;
+    reminders::wake(&app);
+    Ok(reminder)
+}
+
+#[tauri::command]
+async fn list_reminders(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: Option<String>,
+    include_fired: Option<bool>,
+) -> Result<Vec<Reminder>, String> {
+    db.list_reminders(leaf_id.as_deref(), include_fired.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_reminder(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, SqlDatabase>,
+    id: String,
+) -> Result<(), String> {
+    if !db.delete_reminder(&id).await.map_err(|e| e.to_string())? {
+        return Err(format!("Reminder {} not found", id));
+    }
+    reminders::wake(&app);
+    Ok(())
+}
+
+// -------------------------------------------------------
+
+// Word count, reading time, outline and readability of a leaf as last saved.
+#[tauri::command]
+async fn get_leaf_stats(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+) -> Result<Option<LeafStats>, String> {
+    db.leaf_stats(&leaf_id).await.map_err(|e| e.to_string())
+}
+
+// Words written per day from `from` to `to` (YYYY-MM-DD), with streaks.
+#[tauri::command]
+async fn get_writing_activity(
+    db: tauri::State<'_, SqlDatabase>,
+    from: String,
+    to: String,
+) -> Result<WritingActivity, String> {
+    let from = journal::parse_date(&from)?;
+    let to = journal::parse_date(&to)?;
+    let days = db
+        .writing_days(&from.to_string(), &to.to_string())
+        .await
+        .map_err(|e| e.to_string())?;
+    let dates = db.writing_dates().await.map_err(|e| e.to_string())?;
+    stats::activity(from, to, days, &dates)
+}
+
+// -------------------------------------------------------
+
+// The top-level blocks of a leaf as last saved, in order.
+#[tauri::command]
+async fn list_blocks(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+) -> Result<Vec<Block>, String> {
+    db.list_blocks(&leaf_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_block(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+    block_id: String,
+) -> Result<Option<Block>, String> {
+    db.block(&leaf_id, &block_id).await.map_err(|e| e.to_string())
+}
+
+// Replaces one paragraph or heading of a leaf, keeping its block id.
+#[tauri::command]
+async fn update_block(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+    block_id: String,
+    html: String,
+) -> Result<Block, String> {
+    blocks::update_block(&db, &leaf_id, &block_id, &html).await
+}
+
+// Leaves that embed the block.
+#[tauri::command]
+async fn get_block_references(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+    block_id: String,
+) -> Result<Vec<BlockReference>, String> {
+    db.block_references(&leaf_id, &block_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// The leaf's content with its embedded blocks filled in.
+#[tauri::command]
+async fn resolve_transclusions(
+    db: tauri::State<'_, SqlDatabase>,
+    leaf_id: String,
+) -> Result<String, String> {
+    let leaf = db
+        .read::<SqlLeaf>(&leaf_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Leaf {} not found", leaf_id))?;
+    blocks::resolve_transclusions(&db, leaf.content())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// -------------------------------------------------------
+
+#[tauri::command]
+async fn open_leaf_window(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, SqlDatabase>,
+    windows: tauri::State<'_, LeafWindows>,
+    leaf_id: String,
+) -> Result<String, String> {
+    let leaf = db
+        .read::<SqlLeaf>(&leaf_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Leaf {} not found", leaf_id))?;
+    windows.open(&app, &leaf).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_leaf_lock(
+    window: tauri::Window,
+    windows: tauri::State<LeafWindows>,
+    leaf_id: String,
+) -> LeafLock {
+    windows.lock(&leaf_id, window.label())
+}
+
+// Takes the edit lock on a leaf for the calling window. The lock is advisory:
+// windows that don't hold it can still edit, and should show who does.
+#[tauri::command]
+fn acquire_leaf_lock(
+    window: tauri::Window,
+    windows: tauri::State<LeafWindows>,
+    leaf_id: String,
+) -> LeafLock {
+    windows.acquire(window.app_handle(), &leaf_id, window.label())
+}
+
+#[tauri::command]
+fn release_leaf_lock(window: tauri::Window, windows: tauri::State<LeafWindows>, leaf_id: String) {
+    windows.release(window.app_handle(), &leaf_id, window.label());
+}
+
+// -------------------------------------------------------
+
+// Limits the `entity-changed` events the calling window receives to
+// `entities`, or lifts the limit when none are given.
+#[tauri::command]
+fn set_entity_change_filter(
+    window: tauri::Window,
+    filters: tauri::State<ChangeFilters>,
+    entities: Option<Vec<EntityKind>>,
+) {
+    filters.set(window.label(), entities);
+}
+
+// Changes after sequence number `since`, for a window catching up.
+#[tauri::command]
+fn replay_entity_changes(since: u64, entities: Option<Vec<EntityKind>>) -> Replay {
+    events::replay(since, &entities.unwrap_or_default())
+}
+
+// -------------------------------------------------------
+
+// Deep links received before the window was listening. Calling this marks
+// it as listening for `deep-link` events from then on.
+#[tauri::command]
+async fn take_pending_deep_links(app: tauri::AppHandle) -> Result<Vec<DeepLink>, String> {
+    let db = app.try_state::<SqlDatabase>();
+    Ok(app.state::<PendingLinks>().take(db.as_deref()).await)
+}
+
+// Problems found while starting, e.g. settings that were set aside, until the
+// main window takes them to show.
+struct StartupErrors(Mutex<Vec<String>>);
+
+#[tauri::command]
+fn take_startup_errors(errors: tauri::State<StartupErrors>) -> Vec<String> {
+    std::mem::take(&mut *errors.0.lock().unwrap())
 }
 
 // -------------------------------------------------------
@@ -213,22 +1408,115 @@ pub fn run() {
             .path()
             .app_data_dir()
             .expect("failed to get app data dir");
-        let db = Database::new(app_data_dir.clone()).unwrap();
+        let app_config_dir = app
+            .path()
+            .app_config_dir()
+            .expect("failed to get app config dir");
+        // A settings file that can't be read is replaced rather than keeping
+        // the app from starting; the window shows what happened
+        let mut startup_errors = Vec::new();
+        let (workspaces, error) =
+            Workspaces::load_or_reset(&app_config_dir, &app_data_dir).unwrap();
+        startup_errors.extend(error);
+        let mut workspace = workspaces.active();
+        // A workspace on a drive that is not mounted would be recreated empty
+        if !workspace.path.is_dir() {
+            if let Some(available) = workspaces.list().workspaces.into_iter().find(|w| w.path.is_dir()) {
+                eprintln!(
+                    "Workspace folder {} is missing; opening {} instead",
+                    workspace.path.display(),
+                    available.name
+                );
+                workspaces.set_active(&available.id).unwrap();
+                workspace = available;
+            }
+        }
+
+        let db = Database::new(workspace.path.clone()).unwrap();
+        let secrets = SecretStore::new(&app_config_dir, &workspace.path.join("config")).unwrap();
+        let (settings, error) =
+            SettingsStore::load_or_reset(&workspace.path.join("config"), &secrets).unwrap();
+        startup_errors.extend(error);
+        for error in &startup_errors {
+            eprintln!("{}", error);
+        }
+        app.manage(StartupErrors(Mutex::new(startup_errors)));
+        ollama::configure(
+            &settings.get().ai,
+            secrets.get(secrets::OPENAI_API_KEY).ok().flatten(),
+        );
+        app.manage(workspaces);
         app.manage(db);
+        app.manage(secrets);
+        app.manage(settings);
+
+        // The mirror and sync tasks look themselves up in managed state, so
+        // manage them first
+        app.manage(MarkdownMirror::default());
+        app.manage(SyncEngine::default());
+        app.manage(LeafDocs::default());
+        app.manage(CollabServer::default());
+        app.manage(ApiServer::default());
+        app.manage(ChangeFilters::default());
+        app.manage(LeafWindows::default());
+        app.manage(ReminderScheduler::default());
+        events::forward(app.handle().clone());
+        crdt::watch(app.handle().clone());
+
+        // An encrypted database stays closed until `unlock_database` is called
+        if !SqlDatabase::is_encrypted(&workspace.path) {
+            // Use blocking to handle the async SqlDatabase initialization
+            let sql_db = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(SqlDatabase::new(workspace.path))
+                .unwrap();
+            tauri::async_runtime::block_on(open_sql_database(app.handle(), sql_db));
+        }
+
+        backup::start_scheduler(app.handle().clone());
+        reminders::start_scheduler(app.handle().clone());
 
-        // Use blocking to handle the async SqlDatabase initialization
-        let sql_db = tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(SqlDatabase::new(app_data_dir))
-            .unwrap();
-        app.manage(sql_db);
+        app.manage(PendingLinks::default());
+        // Installed builds register the scheme on install; this covers dev
+        // builds and AppImages
+        #[cfg(any(windows, target_os = "linux"))]
+        if let Err(e) = app.deep_link().register_all() {
+            eprintln!("Could not register {}:// links: {}", deeplink::SCHEME, e);
+        }
+        if let Ok(Some(urls)) = app.deep_link().get_current() {
+            deeplink::open_urls(app.handle(), urls);
+        }
+        let handle = app.handle().clone();
+        app.deep_link()
+            .on_open_url(move |event| deeplink::open_urls(&handle, event.urls()));
 
         Ok(())
     })
+        // Must come first: a second launch hands its arguments, including
+        // deep links, to this instance and exits
+        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
+            deeplink::focus_main_window(app);
+        }))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .plugin(tauri_plugin_notification::init())
+        .on_window_event(windows::handle_event)
+        // New commands also go in build.rs and a set in permissions/app.toml
         .invoke_handler(tauri::generate_handler![
             get_config,
             set_config,
+            ai_chat,
+            get_settings,
+            update_settings,
+            reset_settings,
+            list_workspaces,
+            create_workspace,
+            open_workspace,
+            switch_workspace,
+            rename_workspace,
+            close_workspace,
+            set_workspace_data_dir,
             create_leaf,
             read_leaf,
             delete_leaf,
@@ -247,7 +1535,76 @@ pub fn run() {
             sql_read_entity,
             sql_update_entity,
             sql_list_entities,
-            sql_delete_entity
+            sql_delete_entity,
+            get_leaf_tags,
+            list_trash,
+            restore_leaf,
+            get_leaf_children,
+            open_leaf_doc,
+            apply_leaf_doc_update,
+            reload_leaf_doc,
+            get_leaf_doc_state_vector,
+            get_leaf_doc_diff,
+            close_leaf_doc,
+            start_collab_server,
+            stop_collab_server,
+            get_collab_status,
+            set_leaf_shared,
+            list_shared_leaves,
+            import_markdown,
+            import_notion,
+            publish_leaves,
+            start_markdown_mirror,
+            stop_markdown_mirror,
+            sync_markdown_mirror,
+            start_sync,
+            stop_sync,
+            sync_now,
+            create_backup,
+            verify_backup,
+            restore_backup,
+            get_backup_schedule,
+            set_backup_schedule,
+            get_database_status,
+            unlock_database,
+            enable_database_encryption,
+            change_database_passphrase,
+            export_plaintext_database,
+            get_secret_status,
+            get_secret,
+            set_secret,
+            delete_secret,
+            test_secret,
+            unlock_secrets,
+            set_secrets_passphrase,
+            set_secrets_backend,
+            get_api_status,
+            regenerate_api_token,
+            take_pending_deep_links,
+            take_startup_errors,
+            set_entity_change_filter,
+            replay_entity_changes,
+            open_leaf_window,
+            get_leaf_lock,
+            acquire_leaf_lock,
+            release_leaf_lock,
+            get_or_create_daily_note,
+            get_journal_calendar,
+            list_templates,
+            create_leaf_from_template,
+            list_tasks,
+            toggle_task,
+            create_reminder,
+            snooze_reminder,
+            list_reminders,
+            cancel_reminder,
+            get_leaf_stats,
+            get_writing_activity,
+            list_blocks,
+            get_block,
+            update_block,
+            get_block_references,
+            resolve_transclusions
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,767 @@
+use crate::html;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd};
+use std::collections::HashMap;
+
+// Metadata read from a YAML front matter block at the top of a note.
+#[derive(Default)]
+pub struct FrontMatter {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub created_at: Option<String>,
+    pub modified_at: Option<String>,
+    pub tags: Vec<String>,
+}
+
+// Splits `---\n...\n---` off the top of a document.
+pub fn split_front_matter(text: &str) -> (Option<&str>, &str) {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (None, text);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" || line.trim_end() == "..." {
+            let body = &rest[offset + line.len()..];
+            return (Some(&rest[..offset]), body);
+        }
+        offset += line.len();
+    }
+    (None, text)
+}
+
+pub fn parse_front_matter(yaml: &str) -> FrontMatter {
+    let Ok(serde_yaml::Value::Mapping(map)) = serde_yaml::from_str::<serde_yaml::Value>(yaml)
+    else {
+        return FrontMatter::default();
+    };
+
+    let get = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| map.get(serde_yaml::Value::String(key.to_string())))
+    };
+
+    let id = get(&["id"]).and_then(yaml_to_string);
+    let title = get(&["title"]).and_then(yaml_to_string);
+    let created_at = get(&["created", "created_at", "createdAt", "date"])
+        .and_then(yaml_to_string)
+        .and_then(|s| normalize_timestamp(&s));
+    let modified_at = get(&["modified", "modified_at", "modifiedAt", "updated", "updated_at"])
+        .and_then(yaml_to_string)
+        .and_then(|s| normalize_timestamp(&s));
+
+    let tags = match get(&["tags", "tag"]) {
+        Some(serde_yaml::Value::Sequence(items)) => {
+            items.iter().filter_map(yaml_to_string).collect()
+        }
+        Some(value) => yaml_to_string(value)
+            .map(|s| {
+                s.split([',', ' '])
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let tags = tags
+        .into_iter()
+        .map(|t: String| t.trim_start_matches('#').to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    FrontMatter {
+        id,
+        title,
+        created_at,
+        modified_at,
+        tags,
+    }
+}
+
+fn yaml_to_string(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// Accepts the handful of date formats note apps write and returns RFC 3339,
+// the format `SqlDatabase` stores.
+pub fn normalize_timestamp(value: &str) -> Option<String> {
+    let value = value.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc).to_rfc3339());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(dt.and_utc().to_rfc3339());
+        }
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|dt| dt.and_utc().to_rfc3339())
+}
+
+// -------------------------------------------------------
+
+// Turns Obsidian-style `[[Target|Alias]]` and `![[image.png]]` into regular
+// Markdown links so the parser hands them to the link and image resolvers.
+pub fn rewrite_wikilinks(markdown: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut in_fence = false;
+
+    for line in markdown.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        if in_fence || !line.contains("[[") {
+            out.push_str(line);
+            continue;
+        }
+
+        let mut rest = line;
+        while let Some(start) = rest.find("[[") {
+            let Some(len) = rest[start + 2..].find("]]") else {
+                break;
+            };
+            let inner = &rest[start + 2..start + 2 + len];
+            let embed = rest[..start].ends_with('!');
+            let prefix = if embed { &rest[..start - 1] } else { &rest[..start] };
+            out.push_str(prefix);
+
+            let (target, alias) = match inner.split_once('|') {
+                Some((target, alias)) => (target.trim(), Some(alias.trim())),
+                None => (inner.trim(), None),
+            };
+            let dest = target.replace(['<', '>'], "");
+            if embed {
+                out.push_str(&format!("![{}](<{}>)", alias.unwrap_or(target), dest));
+            } else {
+                let label = alias.unwrap_or_else(|| target.split('#').next().unwrap_or(target));
+                let (page, fragment) = match dest.split_once('#') {
+                    Some((page, fragment)) => (page.to_string(), format!("#{}", fragment)),
+                    None => (dest.clone(), String::new()),
+                };
+                let extension = if has_file_extension(&page) { "" } else { ".md" };
+                out.push_str(&format!("[{}](<{}{}{}>)", label, page, extension, fragment));
+            }
+            rest = &rest[start + 2 + len + 2..];
+        }
+        out.push_str(rest);
+    }
+
+    out
+}
+
+// Renders Markdown to the HTML the editor schema parses. Links and image
+// sources go through the resolvers; a link resolver returning `None` for a
+// note link (`*.md`) drops the link and keeps its text.
+pub fn to_html(
+    markdown: &str,
+    mut resolve_link: impl FnMut(&str) -> Option<String>,
+    mut resolve_image: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+    let mut block_ids = take_block_ids(&mut events);
+
+    let mut out_events = Vec::with_capacity(events.len());
+    let mut task_lists: Vec<bool> = Vec::new();
+    let mut dropped_links: Vec<bool> = Vec::new();
+
+    for (i, event) in events.iter().enumerate() {
+        match block_ids.remove(&i) {
+            Some(BlockIdTag::Replace(tag)) => {
+                out_events.push(Event::Html(CowStr::from(tag)));
+                continue;
+            }
+            Some(BlockIdTag::Before(tag)) => out_events.push(Event::Html(CowStr::from(tag))),
+            None => {}
+        }
+        match event {
+            Event::Start(Tag::List(start)) => {
+                let is_task_list = start.is_none() && task_marker(&events[i + 1..]).is_some();
+                task_lists.push(is_task_list);
+                if is_task_list {
+                    out_events.push(Event::Html(CowStr::from("<ul data-type=\"taskList\">")));
+                } else {
+                    out_events.push(event.clone());
+                }
+            }
+            Event::End(TagEnd::List(_)) => {
+                if task_lists.pop().unwrap_or(false) {
+                    out_events.push(Event::Html(CowStr::from("</ul>")));
+                } else {
+                    out_events.push(event.clone());
+                }
+            }
+            Event::Start(Tag::Item) if task_lists.last() == Some(&true) => {
+                let checked = task_marker(&events[i + 1..]).unwrap_or(false);
+                out_events.push(Event::Html(CowStr::from(format!(
+                    "<li data-type=\"taskItem\" data-checked=\"{}\"><label><input type=\"checkbox\"{}><span></span></label><div>",
+                    checked,
+                    if checked { " checked=\"checked\"" } else { "" }
+                ))));
+            }
+            Event::End(TagEnd::Item) if task_lists.last() == Some(&true) => {
+                out_events.push(Event::Html(CowStr::from("</div></li>")));
+            }
+            Event::TaskListMarker(_) => {}
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => match resolve_link(dest_url) {
+                Some(url) => {
+                    dropped_links.push(false);
+                    out_events.push(Event::Start(Tag::Link {
+                        link_type: *link_type,
+                        dest_url: CowStr::from(url),
+                        title: title.clone(),
+                        id: id.clone(),
+                    }));
+                }
+                None if is_note_link(dest_url) => dropped_links.push(true),
+                None => {
+                    dropped_links.push(false);
+                    out_events.push(event.clone());
+                }
+            },
+            Event::End(TagEnd::Link) => {
+                if !dropped_links.pop().unwrap_or(false) {
+                    out_events.push(event.clone());
+                }
+            }
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                let url = resolve_image(dest_url).unwrap_or_else(|| dest_url.to_string());
+                out_events.push(Event::Start(Tag::Image {
+                    link_type: *link_type,
+                    dest_url: CowStr::from(url),
+                    title: title.clone(),
+                    id: id.clone(),
+                }));
+            }
+            _ => out_events.push(event.clone()),
+        }
+    }
+
+    let mut output = String::with_capacity(markdown.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut output, out_events.into_iter());
+    output
+}
+
+// Block ids are written as a `{#id}` marker ending the block's last line.
+fn split_block_id(text: &str) -> Option<(&str, &str)> {
+    let (before, id) = text.trim_end().strip_suffix('}')?.rsplit_once("{#")?;
+    (is_block_id(id) && (before.is_empty() || before.ends_with(char::is_whitespace)))
+        .then(|| (before.trim_end(), id))
+}
+
+// BlockID gives out UUIDs; other ids can't be told apart from text.
+fn is_block_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+// HTML written in place of an event, or before it.
+enum BlockIdTag {
+    Replace(String),
+    Before(String),
+}
+
+// Strips the `{#id}` markers off paragraphs, headings and the text of tight
+// list items, and returns the tags that give those blocks their `blockid`
+// back. Tight items have no paragraph events, so theirs are added.
+fn take_block_ids(events: &mut [Event]) -> HashMap<usize, BlockIdTag> {
+    let find = |events: &[Event], from: usize, end: &dyn Fn(&Event) -> bool| {
+        from + events[from..].iter().position(end).unwrap_or(events.len() - from)
+    };
+    let mut tags = HashMap::new();
+    for i in 0..events.len() {
+        // The block's first and end event, its element, and whether those
+        // events are its own tags or the text between them
+        let (first, end, name, own) = match &events[i] {
+            Event::Start(Tag::Paragraph) => {
+                let end = find(events, i, &|e| matches!(e, Event::End(TagEnd::Paragraph)));
+                (i, end, "p".to_string(), true)
+            }
+            Event::Start(Tag::Heading { level, .. }) => {
+                let end = find(events, i, &|e| matches!(e, Event::End(TagEnd::Heading(_))));
+                (i, end, level.to_string(), true)
+            }
+            Event::Start(Tag::Item) => {
+                let marker = matches!(events.get(i + 1), Some(Event::TaskListMarker(_)));
+                let first = i + 1 + usize::from(marker);
+                let end = find(events, first, &|e| {
+                    matches!(e, Event::End(TagEnd::Item)) || is_block_start(e)
+                });
+                (first, end, "p".to_string(), false)
+            }
+            _ => continue,
+        };
+        if end == first || end >= events.len() {
+            continue;
+        }
+        let Event::Text(text) = &events[end - 1] else {
+            continue;
+        };
+        let Some((before, id)) = split_block_id(text) else {
+            continue;
+        };
+        let (open, close) = (format!("<{} blockid=\"{}\">", name, id), format!("</{}>", name));
+        events[end - 1] = Event::Text(CowStr::from(before.to_string()));
+        if own {
+            tags.insert(first, BlockIdTag::Replace(open));
+            tags.insert(end, BlockIdTag::Replace(close));
+        } else {
+            tags.insert(first, BlockIdTag::Before(open));
+            tags.insert(end, BlockIdTag::Before(close));
+        }
+    }
+    tags
+}
+
+fn is_block_start(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) => !matches!(
+            tag,
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. } | Tag::Image { .. }
+        ),
+        _ => false,
+    }
+}
+
+// Checked state of the task marker opening the next list item, if any.
+fn task_marker(events: &[Event]) -> Option<bool> {
+    for event in events.iter().take(3) {
+        match event {
+            Event::TaskListMarker(checked) => return Some(*checked),
+            Event::Start(Tag::Item) | Event::Start(Tag::Paragraph) => continue,
+            _ => return None,
+        }
+    }
+    None
+}
+
+// `[[report.pdf]]` links a file, `[[Release 1.2]]` links a note.
+fn has_file_extension(target: &str) -> bool {
+    let name = target.rsplit('/').next().unwrap_or(target);
+    name.rsplit_once('.').is_some_and(|(stem, ext)| {
+        !stem.is_empty() && (1..=5).contains(&ext.len()) && ext.chars().all(|c| c.is_ascii_alphabetic())
+    })
+}
+
+pub fn is_note_link(dest: &str) -> bool {
+    let path = dest.split('#').next().unwrap_or(dest);
+    !path.contains("://") && path.to_ascii_lowercase().ends_with(".md")
+}
+
+// Leaf name used when the note has no title: the file stem.
+pub fn title_from_path(path: &str) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let stem = file_name
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .unwrap_or(file_name);
+    html::decode_uri_component(stem)
+}
+
+// -------------------------------------------------------
+
+// Renders editor HTML as Markdown. Task lists, tables and code blocks use the
+// GitHub flavoured syntax `to_html` reads back.
+pub fn from_html(content: &str) -> String {
+    let mut out = String::new();
+    write_blocks(&html::parse(content), &mut out, "");
+    let mut markdown = out.trim().to_string();
+    markdown.push('\n');
+    markdown
+}
+
+fn write_blocks(nodes: &[html::Node], out: &mut String, indent: &str) {
+    let mut inline = String::new();
+    for node in nodes {
+        let is_block = node
+            .name()
+            .is_some_and(|name| html::BLOCK_ELEMENTS.contains(&name) && name != "br");
+        if !is_block {
+            write_inline(node, &mut inline);
+            continue;
+        }
+        flush_paragraph(&mut inline, out, indent);
+        write_block(node, out, indent);
+    }
+    flush_paragraph(&mut inline, out, indent);
+}
+
+fn flush_paragraph(inline: &mut String, out: &mut String, indent: &str) {
+    let text = inline.trim();
+    if !text.is_empty() {
+        push_lines(out, text, indent, indent);
+        out.push_str("\n\n");
+    }
+    inline.clear();
+}
+
+// Writes `text` line by line, the first line after `first` and the rest
+// after `rest`.
+fn push_lines(out: &mut String, text: &str, first: &str, rest: &str) {
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let prefix = if i == 0 { first } else { rest };
+        if line.is_empty() {
+            out.push_str(prefix.trim_end());
+        } else {
+            out.push_str(prefix);
+            out.push_str(line);
+        }
+    }
+}
+
+fn write_block(node: &html::Node, out: &mut String, indent: &str) {
+    let name = node.name().unwrap_or_default();
+    match name {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse::<usize>().unwrap_or(1);
+            let mut text = String::new();
+            node.children().iter().for_each(|c| write_inline(c, &mut text));
+            let text = with_block_id(node, text.trim().to_string());
+            out.push_str(&format!("{}{} {}\n\n", indent, "#".repeat(level), text));
+        }
+        "p" => {
+            let mut text = String::new();
+            node.children().iter().for_each(|c| write_inline(c, &mut text));
+            let mut text = with_block_id(node, text.trim().to_string());
+            flush_paragraph(&mut text, out, indent);
+        }
+        "hr" => out.push_str(&format!("{}---\n\n", indent)),
+        "pre" => {
+            let code = node
+                .children()
+                .iter()
+                .find(|c| c.name() == Some("code"))
+                .unwrap_or(node);
+            let language = code
+                .attr("class")
+                .and_then(|classes| {
+                    classes
+                        .split_whitespace()
+                        .find_map(|c| c.strip_prefix("language-"))
+                })
+                .unwrap_or_default();
+            let text = code.text();
+            out.push_str(&format!("{}```{}\n", indent, language));
+            push_lines(out, text.trim_end_matches('\n'), indent, indent);
+            out.push_str(&format!("\n{}```\n\n", indent));
+        }
+        "blockquote" => {
+            let mut inner = String::new();
+            write_blocks(node.children(), &mut inner, "");
+            let quoted = format!("{}> ", indent);
+            push_lines(out, inner.trim_end(), &quoted, &quoted);
+            out.push_str("\n\n");
+        }
+        "ul" | "ol" => {
+            let task_list = node.attr("data-type") == Some("taskList");
+            let items = node.children().iter().filter(|c| c.name() == Some("li"));
+            for (number, item) in (1..).zip(items) {
+                let marker = if task_list || item.attr("data-type") == Some("taskItem") {
+                    let checked = item.attr("data-checked") == Some("true");
+                    format!("- [{}] ", if checked { "x" } else { " " })
+                } else if name == "ol" {
+                    format!("{}. ", number)
+                } else {
+                    "- ".to_string()
+                };
+
+                // The checkbox label is editor chrome, the content sits in a div
+                let children: Vec<html::Node> = item
+                    .children()
+                    .iter()
+                    .filter(|c| c.name() != Some("label"))
+                    .flat_map(|c| match c.name() {
+                        Some("div") => c.children().to_vec(),
+                        _ => vec![c.clone()],
+                    })
+                    .collect();
+                let child_indent = " ".repeat(marker.len());
+                let mut inner = String::new();
+                write_blocks(&children, &mut inner, "");
+                let inner = inner.trim_end().replace("\n\n", "\n");
+                push_lines(out, &inner, &format!("{}{}", indent, marker), &format!("{}{}", indent, child_indent));
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        "table" => write_table(node, out, indent),
+        _ => write_blocks(node.children(), out, indent),
+    }
+}
+
+// Appends the `{#id}` marker `to_html` reads back into `blockid`.
+fn with_block_id(node: &html::Node, text: String) -> String {
+    match node.attr("blockid").filter(|id| is_block_id(id)) {
+        Some(id) if !text.is_empty() => format!("{} {{#{}}}", text, id),
+        _ => text,
+    }
+}
+
+fn write_table(node: &html::Node, out: &mut String, indent: &str) {
+    fn collect_rows<'a>(node: &'a html::Node, rows: &mut Vec<&'a html::Node>) {
+        for child in node.children() {
+            match child.name() {
+                Some("tr") => rows.push(child),
+                Some(_) => collect_rows(child, rows),
+                None => {}
+            }
+        }
+    }
+    let mut table_rows = Vec::new();
+    collect_rows(node, &mut table_rows);
+
+    for (i, row) in table_rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .children()
+            .iter()
+            .filter(|c| matches!(c.name(), Some("td") | Some("th")))
+            .map(|cell| {
+                let mut text = String::new();
+                cell.children().iter().for_each(|c| write_inline(c, &mut text));
+                text.trim().replace('\n', " ").replace('|', "\\|")
+            })
+            .collect();
+        out.push_str(&format!("{}| {} |\n", indent, cells.join(" | ")));
+        if i == 0 {
+            out.push_str(&format!(
+                "{}|{}\n",
+                indent,
+                " --- |".repeat(cells.len().max(1))
+            ));
+        }
+    }
+    out.push('\n');
+}
+
+fn write_inline(node: &html::Node, out: &mut String) {
+    let children = |out: &mut String| node.children().iter().for_each(|c| write_inline(c, out));
+    match node {
+        html::Node::Text(text) => out.push_str(&escape_inline(text)),
+        html::Node::Element { name, .. } => match name.as_str() {
+            "strong" | "b" => wrap(out, "**", children),
+            "em" | "i" => wrap(out, "*", children),
+            "s" | "del" | "strike" => wrap(out, "~~", children),
+            "code" => out.push_str(&format!("`{}`", node.text())),
+            "br" => out.push_str("  \n"),
+            "a" => {
+                let mut label = String::new();
+                children(&mut label);
+                out.push_str(&format!("[{}]({})", label, destination(node.attr("href"))));
+            }
+            "img" => out.push_str(&format!(
+                "![{}]({})",
+                node.attr("alt").unwrap_or_default(),
+                destination(node.attr("src"))
+            )),
+            _ if html::BLOCK_ELEMENTS.contains(&name.as_str()) => {
+                let mut inner = String::new();
+                write_blocks(node.children(), &mut inner, "");
+                out.push_str(inner.trim());
+            }
+            _ => children(out),
+        },
+    }
+}
+
+fn destination(url: Option<&str>) -> String {
+    let url = url.unwrap_or_default();
+    if url.contains([' ', '(', ')']) {
+        format!("<{}>", url)
+    } else {
+        url.to_string()
+    }
+}
+
+fn wrap(out: &mut String, marker: &str, children: impl Fn(&mut String)) {
+    let mut inner = String::new();
+    children(&mut inner);
+    if inner.trim().is_empty() {
+        out.push_str(&inner);
+    } else {
+        out.push_str(&format!("{}{}{}", marker, inner, marker));
+    }
+}
+
+fn escape_inline(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '*' | '_' | '`' | '[' | ']' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\u{a0}' => out.push(' '),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_ids(nodes: &[html::Node], ids: &mut Vec<(String, String)>) {
+        for node in nodes {
+            match node.attr("blockid") {
+                Some(id) => ids.push((id.to_string(), node.text())),
+                None => block_ids(node.children(), ids),
+            }
+        }
+    }
+
+    #[test]
+    fn splits_and_reads_front_matter() {
+        let text = "\u{feff}---\ntitle: Trip\ncreated: 2024-03-09 14:30\ntags: [\"#travel\", work]\n---\nBody\n";
+        let (yaml, body) = split_front_matter(text);
+        assert_eq!(body, "Body\n");
+        let front_matter = parse_front_matter(yaml.unwrap());
+        assert_eq!(front_matter.title.as_deref(), Some("Trip"));
+        assert_eq!(front_matter.created_at.as_deref(), Some("2024-03-09T14:30:00+00:00"));
+        assert_eq!(front_matter.modified_at, None);
+        assert_eq!(front_matter.tags, ["travel", "work"]);
+
+        // Tags may also be one string, and dates other keys
+        let front_matter = parse_front_matter("tags: \"a, b c\"\ndate: 2024-03-09\nid: 42");
+        assert_eq!(front_matter.tags, ["a", "b", "c"]);
+        assert_eq!(front_matter.created_at.as_deref(), Some("2024-03-09T00:00:00+00:00"));
+        assert_eq!(front_matter.id.as_deref(), Some("42"));
+
+        // Unterminated or malformed front matter is not front matter
+        assert_eq!(split_front_matter("---\ntitle: x\nBody"), (None, "---\ntitle: x\nBody"));
+        assert!(parse_front_matter("- just\n- a list").title.is_none());
+        assert_eq!(normalize_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn rewrites_wikilinks_outside_code() {
+        assert_eq!(
+            rewrite_wikilinks("See [[Release 1.2|the release]] and ![[img.png]]\n"),
+            "See [the release](<Release 1.2.md>) and ![img.png](<img.png>)\n"
+        );
+        assert_eq!(rewrite_wikilinks("[[Plan#Goals]]"), "[Plan](<Plan.md#Goals>)");
+        assert_eq!(rewrite_wikilinks("[[report.pdf]]"), "[report.pdf](<report.pdf>)");
+        let fenced = "```\n[[not a link]]\n```\n";
+        assert_eq!(rewrite_wikilinks(fenced), fenced);
+    }
+
+    #[test]
+    fn renders_task_lists_for_the_editor() {
+        let html = to_html("- [ ] Open\n- [x] Done\n", |_| None, |_| None);
+        assert_eq!(
+            html,
+            concat!(
+                "<ul data-type=\"taskList\">",
+                "<li data-type=\"taskItem\" data-checked=\"false\"><label><input type=\"checkbox\"><span></span></label><div>Open</div></li>",
+                "<li data-type=\"taskItem\" data-checked=\"true\"><label><input type=\"checkbox\" checked=\"checked\"><span></span></label><div>Done</div></li>",
+                "</ul>",
+            )
+        );
+        // Ordinary lists stay lists
+        assert_eq!(to_html("- a\n", |_| None, |_| None), "<ul>\n<li>a</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn resolves_links_and_drops_unresolved_note_links() {
+        let resolve = |dest: &str| (dest == "Known.md").then(|| "/leafs/1".to_string());
+        let html = to_html(
+            "[a](Known.md) [b](Missing.md) [c](https://example.com) ![d](x.png)",
+            resolve,
+            |dest| Some(format!("asset://{}", dest)),
+        );
+        assert_eq!(
+            html,
+            "<p><a href=\"/leafs/1\">a</a> b <a href=\"https://example.com\">c</a> <img src=\"asset://x.png\" alt=\"d\" /></p>\n"
+        );
+    }
+
+    #[test]
+    fn writes_editor_html_as_markdown() {
+        let content = concat!(
+            "<h1>Title</h1>",
+            "<p>Some <strong>bold</strong>, <em>italic</em> and <code>code</code> with a * star</p>",
+            "<ol><li><p>One</p></li><li><p>Two</p></li></ol>",
+            "<pre><code class=\"language-rust\">fn main() {}\n</code></pre>",
+            "<table><tr><th>A</th><th>B</th></tr><tr><td>1</td><td>x|y</td></tr></table>",
+            "<p><a href=\"/leafs/1\">link</a> <img src=\"a b.png\" alt=\"pic\"></p>",
+        );
+        assert_eq!(
+            from_html(content),
+            concat!(
+                "# Title\n\n",
+                "Some **bold**, *italic* and `code` with a \\* star\n\n",
+                "1. One\n2. Two\n\n",
+                "```rust\nfn main() {}\n```\n\n",
+                "| A | B |\n| --- | --- |\n| 1 | x\\|y |\n\n",
+                "[link](/leafs/1) ![pic](<a b.png>)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn block_ids_survive_a_round_trip() {
+        let content = concat!(
+            "<h2 blockid=\"a1\">Title</h2>",
+            "<p blockid=\"b2\">Some <strong>bold</strong> text</p>",
+            "<ul data-type=\"taskList\"><li data-type=\"taskItem\" data-checked=\"false\">",
+            "<label><input type=\"checkbox\"><span></span></label><div><p blockid=\"c3\">Task</p></div></li></ul>",
+            "<p>No id</p>",
+            "<ul><li><p blockid=\"d4\">Item</p><ul><li><p blockid=\"e5\">Nested</p></li></ul></li></ul>",
+            "<blockquote><p blockid=\"f6\">Quote</p></blockquote>",
+        );
+        let markdown = from_html(content);
+        assert!(markdown.contains("## Title {#a1}"));
+        assert!(markdown.contains("- [ ] Task {#c3}"));
+
+        let round_trip = to_html(&markdown, |_| None, |_| None);
+        let mut ids = Vec::new();
+        block_ids(&html::parse(&round_trip), &mut ids);
+        let expected = [
+            ("a1", "Title"),
+            ("b2", "Some bold text"),
+            ("c3", "Task"),
+            ("d4", "Item"),
+            ("e5", "Nested"),
+            ("f6", "Quote"),
+        ];
+        assert_eq!(
+            ids,
+            expected.map(|(id, text)| (id.to_string(), text.to_string()))
+        );
+        assert!(!round_trip.contains("{#"));
+        assert_eq!(from_html(&round_trip), markdown);
+    }
+
+    #[test]
+    fn braces_that_are_not_block_ids_stay_text() {
+        let html = to_html("Set {#a_b} and x{#c}\n", |_| None, |_| None);
+        assert_eq!(html, "<p>Set {#a_b} and x{#c}</p>\n");
+    }
+}
@@ -0,0 +1,373 @@
+use crate::db::{Leaf, Sage, SqlDatabase};
+use crate::html;
+use crate::markdown;
+use crate::settings::McpAccess;
+use serde_json::{json, Value};
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+// Model Context Protocol server: JSON-RPC 2.0 messages exposing leaves and
+// sages as resources plus a few tools. `handle` is shared by the stdio
+// sidecar and the HTTP transport in `api`.
+
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+const DEFAULT_LIMIT: i32 = 10;
+const SNIPPET_CHARS: usize = 200;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+struct RpcError(i64, String);
+
+fn invalid_params(message: impl Into<String>) -> RpcError {
+    RpcError(INVALID_PARAMS, message.into())
+}
+
+fn internal(e: impl std::fmt::Display) -> RpcError {
+    RpcError(INTERNAL_ERROR, e.to_string())
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": error.0, "message": error.1 } })
+}
+
+fn string_arg(args: &Value, name: &str) -> Result<String, RpcError> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| invalid_params(format!("Missing string argument `{}`", name)))
+}
+
+fn limit_arg(args: &Value) -> i32 {
+    args.get("limit")
+        .and_then(Value::as_i64)
+        .map(|limit| limit.clamp(1, 100) as i32)
+        .unwrap_or(DEFAULT_LIMIT)
+}
+
+fn snippet(content: &str) -> String {
+    let text = html::text_content(content);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+fn leaf_markdown(leaf: &Leaf) -> String {
+    format!("# {}\n\n{}", leaf.name(), markdown::from_html(leaf.content()))
+}
+
+// Markdown from an agent, as the HTML the editor stores.
+fn to_html(text: &str) -> String {
+    markdown::to_html(text, |dest| Some(dest.to_string()), |src| Some(src.to_string()))
+}
+
+fn text_result(text: String) -> Value {
+    json!({ "content": [{ "type": "text", "text": text }] })
+}
+
+// -------------------------------------------------------
+
+fn tools(access: McpAccess) -> Vec<Value> {
+    let mut tools = vec![
+        json!({
+            "name": "search_notes",
+            "description": "Find leaves (notes) whose title or text contains the query.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "limit": { "type": "integer", "minimum": 1, "maximum": 100 }
+                },
+                "required": ["query"]
+            },
+            "annotations": { "readOnlyHint": true }
+        }),
+        json!({
+            "name": "semantic_search",
+            "description": "Find leaves and sages closest in meaning to the query.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "limit": { "type": "integer", "minimum": 1, "maximum": 100 }
+                },
+                "required": ["query"]
+            },
+            "annotations": { "readOnlyHint": true }
+        }),
+        json!({
+            "name": "read_leaf",
+            "description": "Read a leaf as Markdown.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "id": { "type": "string" } },
+                "required": ["id"]
+            },
+            "annotations": { "readOnlyHint": true }
+        }),
+    ];
+    if access == McpAccess::ReadWrite {
+        tools.push(json!({
+            "name": "create_leaf",
+            "description": "Create a leaf from Markdown and return its id.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "content": { "type": "string", "description": "Markdown" }
+                },
+                "required": ["name"]
+            }
+        }));
+        tools.push(json!({
+            "name": "append_to_leaf",
+            "description": "Add Markdown to the end of a leaf.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "content": { "type": "string", "description": "Markdown" }
+                },
+                "required": ["id", "content"]
+            }
+        }));
+    }
+    tools
+}
+
+async fn read_leaf(db: &SqlDatabase, id: &str) -> Result<Leaf, RpcError> {
+    db.read::<Leaf>(id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| invalid_params(format!("Leaf {} not found", id)))
+}
+
+async fn call_tool(
+    db: &SqlDatabase,
+    access: McpAccess,
+    name: &str,
+    args: &Value,
+) -> Result<Value, RpcError> {
+    let writes = matches!(name, "create_leaf" | "append_to_leaf");
+    if writes && access != McpAccess::ReadWrite {
+        return Err(invalid_params(format!(
+            "{} needs read-write access, which is turned off in Bonsai's settings",
+            name
+        )));
+    }
+
+    match name {
+        "search_notes" => {
+            let query = string_arg(args, "query")?;
+            let leaves = db
+                .search::<Leaf>(&query, limit_arg(args))
+                .await
+                .map_err(internal)?;
+            if leaves.is_empty() {
+                return Ok(text_result("No matching leaves".to_string()));
+            }
+            let lines: Vec<String> = leaves
+                .iter()
+                .map(|leaf| format!("- {} (id: {})\n  {}", leaf.name(), leaf.id(), snippet(leaf.content())))
+                .collect();
+            Ok(text_result(lines.join("\n")))
+        }
+        "semantic_search" => {
+            let query = string_arg(args, "query")?;
+            let similar = db
+                .find_similar(&query, limit_arg(args))
+                .await
+                .map_err(internal)?;
+            let mut lines = Vec::new();
+            for (id, object_type, distance) in similar {
+                let line = match object_type.as_str() {
+                    "leaf" => db.read::<Leaf>(&id).await.map_err(internal)?.map(|leaf| {
+                        format!("- leaf {} (id: {}, distance {:.3})\n  {}", leaf.name(), id, distance, snippet(leaf.content()))
+                    }),
+                    "sage" => db.read::<Sage>(&id).await.map_err(internal)?.map(|sage| {
+                        format!("- sage {} (id: {}, distance {:.3})\n  {}", sage.name(), id, distance, snippet(sage.description()))
+                    }),
+                    _ => None,
+                };
+                lines.extend(line);
+            }
+            if lines.is_empty() {
+                return Ok(text_result("Nothing similar found".to_string()));
+            }
+            Ok(text_result(lines.join("\n")))
+        }
+        "read_leaf" => {
+            let leaf = read_leaf(db, &string_arg(args, "id")?).await?;
+            Ok(text_result(leaf_markdown(&leaf)))
+        }
+        "create_leaf" => {
+            let name = string_arg(args, "name")?;
+            let content = args.get("content").and_then(Value::as_str).unwrap_or_default();
+            let leaf = Leaf::new(uuid::Uuid::new_v4().to_string(), name, to_html(content));
+            let id = db.create(leaf).await.map_err(internal)?;
+            Ok(text_result(format!("Created leaf {}", id)))
+        }
+        "append_to_leaf" => {
+            let leaf = read_leaf(db, &string_arg(args, "id")?).await?;
+            let addition = to_html(&string_arg(args, "content")?);
+            let content = format!("{}{}", leaf.content(), addition);
+            db.update(Leaf::new(leaf.id().to_string(), leaf.name().to_string(), content))
+                .await
+                .map_err(internal)?;
+            Ok(text_result(format!("Appended to leaf {}", leaf.id())))
+        }
+        _ => unreachable!("tools/call checks the tool exists"),
+    }
+}
+
+// -------------------------------------------------------
+
+async fn list_resources(db: &SqlDatabase) -> Result<Value, RpcError> {
+    let mut resources = Vec::new();
+    for leaf in db.list::<Leaf>().await.map_err(internal)? {
+        resources.push(json!({
+            "uri": format!("bonsai://leaf/{}", leaf.id()),
+            "name": leaf.name(),
+            "mimeType": "text/markdown"
+        }));
+    }
+    for sage in db.list::<Sage>().await.map_err(internal)? {
+        resources.push(json!({
+            "uri": format!("bonsai://sage/{}", sage.id()),
+            "name": sage.name(),
+            "mimeType": "text/plain"
+        }));
+    }
+    Ok(json!({ "resources": resources }))
+}
+
+async fn read_resource(db: &SqlDatabase, uri: &str) -> Result<Value, RpcError> {
+    let not_found = || RpcError(-32002, format!("Resource {} not found", uri));
+    let (mime_type, text) = if let Some(id) = uri.strip_prefix("bonsai://leaf/") {
+        let leaf = db.read::<Leaf>(id).await.map_err(internal)?.ok_or_else(not_found)?;
+        ("text/markdown", leaf_markdown(&leaf))
+    } else if let Some(id) = uri.strip_prefix("bonsai://sage/") {
+        let sage = db.read::<Sage>(id).await.map_err(internal)?.ok_or_else(not_found)?;
+        ("text/plain", format!("{}\n\n{}", sage.name(), sage.description()))
+    } else {
+        return Err(not_found());
+    };
+    Ok(json!({ "contents": [{ "uri": uri, "mimeType": mime_type, "text": text }] }))
+}
+
+async fn dispatch(
+    db: &SqlDatabase,
+    access: McpAccess,
+    method: &str,
+    params: &Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "initialize" => {
+            let requested = params.get("protocolVersion").and_then(Value::as_str);
+            let version = requested
+                .filter(|v| PROTOCOL_VERSIONS.contains(v))
+                .unwrap_or(PROTOCOL_VERSIONS[0]);
+            Ok(json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {}, "resources": {} },
+                "serverInfo": { "name": "bonsai", "version": env!("CARGO_PKG_VERSION") },
+                "instructions": "Bonsai is a note base. Notes are called leaves; sages are AI personas."
+            }))
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools(access) })),
+        "tools/call" => {
+            let name = params
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid_params("Missing tool name"))?;
+            if !tools(McpAccess::ReadWrite).iter().any(|tool| tool["name"] == name) {
+                return Err(invalid_params(format!("Unknown tool {}", name)));
+            }
+            let args = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+            // Failures inside a tool are reported to the model rather than
+            // as protocol errors
+            Ok(call_tool(db, access, name, &args)
+                .await
+                .unwrap_or_else(|RpcError(_, message)| {
+                    json!({ "content": [{ "type": "text", "text": message }], "isError": true })
+                }))
+        }
+        "resources/list" => list_resources(db).await,
+        "resources/templates/list" => Ok(json!({
+            "resourceTemplates": [
+                { "uriTemplate": "bonsai://leaf/{id}", "name": "Leaf", "mimeType": "text/markdown" },
+                { "uriTemplate": "bonsai://sage/{id}", "name": "Sage", "mimeType": "text/plain" }
+            ]
+        })),
+        "resources/read" => {
+            let uri = params
+                .get("uri")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid_params("Missing uri"))?;
+            read_resource(db, uri).await
+        }
+        _ => Err(RpcError(METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+    }
+}
+
+async fn handle_one(db: &SqlDatabase, access: McpAccess, message: Value) -> Option<Value> {
+    let id = message.get("id").cloned();
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        // Responses to requests we never send are ignored
+        return match id {
+            Some(_) if message.get("result").is_some() || message.get("error").is_some() => None,
+            _ => Some(error_response(
+                id.unwrap_or(Value::Null),
+                RpcError(INVALID_REQUEST, "Missing method".to_string()),
+            )),
+        };
+    };
+    let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+    let result = dispatch(db, access, method, &params).await;
+    // Notifications get no response
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    })
+}
+
+// Handles a message or batch and returns what to send back, if anything.
+pub async fn handle(db: &SqlDatabase, access: McpAccess, message: Value) -> Option<Value> {
+    match message {
+        Value::Array(batch) => {
+            let mut responses = Vec::new();
+            for message in batch {
+                responses.extend(handle_one(db, access, message).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        message => handle_one(db, access, message).await,
+    }
+}
+
+// Serves newline-delimited messages on stdin and stdout until stdin closes.
+pub async fn serve_stdio(db: &SqlDatabase, access: McpAccess) -> io::Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(message) => handle(db, access, message).await,
+            Err(e) => Some(error_response(Value::Null, RpcError(PARSE_ERROR, e.to_string()))),
+        };
+        if let Some(response) = response {
+            stdout.write_all(format!("{}\n", response).as_bytes()).await?;
+            stdout.flush().await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,737 @@
+use crate::db::{Leaf, SqlDatabase, TimeStamped};
+use crate::markdown;
+use chrono::Utc;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+// Two-way mirror of every leaf into a folder of Markdown files. Each file
+// carries the leaf id in its front matter, so renames and moves inside the
+// folder are followed instead of producing duplicates.
+
+const STATE_FILE: &str = ".bonsai-mirror.json";
+const DEBOUNCE: Duration = Duration::from_millis(500);
+// Leaf edits made in the app are picked up on this interval.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// What both sides looked like after the last successful sync.
+#[derive(Default, Serialize, Deserialize)]
+struct MirrorState {
+    files: HashMap<String, FileState>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileState {
+    path: String,
+    hash: String,
+    modified_at: String,
+}
+
+#[derive(Serialize)]
+struct FileFrontMatter<'a> {
+    id: &'a str,
+    title: &'a str,
+    created: &'a str,
+    modified: &'a str,
+}
+
+struct DiskFile {
+    path: String,
+    text: String,
+    hash: String,
+    id: Option<String>,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSummary {
+    pub exported: usize,
+    pub imported: usize,
+    pub created: usize,
+    pub deleted: usize,
+    pub conflicts: Vec<String>,
+}
+
+fn hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn load_state(dir: &Path) -> MirrorState {
+    fs::read_to_string(dir.join(STATE_FILE))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn save_state(dir: &Path, state: &MirrorState) -> io::Result<()> {
+    fs::write(dir.join(STATE_FILE), serde_json::to_string_pretty(state)?)
+}
+
+// The leaf as a Markdown file with its metadata in front matter.
+pub fn render_file(leaf: &Leaf) -> String {
+    let front_matter = serde_yaml::to_string(&FileFrontMatter {
+        id: leaf.id(),
+        title: leaf.name(),
+        created: leaf.created_at(),
+        modified: leaf.modified_at(),
+    })
+    .unwrap_or_default();
+    format!(
+        "---\n{}---\n\n{}",
+        front_matter,
+        markdown::from_html(leaf.content())
+    )
+}
+
+fn scan_dir(root: &Path, dir: &Path, files: &mut Vec<DiskFile>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            scan_dir(root, &path, files)?;
+            continue;
+        }
+        if path.extension().and_then(|e| e.to_str()) != Some("md") {
+            continue;
+        }
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let id = markdown::split_front_matter(&text)
+            .0
+            .map(markdown::parse_front_matter)
+            .and_then(|front_matter| front_matter.id);
+        files.push(DiskFile {
+            path: path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/"),
+            hash: hash(&text),
+            text,
+            id,
+        });
+    }
+    Ok(())
+}
+
+// A file name for a new leaf that does not clash with existing files.
+pub fn unique_path(name: &str, taken: &HashSet<String>, suffix: &str) -> String {
+    let base: String = name
+        .chars()
+        .map(|c| if "/\\:*?\"<>|".contains(c) || c.is_control() { '-' } else { c })
+        .collect();
+    let base = base.trim().trim_start_matches('.');
+    let base = if base.is_empty() { "Untitled" } else { base };
+
+    let mut candidate = format!("{}{}.md", base, suffix);
+    let mut n = 2;
+    while taken.contains(&candidate.to_lowercase()) {
+        candidate = format!("{}{} {}.md", base, suffix, n);
+        n += 1;
+    }
+    candidate
+}
+
+// Leaf name and HTML content from a Markdown file.
+fn parse_file(file: &DiskFile) -> (Option<String>, String) {
+    let (front_matter, body) = markdown::split_front_matter(&file.text);
+    let title = front_matter
+        .map(markdown::parse_front_matter)
+        .and_then(|front_matter| front_matter.title)
+        .or_else(|| Some(markdown::title_from_path(&file.path)));
+    (title, markdown::to_html(body, |_| None, |_| None))
+}
+
+// -------------------------------------------------------
+
+// Runs one reconciliation pass between the database and `dir`.
+pub async fn sync_once(db: &SqlDatabase, dir: &Path) -> io::Result<SyncSummary> {
+    fs::create_dir_all(dir)?;
+    let mut state = load_state(dir);
+    let mut summary = SyncSummary::default();
+
+    let leaves = db.list::<Leaf>().await.map_err(to_io_error)?;
+    let mut disk = Vec::new();
+    scan_dir(dir, dir, &mut disk)?;
+
+    let mut taken: HashSet<String> = disk.iter().map(|f| f.path.to_lowercase()).collect();
+    let by_id: HashMap<&str, &DiskFile> = disk
+        .iter()
+        .filter_map(|f| f.id.as_deref().map(|id| (id, f)))
+        .collect();
+    let leaf_ids: HashSet<&str> = leaves.iter().map(|leaf| leaf.id()).collect();
+    let mut next_state = MirrorState::default();
+
+    // An external editor may save a file without its front matter id. Such
+    // files are matched back to their leaf by the path or text they had, so
+    // the leaf is neither deleted nor imported again under a new id.
+    let mut adopted: HashMap<&str, &DiskFile> = HashMap::new();
+    for leaf in &leaves {
+        let Some(known) = state.files.get(leaf.id()) else {
+            continue;
+        };
+        if by_id.contains_key(leaf.id()) {
+            continue;
+        }
+        let rendered = hash(&strip_id(&render_file(leaf)));
+        let mut untracked = disk.iter().filter(|f| {
+            !f.id.as_deref().is_some_and(|id| leaf_ids.contains(id))
+                && !adopted.values().any(|a| a.path == f.path)
+        });
+        let file = untracked
+            .clone()
+            .find(|f| f.path == known.path)
+            .or_else(|| untracked.find(|f| f.hash == known.hash || f.hash == rendered));
+        if let Some(file) = file {
+            adopted.insert(leaf.id(), file);
+        }
+    }
+
+    for leaf in &leaves {
+        let known = state.files.remove(leaf.id());
+        let file = by_id.get(leaf.id()).or_else(|| adopted.get(leaf.id())).copied();
+
+        match (known, file) {
+            // Tracked and still on disk: compare both sides with the last pass
+            (Some(known), Some(file)) => {
+                let db_changed = leaf.modified_at() != known.modified_at;
+                let disk_changed = file.hash != known.hash;
+                match (db_changed, disk_changed) {
+                    (false, false) => {
+                        next_state.files.insert(
+                            leaf.id().to_string(),
+                            FileState {
+                                path: file.path.clone(),
+                                ..known
+                            },
+                        );
+                    }
+                    (true, false) => {
+                        let entry = write_leaf(dir, leaf, &file.path)?;
+                        next_state.files.insert(leaf.id().to_string(), entry);
+                        summary.exported += 1;
+                    }
+                    (false, true) => {
+                        let mut entry = import_file(db, leaf, file).await?;
+                        // Write the id back into a file that lost it
+                        if file.id.as_deref() != Some(leaf.id()) {
+                            if let Some(leaf) = db.read::<Leaf>(leaf.id()).await.map_err(to_io_error)? {
+                                entry = write_leaf(dir, &leaf, &file.path)?;
+                            }
+                        }
+                        next_state.files.insert(leaf.id().to_string(), entry);
+                        summary.imported += 1;
+                    }
+                    (true, true) => {
+                        // Keep the app's version in place and the external
+                        // edit next to it as a conflict copy without an id,
+                        // which the next pass imports as a new leaf.
+                        let conflict = conflict_path(&file.path, &taken);
+                        taken.insert(conflict.to_lowercase());
+                        fs::write(dir.join(&conflict), strip_id(&file.text))?;
+                        let entry = write_leaf(dir, leaf, &file.path)?;
+                        next_state.files.insert(leaf.id().to_string(), entry);
+                        summary.conflicts.push(conflict);
+                    }
+                }
+            }
+            // The file was deleted outside the app. The leaf goes to the
+            // trash, so a mistaken delete keeps its id, tags and links.
+            (Some(known), None) => {
+                if leaf.modified_at() == known.modified_at {
+                    db.trash_leaf(leaf.id(), "Its file was deleted from the Markdown mirror")
+                        .await
+                        .map_err(to_io_error)?;
+                    summary.deleted += 1;
+                } else {
+                    let path = unique_path(leaf.name(), &taken, "");
+                    taken.insert(path.to_lowercase());
+                    let entry = write_leaf(dir, leaf, &path)?;
+                    next_state.files.insert(leaf.id().to_string(), entry);
+                    summary.exported += 1;
+                }
+            }
+            // First time both sides meet, e.g. after the state file was lost.
+            // Without a common base, differing text is kept as a conflict.
+            (None, Some(file)) => {
+                if hash(&render_file(leaf)) != file.hash {
+                    let conflict = conflict_path(&file.path, &taken);
+                    taken.insert(conflict.to_lowercase());
+                    fs::write(dir.join(&conflict), strip_id(&file.text))?;
+                    summary.conflicts.push(conflict);
+                }
+                let entry = write_leaf(dir, leaf, &file.path)?;
+                next_state.files.insert(leaf.id().to_string(), entry);
+                summary.exported += 1;
+            }
+            (None, None) => {
+                let path = unique_path(leaf.name(), &taken, "");
+                taken.insert(path.to_lowercase());
+                let entry = write_leaf(dir, leaf, &path)?;
+                next_state.files.insert(leaf.id().to_string(), entry);
+                summary.exported += 1;
+            }
+        }
+    }
+
+    // Entries left in the old state belong to leaves deleted in the app.
+    // Their files go too, unless they were edited in the meantime.
+    let mut removed = HashSet::new();
+    for (id, known) in state.files.drain() {
+        if let Some(file) = by_id.get(id.as_str()) {
+            if file.hash == known.hash {
+                fs::remove_file(dir.join(&file.path))?;
+                removed.insert(file.path.clone());
+                summary.deleted += 1;
+            }
+        }
+    }
+
+    // Files without a known leaf become new leaves. That includes copies of
+    // a tracked file, which share its id but are not the file we follow.
+    for file in &disk {
+        let id_in_use =
+            |id: &str| leaf_ids.contains(id) || next_state.files.contains_key(id);
+        let tracked = file.id.as_deref().is_some_and(|id| {
+            id_in_use(id) && by_id.get(id).is_some_and(|f| f.path == file.path)
+        });
+        let adopted = adopted.values().any(|a| a.path == file.path);
+        if tracked || adopted || removed.contains(&file.path) {
+            continue;
+        }
+
+        let (title, content) = parse_file(file);
+        let id = file
+            .id
+            .clone()
+            .filter(|id| uuid::Uuid::parse_str(id).is_ok() && !id_in_use(id))
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let leaf = Leaf::new(id.clone(), title.unwrap_or_default(), content);
+        db.create::<Leaf>(leaf).await.map_err(to_io_error)?;
+
+        // Write the id back so later passes can follow the file
+        if let Some(leaf) = db.read::<Leaf>(&id).await.map_err(to_io_error)? {
+            let entry = write_leaf(dir, &leaf, &file.path)?;
+            next_state.files.insert(id, entry);
+        }
+        summary.created += 1;
+    }
+
+    save_state(dir, &next_state)?;
+    Ok(summary)
+}
+
+fn write_leaf(dir: &Path, leaf: &Leaf, path: &str) -> io::Result<FileState> {
+    let text = render_file(leaf);
+    let full_path = dir.join(path);
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&full_path, &text)?;
+    Ok(FileState {
+        path: path.to_string(),
+        hash: hash(&text),
+        modified_at: leaf.modified_at().to_string(),
+    })
+}
+
+async fn import_file(db: &SqlDatabase, leaf: &Leaf, file: &DiskFile) -> io::Result<FileState> {
+    let (title, content) = parse_file(file);
+    let updated = Leaf::new(
+        leaf.id().to_string(),
+        title.unwrap_or_else(|| leaf.name().to_string()),
+        content,
+    );
+    db.update::<Leaf>(updated).await.map_err(to_io_error)?;
+
+    // Record the new timestamp so the next pass does not write the file back
+    let modified_at = db
+        .read::<Leaf>(leaf.id())
+        .await
+        .map_err(to_io_error)?
+        .map(|leaf| leaf.modified_at().to_string())
+        .unwrap_or_default();
+    Ok(FileState {
+        path: file.path.clone(),
+        hash: file.hash.clone(),
+        modified_at,
+    })
+}
+
+fn conflict_path(path: &str, taken: &HashSet<String>) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    let stem = name.strip_suffix(".md").unwrap_or(name);
+    let suffix = format!(" (conflict {})", Utc::now().format("%Y-%m-%d %H%M%S"));
+    let taken_in_dir: HashSet<String> = taken
+        .iter()
+        .filter_map(|p| p.strip_prefix(&dir.to_lowercase()).map(str::to_string))
+        .collect();
+    format!("{}{}", dir, unique_path(stem, &taken_in_dir, &suffix))
+}
+
+fn strip_id(text: &str) -> String {
+    let (front_matter, body) = markdown::split_front_matter(text);
+    match front_matter {
+        Some(yaml) => {
+            let yaml: String = yaml
+                .lines()
+                .filter(|line| !line.starts_with("id:"))
+                .map(|line| format!("{}\n", line))
+                .collect();
+            format!("---\n{}---\n{}", yaml, body)
+        }
+        None => text.to_string(),
+    }
+}
+
+// -------------------------------------------------------
+
+struct MirrorTask {
+    dir: PathBuf,
+    _watcher: RecommendedWatcher,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+// Managed state owning the background watcher, if a mirror is running.
+#[derive(Default)]
+pub struct MarkdownMirror {
+    running: Mutex<Option<MirrorTask>>,
+    // Serializes passes from the watcher and from `sync_markdown_mirror`
+    sync_lock: tokio::sync::Mutex<()>,
+}
+
+impl MarkdownMirror {
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|task| task.dir.clone())
+    }
+
+    pub async fn sync(&self, db: &SqlDatabase, dir: &Path) -> io::Result<SyncSummary> {
+        let _guard = self.sync_lock.lock().await;
+        sync_once(db, dir).await
+    }
+
+    pub fn start(&self, app: AppHandle, dir: PathBuf) -> io::Result<()> {
+        self.stop();
+        fs::create_dir_all(&dir)?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                // Ignore our own bookkeeping file
+                let relevant = event.paths.iter().any(|path| {
+                    !path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+                });
+                if relevant {
+                    let _ = tx.send(());
+                }
+            }
+        })
+        .map_err(to_io_error)?;
+        watcher
+            .watch(&dir, RecursiveMode::Recursive)
+            .map_err(to_io_error)?;
+
+        let task_dir = dir.clone();
+        let task = tauri::async_runtime::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    event = rx.recv() => {
+                        if event.is_none() {
+                            break;
+                        }
+                        // An editor save is a burst of events; wait for it to settle
+                        while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {}
+                    }
+                }
+
+                let db = app.state::<SqlDatabase>();
+                let mirror = app.state::<MarkdownMirror>();
+                match mirror.sync(&db, &task_dir).await {
+                    Ok(summary) if !summary.conflicts.is_empty() => {
+                        eprintln!("Markdown mirror conflicts: {:?}", summary.conflicts);
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Markdown mirror sync failed: {}", e),
+                }
+            }
+        });
+
+        *self.running.lock().unwrap() = Some(MirrorTask {
+            dir,
+            _watcher: watcher,
+            task,
+        });
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().unwrap().take() {
+            running.task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ollama;
+    use crate::settings::AiSettings;
+    use std::sync::OnceLock;
+
+    const ID: &str = "5b0f0c1e-4a8e-4a59-9d1c-6f1f3c2b9a10";
+
+    // Imports are embedded like any other write, so the tests answer for
+    // the model with a local server returning empty vectors.
+    fn fake_model() {
+        static PORT: OnceLock<u16> = OnceLock::new();
+        let port = *PORT.get_or_init(|| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let port = listener.local_addr().unwrap().port();
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                runtime.block_on(async move {
+                    let app = axum::Router::new().route(
+                        "/api/embed",
+                        axum::routing::post(|| async {
+                            axum::Json(serde_json::json!({ "embeddings": [vec![0.0f32; 3072]] }))
+                        }),
+                    );
+                    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                    axum::serve(listener, app).await.unwrap();
+                });
+            });
+            port
+        });
+        let settings = AiSettings {
+            ollama_host: "http://127.0.0.1".to_string(),
+            ollama_port: port,
+            ..AiSettings::default()
+        };
+        ollama::configure(&settings, None);
+    }
+
+    // A workspace database and its mirror folder, removed when dropped.
+    struct Mirror {
+        db: SqlDatabase,
+        root: PathBuf,
+        dir: PathBuf,
+    }
+
+    impl Mirror {
+        async fn new() -> Mirror {
+            fake_model();
+            let root = std::env::temp_dir().join(format!("bonsai-mirror-test-{}", uuid::Uuid::new_v4()));
+            let db = SqlDatabase::open(root.clone(), None).await.unwrap();
+            Mirror {
+                db,
+                dir: root.join("mirror"),
+                root,
+            }
+        }
+
+        // A mirror with the leaf `ID` written out as `Plan.md`.
+        async fn with_plan() -> Mirror {
+            let mirror = Mirror::new().await;
+            let leaf = Leaf::new(ID.to_string(), "Plan".to_string(), "<p>First draft</p>".to_string());
+            mirror.db.create(leaf).await.unwrap();
+            assert_eq!(mirror.sync().await.exported, 1);
+            mirror
+        }
+
+        async fn sync(&self) -> SyncSummary {
+            sync_once(&self.db, &self.dir).await.unwrap()
+        }
+
+        fn read(&self, path: &str) -> String {
+            fs::read_to_string(self.dir.join(path)).unwrap()
+        }
+
+        fn write(&self, path: &str, text: &str) {
+            let path = self.dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+
+        fn files(&self) -> Vec<String> {
+            let mut files = Vec::new();
+            scan_dir(&self.dir, &self.dir, &mut files).unwrap();
+            let mut paths: Vec<String> = files.into_iter().map(|file| file.path).collect();
+            paths.sort();
+            paths
+        }
+
+        async fn leaves(&self) -> Vec<Leaf> {
+            let mut leaves = self.db.list::<Leaf>().await.unwrap();
+            leaves.sort_by(|a, b| a.name().cmp(b.name()));
+            leaves
+        }
+    }
+
+    impl Drop for Mirror {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_renamed_files_by_their_id() {
+        let mirror = Mirror::with_plan().await;
+        fs::create_dir_all(mirror.dir.join("notes")).unwrap();
+        fs::rename(mirror.dir.join("Plan.md"), mirror.dir.join("notes/Renamed.md")).unwrap();
+
+        let summary = mirror.sync().await;
+        assert_eq!((summary.created, summary.deleted), (0, 0));
+        assert_eq!(mirror.files(), ["notes/Renamed.md"]);
+
+        let text = mirror.read("notes/Renamed.md").replace("First draft", "Moved and edited");
+        mirror.write("notes/Renamed.md", &text);
+        assert_eq!(mirror.sync().await.imported, 1);
+        let leaves = mirror.leaves().await;
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].content(), "<p>Moved and edited</p>\n");
+    }
+
+    #[tokio::test]
+    async fn adopts_files_that_lost_their_id() {
+        let mirror = Mirror::with_plan().await;
+
+        // Saved in place without the id, and edited
+        let text = strip_id(&mirror.read("Plan.md")).replace("First draft", "Edited outside");
+        mirror.write("Plan.md", &text);
+        let summary = mirror.sync().await;
+        assert_eq!((summary.imported, summary.created), (1, 0));
+        assert!(mirror.read("Plan.md").contains(&format!("id: {}", ID)));
+
+        // Renamed without the id, text unchanged
+        let text = strip_id(&mirror.read("Plan.md"));
+        fs::remove_file(mirror.dir.join("Plan.md")).unwrap();
+        mirror.write("Other.md", &text);
+        let summary = mirror.sync().await;
+        assert_eq!((summary.created, summary.deleted), (0, 0));
+        assert!(mirror.read("Other.md").contains(&format!("id: {}", ID)));
+
+        let leaves = mirror.leaves().await;
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].content(), "<p>Edited outside</p>\n");
+    }
+
+    #[tokio::test]
+    async fn imports_new_files_without_an_id() {
+        let mirror = Mirror::new().await;
+        mirror.write("Idea.md", "Just text\n");
+
+        assert_eq!(mirror.sync().await.created, 1);
+        let leaves = mirror.leaves().await;
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].name(), "Idea");
+        assert_eq!(leaves[0].content(), "<p>Just text</p>\n");
+        assert!(mirror.read("Idea.md").contains(&format!("id: {}", leaves[0].id())));
+
+        assert_eq!(mirror.sync().await.created, 0);
+        assert_eq!(mirror.leaves().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_conflicting_edits_as_a_copy() {
+        let mirror = Mirror::with_plan().await;
+        let edit = Leaf::new(ID.to_string(), String::new(), "<p>App edit</p>".to_string());
+        mirror.db.update(edit).await.unwrap();
+        let text = mirror.read("Plan.md").replace("First draft", "File edit");
+        mirror.write("Plan.md", &text);
+
+        let summary = mirror.sync().await;
+        assert_eq!(summary.conflicts.len(), 1);
+        let conflict = mirror.read(&summary.conflicts[0]);
+        assert!(conflict.contains("File edit"));
+        assert!(!conflict.contains("id:"));
+        assert!(mirror.read("Plan.md").contains("App edit"));
+
+        // The copy becomes a leaf of its own on the next pass
+        assert_eq!(mirror.sync().await.created, 1);
+        assert_eq!(mirror.leaves().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn trashes_leaves_whose_file_was_deleted() {
+        let mirror = Mirror::with_plan().await;
+        let db = &mirror.db;
+        db.set_tags(ID, &["work".to_string()]).await.unwrap();
+        db.add_journal_entry("2024-03-09", ID).await.unwrap();
+        db.set_shared(ID, true).await.unwrap();
+        let now = Utc::now().to_rfc3339();
+        db.add_reminder("r1", ID, None, "Call back", "2030-01-01T09:00:00Z", &now)
+            .await
+            .unwrap();
+
+        fs::remove_file(mirror.dir.join("Plan.md")).unwrap();
+        assert_eq!(mirror.sync().await.deleted, 1);
+        assert!(mirror.leaves().await.is_empty());
+        let trash = db.list_trash().await.unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].leaf_id, ID);
+        assert_eq!(db.journal_entry("2024-03-09").await.unwrap(), None);
+
+        // Everything `delete` dropped comes back with it
+        assert!(db.restore_leaf(ID).await.unwrap());
+        assert_eq!(db.read::<Leaf>(ID).await.unwrap().unwrap().content(), "<p>First draft</p>");
+        assert_eq!(db.list_tags(ID).await.unwrap(), ["work"]);
+        assert_eq!(db.journal_entry("2024-03-09").await.unwrap().as_deref(), Some(ID));
+        assert!(db.is_shared(ID).await.unwrap());
+        let reminders = db.list_reminders(Some(ID), true).await.unwrap();
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].note, "Call back");
+        assert!(db.list_trash().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn first_meeting_keeps_differing_files_as_conflicts() {
+        let mirror = Mirror::with_plan().await;
+
+        // Same text on both sides: nothing to keep
+        fs::remove_file(mirror.dir.join(STATE_FILE)).unwrap();
+        let summary = mirror.sync().await;
+        assert!(summary.conflicts.is_empty());
+        assert_eq!(mirror.files(), ["Plan.md"]);
+
+        // Different text and no base to merge from
+        fs::remove_file(mirror.dir.join(STATE_FILE)).unwrap();
+        let text = mirror.read("Plan.md").replace("First draft", "Older copy");
+        mirror.write("Plan.md", &text);
+        let summary = mirror.sync().await;
+        assert_eq!(summary.conflicts.len(), 1);
+        assert!(mirror.read(&summary.conflicts[0]).contains("Older copy"));
+        assert!(mirror.read("Plan.md").contains("First draft"));
+        assert_eq!(mirror.leaves().await.len(), 1);
+    }
+}
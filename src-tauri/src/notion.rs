@@ -0,0 +1,315 @@
+use crate::db::{Leaf, SqlDatabase};
+use crate::filesystem::Database;
+use crate::html;
+use crate::importer::{
+    self, file_name, parent_dir, resolve_attachment, resolve_link, write_leaves, Attachments,
+    FailedFile, ImportReport, PathIndex, PendingLeaf,
+};
+use crate::markdown;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+// Notion appends a 32 character hex id to every exported file and folder
+// name: `Meeting notes 1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d.md`.
+fn strip_notion_id(name: &str) -> &str {
+    match name.rsplit_once(' ') {
+        Some((title, id)) if id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()) => title,
+        _ => name,
+    }
+}
+
+fn stem(path: &str) -> &str {
+    let name = file_name(path);
+    name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name)
+}
+
+fn extension(path: &str) -> String {
+    file_name(path)
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+enum Kind {
+    Markdown,
+    Html,
+    Database,
+}
+
+fn kind(path: &str) -> Option<Kind> {
+    match extension(path).as_str() {
+        "md" => Some(Kind::Markdown),
+        "html" | "htm" => Some(Kind::Html),
+        "csv" => Some(Kind::Database),
+        _ => None,
+    }
+}
+
+// The page a file is nested under: Notion stores the children of
+// `Parent <id>.md` in the folder `Parent <id>/`.
+fn parent_page(index: &PathIndex, ids: &HashMap<usize, String>, path: &str) -> Option<usize> {
+    let dir = parent_dir(path);
+    if dir.is_empty() {
+        return None;
+    }
+    let parent = parent_dir(dir);
+    let name = file_name(dir);
+    [".md", ".html", ".csv", "_all.csv"]
+        .iter()
+        .filter_map(|ext| index.resolve(parent, &html::encode_uri_component(&format!("{}{}", name, ext))))
+        .find(|i| ids.contains_key(i))
+}
+
+// -------------------------------------------------------
+
+// Imports a Notion "Markdown & CSV" or "HTML" export archive (or its
+// extracted folder), keeping the page hierarchy as nested leaves.
+pub async fn import_notion(
+    sql_db: &SqlDatabase,
+    db: &Database,
+    source: &Path,
+) -> io::Result<ImportReport> {
+    let files = importer::read_source(source)?;
+    let index = PathIndex::new(files.iter().map(|f| f.path.as_str()));
+    let mut report = ImportReport::default();
+
+    // Newer exports write every database twice; `_all.csv` has all rows.
+    let pages: Vec<usize> = (0..files.len())
+        .filter(|&i| kind(&files[i].path).is_some())
+        .filter(|&i| {
+            let path = &files[i].path;
+            !(extension(path) == "csv"
+                && !path.ends_with("_all.csv")
+                && index
+                    .resolve("", &html::encode_uri_component(&format!("{}_all.csv", &path[..path.len() - 4])))
+                    .is_some())
+        })
+        .collect();
+
+    let ids: HashMap<usize, String> = pages
+        .iter()
+        .map(|&i| (i, Uuid::new_v4().to_string()))
+        .collect();
+
+    let attachments = RefCell::new(Attachments::new(db, &files));
+    let mut pending = Vec::with_capacity(pages.len());
+
+    for &i in &pages {
+        let file = &files[i];
+        let Ok(text) = std::str::from_utf8(&file.data) else {
+            report.failed.push(FailedFile {
+                path: file.path.clone(),
+                error: "File is not valid UTF-8".to_string(),
+            });
+            continue;
+        };
+        let dir = parent_dir(&file.path);
+        let fallback_name = strip_notion_id(stem(&file.path).trim_end_matches("_all")).to_string();
+
+        let (name, content) = match kind(&file.path) {
+            Some(Kind::Markdown) => {
+                let content = markdown::to_html(
+                    text,
+                    |dest| resolve_link(&index, &ids, &attachments, dir, dest),
+                    |dest| resolve_attachment(&index, &attachments, dir, dest),
+                );
+                let name = text
+                    .lines()
+                    .find(|line| !line.trim().is_empty())
+                    .and_then(|line| line.strip_prefix("# "))
+                    .map(|title| title.trim().to_string())
+                    .unwrap_or(fallback_name);
+                (name, content)
+            }
+            Some(Kind::Html) => {
+                let title = html::inner_html(text, |t| html::has_class(t, "page-title"))
+                    .map(html::text_content)
+                    .filter(|title| !title.is_empty())
+                    .unwrap_or(fallback_name);
+                let body = html::inner_html(text, |t| html::has_class(t, "page-body"))
+                    .or_else(|| html::inner_html(text, |t| t.is_start("body")))
+                    .unwrap_or(text);
+                let body = html::rewrite_attrs(body, |tag, attr, value| match (tag, attr) {
+                    ("a", "href") => resolve_link(&index, &ids, &attachments, dir, value),
+                    ("img", "src") => resolve_attachment(&index, &attachments, dir, value),
+                    _ => None,
+                });
+                let content = format!("<h1>{}</h1>{}", html::escape(&title), body);
+                (title, content)
+            }
+            Some(Kind::Database) => {
+                let row_dir = file.path.trim_end_matches(".csv").trim_end_matches("_all");
+                let content = database_to_html(
+                    &fallback_name,
+                    text,
+                    |title| {
+                        let row = format!("{}.md", title);
+                        index
+                            .resolve(row_dir, &html::encode_uri_component(&row))
+                            .or_else(|| row_page(&files, row_dir, title))
+                            .and_then(|row| ids.get(&row))
+                            .map(|id| format!("/leafs/{}", id))
+                    },
+                );
+                (fallback_name, content)
+            }
+            None => continue,
+        };
+
+        pending.push(PendingLeaf {
+            path: file.path.clone(),
+            leaf: Leaf::new(ids[&i].clone(), name, content),
+            tags: Vec::new(),
+        });
+    }
+
+    // Remember where each page sits before the pending leaves are consumed
+    let parents: HashMap<String, (String, i64)> = pages
+        .iter()
+        .enumerate()
+        .filter_map(|(position, &i)| {
+            let parent = parent_page(&index, &ids, &files[i].path)?;
+            Some((files[i].path.clone(), (ids[&parent].clone(), position as i64)))
+        })
+        .collect();
+
+    write_leaves(sql_db, pending, &mut report).await;
+
+    for imported in &report.imported {
+        if let Some((parent_id, position)) = parents.get(&imported.path) {
+            if let Err(e) = sql_db.set_parent(&imported.leaf_id, parent_id, *position).await {
+                eprintln!("Failed to nest {} under its parent: {}", imported.path, e);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Database rows are exported as `<row title> <id>.md` next to the CSV.
+fn row_page(files: &[importer::SourceFile], row_dir: &str, title: &str) -> Option<usize> {
+    let prefix = format!("{}/", row_dir);
+    files.iter().position(|f| {
+        f.path.starts_with(&prefix)
+            && !f.path[prefix.len()..].contains('/')
+            && extension(&f.path) == "md"
+            && strip_notion_id(stem(&f.path)) == title
+    })
+}
+
+// Renders a Notion database as a table. The first column holds the row title
+// and links to the row's page when one was exported.
+fn database_to_html(
+    name: &str,
+    csv: &str,
+    mut row_link: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let rows = parse_csv(csv.strip_prefix('\u{feff}').unwrap_or(csv));
+    let mut out = format!("<h1>{}</h1><table><tbody>", html::escape(name));
+
+    for (r, row) in rows.iter().enumerate() {
+        out.push_str("<tr>");
+        for (c, cell) in row.iter().enumerate() {
+            let tag = if r == 0 { "th" } else { "td" };
+            let text = html::escape(cell);
+            let text = match (r, c) {
+                (r, 0) if r > 0 => match row_link(cell) {
+                    Some(href) => format!("<a href=\"{}\">{}</a>", html::escape(&href), text),
+                    None => text,
+                },
+                _ => text,
+            };
+            out.push_str(&format!("<{tag}><p>{text}</p></{tag}>"));
+        }
+        out.push_str("</tr>");
+    }
+
+    out.push_str("</tbody></table>");
+    out
+}
+
+// RFC 4180 CSV: quoted fields may contain commas, quotes and newlines.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows.retain(|row| row.iter().any(|cell| !cell.is_empty()));
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_only_notion_ids() {
+        assert_eq!(strip_notion_id("Plan 0123456789abcdef0123456789ABCDEF"), "Plan");
+        assert_eq!(strip_notion_id("My Plan 0123456789abcdef0123456789abcdef"), "My Plan");
+        assert_eq!(strip_notion_id("Plan 2024"), "Plan 2024");
+        let not_hex = "Plan 0123456789abcdef0123456789abcdeg";
+        assert_eq!(strip_notion_id(not_hex), not_hex);
+        let only_id = "0123456789abcdef0123456789abcdef";
+        assert_eq!(strip_notion_id(only_id), only_id);
+    }
+
+    #[test]
+    fn parses_quoted_csv() {
+        let text = "Name,Notes\r\n\"Smith, J\",\"said \"\"hi\"\"\nthen left\"\n,\nlast,row";
+        assert_eq!(
+            parse_csv(text),
+            vec![
+                vec!["Name", "Notes"],
+                vec!["Smith, J", "said \"hi\"\nthen left"],
+                vec!["last", "row"],
+            ]
+        );
+        // A quote inside an unquoted field is text
+        assert_eq!(parse_csv("a\"b,c\n"), vec![vec!["a\"b", "c"]]);
+        assert!(parse_csv("").is_empty());
+    }
+
+    #[test]
+    fn renders_databases_with_row_links() {
+        let html = database_to_html("Tasks <1>", "\u{feff}Name,Done\nWrite,Yes\nShip,No\n", |title| {
+            (title == "Write").then(|| "/leafs/7".to_string())
+        });
+        assert_eq!(
+            html,
+            concat!(
+                "<h1>Tasks &lt;1&gt;</h1><table><tbody>",
+                "<tr><th><p>Name</p></th><th><p>Done</p></th></tr>",
+                "<tr><td><p><a href=\"/leafs/7\">Write</a></p></td><td><p>Yes</p></td></tr>",
+                "<tr><td><p>Ship</p></td><td><p>No</p></td></tr>",
+                "</tbody></table>",
+            )
+        );
+    }
+}
@@ -1,13 +1,157 @@
+use crate::settings::{AiProvider, AiSettings};
 use ollama_rs::{Ollama, generation::embeddings::request::GenerateEmbeddingsRequest};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
 
-pub async fn get_ollama_embedding(text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-    let ollama = Ollama::default();
+// Embeddings and chat replies from the configured AI provider: a local
+// Ollama server, or OpenAI with the key from the secret store.
+
+// The one OpenAI model whose vectors fit the 3072-dimensional embeddings table
+const OPENAI_EMBEDDING_MODEL: &str = "text-embedding-3-large";
+const OPENAI_EMBEDDINGS_URL: &str = "https://api.openai.com/v1/embeddings";
+// Ollama uses the chat model from the AI settings
+const OPENAI_CHAT_MODEL: &str = "gpt-4o-mini";
+const OPENAI_CHAT_URL: &str = "https://api.openai.com/v1/chat/completions";
+
+struct Config {
+    settings: AiSettings,
+    openai_api_key: Option<String>,
+}
+
+// Set from the AI settings and secrets at startup and whenever they change
+static CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+
+#[derive(Deserialize)]
+struct OpenAiEmbeddings {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Deserialize)]
+struct OllamaChat {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChat {
+    choices: Vec<OpenAiChoice>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: ChatMessage,
+}
+
+pub fn configure(settings: &AiSettings, openai_api_key: Option<String>) {
+    *CONFIG.write().unwrap() = Some(Config {
+        settings: settings.clone(),
+        openai_api_key,
+    });
+}
+
+fn current() -> (AiSettings, Option<String>) {
+    match &*CONFIG.read().unwrap() {
+        Some(config) => (config.settings.clone(), config.openai_api_key.clone()),
+        None => (AiSettings::default(), None),
+    }
+}
+
+fn openai_key(key: Option<String>) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(key.ok_or("OpenAI is selected but no API key is set, or the secrets are locked")?)
+}
+
+pub async fn get_embedding(text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    let (settings, openai_api_key) = current();
+    match settings.provider {
+        AiProvider::Ollama => get_ollama_embedding(&settings, text).await,
+        AiProvider::OpenAi => {
+            let key = openai_key(openai_api_key)?;
+            get_openai_embedding(&key, text).await
+        }
+    }
+}
+
+// The key never leaves the backend; the editor asks for replies through here
+pub async fn chat(messages: &[ChatMessage]) -> Result<String, Box<dyn std::error::Error>> {
+    let (settings, openai_api_key) = current();
+    match settings.provider {
+        AiProvider::Ollama => get_ollama_chat(&settings, messages).await,
+        AiProvider::OpenAi => {
+            let key = openai_key(openai_api_key)?;
+            get_openai_chat(&key, messages).await
+        }
+    }
+}
+
+async fn get_ollama_embedding(
+    settings: &AiSettings,
+    text: &str,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    let ollama = Ollama::new(settings.ollama_host.clone(), settings.ollama_port);
     
     let request = GenerateEmbeddingsRequest::new(
-        "llama3.2:3b".to_string(),
+        settings.embedding_model.clone(),
         text.to_string().into()
     );
     
     let response = ollama.generate_embeddings(request).await?;
     Ok(response.embeddings[0].clone())
 }
+
+async fn get_openai_embedding(key: &str, text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    let response: OpenAiEmbeddings = reqwest::Client::new()
+        .post(OPENAI_EMBEDDINGS_URL)
+        .bearer_auth(key)
+        .json(&serde_json::json!({ "model": OPENAI_EMBEDDING_MODEL, "input": text }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let embedding = response.data.into_iter().next().ok_or("OpenAI returned no embedding")?;
+    Ok(embedding.embedding)
+}
+
+async fn get_ollama_chat(
+    settings: &AiSettings,
+    messages: &[ChatMessage],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let url = format!("{}:{}/api/chat", settings.ollama_host, settings.ollama_port);
+    let response: OllamaChat = reqwest::Client::new()
+        .post(url)
+        .json(&serde_json::json!({
+            "model": settings.chat_model,
+            "messages": messages,
+            "stream": false,
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(response.message.content)
+}
+
+async fn get_openai_chat(key: &str, messages: &[ChatMessage]) -> Result<String, Box<dyn std::error::Error>> {
+    let response: OpenAiChat = reqwest::Client::new()
+        .post(OPENAI_CHAT_URL)
+        .bearer_auth(key)
+        .json(&serde_json::json!({ "model": OPENAI_CHAT_MODEL, "messages": messages }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let choice = response.choices.into_iter().next().ok_or("OpenAI returned no reply")?;
+    Ok(choice.message.content)
+}
@@ -0,0 +1,404 @@
+use crate::db::Leaf;
+use crate::filesystem::asset_path;
+use crate::html::{self, Heading, Token};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const STYLESHEET: &str = "
+body { margin: 0 auto; max-width: 42rem; padding: 2rem 1rem; font-family: Georgia, 'Times New Roman', serif; line-height: 1.6; color: #1f1f1f; }
+h1, h2, h3, h4, h5, h6 { font-family: -apple-system, 'Helvetica Neue', Arial, sans-serif; line-height: 1.25; margin: 1.6em 0 0.6em; }
+img { max-width: 100%; height: auto; }
+pre { background: #f4f4f4; padding: 0.75rem 1rem; overflow-x: auto; border-radius: 4px; }
+code { font-family: Menlo, Consolas, monospace; font-size: 0.9em; }
+blockquote { margin: 1em 0; padding-left: 1em; border-left: 3px solid #d0d0d0; color: #555; }
+table { border-collapse: collapse; width: 100%; }
+td, th { border: 1px solid #d0d0d0; padding: 0.3rem 0.5rem; vertical-align: top; }
+ul[data-type='taskList'] { list-style: none; padding-left: 0.5rem; }
+ul[data-type='taskList'] li { display: flex; gap: 0.5rem; }
+nav.toc ol { list-style: none; padding-left: 1rem; }
+article + article { page-break-before: always; break-before: page; }
+";
+
+// A leaf prepared for output: heading ids filled in and the table of
+// contents computed the same way the editor's sidebar does.
+struct Section {
+    name: String,
+    content: String,
+    headings: Vec<Heading>,
+}
+
+fn prepare_section(leaf: &Leaf, index: usize) -> Section {
+    let mut headings = html::headings(leaf.content());
+    // Generated ids only need to be unique within one publication
+    for heading in headings.iter_mut() {
+        if heading.id.starts_with("heading-") {
+            heading.id = format!("s{}-{}", index + 1, heading.id);
+        }
+    }
+
+    let mut ids = headings.iter().map(|h| h.id.clone());
+    let content = html::rewrite_heading_ids(leaf.content(), || ids.next());
+    let content = replace_toc_nodes(&content, &toc_list(&headings, ""));
+
+    Section {
+        name: leaf.name().to_string(),
+        content,
+        headings,
+    }
+}
+
+// Nested `<ol>` following the normalized heading levels.
+fn toc_list(headings: &[Heading], href_prefix: &str) -> String {
+    let mut out = String::new();
+    let mut depth = 0;
+    for heading in headings {
+        let level = heading.level as usize;
+        if level > depth {
+            for _ in depth..level {
+                out.push_str("<ol><li>");
+            }
+        } else {
+            out.push_str("</li>");
+            for _ in level..depth {
+                out.push_str("</ol></li>");
+            }
+            out.push_str("<li>");
+        }
+        depth = level;
+        out.push_str(&format!(
+            "<a href=\"{}#{}\">{}</a>",
+            href_prefix,
+            html::escape(&heading.id),
+            html::escape(&heading.text_content)
+        ));
+    }
+    for _ in 0..depth {
+        out.push_str("</li></ol>");
+    }
+    out
+}
+
+// The editor renders `<div data-type="table-of-content">` as a live outline;
+// published output gets a static one in its place.
+fn replace_toc_nodes(content: &str, toc: &str) -> String {
+    let tokens = html::tokenize(content);
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let (token, span) = &tokens[i];
+        if token.attr("data-type") == Some("table-of-content") {
+            let end = html::element_end(&tokens, i);
+            out.push_str(&content[last..span.start]);
+            out.push_str(&format!("<nav class=\"toc\">{}</nav>", toc));
+            last = end;
+            while i < tokens.len() && tokens[i].1.end <= end {
+                i += 1;
+            }
+            continue;
+        }
+        i += 1;
+    }
+    out.push_str(&content[last..]);
+    out
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+// Local file behind an image source, for asset URLs and plain paths. Only
+// files in the workspace's uploads folder are embedded, so a leaf can't pull
+// arbitrary files from disk into a publication.
+fn local_image(src: &str, uploads: &Path) -> Option<PathBuf> {
+    let path = asset_path(src).unwrap_or_else(|| src.into());
+    if !path.is_absolute() {
+        return None;
+    }
+    let path = path.canonicalize().ok()?;
+    let uploads = uploads.canonicalize().ok()?;
+    (path.starts_with(&uploads) && path.is_file()).then_some(path)
+}
+
+fn leaf_positions(leaves: &[Leaf]) -> HashMap<String, usize> {
+    leaves
+        .iter()
+        .enumerate()
+        .map(|(index, leaf)| (leaf.id().to_string(), index))
+        .collect()
+}
+
+// Position of the published leaf an internal `/leafs/<id>` link points at.
+fn leaf_link(positions: &HashMap<String, usize>, href: &str) -> Option<usize> {
+    let id = href.strip_prefix("/leafs/")?;
+    let id = id.split(['#', '?']).next().unwrap_or(id);
+    positions.get(id).copied()
+}
+
+// -------------------------------------------------------
+
+// One self-contained HTML file: styles inlined, local images embedded as
+// data URIs. Remote images are left as they are.
+pub fn render_html(leaves: &[Leaf], title: &str, uploads: &Path) -> String {
+    let positions = leaf_positions(leaves);
+    let mut body = String::new();
+    for (index, leaf) in leaves.iter().enumerate() {
+        let section = prepare_section(leaf, index);
+        let content = html::rewrite_attrs(&section.content, |tag, attr, value| match (tag, attr) {
+            ("a", "href") => leaf_link(&positions, value).map(|index| format!("#leaf-{}", index + 1)),
+            ("img", "src") => {
+                let path = local_image(value, uploads)?;
+                let data = fs::read(&path).ok()?;
+                Some(format!(
+                    "data:{};base64,{}",
+                    mime_type(&path),
+                    STANDARD.encode(data)
+                ))
+            }
+            _ => None,
+        });
+        body.push_str(&format!(
+            "<article id=\"leaf-{}\">{}</article>\n",
+            index + 1,
+            content
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        html::escape(title),
+        STYLESHEET,
+        body
+    )
+}
+
+pub fn publish_html(leaves: &[Leaf], title: &str, uploads: &Path, path: &Path) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(render_html(leaves, title, uploads).as_bytes())
+}
+
+// -------------------------------------------------------
+
+// EPUB content documents are XML, so void elements need closing slashes and
+// every attribute a quoted value.
+fn to_xhtml(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut open: Vec<String> = Vec::new();
+    for (token, _) in html::tokenize(content) {
+        match token {
+            Token::Start {
+                name,
+                attrs,
+                self_closing,
+            } => {
+                // `blockid` and friends are editor state, not valid XHTML
+                let attrs: Vec<_> = attrs
+                    .into_iter()
+                    .filter(|(key, _)| !key.contains(':') && key != "blockid" && key != "contenteditable")
+                    .collect();
+                let tag = html::render_start_tag(&name, &attrs, false);
+                if self_closing {
+                    out.push_str(&format!("{} />", &tag[..tag.len() - 1]));
+                } else {
+                    out.push_str(&tag);
+                    open.push(name);
+                }
+            }
+            Token::End { name } => {
+                if let Some(pos) = open.iter().rposition(|n| *n == name) {
+                    for unclosed in open.drain(pos..).rev() {
+                        out.push_str(&format!("</{}>", unclosed));
+                    }
+                }
+            }
+            Token::Text(text) => out.push_str(&html::escape(&text)),
+            Token::Comment(_) => {}
+        }
+    }
+    for unclosed in open.into_iter().rev() {
+        out.push_str(&format!("</{}>", unclosed));
+    }
+    out
+}
+
+fn xhtml_document(title: &str, body: &str, extra_head: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"en\" xml:lang=\"en\">\n<head>\n<meta charset=\"utf-8\" />\n<title>{}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\" />{}\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        html::escape(title),
+        extra_head,
+        body
+    )
+}
+
+// EPUB 3 with one chapter per leaf and a navigation document built from the
+// headings of every chapter.
+pub fn publish_epub(
+    leaves: &[Leaf],
+    title: &str,
+    author: &str,
+    uploads: &Path,
+    path: &Path,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // The mimetype entry must come first and be stored uncompressed
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(
+        b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n<rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n</rootfiles>\n</container>\n",
+    )?;
+
+    zip.start_file("OEBPS/style.css", deflated)?;
+    zip.write_all(STYLESHEET.as_bytes())?;
+
+    let positions = leaf_positions(leaves);
+    let mut images: HashMap<PathBuf, String> = HashMap::new();
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    let mut nav_entries = String::new();
+
+    for (index, leaf) in leaves.iter().enumerate() {
+        let section = prepare_section(leaf, index);
+        let file_name = format!("chapter-{}.xhtml", index + 1);
+
+        let mut new_images = Vec::new();
+        let content = html::rewrite_attrs(&section.content, |tag, attr, value| match (tag, attr) {
+            ("a", "href") => leaf_link(&positions, value)
+                .map(|index| format!("chapter-{}.xhtml", index + 1)),
+            ("img", "src") => {
+                let path = local_image(value, uploads)?;
+                if let Some(name) = images.get(&path) {
+                    return Some(name.clone());
+                }
+                let number = images.len() + 1;
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("bin");
+                let name = format!("images/image-{}.{}", number, extension);
+                images.insert(path.clone(), name.clone());
+                new_images.push((path, name.clone(), number));
+                Some(name)
+            }
+            _ => None,
+        });
+
+        for (image_path, name, number) in new_images {
+            let data = fs::read(&image_path)?;
+            zip.start_file(format!("OEBPS/{}", name), deflated)?;
+            zip.write_all(&data)?;
+            manifest.push_str(&format!(
+                "<item id=\"img-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                number,
+                name,
+                mime_type(&image_path)
+            ));
+        }
+
+        zip.start_file(format!("OEBPS/{}", file_name), deflated)?;
+        zip.write_all(xhtml_document(&section.name, &to_xhtml(&content), "").as_bytes())?;
+
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{0}\" href=\"{1}\" media-type=\"application/xhtml+xml\"/>\n",
+            index + 1,
+            file_name
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", index + 1));
+
+        // Chapters without headings still get an entry under their leaf name
+        let chapter_toc = if section.headings.is_empty() {
+            String::new()
+        } else {
+            toc_list(&section.headings, &file_name)
+        };
+        nav_entries.push_str(&format!(
+            "<li><a href=\"{}\">{}</a>{}</li>",
+            file_name,
+            html::escape(&section.name),
+            chapter_toc
+        ));
+    }
+
+    let nav = xhtml_document(
+        title,
+        &format!(
+            "<nav epub:type=\"toc\" id=\"toc\" class=\"toc\">\n<h1>Contents</h1>\n<ol>{}</ol>\n</nav>",
+            nav_entries
+        ),
+        "",
+    );
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(nav.as_bytes())?;
+
+    let modified = Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+    let opf = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"en\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<dc:identifier id=\"book-id\">urn:uuid:{}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:creator>{}</dc:creator>\n<dc:language>en</dc:language>\n<meta property=\"dcterms:modified\">{}</meta>\n</metadata>\n<manifest>\n<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+        Uuid::new_v4(),
+        html::escape(title),
+        html::escape(author),
+        modified,
+        manifest,
+        spine
+    );
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(opf.as_bytes())?;
+
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn embeds_only_images_from_the_uploads_folder() {
+        let root = TempDir(std::env::temp_dir().join(format!("bonsai-publish-{}", Uuid::new_v4())));
+        let uploads = root.0.join("uploads");
+        fs::create_dir_all(&uploads).unwrap();
+        fs::write(uploads.join("a.png"), "png").unwrap();
+        fs::write(root.0.join("secret.png"), "secret").unwrap();
+
+        let inside = uploads.join("a.png").to_string_lossy().to_string();
+        let outside = root.0.join("secret.png").to_string_lossy().to_string();
+        let escaping = uploads.join("../secret.png").to_string_lossy().to_string();
+        assert!(local_image(&inside, &uploads).is_some());
+        assert!(local_image(&outside, &uploads).is_none());
+        assert!(local_image(&escaping, &uploads).is_none());
+        assert!(local_image("uploads/a.png", &uploads).is_none());
+
+        let content = format!("<p><img src=\"{}\"><img src=\"{}\"></p>", inside, outside);
+        let leaf = Leaf::new("leaf".to_string(), "Leaf".to_string(), content);
+        let page = render_html(&[leaf], "Title", &uploads);
+        assert!(page.contains(&format!("data:image/png;base64,{}", STANDARD.encode("png"))));
+        assert!(page.contains(&format!("src=\"{}\"", outside)));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::db::{Leaf, SqlDatabase};
+use chrono::{DateTime, Local, LocalResult, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+use sqlx::Error as SqlxError;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::Notify;
+
+// "Remind me about this" for leaves and blocks. Times are stored in UTC and
+// shown as a native notification when due. Reminders that came due while the
+// app was closed fire on the next launch, marked as missed.
+
+pub const EVENT: &str = "reminder-fired";
+// The longest the scheduler sleeps. Sleeps pause while the machine is
+// suspended, so waking regularly keeps reminders on time after a resume.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+// Reminders fired later than this after their time are reported as missed
+const MISSED_AFTER: chrono::Duration = chrono::Duration::minutes(2);
+const MAX_NOTE_LEN: usize = 1_000;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reminder {
+    pub id: String,
+    pub leaf_id: String,
+    pub leaf_name: String,
+    pub block_id: Option<String>,
+    pub note: String,
+    // RFC 3339 in UTC
+    pub due_at: String,
+    pub fired_at: Option<String>,
+    pub created_at: String,
+}
+
+// Sortable as text, which the queries rely on.
+pub fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// Takes RFC 3339 (`2026-10-20T09:00:00+02:00`), or a time without an offset
+// (`2026-10-20T09:00`), which is read in the system time zone.
+pub fn parse_time(text: &str) -> Result<DateTime<Utc>, String> {
+    parse_time_in(text, &Local)
+}
+
+fn parse_time_in<Tz: TimeZone>(text: &str, zone: &Tz) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M"))
+        .map_err(|_| format!("Invalid time {}; expected YYYY-MM-DDTHH:MM", text))?;
+    let local = match zone.from_local_datetime(&naive) {
+        LocalResult::Single(time) => time,
+        // Clocks went back and the time happens twice; take the first
+        LocalResult::Ambiguous(first, _) => first,
+        // Clocks went forward past it; take the same time an hour on
+        LocalResult::None => zone
+            .from_local_datetime(&(naive + chrono::Duration::hours(1)))
+            .earliest()
+            .ok_or_else(|| format!("{} does not exist in the local time zone", text))?,
+    };
+    Ok(local.with_timezone(&Utc))
+}
+
+fn future_time(text: &str) -> Result<String, String> {
+    let time = parse_time(text)?;
+    if time <= Utc::now() {
+        return Err(format!("{} is in the past", text));
+    }
+    Ok(timestamp(time))
+}
+
+pub async fn create(
+    db: &SqlDatabase,
+    leaf_id: &str,
+    block_id: Option<&str>,
+    at: &str,
+    note: &str,
+) -> Result<Reminder, String> {
+    let due_at = future_time(at)?;
+    if note.chars().count() > MAX_NOTE_LEN {
+        return Err("The reminder's note is too long".to_string());
+    }
+    let leaf = db
+        .read::<Leaf>(leaf_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Leaf {} not found", leaf_id))?;
+    if let Some(block_id) = block_id {
+        if !leaf.content().contains(&format!("blockid=\"{}\"", block_id)) {
+            return Err(format!("Block {} not found in {}", block_id, leaf.name()));
+        }
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    db.add_reminder(&id, leaf_id, block_id, note.trim(), &due_at, &timestamp(Utc::now()))
+        .await
+        .map_err(|e| e.to_string())?;
+    db.reminder(&id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| SqlxError::RowNotFound.to_string())
+}
+
+// Moves a reminder to `until`, pending again even if it has fired.
+pub async fn snooze(db: &SqlDatabase, id: &str, until: &str) -> Result<Reminder, String> {
+    let due_at = future_time(until)?;
+    if !db.reschedule_reminder(id, &due_at).await.map_err(|e| e.to_string())? {
+        return Err(format!("Reminder {} not found", id));
+    }
+    db.reminder(id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| SqlxError::RowNotFound.to_string())
+}
+
+// -------------------------------------------------------
+
+#[derive(Default)]
+pub struct ReminderScheduler {
+    changed: Notify,
+}
+
+// Has the scheduler look at the reminders again, e.g. after one was added
+// or another workspace opened.
+pub fn wake(app: &AppHandle) {
+    app.state::<ReminderScheduler>().changed.notify_one();
+}
+
+fn show(app: &AppHandle, reminder: &Reminder, now: DateTime<Utc>) {
+    let due = parse_time(&reminder.due_at).unwrap_or(now);
+    let title = if now - due > MISSED_AFTER {
+        format!(
+            "Missed reminder from {}",
+            due.with_timezone(&Local).format("%b %-d, %H:%M")
+        )
+    } else {
+        "Reminder".to_string()
+    };
+    let body = match reminder.note.as_str() {
+        "" => reminder.leaf_name.clone(),
+        note => format!("{}\n{}", reminder.leaf_name, note),
+    };
+    if let Err(e) = app.notification().builder().title(title).body(body).show() {
+        eprintln!("Could not show reminder {}: {}", reminder.id, e);
+    }
+    // Lets the window offer to open the leaf or snooze
+    let _ = app.emit(EVENT, reminder);
+}
+
+// Fires the reminders that are due and returns when the next one is.
+async fn fire_due(app: &AppHandle) -> Result<Option<DateTime<Utc>>, SqlxError> {
+    // An encrypted database is not managed until it has been unlocked
+    let Some(db) = app.try_state::<SqlDatabase>() else {
+        return Ok(None);
+    };
+    let now = Utc::now();
+    for mut reminder in db.due_reminders(&timestamp(now)).await? {
+        // Marked first, so a crash can't show it twice
+        let fired_at = timestamp(now);
+        db.mark_reminder_fired(&reminder.id, &fired_at).await?;
+        reminder.fired_at = Some(fired_at);
+        show(app, &reminder, now);
+    }
+    Ok(db
+        .next_reminder_due()
+        .await?
+        .and_then(|due_at| parse_time(&due_at).ok()))
+}
+
+pub fn start_scheduler(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let next = fire_due(&app).await.unwrap_or_else(|e| {
+                eprintln!("Could not check reminders: {}", e);
+                None
+            });
+            let wait = next
+                .and_then(|next| (next - Utc::now()).to_std().ok())
+                .map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT));
+            let scheduler = app.state::<ReminderScheduler>();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = scheduler.changed.notified() => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDate};
+
+    // Central European time in 2026: clocks go forward at 02:00 on March 29
+    // and back at 03:00 on October 25.
+    #[derive(Clone)]
+    struct Berlin;
+
+    fn utc(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M").unwrap()
+    }
+
+    fn hours(hours: i32) -> FixedOffset {
+        FixedOffset::east_opt(hours * 3600).unwrap()
+    }
+
+    impl TimeZone for Berlin {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Berlin
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let valid: Vec<FixedOffset> = [hours(2), hours(1)]
+                .into_iter()
+                .filter(|offset| self.offset_from_utc_datetime(&(*local - *offset)) == *offset)
+                .collect();
+            match valid.as_slice() {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(*offset),
+                [first, second, ..] => LocalResult::Ambiguous(*first, *second),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, time: &NaiveDateTime) -> FixedOffset {
+            if (utc("2026-03-29T01:00")..utc("2026-10-25T01:00")).contains(time) {
+                hours(2)
+            } else {
+                hours(1)
+            }
+        }
+    }
+
+    fn parse(text: &str) -> Result<String, String> {
+        parse_time_in(text, &Berlin).map(timestamp)
+    }
+
+    #[test]
+    fn reads_times_with_and_without_an_offset() {
+        assert_eq!(parse("2026-10-20T09:00:00+02:00").as_deref(), Ok("2026-10-20T07:00:00Z"));
+        assert_eq!(parse("2026-10-20T09:00:00Z").as_deref(), Ok("2026-10-20T09:00:00Z"));
+        assert_eq!(parse("2026-07-01T09:00").as_deref(), Ok("2026-07-01T07:00:00Z"));
+        assert_eq!(parse("2026-01-10T09:00:15").as_deref(), Ok("2026-01-10T08:00:15Z"));
+    }
+
+    #[test]
+    fn moves_times_skipped_by_dst_an_hour_on() {
+        assert_eq!(parse("2026-03-29T02:30").as_deref(), Ok("2026-03-29T01:30:00Z"));
+        assert_eq!(parse("2026-03-29T03:30").as_deref(), Ok("2026-03-29T01:30:00Z"));
+    }
+
+    #[test]
+    fn takes_the_first_of_repeated_times() {
+        assert_eq!(parse("2026-10-25T02:30").as_deref(), Ok("2026-10-25T00:30:00Z"));
+        assert_eq!(parse("2026-10-25T03:30").as_deref(), Ok("2026-10-25T02:30:00Z"));
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        for text in ["tomorrow", "2026-10-20 09:00", "2026-10-20", "2026-02-30T09:00", ""] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::html::{self, Node};
+use ammonia::Builder;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+// Cleans leaf HTML before it is stored. Only the elements and attributes the
+// editor's nodes and marks produce are kept, so pasted or imported markup
+// can't bring scripts or event handlers into the webview. The result is
+// normalized: attributes sorted by name and empty inline elements dropped,
+// so the same document always serializes the same way.
+
+const TAGS: &[&str] = &[
+    // Blocks
+    "p", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre", "hr", "br", "div",
+    "figure", "figcaption", "img",
+    "ul", "ol", "li", "label", "input",
+    "table", "colgroup", "col", "thead", "tbody", "tfoot", "tr", "th", "td",
+    // Marks
+    "a", "strong", "b", "em", "i", "u", "s", "del", "strike", "code", "mark", "sub", "sup",
+    "span",
+];
+
+// `blockid` is the BlockID extension's; extensions keep their other
+// attributes in `data-*`
+const GENERIC_ATTRIBUTES: &[&str] = &["blockid", "class", "style"];
+
+const TAG_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("a", &["href", "target", "rel", "title"]),
+    ("img", &["src", "alt", "title", "width", "height"]),
+    ("input", &["type", "checked", "disabled"]),
+    ("ol", &["start", "type"]),
+    ("col", &["span"]),
+    ("th", &["colspan", "rowspan", "colwidth"]),
+    ("td", &["colspan", "rowspan", "colwidth"]),
+];
+
+// Text style, color, highlight, font size and alignment
+const STYLE_PROPERTIES: &[&str] = &[
+    "color",
+    "background-color",
+    "font-family",
+    "font-size",
+    "text-align",
+    "width",
+];
+
+// `asset` for uploads; `data` for pasted images only, see `filter_attribute`
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto", "tel", "asset", "bonsai", "data"];
+
+// Dropped when they end up with nothing inside
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "strong", "b", "em", "i", "u", "s", "del", "strike", "mark", "sub", "sup", "span",
+];
+
+fn filter_attribute<'u>(_element: &str, attribute: &str, value: &'u str) -> Option<Cow<'u, str>> {
+    match attribute {
+        "href" if value.trim_start().to_ascii_lowercase().starts_with("data:") => None,
+        _ => Some(Cow::Borrowed(value)),
+    }
+}
+
+fn sanitizer() -> &'static Builder<'static> {
+    static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+    SANITIZER.get_or_init(|| {
+        let mut builder = Builder::empty();
+        builder
+            .add_tags(TAGS)
+            .add_generic_attributes(GENERIC_ATTRIBUTES)
+            .add_generic_attribute_prefixes(&["data-"])
+            .url_schemes(URL_SCHEMES.iter().copied().collect())
+            .filter_style_properties(STYLE_PROPERTIES.iter().copied().collect::<HashSet<_>>())
+            .attribute_filter(filter_attribute)
+            // Links keep the attributes the editor gave them
+            .link_rel(None);
+        for (tag, attributes) in TAG_ATTRIBUTES {
+            builder.add_tag_attributes(tag, *attributes);
+        }
+        builder
+    })
+}
+
+fn normalize(nodes: Vec<Node>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Text(text) if text.is_empty() => None,
+            Node::Text(text) => Some(Node::Text(text)),
+            Node::Element {
+                name,
+                mut attrs,
+                children,
+            } => {
+                let children = normalize(children);
+                if children.is_empty() && INLINE_ELEMENTS.contains(&name.as_str()) {
+                    return None;
+                }
+                // Task list checkboxes are the only inputs
+                let checkbox = attrs.iter().any(|(key, value)| key == "type" && value == "checkbox");
+                if name == "input" && !checkbox {
+                    return None;
+                }
+                attrs.sort();
+                attrs.dedup_by(|a, b| a.0 == b.0);
+                Some(Node::Element {
+                    name,
+                    attrs,
+                    children,
+                })
+            }
+        })
+        .collect()
+}
+
+pub fn clean(content: &str) -> String {
+    let cleaned = sanitizer().clean(content).to_string();
+    html::render(&normalize(html::parse(&cleaned)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_scripts_and_event_handlers() {
+        assert_eq!(clean("<p>Hi<script>alert(1)</script></p>"), "<p>Hi</p>");
+        assert_eq!(
+            clean("<img src=\"asset://localhost/a.png\" onerror=\"alert(1)\">"),
+            "<img src=\"asset://localhost/a.png\">"
+        );
+        assert_eq!(
+            clean("<p onclick=\"alert(1)\" style=\"color: red\">Hi</p>"),
+            "<p style=\"color:red\">Hi</p>"
+        );
+    }
+
+    #[test]
+    fn drops_script_and_data_links() {
+        assert_eq!(clean("<a href=\"javascript:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(clean("<a href=\" DATA:text/html,<b>\">x</a>"), "<a>x</a>");
+        // Pasted images may still be inlined
+        assert_eq!(
+            clean("<img src=\"data:image/png;base64,AAAA\">"),
+            "<img src=\"data:image/png;base64,AAAA\">"
+        );
+    }
+
+    #[test]
+    fn keeps_editor_attributes_and_task_checkboxes() {
+        let task = concat!(
+            "<ul data-type=\"taskList\"><li data-checked=\"true\" data-type=\"taskItem\">",
+            "<label><input checked=\"checked\" type=\"checkbox\"><span></span></label>",
+            "<div><p blockid=\"b1\">Done</p></div></li></ul>",
+        );
+        assert_eq!(clean(task), task.replace("<span></span>", ""));
+        assert_eq!(clean("<p><input type=\"text\" value=\"x\">Hi</p>"), "<p>Hi</p>");
+    }
+
+    #[test]
+    fn sorts_attributes_and_drops_empty_inline_elements() {
+        assert_eq!(
+            clean("<p style=\"text-align: center\" data-x=\"1\" blockid=\"b1\">Hi</p>"),
+            "<p blockid=\"b1\" data-x=\"1\" style=\"text-align:center\">Hi</p>"
+        );
+        assert_eq!(
+            clean("<p>a<strong></strong><em><span></span></em>b<br></p>"),
+            "<p>ab<br></p>"
+        );
+    }
+}
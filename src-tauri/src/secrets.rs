@@ -0,0 +1,484 @@
+use crate::crypto;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const OPENAI_API_KEY: &str = "openai_api_key";
+// Bearer token for the localhost HTTP API
+pub const API_TOKEN: &str = "api_token";
+const KEYRING_SERVICE: &str = "bonsai";
+const MASK: char = '•';
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SecretBackend {
+    #[default]
+    Vault,
+    Keyring,
+}
+
+// Stored next to the vault. Holds secret names only, since the OS keyring
+// cannot list what it stores.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+struct SecretSettings {
+    backend: SecretBackend,
+    passphrase_protected: bool,
+    names: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretInfo {
+    pub name: String,
+    pub masked: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretStoreStatus {
+    pub backend: SecretBackend,
+    pub passphrase_protected: bool,
+    pub unlocked: bool,
+    pub secrets: Vec<SecretInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretTest {
+    pub ok: bool,
+    pub message: String,
+}
+
+struct StoreState {
+    settings: SecretSettings,
+    // Key the vault is encrypted with; `None` while a passphrase-protected
+    // vault is locked.
+    vault_key: Option<String>,
+}
+
+// API keys and other credentials, kept out of `config.json`. Values never
+// leave the backend unmasked.
+pub struct SecretStore {
+    settings_path: PathBuf,
+    vault_path: PathBuf,
+    state: Mutex<StoreState>,
+}
+
+pub fn mask(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    if chars.len() <= 8 {
+        return MASK.to_string().repeat(8);
+    }
+    let start: String = chars[..3].iter().collect();
+    let end: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}{}{}", start, MASK.to_string().repeat(4), end)
+}
+
+// True for values produced by `mask`, which the frontend may send back
+// unchanged when saving other settings.
+pub fn is_masked(value: &str) -> bool {
+    value.contains(MASK)
+}
+
+// For read-only loads that find files an older version wrote.
+pub fn needs_upgrade(file: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is from an older version; open Bonsai once to upgrade it", file),
+    )
+}
+
+fn locked_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Secrets are locked; enter the vault passphrase",
+    )
+}
+
+fn keyring_error(e: keyring::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn keyring_entry(name: &str) -> io::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, name).map_err(keyring_error)
+}
+
+fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Secret names may only contain letters, digits, '_', '-' and '.'",
+        ))
+    }
+}
+
+impl SecretStore {
+    // The store lives in the app config folder rather than in a workspace:
+    // the vault is bound to this machine, so it must not travel with a
+    // workspace kept in a synced folder. Files from `legacy_dir`, where
+    // older versions kept them, are moved over on first run.
+    pub fn new(config_dir: &Path, legacy_dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(config_dir)?;
+        for file in ["secrets.json", "secrets.vault", "secrets.key"] {
+            let legacy = legacy_dir.join(file);
+            if legacy.exists() && !config_dir.join(file).exists() {
+                fs::copy(&legacy, config_dir.join(file))?;
+                fs::remove_file(legacy)?;
+            }
+        }
+        let settings_path = config_dir.join("secrets.json");
+        let settings: SecretSettings = if settings_path.exists() {
+            serde_json::from_str(&fs::read_to_string(&settings_path)?)?
+        } else {
+            SecretSettings::default()
+        };
+        let vault_key = if settings.passphrase_protected {
+            None
+        } else {
+            Some(machine_key(config_dir)?)
+        };
+        Ok(Self {
+            settings_path,
+            vault_path: config_dir.join("secrets.vault"),
+            state: Mutex::new(StoreState {
+                settings,
+                vault_key,
+            }),
+        })
+    }
+
+    // Opens the store without creating or moving files, for the command-line
+    // tools. Fails while files still wait in `legacy_dir` to be moved.
+    pub fn read_only(config_dir: &Path, legacy_dir: &Path) -> io::Result<Self> {
+        for file in ["secrets.json", "secrets.vault", "secrets.key"] {
+            if legacy_dir.join(file).exists() && !config_dir.join(file).exists() {
+                return Err(needs_upgrade(file));
+            }
+        }
+        let settings_path = config_dir.join("secrets.json");
+        let settings: SecretSettings = if settings_path.exists() {
+            serde_json::from_str(&fs::read_to_string(&settings_path)?)?
+        } else {
+            SecretSettings::default()
+        };
+        let vault_key = if settings.passphrase_protected {
+            None
+        } else {
+            existing_machine_key(config_dir)?
+        };
+        Ok(Self {
+            settings_path,
+            vault_path: config_dir.join("secrets.vault"),
+            state: Mutex::new(StoreState {
+                settings,
+                vault_key,
+            }),
+        })
+    }
+
+    pub fn status(&self) -> io::Result<SecretStoreStatus> {
+        let state = self.state.lock().unwrap();
+        let unlocked = state.settings.backend == SecretBackend::Keyring || state.vault_key.is_some();
+        let mut secrets = Vec::new();
+        if unlocked {
+            for name in &state.settings.names {
+                if let Some(value) = self.read(&state, name)? {
+                    secrets.push(SecretInfo {
+                        name: name.clone(),
+                        masked: mask(&value),
+                    });
+                }
+            }
+        }
+        Ok(SecretStoreStatus {
+            backend: state.settings.backend,
+            passphrase_protected: state.settings.passphrase_protected,
+            unlocked,
+            secrets,
+        })
+    }
+
+    // Plain value for Rust-side callers; commands return `masked` instead.
+    pub fn get(&self, name: &str) -> io::Result<Option<String>> {
+        let state = self.state.lock().unwrap();
+        self.read(&state, name)
+    }
+
+    pub fn masked(&self, name: &str) -> io::Result<Option<String>> {
+        Ok(self.get(name)?.map(|value| mask(&value)))
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> io::Result<SecretInfo> {
+        validate_name(name)?;
+        let mut state = self.state.lock().unwrap();
+        match state.settings.backend {
+            SecretBackend::Vault => {
+                let mut vault = self.read_vault(&state)?;
+                vault.insert(name.to_string(), value.to_string());
+                self.write_vault(&state, &vault)?;
+            }
+            SecretBackend::Keyring => {
+                keyring_entry(name)?
+                    .set_password(value)
+                    .map_err(keyring_error)?;
+            }
+        }
+        if !state.settings.names.iter().any(|n| n == name) {
+            state.settings.names.push(name.to_string());
+            state.settings.names.sort();
+            self.save_settings(&state.settings)?;
+        }
+        Ok(SecretInfo {
+            name: name.to_string(),
+            masked: mask(value),
+        })
+    }
+
+    pub fn delete(&self, name: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.settings.backend {
+            SecretBackend::Vault => {
+                let mut vault = self.read_vault(&state)?;
+                if vault.remove(name).is_some() {
+                    self.write_vault(&state, &vault)?;
+                }
+            }
+            SecretBackend::Keyring => match keyring_entry(name)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(e) => return Err(keyring_error(e)),
+            },
+        }
+        state.settings.names.retain(|n| n != name);
+        self.save_settings(&state.settings)
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if self.vault_path.exists() {
+            // Fails with a clear error when the passphrase is wrong
+            crypto::decrypt(passphrase, &fs::read(&self.vault_path)?)?;
+        }
+        state.vault_key = Some(passphrase.to_string());
+        Ok(())
+    }
+
+    // Protects the vault with a passphrase, or goes back to the machine key
+    // when `passphrase` is `None`.
+    pub fn set_passphrase(&self, passphrase: Option<&str>) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let vault = self.read_vault(&state)?;
+        let key = match passphrase.filter(|p| !p.is_empty()) {
+            Some(passphrase) => passphrase.to_string(),
+            None => machine_key(self.settings_path.parent().unwrap())?,
+        };
+        state.vault_key = Some(key);
+        state.settings.passphrase_protected = passphrase.is_some_and(|p| !p.is_empty());
+        self.write_vault(&state, &vault)?;
+        self.save_settings(&state.settings)
+    }
+
+    // Moves every secret to the other backend.
+    pub fn set_backend(&self, backend: SecretBackend) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.settings.backend == backend {
+            return Ok(());
+        }
+        let mut secrets = BTreeMap::new();
+        for name in &state.settings.names {
+            if let Some(value) = self.read(&state, name)? {
+                secrets.insert(name.clone(), value);
+            }
+        }
+
+        match backend {
+            SecretBackend::Keyring => {
+                for (name, value) in &secrets {
+                    keyring_entry(name)?
+                        .set_password(value)
+                        .map_err(keyring_error)?;
+                }
+                if self.vault_path.exists() {
+                    fs::remove_file(&self.vault_path)?;
+                }
+            }
+            SecretBackend::Vault => {
+                if state.vault_key.is_none() {
+                    state.vault_key = Some(machine_key(self.settings_path.parent().unwrap())?);
+                    state.settings.passphrase_protected = false;
+                }
+                self.write_vault(&state, &secrets)?;
+                for name in secrets.keys() {
+                    let _ = keyring_entry(name)?.delete_credential();
+                }
+            }
+        }
+
+        state.settings.backend = backend;
+        self.save_settings(&state.settings)
+    }
+
+    fn read(&self, state: &StoreState, name: &str) -> io::Result<Option<String>> {
+        match state.settings.backend {
+            SecretBackend::Vault => Ok(self.read_vault(state)?.remove(name)),
+            SecretBackend::Keyring => match keyring_entry(name)?.get_password() {
+                Ok(value) => Ok(Some(value)),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(keyring_error(e)),
+            },
+        }
+    }
+
+    fn read_vault(&self, state: &StoreState) -> io::Result<BTreeMap<String, String>> {
+        let key = state.vault_key.as_deref().ok_or_else(locked_error)?;
+        if !self.vault_path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let data = crypto::decrypt(key, &fs::read(&self.vault_path)?)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    fn write_vault(&self, state: &StoreState, vault: &BTreeMap<String, String>) -> io::Result<()> {
+        let key = state.vault_key.as_deref().ok_or_else(locked_error)?;
+        let data = crypto::encrypt(key, &serde_json::to_vec(vault)?)?;
+        let partial = self.vault_path.with_extension("partial");
+        fs::write(&partial, data)?;
+        fs::rename(&partial, &self.vault_path)
+    }
+
+    fn save_settings(&self, settings: &SecretSettings) -> io::Result<()> {
+        // The vault already writes through `secrets.partial`
+        let partial = self.settings_path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_string_pretty(settings)?)?;
+        fs::rename(&partial, &self.settings_path)
+    }
+}
+
+// -------------------------------------------------------
+
+// Checks a secret against the service it belongs to. Secrets without a
+// known service only need to be present.
+pub async fn test(name: &str, value: Option<String>) -> SecretTest {
+    let Some(value) = value else {
+        return SecretTest {
+            ok: false,
+            message: "Secret is not set".to_string(),
+        };
+    };
+    if name != OPENAI_API_KEY {
+        return SecretTest {
+            ok: true,
+            message: "Secret is set".to_string(),
+        };
+    }
+
+    let response = reqwest::Client::new()
+        .get("https://api.openai.com/v1/models")
+        .bearer_auth(value)
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => SecretTest {
+            ok: true,
+            message: "OpenAI accepted the key".to_string(),
+        },
+        Ok(response) if response.status() == reqwest::StatusCode::UNAUTHORIZED => SecretTest {
+            ok: false,
+            message: "OpenAI rejected the key".to_string(),
+        },
+        Ok(response) => SecretTest {
+            ok: false,
+            message: format!("OpenAI responded with {}", response.status()),
+        },
+        Err(e) => SecretTest {
+            ok: false,
+            message: format!("Could not reach OpenAI: {}", e),
+        },
+    }
+}
+
+// -------------------------------------------------------
+
+// Default vault key, tied to this machine and user so a copied vault cannot
+// be opened elsewhere. Falls back to a random key kept beside the vault.
+fn machine_key(config_dir: &Path) -> io::Result<String> {
+    let key_path = config_dir.join("secrets.key");
+    if machine_id().is_none() && !key_path.exists() {
+        let key: String = crypto::random_bytes::<32>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        fs::write(&key_path, key)?;
+    }
+    existing_machine_key(config_dir)?.ok_or_else(locked_error)
+}
+
+// The key `machine_key` returns, without creating `secrets.key`. None while
+// that file is missing, in which case the vault was never written.
+fn existing_machine_key(config_dir: &Path) -> io::Result<Option<String>> {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    let id = match machine_id() {
+        Some(id) => id,
+        None => match fs::read_to_string(config_dir.join("secrets.key")) {
+            Ok(id) => id,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        },
+    };
+    Ok(Some(format!("bonsai:{}:{}", id.trim(), user)))
+}
+
+#[cfg(target_os = "linux")]
+fn machine_id() -> Option<String> {
+    fs::read_to_string("/etc/machine-id")
+        .or_else(|_| fs::read_to_string("/var/lib/dbus/machine-id"))
+        .ok()
+        .filter(|id| !id.trim().is_empty())
+}
+
+#[cfg(target_os = "macos")]
+fn machine_id() -> Option<String> {
+    let output = std::process::Command::new("ioreg")
+        .args(["-rd1", "-c", "IOPlatformExpertDevice"])
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.lines()
+        .find(|line| line.contains("IOPlatformUUID"))
+        .and_then(|line| line.rsplit('"').nth(1))
+        .map(str::to_string)
+}
+
+#[cfg(windows)]
+fn machine_id() -> Option<String> {
+    let output = std::process::Command::new("reg")
+        .args([
+            "query",
+            r"HKLM\SOFTWARE\Microsoft\Cryptography",
+            "/v",
+            "MachineGuid",
+        ])
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.lines()
+        .find(|line| line.contains("MachineGuid"))
+        .and_then(|line| line.split_whitespace().last())
+        .map(str::to_string)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn machine_id() -> Option<String> {
+    None
+}
@@ -0,0 +1,593 @@
+use crate::journal;
+use crate::secrets::{self, SecretStore};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+pub const CHANGED_EVENT: &str = "settings-changed";
+
+// Each step upgrades a settings document from version `i` to `i + 1`.
+const UPGRADES: &[fn(&mut Map<String, Value>)] = &[upgrade_v0, upgrade_v1];
+pub const SCHEMA_VERSION: u64 = UPGRADES.len() as u64;
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AiProvider {
+    #[default]
+    Ollama,
+    OpenAi,
+}
+
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AiSettings {
+    pub provider: AiProvider,
+    pub ollama_host: String,
+    pub ollama_port: u16,
+    pub chat_model: String,
+    // Ollama model; must produce 3072-dimensional vectors to fit the
+    // embeddings table. OpenAI always uses text-embedding-3-large.
+    pub embedding_model: String,
+}
+
+impl Default for AiSettings {
+    fn default() -> Self {
+        Self {
+            provider: AiProvider::Ollama,
+            ollama_host: "http://localhost".to_string(),
+            ollama_port: 11434,
+            chat_model: "llama3.2:3b".to_string(),
+            embedding_model: "llama3.2:3b".to_string(),
+        }
+    }
+}
+
+// Automatic backups, written to `dir` (or `backups/` in the data folder)
+// every `interval_hours`, keeping the newest `keep` archives.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BackupSchedule {
+    pub enabled: bool,
+    pub dir: Option<PathBuf>,
+    pub interval_hours: u32,
+    pub keep: u32,
+}
+
+impl Default for BackupSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            interval_hours: 24,
+            keep: 7,
+        }
+    }
+}
+
+// The localhost HTTP API. Its bearer token is kept in the secret store.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 7322,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum McpAccess {
+    #[default]
+    ReadOnly,
+    ReadWrite,
+}
+
+// The Model Context Protocol server, reached through the `bonsai-mcp`
+// sidecar or the HTTP API. Agents may only change leaves with read-write
+// access.
+#[derive(Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct McpSettings {
+    pub enabled: bool,
+    pub access: McpAccess,
+}
+
+// Daily notes. `name_template` is a strftime pattern for the note's name and
+// `template` the Markdown it starts with, where `{{date}}` stands for the
+// date as YYYY-MM-DD and `{{title}}` for the name.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JournalSettings {
+    pub name_template: String,
+    pub template: String,
+    // Move the previous entry's unchecked tasks into a new one
+    pub carry_over_tasks: bool,
+}
+
+impl Default for JournalSettings {
+    fn default() -> Self {
+        Self {
+            name_template: "%Y-%m-%d".to_string(),
+            template: String::new(),
+            carry_over_tasks: true,
+        }
+    }
+}
+
+// Every field has a default, so documents written by older versions (or
+// missing fields added later) always deserialize.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub version: u64,
+    pub theme: String,
+    pub ai: AiSettings,
+    pub autosave_interval_ms: u64,
+    pub mirror_dir: Option<PathBuf>,
+    // Shared folder for syncing with other devices
+    pub sync_dir: Option<PathBuf>,
+    pub backup: BackupSchedule,
+    pub api: ApiSettings,
+    pub mcp: McpSettings,
+    pub journal: JournalSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            theme: "dark".to_string(),
+            ai: AiSettings::default(),
+            autosave_interval_ms: 1000,
+            mirror_dir: None,
+            sync_dir: None,
+            backup: BackupSchedule::default(),
+            api: ApiSettings::default(),
+            mcp: McpSettings::default(),
+            journal: JournalSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if !matches!(self.theme.as_str(), "dark" | "light" | "system") {
+            problems.push(format!("Unknown theme \"{}\"", self.theme));
+        }
+        if !(self.ai.ollama_host.starts_with("http://") || self.ai.ollama_host.starts_with("https://")) {
+            problems.push("Ollama host must start with http:// or https://".to_string());
+        }
+        if self.ai.ollama_port == 0 {
+            problems.push("Ollama port must not be 0".to_string());
+        }
+        if self.ai.chat_model.trim().is_empty() {
+            problems.push("Chat model must not be empty".to_string());
+        }
+        if self.ai.embedding_model.trim().is_empty() {
+            problems.push("Embedding model must not be empty".to_string());
+        }
+        if !(250..=600_000).contains(&self.autosave_interval_ms) {
+            problems.push("Autosave interval must be between 250 ms and 10 minutes".to_string());
+        }
+        for (name, dir) in [
+            ("Mirror directory", &self.mirror_dir),
+            ("Sync directory", &self.sync_dir),
+            ("Backup directory", &self.backup.dir),
+        ] {
+            if dir.as_ref().is_some_and(|dir| !dir.is_absolute()) {
+                problems.push(format!("{} must be an absolute path", name));
+            }
+        }
+        if self.backup.interval_hours == 0 {
+            problems.push("Backup interval must be at least one hour".to_string());
+        }
+        if self.backup.keep == 0 {
+            problems.push("At least one backup must be kept".to_string());
+        }
+        if self.api.port == 0 {
+            problems.push("API port must not be 0".to_string());
+        }
+        // Formatting a sample date also catches fields a date doesn't have
+        let name_template = &self.journal.name_template;
+        let sample = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+        if name_template.trim().is_empty() || journal::title(sample, name_template).is_err() {
+            problems.push("Daily note name must be a valid date format".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("; "))
+        }
+    }
+}
+
+// Version 0 is the original `config.json`, whose only other field was the
+// OpenAI key. That moves to the secret store before upgrading.
+fn upgrade_v0(doc: &mut Map<String, Value>) {
+    doc.remove("openaiApiKey");
+}
+
+// Settings became per workspace, so the data location moved to the
+// workspace list.
+fn upgrade_v1(doc: &mut Map<String, Value>) {
+    doc.remove("dataDir");
+}
+
+// RFC 7396 merge patch: objects merge recursively, `null` removes a field.
+fn merge(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    merge(target.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+fn read_json(path: &Path) -> io::Result<Option<Value>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Moves a file that could not be read out of the way, so defaults can take
+// its place while it is kept for the user to fix, e.g. `settings.json` to
+// `settings.json.broken-20261019-101500`.
+pub fn set_aside(path: &Path) -> io::Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".broken-{}", Utc::now().format("%Y%m%d-%H%M%S")));
+    let aside = path.with_file_name(name);
+    fs::rename(path, &aside)?;
+    Ok(aside)
+}
+
+// Shape of the original `get_config`/`set_config` commands, which the
+// preferences page still uses. The key is only ever sent out masked.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LegacyConfig {
+    pub openai_api_key: String,
+    pub theme: String,
+}
+
+impl Default for LegacyConfig {
+    fn default() -> Self {
+        Self {
+            openai_api_key: String::new(),
+            theme: Settings::default().theme,
+        }
+    }
+}
+
+// -------------------------------------------------------
+
+// Settings of the open workspace, kept in its `config` folder.
+pub struct SettingsStore {
+    path: RwLock<PathBuf>,
+    settings: RwLock<Settings>,
+}
+
+impl SettingsStore {
+    // Loads `settings.json` from `config_dir`, upgrading older documents. On
+    // first run the per-feature files older versions kept there are folded in.
+    pub fn load(config_dir: &Path, secrets: &SecretStore) -> io::Result<Self> {
+        fs::create_dir_all(config_dir)?;
+        let path = config_dir.join("settings.json");
+        let legacy_files = [
+            config_dir.join("config.json"),
+            config_dir.join("mirror.json"),
+            config_dir.join("backup.json"),
+        ];
+
+        let mut doc = match read_json(&path)? {
+            Some(Value::Object(doc)) => doc,
+            Some(_) => return Err(invalid_data("settings.json is not an object".to_string())),
+            None => {
+                let mut doc = match read_json(&legacy_files[0])? {
+                    Some(Value::Object(doc)) => doc,
+                    _ => Map::new(),
+                };
+                doc.insert("version".to_string(), Value::from(0));
+                if let Some(dir) = read_json(&legacy_files[1])? {
+                    doc.insert("mirrorDir".to_string(), dir);
+                }
+                if let Some(schedule) = read_json(&legacy_files[2])? {
+                    doc.insert("backup".to_string(), schedule);
+                }
+                doc
+            }
+        };
+
+        let version = doc.get("version").and_then(Value::as_u64).unwrap_or(0);
+        if version == 0 {
+            if let Some(key) = doc.get("openaiApiKey").and_then(Value::as_str) {
+                if !key.is_empty() && !secrets::is_masked(key) {
+                    // Not InvalidData, which would mean the document is broken
+                    secrets.set(secrets::OPENAI_API_KEY, key).map_err(|e| {
+                        io::Error::other(format!("Could not store the OpenAI API key: {}", e))
+                    })?;
+                }
+            }
+        }
+        for upgrade in UPGRADES.iter().skip(version as usize) {
+            upgrade(&mut doc);
+        }
+        // Documents from a newer version keep their version number so they
+        // are not downgraded on disk.
+        doc.insert("version".to_string(), Value::from(version.max(SCHEMA_VERSION)));
+
+        let settings: Settings = serde_json::from_value(Value::Object(doc))?;
+        settings.validate().map_err(invalid_data)?;
+        let store = Self {
+            path: RwLock::new(path),
+            settings: RwLock::new(settings),
+        };
+        if version < SCHEMA_VERSION {
+            store.save(&store.get())?;
+            for file in legacy_files.iter().filter(|file| file.exists()) {
+                fs::remove_file(file)?;
+            }
+        }
+        Ok(store)
+    }
+
+    // Like `load`, but a document that can't be read or is invalid is set
+    // aside and replaced with the defaults instead of failing. Returns what
+    // was wrong with it, for the user to see.
+    pub fn load_or_reset(
+        config_dir: &Path,
+        secrets: &SecretStore,
+    ) -> io::Result<(Self, Option<String>)> {
+        let error = match Self::load(config_dir, secrets) {
+            Ok(store) => return Ok((store, None)),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => e,
+            Err(e) => return Err(e),
+        };
+        let path = config_dir.join("settings.json");
+        // Without settings.json the broken document is one of the legacy files,
+        // which are ignored from now on
+        let message = if path.exists() {
+            let aside = set_aside(&path)?;
+            format!(
+                "The settings could not be read ({}). They were moved to {} and the defaults are used instead.",
+                error,
+                aside.display()
+            )
+        } else {
+            format!(
+                "The old settings could not be read ({}); the defaults are used instead.",
+                error
+            )
+        };
+        let store = Self {
+            path: RwLock::new(path),
+            settings: RwLock::new(Settings::default()),
+        };
+        store.save(&store.get())?;
+        Ok((store, Some(message)))
+    }
+
+    // Loads `settings.json` without writing anything, for the command-line
+    // tools. Documents that need upgrading are refused.
+    pub fn read_only(config_dir: &Path) -> io::Result<Self> {
+        let path = config_dir.join("settings.json");
+        let settings = match read_json(&path)? {
+            Some(doc) => {
+                let version = doc.get("version").and_then(Value::as_u64).unwrap_or(0);
+                if version < SCHEMA_VERSION {
+                    return Err(secrets::needs_upgrade("settings.json"));
+                }
+                let settings: Settings = serde_json::from_value(doc)?;
+                settings.validate().map_err(invalid_input)?;
+                settings
+            }
+            None => {
+                if let Some(file) = ["config.json", "mirror.json", "backup.json"]
+                    .iter()
+                    .find(|file| config_dir.join(file).exists())
+                {
+                    return Err(secrets::needs_upgrade(file));
+                }
+                Settings::default()
+            }
+        };
+        Ok(Self {
+            path: RwLock::new(path),
+            settings: RwLock::new(settings),
+        })
+    }
+
+    // Takes over the settings of another workspace.
+    pub fn replace_with(&self, other: SettingsStore) {
+        let mut settings = self.settings.write().unwrap();
+        *self.path.write().unwrap() = other.path.into_inner().unwrap();
+        *settings = other.settings.into_inner().unwrap();
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.read().unwrap().clone()
+    }
+
+    // Applies a partial document and returns the new settings. Nothing is
+    // stored when the result fails validation.
+    pub fn update(&self, changes: Value) -> io::Result<Settings> {
+        let mut settings = self.settings.write().unwrap();
+        let mut doc = serde_json::to_value(&*settings)?;
+        merge(&mut doc, changes);
+        let mut updated: Settings = serde_json::from_value(doc)
+            .map_err(|e| invalid_input(e.to_string()))?;
+        updated.version = settings.version;
+        updated.validate().map_err(invalid_input)?;
+
+        self.save(&updated)?;
+        *settings = updated.clone();
+        Ok(updated)
+    }
+
+    pub fn reset(&self) -> io::Result<Settings> {
+        let mut settings = self.settings.write().unwrap();
+        let defaults = Settings::default();
+        self.save(&defaults)?;
+        *settings = defaults.clone();
+        Ok(defaults)
+    }
+
+    fn save(&self, settings: &Settings) -> io::Result<()> {
+        let path = self.path.read().unwrap();
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_string_pretty(settings)?)?;
+        fs::rename(&partial, &*path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    // A config folder with its secret store, removed when dropped.
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new() -> ConfigDir {
+            ConfigDir(std::env::temp_dir().join(format!("bonsai-settings-test-{}", Uuid::new_v4())))
+        }
+
+        fn write(&self, file: &str, text: &str) {
+            fs::create_dir_all(&self.0).unwrap();
+            fs::write(self.0.join(file), text).unwrap();
+        }
+
+        fn secrets(&self) -> SecretStore {
+            SecretStore::new(&self.0.join("secrets"), &self.0.join("secrets")).unwrap()
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(doc) => doc,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn upgrades_run_in_order_from_any_version() {
+        let mut doc = object(serde_json::json!({ "openaiApiKey": "sk", "dataDir": "/d", "theme": "light" }));
+        for upgrade in UPGRADES {
+            upgrade(&mut doc);
+        }
+        assert_eq!(Value::Object(doc), serde_json::json!({ "theme": "light" }));
+
+        // A version 1 document only runs the later steps
+        let mut doc = object(serde_json::json!({ "openaiApiKey": "kept", "dataDir": "/d" }));
+        for upgrade in UPGRADES.iter().skip(1) {
+            upgrade(&mut doc);
+        }
+        assert_eq!(Value::Object(doc), serde_json::json!({ "openaiApiKey": "kept" }));
+    }
+
+    #[test]
+    fn first_load_folds_in_legacy_files() {
+        let dir = ConfigDir::new();
+        let secrets = dir.secrets();
+        let key = "sk-legacy-key-0123456789";
+        dir.write(
+            "config.json",
+            &format!(r#"{{"openaiApiKey":"{}","theme":"light","dataDir":"/data"}}"#, key),
+        );
+        dir.write("mirror.json", r#""/mirror""#);
+        dir.write("backup.json", r#"{"enabled":true,"keep":3}"#);
+
+        let settings = SettingsStore::load(&dir.0, &secrets).unwrap().get();
+        assert_eq!(settings.version, SCHEMA_VERSION);
+        assert_eq!(settings.theme, "light");
+        assert_eq!(settings.mirror_dir, Some(PathBuf::from("/mirror")));
+        assert!(settings.backup.enabled);
+        assert_eq!((settings.backup.keep, settings.backup.interval_hours), (3, 24));
+        assert_eq!(secrets.get(secrets::OPENAI_API_KEY).unwrap().as_deref(), Some(key));
+
+        for file in ["config.json", "mirror.json", "backup.json"] {
+            assert!(!dir.0.join(file).exists(), "{}", file);
+        }
+        let saved = fs::read_to_string(dir.0.join("settings.json")).unwrap();
+        assert!(!saved.contains(key) && !saved.contains("dataDir"));
+        let reloaded = SettingsStore::read_only(&dir.0).unwrap().get();
+        assert!(reloaded == settings);
+    }
+
+    #[test]
+    fn newer_documents_keep_their_version() {
+        let dir = ConfigDir::new();
+        let text = r#"{"version":99,"theme":"system","futureField":1}"#;
+        dir.write("settings.json", text);
+
+        let settings = SettingsStore::load(&dir.0, &dir.secrets()).unwrap().get();
+        assert_eq!((settings.version, settings.theme.as_str()), (99, "system"));
+        assert_eq!(fs::read_to_string(dir.0.join("settings.json")).unwrap(), text);
+    }
+
+    #[test]
+    fn read_only_refuses_documents_that_need_upgrading() {
+        let dir = ConfigDir::new();
+        dir.write("settings.json", r#"{"version":0}"#);
+        assert!(SettingsStore::read_only(&dir.0).is_err());
+        fs::remove_file(dir.0.join("settings.json")).unwrap();
+        dir.write("config.json", "{}");
+        assert!(SettingsStore::read_only(&dir.0).is_err());
+    }
+
+    #[test]
+    fn broken_or_invalid_documents_are_set_aside() {
+        let invalid = format!(r#"{{"version":{},"ai":{{"ollamaHost":"ftp://x"}}}}"#, SCHEMA_VERSION);
+        for text in ["{ not json", "[]", invalid.as_str()] {
+            let dir = ConfigDir::new();
+            let secrets = dir.secrets();
+            dir.write("settings.json", text);
+            assert!(SettingsStore::load(&dir.0, &secrets).is_err());
+
+            let (store, error) = SettingsStore::load_or_reset(&dir.0, &secrets).unwrap();
+            assert!(error.is_some(), "{}", text);
+            assert!(store.get() == Settings::default());
+            let aside = fs::read_dir(&dir.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| path.to_string_lossy().contains("settings.json.broken-"))
+                .unwrap();
+            assert_eq!(fs::read_to_string(aside).unwrap(), text);
+            assert!(SettingsStore::load_or_reset(&dir.0, &secrets).unwrap().1.is_none());
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::html::{self, Heading};
+use crate::journal;
+use chrono::{Local, NaiveDate};
+use serde::Serialize;
+
+// Writing metrics for leaves, recomputed whenever a leaf's content is
+// written, and the words written per day, counted from the edits made in
+// the app. Days are local dates.
+
+const WORDS_PER_MINUTE: usize = 200;
+const DATE_FORMAT: &str = "%Y-%m-%d";
+// Days returned by one `activity` call
+const MAX_RANGE_DAYS: i64 = 5 * 366;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafStats {
+    pub leaf_id: String,
+    pub word_count: i64,
+    // Characters of the text, spaces included and line breaks not
+    pub character_count: i64,
+    pub reading_minutes: i64,
+    pub headings: Vec<Heading>,
+    // Flesch reading ease: around 60-70 is plain English, lower is harder.
+    // None for leaves without text.
+    pub readability: Option<f64>,
+    pub modified_at: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WritingDay {
+    pub date: String,
+    pub words_added: i64,
+    pub words_removed: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WritingActivity {
+    // Every day of the range, oldest first, days without writing included
+    pub days: Vec<WritingDay>,
+    pub total_words_added: i64,
+    pub total_words_removed: i64,
+    // Consecutive days with words added, up to today or yesterday
+    pub current_streak: i64,
+    pub longest_streak: i64,
+}
+
+pub fn today() -> String {
+    Local::now().date_naive().format(DATE_FORMAT).to_string()
+}
+
+// Vowel groups, less a silent final `e`. Rough, but what readability scores
+// are calibrated against.
+fn syllables(word: &str) -> usize {
+    let word = word.to_lowercase();
+    let mut count = 0;
+    let mut previous_vowel = false;
+    for c in word.chars() {
+        let vowel = "aeiouy".contains(c);
+        if vowel && !previous_vowel {
+            count += 1;
+        }
+        previous_vowel = vowel;
+    }
+    if word.ends_with('e') && !word.ends_with("le") && count > 1 {
+        count -= 1;
+    }
+    count.max(1)
+}
+
+fn readability(text: &str) -> Option<f64> {
+    // Block boundaries end sentences too, e.g. headings and list items
+    let sentences: Vec<Vec<&str>> = text
+        .split(['.', '!', '?', '\n'])
+        .map(|sentence| {
+            sentence
+                .split_whitespace()
+                .filter(|word| word.chars().any(char::is_alphabetic))
+                .collect::<Vec<_>>()
+        })
+        .filter(|words| !words.is_empty())
+        .collect();
+    let words: Vec<&str> = sentences.iter().flatten().copied().collect();
+    if words.is_empty() {
+        return None;
+    }
+    let syllables: usize = words.iter().map(|word| syllables(word)).sum();
+    let words_per_sentence = words.len() as f64 / sentences.len() as f64;
+    let syllables_per_word = syllables as f64 / words.len() as f64;
+    let score = 206.835 - 1.015 * words_per_sentence - 84.6 * syllables_per_word;
+    Some((score * 10.0).round() / 10.0)
+}
+
+pub fn compute(leaf_id: &str, content: &str, modified_at: &str) -> LeafStats {
+    let text = html::text_content(content);
+    let word_count = text.split_whitespace().count();
+    LeafStats {
+        leaf_id: leaf_id.to_string(),
+        word_count: word_count as i64,
+        character_count: text.chars().filter(|c| *c != '\n').count() as i64,
+        reading_minutes: word_count.div_ceil(WORDS_PER_MINUTE) as i64,
+        headings: html::headings(content),
+        readability: readability(&text),
+        modified_at: modified_at.to_string(),
+    }
+}
+
+// Lengths of the runs of consecutive dates in `dates`, which are sorted.
+fn streaks(dates: &[NaiveDate]) -> Vec<(NaiveDate, i64)> {
+    let mut runs: Vec<(NaiveDate, i64)> = Vec::new();
+    for date in dates {
+        match runs.last_mut() {
+            Some((last, length)) if last.succ_opt() == Some(*date) => {
+                *last = *date;
+                *length += 1;
+            }
+            _ => runs.push((*date, 1)),
+        }
+    }
+    runs
+}
+
+// `recorded` holds the days with any writing, `writing_dates` every date
+// words were added on; both sorted.
+pub fn activity(
+    from: NaiveDate,
+    to: NaiveDate,
+    recorded: Vec<WritingDay>,
+    writing_dates: &[String],
+) -> Result<WritingActivity, String> {
+    if to < from {
+        return Err("The end of the range is before its start".to_string());
+    }
+    if (to - from).num_days() >= MAX_RANGE_DAYS {
+        return Err(format!("Ranges are limited to {} days", MAX_RANGE_DAYS));
+    }
+
+    let mut recorded = recorded.into_iter().peekable();
+    let mut days = Vec::new();
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        let date = date.format(DATE_FORMAT).to_string();
+        match recorded.next_if(|day| day.date == date) {
+            Some(day) => days.push(day),
+            None => days.push(WritingDay {
+                date,
+                words_added: 0,
+                words_removed: 0,
+            }),
+        }
+    }
+
+    let dates: Vec<NaiveDate> = writing_dates
+        .iter()
+        .filter_map(|date| journal::parse_date(date).ok())
+        .collect();
+    let runs = streaks(&dates);
+    let today = Local::now().date_naive();
+    // Today still counts as part of a streak before anything is written
+    let current_streak = runs
+        .last()
+        .filter(|(last, _)| *last == today || last.succ_opt() == Some(today))
+        .map_or(0, |(_, length)| *length);
+
+    Ok(WritingActivity {
+        total_words_added: days.iter().map(|day| day.words_added).sum(),
+        total_words_removed: days.iter().map(|day| day.words_removed).sum(),
+        days,
+        current_streak,
+        longest_streak: runs.iter().map(|(_, length)| *length).max().unwrap_or(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn date(text: &str) -> NaiveDate {
+        journal::parse_date(text).unwrap()
+    }
+
+    #[test]
+    fn counts_syllables_roughly() {
+        assert_eq!(syllables("readability"), 5);
+        assert_eq!(syllables("Make"), 1);
+        assert_eq!(syllables("table"), 2);
+        assert_eq!(syllables("rhythm"), 1);
+        assert_eq!(syllables("the"), 1);
+        assert_eq!(syllables("42"), 1);
+    }
+
+    #[test]
+    fn computes_leaf_stats() {
+        let stats = compute("leaf", "<h1>Title</h1><p>The cat sat. The dog ran!</p>", "t");
+        assert_eq!(stats.word_count, 7);
+        assert_eq!(stats.character_count, 30);
+        assert_eq!(stats.reading_minutes, 1);
+        assert_eq!(stats.readability, Some(107.8));
+        assert_eq!(stats.headings.len(), 1);
+        assert_eq!(stats.headings[0].text_content, "Title");
+
+        let empty = compute("leaf", "<p></p>", "t");
+        assert_eq!((empty.word_count, empty.reading_minutes), (0, 0));
+        assert_eq!(empty.readability, None);
+        assert_eq!(readability("42 - 7"), None);
+    }
+
+    #[test]
+    fn finds_runs_of_days() {
+        let dates = ["2024-02-27", "2024-02-28", "2024-02-29", "2024-03-01", "2024-03-03"];
+        let dates: Vec<NaiveDate> = dates.into_iter().map(date).collect();
+        assert_eq!(streaks(&dates), [(date("2024-03-01"), 4), (date("2024-03-03"), 1)]);
+        assert!(streaks(&[]).is_empty());
+    }
+
+    #[test]
+    fn fills_in_days_and_streaks() {
+        let today = Local::now().date_naive();
+        let day = |offset: i64| (today - Duration::days(offset)).format(DATE_FORMAT).to_string();
+        let recorded = vec![WritingDay {
+            date: day(1),
+            words_added: 10,
+            words_removed: 2,
+        }];
+        let writing_dates = [day(5), day(4), day(3), day(1), "not a date".to_string()];
+        let written = activity(today - Duration::days(2), today, recorded, &writing_dates).unwrap();
+
+        let days: Vec<(&str, i64)> = written
+            .days
+            .iter()
+            .map(|day| (day.date.as_str(), day.words_added))
+            .collect();
+        assert_eq!(days, [(day(2).as_str(), 0), (day(1).as_str(), 10), (day(0).as_str(), 0)]);
+        assert_eq!((written.total_words_added, written.total_words_removed), (10, 2));
+        assert_eq!((written.current_streak, written.longest_streak), (1, 3));
+
+        // A streak that ended before yesterday is over
+        let ended = activity(today, today, Vec::new(), &[day(3), day(2)]).unwrap();
+        assert_eq!((ended.current_streak, ended.longest_streak), (0, 2));
+    }
+
+    #[test]
+    fn refuses_bad_ranges() {
+        let from = date("2024-01-10");
+        assert!(activity(from, from - Duration::days(1), Vec::new(), &[]).is_err());
+        assert!(activity(from, from + Duration::days(MAX_RANGE_DAYS), Vec::new(), &[]).is_err());
+        let longest = activity(from, from + Duration::days(MAX_RANGE_DAYS - 1), Vec::new(), &[]);
+        assert_eq!(longest.unwrap().days.len() as i64, MAX_RANGE_DAYS);
+    }
+}
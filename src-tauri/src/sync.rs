@@ -0,0 +1,831 @@
+use crate::db::{Entity, Leaf, Sage, SqlDatabase, TimeStamped};
+use crate::filesystem::Database;
+use chrono::Utc;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+
+// Sync between devices through a shared folder (Dropbox, Syncthing, a
+// network drive...). Every device appends its changes to its own log,
+// `<device id>.jsonl`, and never touches the logs of others, so the folder
+// never sees two writers on one file. Each pass publishes local edits found
+// by comparing the database with what was last synced, then merges the new
+// entries from every other log field by field.
+
+const LOG_EXTENSION: &str = "jsonl";
+// Kept in the workspace `config` folder, next to the settings
+const STATE_FILE: &str = "sync.json";
+const DEBOUNCE: Duration = Duration::from_millis(500);
+// Local edits are published on this interval.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+// Fields that are not synced: the id addresses the entity and the
+// modification time is stamped by whichever device applies a change.
+const UNSYNCED_FIELDS: &[&str] = &["id", "modifiedAt"];
+// Only part of a full copy, so a change carrying it can recreate an entity
+const CREATED_FIELD: &str = "createdAt";
+// Concurrent edits of this leaf field are kept side by side
+const CONTENT_FIELD: &str = "content";
+
+// Hybrid logical clock: wall time in milliseconds, a counter for events in
+// the same millisecond (or while the wall clock lags a remote one), and the
+// device as the final tie-break. Ordering follows causality across devices.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Hlc {
+    millis: u64,
+    counter: u32,
+    device: String,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl Hlc {
+    // Advances the clock for a local event and returns its timestamp.
+    fn tick(&mut self) -> Hlc {
+        let now = now_millis();
+        if now > self.millis {
+            self.millis = now;
+            self.counter = 0;
+        } else {
+            self.counter += 1;
+        }
+        self.clone()
+    }
+
+    // Moves the clock past a timestamp received from another device.
+    fn observe(&mut self, remote: &Hlc) {
+        let now = now_millis();
+        let millis = now.max(self.millis).max(remote.millis);
+        self.counter = if millis == self.millis && millis == remote.millis {
+            self.counter.max(remote.counter) + 1
+        } else if millis == self.millis {
+            self.counter + 1
+        } else if millis == remote.millis {
+            remote.counter + 1
+        } else {
+            0
+        };
+        self.millis = millis;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Operation {
+    Put,
+    Delete,
+}
+
+// One line of a device log.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Change {
+    seq: u64,
+    hlc: Hlc,
+    entity_type: String,
+    entity_id: String,
+    version: u64,
+    op: Operation,
+    // New values of the fields this change touched
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    fields: Map<String, Value>,
+    // Clock of each field's value the change was made on top of
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    base: BTreeMap<String, Hlc>,
+}
+
+// What every synced entity looked like after the last pass.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncState {
+    device_id: String,
+    clock: Hlc,
+    seq: u64,
+    // Last sequence number merged from each other device
+    applied: HashMap<String, u64>,
+    // Keyed by `<entity type>:<id>`
+    entities: HashMap<String, EntityState>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EntityState {
+    version: u64,
+    deleted: bool,
+    fields: BTreeMap<String, FieldState>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FieldState {
+    hash: String,
+    hlc: Hlc,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub published: usize,
+    pub applied: usize,
+    // Names of the leaves created for conflicting content
+    pub conflicts: Vec<String>,
+}
+
+fn hash(value: &Value) -> String {
+    format!("{:x}", Sha256::digest(value.to_string().as_bytes()))
+}
+
+fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn entity_key(entity_type: &str, id: &str) -> String {
+    format!("{}:{}", entity_type, id)
+}
+
+fn log_path(dir: &Path, device_id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", device_id, LOG_EXTENSION))
+}
+
+// Entries of a log in order. A line still being written by the sync client
+// has no trailing newline yet and is left for the next pass.
+fn read_log(path: &Path) -> io::Result<Vec<Change>> {
+    let text = fs::read_to_string(path)?;
+    let complete = text.rfind('\n').map_or("", |end| &text[..end]);
+    let mut changes = Vec::new();
+    for line in complete.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(change) => changes.push(change),
+            Err(e) => eprintln!("Skipping unreadable sync entry in {}: {}", path.display(), e),
+        }
+    }
+    Ok(changes)
+}
+
+fn append_log(path: &Path, changes: &[Change]) -> io::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for change in changes {
+        lines.push_str(&serde_json::to_string(change)?);
+        lines.push('\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(lines.as_bytes())?;
+    file.sync_all()
+}
+
+fn load_state(state_path: &Path, dir: &Path) -> io::Result<SyncState> {
+    let mut state: SyncState = match fs::read_to_string(state_path) {
+        Ok(text) => serde_json::from_str(&text)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => SyncState::default(),
+        Err(e) => return Err(e),
+    };
+    if state.device_id.is_empty() {
+        state.device_id = Uuid::new_v4().to_string();
+    }
+    state.clock.device = state.device_id.clone();
+    // Never reuse a sequence number, even if the state was saved before the
+    // log reached it
+    let own_log = log_path(dir, &state.device_id);
+    if own_log.exists() {
+        if let Some(last) = read_log(&own_log)?.last() {
+            state.seq = state.seq.max(last.seq);
+        }
+    }
+    Ok(state)
+}
+
+fn save_state(state_path: &Path, state: &SyncState) -> io::Result<()> {
+    if let Some(parent) = state_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = state_path.with_extension("partial");
+    fs::write(&partial, serde_json::to_string(state)?)?;
+    fs::rename(&partial, state_path)
+}
+
+// -------------------------------------------------------
+
+fn synced_fields<T: Entity>(entity: &T) -> io::Result<Map<String, Value>> {
+    let Value::Object(mut fields) = serde_json::to_value(entity)? else {
+        return Err(to_io_error("Entity is not an object"));
+    };
+    for name in UNSYNCED_FIELDS {
+        fields.remove(*name);
+    }
+    Ok(fields)
+}
+
+async fn list_fields<T: Entity>(
+    db: &SqlDatabase,
+) -> io::Result<Vec<(String, String, Map<String, Value>)>> {
+    let mut entities = Vec::new();
+    for entity in db.list::<T>().await.map_err(to_io_error)? {
+        entities.push((
+            T::get_object_type().to_string(),
+            entity.get_id().to_string(),
+            synced_fields(&entity)?,
+        ));
+    }
+    Ok(entities)
+}
+
+async fn read_fields<T: Entity>(db: &SqlDatabase, id: &str) -> io::Result<Option<Map<String, Value>>> {
+    match db.read::<T>(id).await.map_err(to_io_error)? {
+        Some(entity) => Ok(Some(synced_fields(&entity)?)),
+        None => Ok(None),
+    }
+}
+
+async fn write_fields<T: Entity + TimeStamped + DeserializeOwned>(
+    db: &SqlDatabase,
+    id: &str,
+    mut fields: Map<String, Value>,
+) -> io::Result<()> {
+    fields.insert("id".to_string(), Value::from(id));
+    fields.insert("modifiedAt".to_string(), Value::from(Utc::now().to_rfc3339()));
+    let entity: T = serde_json::from_value(Value::Object(fields))?;
+    // Embedded after the pass, so merging works without the embedding model
+    db.put_deferred(entity).await.map_err(to_io_error)
+}
+
+// Dispatch on the entity types that take part in sync.
+async fn all_fields(db: &SqlDatabase) -> io::Result<Vec<(String, String, Map<String, Value>)>> {
+    let mut entities = list_fields::<Leaf>(db).await?;
+    entities.extend(list_fields::<Sage>(db).await?);
+    Ok(entities)
+}
+
+async fn entity_fields(
+    db: &SqlDatabase,
+    entity_type: &str,
+    id: &str,
+) -> io::Result<Option<Map<String, Value>>> {
+    match entity_type {
+        "leaf" => read_fields::<Leaf>(db, id).await,
+        "sage" => read_fields::<Sage>(db, id).await,
+        _ => Ok(None),
+    }
+}
+
+async fn store_fields(
+    db: &SqlDatabase,
+    entity_type: &str,
+    id: &str,
+    fields: Map<String, Value>,
+) -> io::Result<()> {
+    match entity_type {
+        "leaf" => write_fields::<Leaf>(db, id, fields).await,
+        "sage" => write_fields::<Sage>(db, id, fields).await,
+        _ => Ok(()),
+    }
+}
+
+async fn delete_entity(db: &SqlDatabase, entity_type: &str, id: &str) -> io::Result<()> {
+    match entity_type {
+        "leaf" => db.delete::<Leaf>(id).await.map_err(to_io_error),
+        "sage" => db.delete::<Sage>(id).await.map_err(to_io_error),
+        _ => Ok(()),
+    }
+}
+
+// -------------------------------------------------------
+
+// Turns differences between the database and the state into log entries.
+async fn collect_local_changes(db: &SqlDatabase, state: &mut SyncState) -> io::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    let mut present = HashSet::new();
+
+    for (entity_type, id, fields) in all_fields(db).await? {
+        let key = entity_key(&entity_type, &id);
+        present.insert(key.clone());
+        let entry = state.entities.entry(key).or_default();
+
+        let mut changed = Map::new();
+        let mut base = BTreeMap::new();
+        for (name, value) in fields {
+            let known = entry.fields.get(&name);
+            // A recreated entity is published in full
+            if entry.deleted || known.is_none_or(|field| field.hash != hash(&value)) {
+                if let Some(field) = known {
+                    base.insert(name.clone(), field.hlc.clone());
+                }
+                changed.insert(name, value);
+            }
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        let hlc = state.clock.tick();
+        state.seq += 1;
+        entry.version += 1;
+        entry.deleted = false;
+        for (name, value) in &changed {
+            entry.fields.insert(
+                name.clone(),
+                FieldState {
+                    hash: hash(value),
+                    hlc: hlc.clone(),
+                },
+            );
+        }
+        changes.push(Change {
+            seq: state.seq,
+            hlc,
+            entity_type,
+            entity_id: id,
+            version: entry.version,
+            op: Operation::Put,
+            fields: changed,
+            base,
+        });
+    }
+
+    for (key, entry) in state.entities.iter_mut() {
+        if entry.deleted || present.contains(key) {
+            continue;
+        }
+        let (entity_type, id) = key.split_once(':').unwrap_or_default();
+        state.seq += 1;
+        entry.version += 1;
+        entry.deleted = true;
+        changes.push(Change {
+            seq: state.seq,
+            hlc: state.clock.tick(),
+            entity_type: entity_type.to_string(),
+            entity_id: id.to_string(),
+            version: entry.version,
+            op: Operation::Delete,
+            fields: Map::new(),
+            base: entry
+                .fields
+                .iter()
+                .map(|(name, field)| (name.clone(), field.hlc.clone()))
+                .collect(),
+        });
+    }
+
+    Ok(changes)
+}
+
+// Keeps this device's side of a content conflict as a separate leaf. Only
+// the device that wrote the losing text makes the copy, so each conflict
+// produces exactly one.
+async fn save_conflict_copy(
+    db: &SqlDatabase,
+    fields: &Map<String, Value>,
+    report: &mut SyncReport,
+) -> io::Result<()> {
+    let name = format!(
+        "{} (conflict {})",
+        fields.get("name").and_then(Value::as_str).unwrap_or_default(),
+        Utc::now().format("%Y-%m-%d %H%M%S")
+    );
+    let mut copy = fields.clone();
+    copy.insert("name".to_string(), Value::from(name.clone()));
+    copy.insert(CREATED_FIELD.to_string(), Value::from(Utc::now().to_rfc3339()));
+    store_fields(db, Leaf::get_object_type(), &Uuid::new_v4().to_string(), copy).await?;
+    report.conflicts.push(name);
+    Ok(())
+}
+
+async fn apply_remote_change(
+    db: &SqlDatabase,
+    state: &mut SyncState,
+    change: Change,
+    report: &mut SyncReport,
+) -> io::Result<()> {
+    state.clock.observe(&change.hlc);
+    let entry = state
+        .entities
+        .entry(entity_key(&change.entity_type, &change.entity_id))
+        .or_default();
+    let exists = !entry.deleted && !entry.fields.is_empty();
+
+    match change.op {
+        Operation::Delete => {
+            if !exists {
+                entry.deleted = true;
+                return Ok(());
+            }
+            // Edits the other device had not seen win over its delete. They
+            // are published again in full so it gets the entity back.
+            let edited = entry
+                .fields
+                .iter()
+                .any(|(name, field)| change.base.get(name) != Some(&field.hlc));
+            if edited {
+                for field in entry.fields.values_mut() {
+                    field.hash.clear();
+                }
+                return Ok(());
+            }
+            delete_entity(db, &change.entity_type, &change.entity_id).await?;
+            entry.deleted = true;
+            entry.version = entry.version.max(change.version);
+        }
+        Operation::Put => {
+            if !exists {
+                // A partial edit of an entity deleted here is republished
+                // in full by its author once it sees the delete
+                if !change.fields.contains_key(CREATED_FIELD) {
+                    return Ok(());
+                }
+                for (name, value) in &change.fields {
+                    entry.fields.insert(
+                        name.clone(),
+                        FieldState {
+                            hash: hash(value),
+                            hlc: change.hlc.clone(),
+                        },
+                    );
+                }
+                entry.deleted = false;
+                entry.version = entry.version.max(change.version);
+                store_fields(db, &change.entity_type, &change.entity_id, change.fields).await?;
+                return Ok(());
+            }
+
+            let current = entity_fields(db, &change.entity_type, &change.entity_id)
+                .await?
+                .unwrap_or_default();
+            let mut merged = current.clone();
+            let mut changed = false;
+            for (name, value) in change.fields {
+                let value_hash = hash(&value);
+                let take = match entry.fields.get_mut(&name) {
+                    None => true,
+                    Some(field) if field.hlc >= change.hlc && field.hash == value_hash => false,
+                    // Made on top of what is here: no conflict
+                    Some(field) if change.base.get(&name) == Some(&field.hlc) => true,
+                    Some(field) if field.hash == value_hash => {
+                        field.hlc = field.hlc.clone().max(change.hlc.clone());
+                        false
+                    }
+                    // Both devices changed the field; the later edit wins
+                    Some(field) => {
+                        let remote_wins = change.hlc > field.hlc;
+                        let conflicting_content = change.entity_type == Leaf::get_object_type()
+                            && name == CONTENT_FIELD;
+                        if remote_wins && conflicting_content && field.hlc.device == state.device_id {
+                            save_conflict_copy(db, &current, report).await?;
+                        }
+                        remote_wins
+                    }
+                };
+                if take {
+                    entry.fields.insert(
+                        name.clone(),
+                        FieldState {
+                            hash: value_hash,
+                            hlc: change.hlc.clone(),
+                        },
+                    );
+                    merged.insert(name, value);
+                    changed = true;
+                }
+            }
+            entry.version = entry.version.max(change.version);
+            if changed {
+                store_fields(db, &change.entity_type, &change.entity_id, merged).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// One full pass: publish local edits, merge what other devices wrote, then
+// publish anything the merge produced (conflict copies, restored entities).
+pub async fn sync_once(db: &SqlDatabase, state_path: &Path, dir: &Path) -> io::Result<SyncReport> {
+    fs::create_dir_all(dir)?;
+    let mut state = load_state(state_path, dir)?;
+    let own_log = log_path(dir, &state.device_id);
+    let mut report = SyncReport::default();
+
+    let local = collect_local_changes(db, &mut state).await?;
+    report.published += local.len();
+    append_log(&own_log, &local)?;
+    save_state(state_path, &state)?;
+
+    let mut remote = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(LOG_EXTENSION) || path == own_log {
+            continue;
+        }
+        let Some(device) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let applied = state.applied.get(&device).copied().unwrap_or(0);
+        remote.extend(
+            read_log(&path)?
+                .into_iter()
+                .filter(|change| change.seq > applied)
+                .map(|change| (device.clone(), change)),
+        );
+    }
+    remote.sort_by(|a, b| a.1.hlc.cmp(&b.1.hlc));
+
+    for (device, change) in remote {
+        let seq = change.seq;
+        apply_remote_change(db, &mut state, change, &mut report).await?;
+        report.applied += 1;
+        let applied = state.applied.entry(device).or_default();
+        *applied = (*applied).max(seq);
+    }
+
+    let merged = collect_local_changes(db, &mut state).await?;
+    report.published += merged.len();
+    append_log(&own_log, &merged)?;
+    save_state(state_path, &state)?;
+
+    if let Err(e) = db.refresh_embeddings().await {
+        eprintln!("Could not embed synced entities, will retry: {}", e);
+    }
+    Ok(report)
+}
+
+// -------------------------------------------------------
+
+struct SyncTask {
+    dir: PathBuf,
+    _watcher: RecommendedWatcher,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+// Managed state owning the background sync, if one is running.
+#[derive(Default)]
+pub struct SyncEngine {
+    running: Mutex<Option<SyncTask>>,
+    // Serializes passes from the watcher and from `sync_now`
+    sync_lock: tokio::sync::Mutex<()>,
+}
+
+impl SyncEngine {
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|task| task.dir.clone())
+    }
+
+    pub async fn sync(&self, db: &SqlDatabase, root_dir: &Path, dir: &Path) -> io::Result<SyncReport> {
+        let _guard = self.sync_lock.lock().await;
+        sync_once(db, &root_dir.join("config").join(STATE_FILE), dir).await
+    }
+
+    // Syncs right away, then whenever another device's log changes and on
+    // a fixed interval for local edits.
+    pub fn start(&self, app: AppHandle, dir: PathBuf) -> io::Result<()> {
+        self.stop();
+        fs::create_dir_all(&dir)?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                let relevant = event
+                    .paths
+                    .iter()
+                    .any(|path| path.extension().and_then(|e| e.to_str()) == Some(LOG_EXTENSION));
+                if relevant {
+                    let _ = tx.send(());
+                }
+            }
+        })
+        .map_err(to_io_error)?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .map_err(to_io_error)?;
+
+        let task_dir = dir.clone();
+        let task = tauri::async_runtime::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    event = rx.recv() => {
+                        if event.is_none() {
+                            break;
+                        }
+                        // Sync clients write in bursts; wait for them to settle
+                        while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {}
+                    }
+                }
+
+                let db = app.state::<SqlDatabase>();
+                let root_dir = app.state::<Database>().root_dir();
+                let engine = app.state::<SyncEngine>();
+                match engine.sync(&db, &root_dir, &task_dir).await {
+                    Ok(report) if !report.conflicts.is_empty() => {
+                        eprintln!("Sync conflicts: {:?}", report.conflicts);
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Sync failed: {}", e),
+                }
+            }
+        });
+
+        *self.running.lock().unwrap() = Some(SyncTask {
+            dir,
+            _watcher: watcher,
+            task,
+        });
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().unwrap().take() {
+            running.task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A workspace of its own for each simulated device, all syncing through
+    // one shared folder. Everything is removed when dropped.
+    struct Device {
+        db: SqlDatabase,
+        root: PathBuf,
+        state_path: PathBuf,
+        shared: PathBuf,
+    }
+
+    impl Device {
+        async fn new(shared: &Path) -> Device {
+            let root = std::env::temp_dir().join(format!("bonsai-sync-test-{}", Uuid::new_v4()));
+            let db = SqlDatabase::open(root.clone(), None).await.unwrap();
+            Device {
+                db,
+                state_path: root.join("config").join(STATE_FILE),
+                root,
+                shared: shared.to_path_buf(),
+            }
+        }
+
+        async fn sync(&self) -> SyncReport {
+            sync_once(&self.db, &self.state_path, &self.shared).await.unwrap()
+        }
+
+        // Writes a leaf the way an edit in the app would, minus the embedding
+        async fn write(&self, id: &str, name: &str, content: &str) {
+            let created_at = match self.db.read::<Leaf>(id).await.unwrap() {
+                Some(leaf) => leaf.created_at().to_string(),
+                None => Utc::now().to_rfc3339(),
+            };
+            let mut leaf = Leaf::new(id.to_string(), name.to_string(), content.to_string());
+            leaf.set_created_at(created_at);
+            leaf.set_modified_at(Utc::now().to_rfc3339());
+            self.db.put_deferred(leaf).await.unwrap();
+        }
+
+        async fn leaf(&self, id: &str) -> Option<Leaf> {
+            self.db.read::<Leaf>(id).await.unwrap()
+        }
+
+        async fn leaves(&self) -> Vec<Leaf> {
+            let mut leaves = self.db.list::<Leaf>().await.unwrap();
+            leaves.sort_by(|a, b| a.name().cmp(b.name()));
+            leaves
+        }
+    }
+
+    impl Drop for Device {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    struct SharedDir(PathBuf);
+
+    impl SharedDir {
+        fn new() -> SharedDir {
+            SharedDir(std::env::temp_dir().join(format!("bonsai-sync-shared-{}", Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for SharedDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const ID: &str = "5b0f0c1e-4a8e-4a59-9d1c-6f1f3c2b9a10";
+
+    // Two devices that both have the leaf `ID`.
+    async fn paired(shared: &SharedDir) -> (Device, Device) {
+        let a = Device::new(&shared.0).await;
+        let b = Device::new(&shared.0).await;
+        a.write(ID, "Plan", "<p>First draft</p>").await;
+        a.sync().await;
+        b.sync().await;
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn copies_new_leaves_to_other_devices() {
+        let shared = SharedDir::new();
+        let (_a, b) = paired(&shared).await;
+
+        let leaf = b.leaf(ID).await.unwrap();
+        assert_eq!(leaf.name(), "Plan");
+        assert_eq!(leaf.content(), "<p>First draft</p>");
+    }
+
+    #[tokio::test]
+    async fn merges_edits_of_different_fields() {
+        let shared = SharedDir::new();
+        let (a, b) = paired(&shared).await;
+
+        a.write(ID, "Plan for Q3", "<p>First draft</p>").await;
+        b.write(ID, "Plan", "<p>Second draft</p>").await;
+        a.sync().await;
+        let report = b.sync().await;
+        a.sync().await;
+
+        assert!(report.conflicts.is_empty());
+        for device in [&a, &b] {
+            let leaf = device.leaf(ID).await.unwrap();
+            assert_eq!(leaf.name(), "Plan for Q3");
+            assert_eq!(leaf.content(), "<p>Second draft</p>");
+            assert_eq!(device.leaves().await.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn edit_wins_over_concurrent_delete() {
+        let shared = SharedDir::new();
+        let (a, b) = paired(&shared).await;
+
+        a.db.delete::<Leaf>(ID).await.unwrap();
+        b.write(ID, "Plan", "<p>Kept</p>").await;
+        a.sync().await;
+        b.sync().await;
+        a.sync().await;
+
+        for device in [&a, &b] {
+            let leaf = device.leaf(ID).await.expect("the edited leaf is restored");
+            assert_eq!(leaf.content(), "<p>Kept</p>");
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_reaches_devices_without_edits() {
+        let shared = SharedDir::new();
+        let (a, b) = paired(&shared).await;
+
+        a.db.delete::<Leaf>(ID).await.unwrap();
+        a.sync().await;
+        b.sync().await;
+
+        assert!(b.leaf(ID).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn keeps_conflicting_content_as_a_copy() {
+        let shared = SharedDir::new();
+        let (a, b) = paired(&shared).await;
+
+        a.write(ID, "Plan", "<p>From A</p>").await;
+        b.write(ID, "Plan", "<p>From B</p>").await;
+        let first = a.sync().await;
+        let second = b.sync().await;
+        let third = a.sync().await;
+        b.sync().await;
+
+        // B's edit is published last and wins; A, whose text lost, keeps it
+        // as a copy
+        assert!(first.conflicts.is_empty());
+        assert!(second.conflicts.is_empty());
+        assert_eq!(third.conflicts.len(), 1);
+        for device in [&a, &b] {
+            let leaves = device.leaves().await;
+            assert_eq!(leaves.len(), 2);
+            assert_eq!(device.leaf(ID).await.unwrap().content(), "<p>From B</p>");
+            let copy = leaves.iter().find(|leaf| leaf.id() != ID).unwrap();
+            assert!(copy.name().starts_with("Plan (conflict "));
+            assert_eq!(copy.content(), "<p>From A</p>");
+        }
+    }
+}
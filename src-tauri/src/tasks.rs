@@ -0,0 +1,297 @@
+use crate::db::{Leaf, SqlDatabase};
+use crate::html::{self, Node, Token};
+use crate::journal;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::Error as SqlxError;
+
+// Checklist items of every leaf, indexed into the `tasks` table whenever a
+// leaf's content is written so they can be listed outside their documents.
+// Tasks are numbered by their position in the leaf, nested ones included;
+// toggling one rewrites its item in the leaf's HTML.
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// A task as found in a leaf's HTML.
+pub struct TaskItem {
+    pub position: i64,
+    // The item's own block id, or that of the closest block before it
+    pub block_id: Option<String>,
+    pub text: String,
+    pub checked: bool,
+    // From an `@2026-10-20` marker in the text
+    pub due_date: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Task {
+    pub leaf_id: String,
+    pub leaf_name: String,
+    pub position: i64,
+    pub block_id: Option<String>,
+    pub text: String,
+    pub checked: bool,
+    pub due_date: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskStatus {
+    Open,
+    Done,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TaskFilter {
+    pub status: Option<TaskStatus>,
+    // Inclusive, as YYYY-MM-DD. Tasks without a due date are left out when
+    // either is set.
+    pub due_from: Option<String>,
+    pub due_to: Option<String>,
+    pub leaf_id: Option<String>,
+}
+
+impl TaskFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        for date in self.due_from.iter().chain(&self.due_to) {
+            journal::parse_date(date)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_task_item(node: &Node) -> bool {
+    node.name() == Some("li") && node.attr("data-type") == Some("taskItem")
+}
+
+fn is_task_item_token(token: &Token) -> bool {
+    token.is_start("li") && token.attr("data-type") == Some("taskItem")
+}
+
+// Whether `node` is part of the item itself rather than its checkbox or the
+// tasks nested under it.
+fn is_item_content(node: &Node) -> bool {
+    !matches!(node.name(), Some("label" | "ul" | "ol"))
+}
+
+fn item_text(node: &Node, out: &mut String) {
+    for child in node.children().iter().filter(|child| is_item_content(child)) {
+        match child {
+            Node::Text(text) => out.push_str(text),
+            element => {
+                // Keep words in separate paragraphs apart
+                out.push(' ');
+                item_text(element, out);
+            }
+        }
+    }
+}
+
+fn item_block_id(node: &Node) -> Option<String> {
+    node.attr("blockid").map(str::to_string).or_else(|| {
+        node.children()
+            .iter()
+            .filter(|child| is_item_content(child))
+            .find_map(item_block_id)
+    })
+}
+
+// The first `@YYYY-MM-DD` that stands on its own, e.g. not part of an email
+// address.
+pub fn due_date(text: &str) -> Option<String> {
+    text.match_indices('@').find_map(|(i, _)| {
+        if text[..i].chars().next_back().is_some_and(|c| !c.is_whitespace() && c != '(') {
+            return None;
+        }
+        let date = text.get(i + 1..i + 11)?;
+        if text[i + 11..].chars().next().is_some_and(char::is_alphanumeric) {
+            return None;
+        }
+        let date = NaiveDate::parse_from_str(date, DATE_FORMAT).ok()?;
+        Some(date.format(DATE_FORMAT).to_string())
+    })
+}
+
+fn collect(nodes: &[Node], block_id: &mut Option<String>, out: &mut Vec<TaskItem>) {
+    for node in nodes {
+        if let Some(id) = node.attr("blockid") {
+            *block_id = Some(id.to_string());
+        }
+        if is_task_item(node) {
+            let mut text = String::new();
+            item_text(node, &mut text);
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            out.push(TaskItem {
+                position: out.len() as i64,
+                block_id: item_block_id(node).or_else(|| block_id.clone()),
+                checked: node.attr("data-checked") == Some("true"),
+                due_date: due_date(&text),
+                text,
+            });
+        }
+        collect(node.children(), block_id, out);
+    }
+}
+
+// Every task item in `content`, in document order.
+pub fn extract(content: &str) -> Vec<TaskItem> {
+    let mut tasks = Vec::new();
+    collect(&html::parse(content), &mut None, &mut tasks);
+    tasks
+}
+
+// `content` with the task at `position` checked or unchecked, or `None` when
+// there is no such task. Only the item's tag and its checkbox are rewritten.
+pub fn set_checked(content: &str, position: i64, checked: bool) -> Option<String> {
+    let tokens = html::tokenize(content);
+    let start = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, (token, _))| is_task_item_token(token))
+        .nth(usize::try_from(position).ok()?)?
+        .0;
+
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    let mut rewrite = |out: &mut String, index: usize, attrs: Vec<(String, String)>| {
+        let (Token::Start { name, self_closing, .. }, span) = &tokens[index] else {
+            return;
+        };
+        out.push_str(&content[last..span.start]);
+        out.push_str(&html::render_start_tag(name, &attrs, *self_closing));
+        last = span.end;
+    };
+
+    let mut attrs = match &tokens[start].0 {
+        Token::Start { attrs, .. } => attrs.clone(),
+        _ => return None,
+    };
+    attrs.retain(|(key, _)| !key.eq_ignore_ascii_case("data-checked"));
+    attrs.push(("data-checked".to_string(), checked.to_string()));
+    rewrite(&mut out, start, attrs);
+
+    // The item's checkbox comes before its text and any nested list
+    let checkbox = tokens[start + 1..]
+        .iter()
+        .position(|(token, _)| {
+            ["input", "li", "ul", "ol"].iter().any(|name| token.is_start(name)) || token.is_end("li")
+        })
+        .map(|offset| start + 1 + offset)
+        .filter(|index| tokens[*index].0.is_start("input"));
+    if let Some(index) = checkbox {
+        if let Token::Start { attrs, .. } = &tokens[index].0 {
+            let mut attrs = attrs.clone();
+            attrs.retain(|(key, _)| !key.eq_ignore_ascii_case("checked"));
+            if checked {
+                attrs.push(("checked".to_string(), "checked".to_string()));
+            }
+            rewrite(&mut out, index, attrs);
+        }
+    }
+    out.push_str(&content[last..]);
+    Some(out)
+}
+
+// Checks or unchecks a task in its leaf. `text` is the task as the caller
+// last saw it; if the leaf has changed so the position now holds another
+// task, nothing is written.
+pub async fn toggle(
+    db: &SqlDatabase,
+    leaf_id: &str,
+    position: i64,
+    text: &str,
+    checked: bool,
+) -> Result<Task, String> {
+    let leaf = db
+        .read::<Leaf>(leaf_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Leaf {} not found", leaf_id))?;
+    let current = extract(leaf.content())
+        .into_iter()
+        .find(|task| task.position == position);
+    if current.is_none_or(|task| task.text != text) {
+        return Err("The task has changed since it was listed".to_string());
+    }
+    let content = set_checked(leaf.content(), position, checked)
+        .ok_or_else(|| "The task has changed since it was listed".to_string())?;
+    db.update(Leaf::new(leaf_id.to_string(), leaf.name().to_string(), content))
+        .await
+        .map_err(|e| e.to_string())?;
+    db.task(leaf_id, position)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| SqlxError::RowNotFound.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = concat!(
+        "<ul data-type=\"taskList\">",
+        "<li data-type=\"taskItem\" data-checked=\"false\"><label><input type=\"checkbox\"></label>",
+        "<div><p blockid=\"a\">Call @2024-05-01</p>",
+        "<ul data-type=\"taskList\"><li data-type=\"taskItem\" data-checked=\"true\">",
+        "<label><input type=\"checkbox\" checked=\"checked\"></label><div><p>Nested</p></div></li></ul>",
+        "</div></li></ul>",
+    );
+
+    #[test]
+    fn checks_only_the_item_and_its_checkbox() {
+        assert_eq!(
+            set_checked(LIST, 0, true).unwrap(),
+            LIST.replacen(
+                "data-checked=\"false\"><label><input type=\"checkbox\">",
+                "data-checked=\"true\"><label><input type=\"checkbox\" checked=\"checked\">",
+                1
+            )
+        );
+        assert_eq!(
+            set_checked(LIST, 1, false).unwrap(),
+            LIST.replacen(
+                "data-checked=\"true\"><label><input type=\"checkbox\" checked=\"checked\">",
+                "data-checked=\"false\"><label><input type=\"checkbox\">",
+                1
+            )
+        );
+        // Setting the state it already has changes nothing
+        assert_eq!(set_checked(LIST, 1, true).unwrap(), LIST);
+    }
+
+    #[test]
+    fn checks_items_without_a_checkbox() {
+        let item = "<li data-type=\"taskItem\"><p>Bare</p></li>";
+        assert_eq!(
+            set_checked(item, 0, true).unwrap(),
+            "<li data-type=\"taskItem\" data-checked=\"true\"><p>Bare</p></li>"
+        );
+    }
+
+    #[test]
+    fn refuses_missing_positions() {
+        assert_eq!(set_checked(LIST, 2, true), None);
+        assert_eq!(set_checked(LIST, -1, true), None);
+        assert_eq!(set_checked("<p>No tasks</p>", 0, true), None);
+    }
+
+    #[test]
+    fn extracts_nested_tasks_with_due_dates() {
+        let tasks = extract(LIST);
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(
+            (tasks[0].text.as_str(), tasks[0].checked, tasks[0].block_id.as_deref()),
+            ("Call @2024-05-01", false, Some("a"))
+        );
+        assert_eq!(tasks[0].due_date.as_deref(), Some("2024-05-01"));
+        assert_eq!((tasks[1].text.as_str(), tasks[1].checked), ("Nested", true));
+        assert_eq!(tasks[1].block_id.as_deref(), Some("a"));
+
+        assert_eq!(due_date("mail me@2024-05-01"), None);
+        assert_eq!(due_date("(@2024-05-01)").as_deref(), Some("2024-05-01"));
+        assert_eq!(due_date("@2024-13-01"), None);
+        assert_eq!(due_date("@2024-05-012"), None);
+    }
+}
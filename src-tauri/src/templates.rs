@@ -0,0 +1,303 @@
+use crate::db::{Leaf, SqlDatabase, Template};
+use crate::html;
+use crate::markdown;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use sqlx::Error as SqlxError;
+use std::collections::HashMap;
+
+// Starting content for new leaves. Templates are HTML like leaves, with
+// variables filled in when a leaf is created from one:
+//
+//   {{title}}           the new leaf's name
+//   {{date}}, {{time}}  now, as 2024-05-01 and 14:30; `{{date:%A}}` takes
+//                       any strftime format
+//   {{clipboard}}       text on the clipboard
+//   {{prompt:Label}}    asked for when the leaf is created
+//
+// Anything else in braces is left as it is. The built-in templates live here
+// rather than in the database, so they can't be edited or deleted.
+
+const BUILTIN_PREFIX: &str = "builtin-";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TIME_FORMAT: &str = "%H:%M";
+
+// Id suffix, name, description and Markdown content
+const BUILTINS: &[(&str, &str, &str, &str)] = &[
+    (
+        "meeting-notes",
+        "Meeting notes",
+        "Attendees, agenda, notes and action items",
+        "# {{title}}
+
+**Date:** {{date}} {{time}}
+
+**Attendees:** {{prompt:Attendees}}
+
+## Agenda
+
+{{prompt:Agenda}}
+
+## Notes
+
+## Decisions
+
+## Action items
+
+- [ ] Follow up
+",
+    ),
+    (
+        "blog-post-outline",
+        "Blog post outline",
+        "Hook, main points and a call to action",
+        "# {{title}}
+
+*Outline started {{date}}. Audience: {{prompt:Audience}}*
+
+## Hook
+
+## Main points
+
+1. First point
+2. Second point
+3. Third point
+
+## Conclusion
+
+## Call to action
+",
+    ),
+    (
+        "weekly-review",
+        "Weekly review",
+        "Look back on the week and plan the next one",
+        "# {{title}}
+
+*Week of {{date:%B %-d, %Y}}*
+
+## Wins
+
+## Challenges
+
+## Lessons learned
+
+## Priorities for next week
+
+- [ ] Priority one
+- [ ] Priority two
+- [ ] Priority three
+",
+    ),
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateInfo {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub builtin: bool,
+    // Labels of the `{{prompt:..}}` variables, in order of appearance
+    pub prompts: Vec<String>,
+    pub uses_clipboard: bool,
+}
+
+fn builtin(id: &str) -> Option<Template> {
+    let suffix = id.strip_prefix(BUILTIN_PREFIX)?;
+    BUILTINS
+        .iter()
+        .find(|(builtin_id, ..)| *builtin_id == suffix)
+        .map(|(_, name, description, content)| {
+            Template::new(
+                id.to_string(),
+                name.to_string(),
+                description.to_string(),
+                markdown::to_html(content, |_| None, |_| None),
+            )
+        })
+}
+
+fn info(template: &Template, builtin: bool) -> TemplateInfo {
+    TemplateInfo {
+        id: template.id().to_string(),
+        name: template.name().to_string(),
+        description: template.description().to_string(),
+        builtin,
+        prompts: prompts(template.content()),
+        uses_clipboard: uses_clipboard(template.content()),
+    }
+}
+
+// The built-in templates followed by the workspace's own.
+pub async fn list(db: &SqlDatabase) -> Result<Vec<TemplateInfo>, SqlxError> {
+    let mut templates: Vec<TemplateInfo> = BUILTINS
+        .iter()
+        .filter_map(|(id, ..)| builtin(&format!("{}{}", BUILTIN_PREFIX, id)))
+        .map(|template| info(&template, true))
+        .collect();
+    let mut stored = db.list::<Template>().await?;
+    stored.sort_by_key(|template| template.name().to_lowercase());
+    templates.extend(stored.iter().map(|template| info(template, false)));
+    Ok(templates)
+}
+
+pub async fn find(db: &SqlDatabase, id: &str) -> Result<Option<Template>, SqlxError> {
+    match builtin(id) {
+        Some(template) => Ok(Some(template)),
+        None => db.read::<Template>(id).await,
+    }
+}
+
+// -------------------------------------------------------
+
+// The `{{...}}` variables in `content`: their text as written and the range
+// of the whole variable, braces included.
+fn variables(content: &str) -> Vec<(&str, std::ops::Range<usize>)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = content[offset..].find("{{") {
+        let start = offset + start;
+        let Some(end) = content[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + end;
+        found.push((content[start + 2..end].trim(), start..end + 2));
+        offset = end + 2;
+    }
+    found
+}
+
+// Labels are HTML-escaped in the template like any other text.
+pub fn prompts(content: &str) -> Vec<String> {
+    let mut labels: Vec<String> = Vec::new();
+    for (variable, _) in variables(content) {
+        if let Some(label) = variable.strip_prefix("prompt:") {
+            let label = html::unescape(label.trim());
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+    }
+    labels
+}
+
+pub fn uses_clipboard(content: &str) -> bool {
+    variables(content).iter().any(|(variable, _)| *variable == "clipboard")
+}
+
+fn format_time(now: &DateTime<Local>, format: &str) -> Option<String> {
+    let format = html::unescape(format.trim());
+    if format.is_empty() || StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        return None;
+    }
+    Some(now.format(&format).to_string())
+}
+
+// Fills in the variables of `content`. `values` holds the answers to the
+// prompts by label; unanswered prompts are left empty.
+pub fn render(
+    content: &str,
+    title: &str,
+    values: &HashMap<String, String>,
+    clipboard: Option<&str>,
+    now: DateTime<Local>,
+) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for (variable, range) in variables(content) {
+        let value = match variable.split_once(':') {
+            None => match variable {
+                "title" => Some(title.to_string()),
+                "date" => Some(now.format(DATE_FORMAT).to_string()),
+                "time" => Some(now.format(TIME_FORMAT).to_string()),
+                "clipboard" => Some(clipboard.unwrap_or_default().to_string()),
+                _ => None,
+            },
+            Some(("date" | "time", format)) => format_time(&now, format),
+            Some(("prompt", label)) => Some(
+                values
+                    .get(&html::unescape(label.trim()))
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            Some(_) => None,
+        };
+        if let Some(value) = value {
+            out.push_str(&content[last..range.start]);
+            // The time formats are ours, but may still contain `<` or `&`
+            out.push_str(&html::escape(&value));
+            last = range.end;
+        }
+    }
+    out.push_str(&content[last..]);
+    out
+}
+
+pub async fn create_leaf(
+    db: &SqlDatabase,
+    template: &Template,
+    title: &str,
+    values: &HashMap<String, String>,
+    clipboard: Option<&str>,
+) -> Result<String, SqlxError> {
+    let title = match title.trim() {
+        "" => template.name(),
+        title => title,
+    };
+    let content = render(template.content(), title, values, clipboard, Local::now());
+    let leaf = Leaf::new(uuid::Uuid::new_v4().to_string(), title.to_string(), content);
+    db.create(leaf).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, 14, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn fills_in_variables() {
+        let values = HashMap::from([("Who & why".to_string(), "<Ann>".to_string())]);
+        let content = concat!(
+            "<h1>{{ title }}</h1><p>{{date}} {{time}} {{date:%A}}</p>",
+            "<p>{{clipboard}} {{prompt: Who &amp; why }} {{prompt:Unanswered}}</p>",
+        );
+        assert_eq!(
+            render(content, "Tom & Jerry", &values, Some("copied"), now()),
+            concat!(
+                "<h1>Tom &amp; Jerry</h1><p>2024-05-01 14:30 Wednesday</p>",
+                "<p>copied &lt;Ann&gt; </p>",
+            )
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_and_broken_variables_alone() {
+        let content = "{{unknown}} {{date:%Q}} {{date:}} {{other:x}} {{title";
+        assert_eq!(render(content, "T", &HashMap::new(), None, now()), content);
+        assert_eq!(render("{{clipboard}}", "T", &HashMap::new(), None, now()), "");
+    }
+
+    #[test]
+    fn lists_prompts_once_and_the_clipboard() {
+        let content = "{{prompt:A &amp; B}} {{clipboard}} {{prompt: A &amp; B }} {{prompt:C}}";
+        assert_eq!(prompts(content), ["A & B", "C"]);
+        assert!(uses_clipboard(content));
+        assert!(!uses_clipboard("{{clipboard:x}}"));
+    }
+
+    #[test]
+    fn builtins_render_from_markdown() {
+        for (suffix, ..) in BUILTINS {
+            let template = builtin(&format!("{}{}", BUILTIN_PREFIX, suffix)).unwrap();
+            assert!(template.content().starts_with('<'));
+        }
+        assert!(builtin("builtin-missing").is_none());
+        assert!(builtin(BUILTINS[0].0).is_none());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::db::{Leaf, SqlDatabase};
+use crate::workspace::Workspaces;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{
+    AppHandle, Emitter, Manager, WebviewUrl, WebviewWindow, WebviewWindowBuilder, Window,
+    WindowEvent,
+};
+
+// Leaves opened in windows of their own. Each leaf's window size and
+// position are remembered per workspace, and the windows open when the app
+// quits reopen with it.
+//
+// One window at a time holds a leaf's edit lock. The lock is advisory: the
+// leaf's document merges concurrent edits from every window and collaboration
+// peer alike, so nothing is refused because of it. It only tells windows who
+// else is editing, through `leaf-lock-changed`.
+
+pub const MAIN_WINDOW: &str = "main";
+// Matched by `capabilities/leaf-window.json`
+const LABEL_PREFIX: &str = "leaf-";
+const STATE_FILE: &str = "windows.json";
+pub const LOCK_EVENT: &str = "leaf-lock-changed";
+
+const DEFAULT_WIDTH: f64 = 800.0;
+const DEFAULT_HEIGHT: f64 = 700.0;
+
+fn to_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+// In logical pixels
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Geometry {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    maximized: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct WindowState {
+    geometry: HashMap<String, Geometry>,
+    // Leaves whose windows were open, in the order they were opened
+    open: Vec<String>,
+}
+
+// Who may edit a leaf, as seen by the window that asked.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafLock {
+    pub leaf_id: String,
+    // Label of the window holding the lock
+    pub holder: Option<String>,
+    // No other window holds the lock. Only a hint for the editor's indicator.
+    pub editable: bool,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LockChange<'a> {
+    leaf_id: &'a str,
+    holder: Option<&'a str>,
+}
+
+pub fn label(leaf_id: &str) -> String {
+    format!("{}{}", LABEL_PREFIX, leaf_id)
+}
+
+fn leaf_id(label: &str) -> Option<&str> {
+    label.strip_prefix(LABEL_PREFIX)
+}
+
+// Window labels only allow some characters, which generated ids stay within.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn geometry(window: &WebviewWindow) -> tauri::Result<Geometry> {
+    let scale = window.scale_factor()?;
+    let position = window.outer_position()?.to_logical::<f64>(scale);
+    let size = window.inner_size()?.to_logical::<f64>(scale);
+    Ok(Geometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        maximized: window.is_maximized()?,
+    })
+}
+
+#[derive(Default)]
+pub struct LeafWindows {
+    // Serializes changes to the state file
+    state: Mutex<()>,
+    // Leaf id to the label of the window editing it
+    locks: Mutex<HashMap<String, String>>,
+}
+
+impl LeafWindows {
+    fn state_path(app: &AppHandle) -> PathBuf {
+        let workspace = app.state::<Workspaces>().active();
+        workspace.path.join("config").join(STATE_FILE)
+    }
+
+    fn read_state(app: &AppHandle) -> WindowState {
+        fs::read_to_string(Self::state_path(app))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn update_state(&self, app: &AppHandle, f: impl FnOnce(&mut WindowState)) -> io::Result<()> {
+        let _guard = self.state.lock().unwrap();
+        let path = Self::state_path(app);
+        let mut state = Self::read_state(app);
+        f(&mut state);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(&state)?)
+    }
+
+    // Opens `leaf` in its own window, or focuses the one already showing it.
+    pub fn open(&self, app: &AppHandle, leaf: &Leaf) -> io::Result<String> {
+        if !valid_id(leaf.id()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Leaf id {} can't be used as a window label", leaf.id()),
+            ));
+        }
+        let label = label(leaf.id());
+        if let Some(window) = app.get_webview_window(&label) {
+            let _ = window.unminimize();
+            window.set_focus().map_err(to_io_error)?;
+            return Ok(label);
+        }
+
+        let mut saved = None;
+        self.update_state(app, |state| {
+            saved = state.geometry.get(leaf.id()).copied();
+            if !state.open.iter().any(|id| id == leaf.id()) {
+                state.open.push(leaf.id().to_string());
+            }
+        })?;
+        let url = WebviewUrl::App(format!("leafs/{}", leaf.id()).into());
+        let mut builder = WebviewWindowBuilder::new(app, &label, url)
+            .title(leaf.name())
+            .inner_size(DEFAULT_WIDTH, DEFAULT_HEIGHT);
+        if let Some(geometry) = saved {
+            builder = builder
+                .inner_size(geometry.width, geometry.height)
+                .position(geometry.x, geometry.y)
+                .maximized(geometry.maximized);
+        }
+        builder.build().map_err(to_io_error)?;
+        Ok(label)
+    }
+
+    // Reopens the windows that were open when the workspace was last used.
+    pub async fn restore(&self, app: &AppHandle, db: &SqlDatabase) {
+        for id in Self::read_state(app).open {
+            let result = match db.read::<Leaf>(&id).await {
+                Ok(Some(leaf)) => self.open(app, &leaf).map(|_| ()),
+                // Deleted since; forget its window
+                Ok(None) => self.update_state(app, |state| state.open.retain(|open| open != &id)),
+                Err(e) => Err(to_io_error(e)),
+            };
+            if let Err(e) = result {
+                eprintln!("Could not reopen the window of leaf {}: {}", id, e);
+            }
+        }
+    }
+
+    // Records the geometry of the open leaf windows. With `forget` they
+    // won't be reopened either.
+    fn remember(&self, app: &AppHandle, windows: &[WebviewWindow], forget: bool) {
+        let geometries: Vec<(String, Geometry)> = windows
+            .iter()
+            .filter_map(|window| {
+                let id = leaf_id(window.label())?;
+                geometry(window).ok().map(|geometry| (id.to_string(), geometry))
+            })
+            .collect();
+        let result = self.update_state(app, |state| {
+            for (id, geometry) in geometries {
+                if forget {
+                    state.open.retain(|open| open != &id);
+                }
+                state.geometry.insert(id, geometry);
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Could not save window state: {}", e);
+        }
+    }
+
+    // Closes every leaf window, e.g. before another workspace opens. They
+    // reopen when this workspace is next opened.
+    pub fn close_all(&self, app: &AppHandle) {
+        let windows: Vec<WebviewWindow> = app
+            .webview_windows()
+            .into_values()
+            .filter(|window| leaf_id(window.label()).is_some())
+            .collect();
+        self.remember(app, &windows, false);
+        for window in windows {
+            let _ = window.destroy();
+        }
+    }
+
+    // -------------------------------------------------------
+
+    fn lock_changed(app: &AppHandle, leaf_id: &str, holder: Option<&str>) {
+        let _ = app.emit(LOCK_EVENT, LockChange { leaf_id, holder });
+    }
+
+    pub fn lock(&self, leaf_id: &str, window: &str) -> LeafLock {
+        let holder = self.locks.lock().unwrap().get(leaf_id).cloned();
+        LeafLock {
+            leaf_id: leaf_id.to_string(),
+            editable: holder.as_deref().is_none_or(|holder| holder == window),
+            holder,
+        }
+    }
+
+    // Takes the edit lock on the leaf for `window` unless another window
+    // holds it.
+    pub fn acquire(&self, app: &AppHandle, leaf_id: &str, window: &str) -> LeafLock {
+        let acquired = {
+            let mut locks = self.locks.lock().unwrap();
+            match locks.get(leaf_id) {
+                Some(_) => false,
+                None => {
+                    locks.insert(leaf_id.to_string(), window.to_string());
+                    true
+                }
+            }
+        };
+        if acquired {
+            Self::lock_changed(app, leaf_id, Some(window));
+        }
+        self.lock(leaf_id, window)
+    }
+
+    pub fn release(&self, app: &AppHandle, leaf_id: &str, window: &str) {
+        let released = {
+            let mut locks = self.locks.lock().unwrap();
+            match locks.get(leaf_id) {
+                Some(holder) if holder == window => locks.remove(leaf_id).is_some(),
+                _ => false,
+            }
+        };
+        if released {
+            Self::lock_changed(app, leaf_id, None);
+        }
+    }
+
+    fn release_window(&self, app: &AppHandle, window: &str) {
+        let released: Vec<String> = {
+            let mut locks = self.locks.lock().unwrap();
+            let ids: Vec<String> = locks
+                .iter()
+                .filter(|(_, holder)| holder.as_str() == window)
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter().for_each(|id| {
+                locks.remove(id);
+            });
+            ids
+        };
+        for id in released {
+            Self::lock_changed(app, &id, None);
+        }
+    }
+}
+
+// Window events for every window of the app. Closing the main window quits,
+// leaving the open leaf windows to reopen on the next launch.
+pub fn handle_event(window: &Window, event: &WindowEvent) {
+    let app = window.app_handle();
+    let windows = app.state::<LeafWindows>();
+    match event {
+        WindowEvent::CloseRequested { .. } if window.label() == MAIN_WINDOW => {
+            let leaf_windows: Vec<WebviewWindow> = app
+                .webview_windows()
+                .into_values()
+                .filter(|window| leaf_id(window.label()).is_some())
+                .collect();
+            windows.remember(app, &leaf_windows, false);
+            app.exit(0);
+        }
+        WindowEvent::CloseRequested { .. } => {
+            if let Some(window) = app.get_webview_window(window.label()) {
+                windows.remember(app, &[window], true);
+            }
+        }
+        WindowEvent::Destroyed => windows.release_window(app, window.label()),
+        _ => {}
+    }
+}
@@ -0,0 +1,447 @@
+use crate::settings;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use uuid::Uuid;
+
+pub const CHANGED_EVENT: &str = "workspace-changed";
+const DEFAULT_ID: &str = "default";
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceInfo {
+    pub id: String,
+    pub name: String,
+    // Holds the database, uploads and the `config` folder with settings
+    pub path: PathBuf,
+    pub created_at: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceList {
+    pub active: String,
+    // Where new workspaces are created, e.g. a synced Dropbox folder.
+    // Defaults to the app data folder.
+    pub data_dir: Option<PathBuf>,
+    pub workspaces: Vec<WorkspaceInfo>,
+}
+
+fn not_found(id: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Workspace {} not found", id),
+    )
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+fn validate_name(name: &str) -> io::Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(invalid_input("Workspace name must not be empty"));
+    }
+    Ok(name.to_string())
+}
+
+// Folder name for a new workspace: the name without characters that are
+// awkward in paths, made unique within `parent`.
+fn folder_for(parent: &Path, name: &str) -> PathBuf {
+    let base: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') { c } else { '-' })
+        .collect();
+    let base = base.trim().trim_matches('-');
+    let base = if base.is_empty() { "Workspace" } else { base };
+    let mut candidate = parent.join(base);
+    let mut n = 2;
+    while candidate.exists() {
+        candidate = parent.join(format!("{} {}", base, n));
+        n += 1;
+    }
+    candidate
+}
+
+// Folders of the data folder that hold a workspace's files
+const LEGACY_FOLDERS: [&str; 3] = ["config", "uploads", "sages"];
+
+// Moves what `from` holds into `to`, keeping files `to` already has. `from`
+// is removed once empty.
+fn move_missing(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if !target.exists() {
+            fs::rename(entry.path(), &target)?;
+        } else if entry.file_type()?.is_dir() && target.is_dir() {
+            move_missing(&entry.path(), &target)?;
+        }
+    }
+    let _ = fs::remove_dir(from);
+    Ok(())
+}
+
+// -------------------------------------------------------
+
+// The list of known workspaces and which one is open. Kept in the app config
+// folder, so it survives even when workspaces live somewhere else.
+pub struct Workspaces {
+    path: PathBuf,
+    default_data_dir: PathBuf,
+    list: RwLock<WorkspaceList>,
+    // Held while a switch is in progress so two cannot interleave
+    switching: tokio::sync::Mutex<()>,
+}
+
+impl Workspaces {
+    // Loads `workspaces.json`. On first run the original data folder becomes
+    // the default workspace and the settings saved beside this file move
+    // into it.
+    pub fn load(config_dir: &Path, app_data_dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(config_dir)?;
+        let path = config_dir.join("workspaces.json");
+        let list = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            let default = WorkspaceInfo {
+                id: DEFAULT_ID.to_string(),
+                name: "Bonsai".to_string(),
+                path: app_data_dir.join("bonsai"),
+                created_at: Utc::now().to_rfc3339(),
+            };
+            // Files kept straight in the data folder belong to the default
+            // workspace now
+            for folder in LEGACY_FOLDERS {
+                move_missing(&app_data_dir.join(folder), &default.path.join(folder))?;
+            }
+            let settings = config_dir.join("settings.json");
+            if settings.exists() {
+                let target = default.path.join("config");
+                fs::create_dir_all(&target)?;
+                fs::rename(&settings, target.join("settings.json"))?;
+            }
+            WorkspaceList {
+                active: DEFAULT_ID.to_string(),
+                data_dir: None,
+                workspaces: vec![default],
+            }
+        };
+
+        let workspaces = Self {
+            path,
+            default_data_dir: app_data_dir.to_path_buf(),
+            list: RwLock::new(list),
+            switching: tokio::sync::Mutex::new(()),
+        };
+        workspaces.save(&workspaces.list())?;
+        Ok(workspaces)
+    }
+
+    // Like `load`, but a list that can't be read is set aside and a new one
+    // started with the default workspace. The other workspaces' folders are
+    // left alone. Returns what was wrong with the list, for the user to see.
+    pub fn load_or_reset(
+        config_dir: &Path,
+        app_data_dir: &Path,
+    ) -> io::Result<(Self, Option<String>)> {
+        let error = match Self::load(config_dir, app_data_dir) {
+            Ok(workspaces) => return Ok((workspaces, None)),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => e,
+            Err(e) => return Err(e),
+        };
+        let aside = settings::set_aside(&config_dir.join("workspaces.json"))?;
+        let message = format!(
+            "The list of workspaces could not be read ({}). It was moved to {} and only the default workspace is listed.",
+            error,
+            aside.display()
+        );
+        Ok((Self::load(config_dir, app_data_dir)?, Some(message)))
+    }
+
+    // Loads `workspaces.json` without writing anything, for the command-line
+    // tools. Fails until the app has set the list up.
+    pub fn read_only(config_dir: &Path, app_data_dir: &Path) -> io::Result<Self> {
+        let path = config_dir.join("workspaces.json");
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No workspaces set up yet; open Bonsai once first",
+            ));
+        }
+        let list = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        Ok(Self {
+            path,
+            default_data_dir: app_data_dir.to_path_buf(),
+            list: RwLock::new(list),
+            switching: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub async fn begin_switch(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.switching.lock().await
+    }
+
+    pub fn list(&self) -> WorkspaceList {
+        self.list.read().unwrap().clone()
+    }
+
+    pub fn get(&self, id: &str) -> io::Result<WorkspaceInfo> {
+        self.list
+            .read()
+            .unwrap()
+            .workspaces
+            .iter()
+            .find(|w| w.id == id)
+            .cloned()
+            .ok_or_else(|| not_found(id))
+    }
+
+    pub fn active(&self) -> WorkspaceInfo {
+        let list = self.list.read().unwrap();
+        list.workspaces
+            .iter()
+            .find(|w| w.id == list.active)
+            .or_else(|| list.workspaces.first())
+            .cloned()
+            .expect("workspace list is never empty")
+    }
+
+    pub fn set_active(&self, id: &str) -> io::Result<()> {
+        self.change(|list| {
+            if !list.workspaces.iter().any(|w| w.id == id) {
+                return Err(not_found(id));
+            }
+            list.active = id.to_string();
+            Ok(())
+        })
+    }
+
+    pub fn set_data_dir(&self, dir: Option<PathBuf>) -> io::Result<()> {
+        if let Some(dir) = &dir {
+            if !dir.is_absolute() {
+                return Err(invalid_input("Data directory must be an absolute path"));
+            }
+            fs::create_dir_all(dir)?;
+        }
+        self.change(|list| {
+            list.data_dir = dir;
+            Ok(())
+        })
+    }
+
+    // Registers a new, empty workspace. Without a `path` it gets its own
+    // folder inside the data directory.
+    pub fn create(&self, name: &str, path: Option<PathBuf>) -> io::Result<WorkspaceInfo> {
+        let name = validate_name(name)?;
+        let path = match path {
+            Some(path) => {
+                if path.is_dir() && fs::read_dir(&path)?.next().is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "Folder is not empty; open it as an existing workspace instead",
+                    ));
+                }
+                path
+            }
+            None => {
+                let parent = self.list().data_dir.unwrap_or_else(|| self.default_data_dir.clone());
+                folder_for(&parent, &name)
+            }
+        };
+        if !path.is_absolute() {
+            return Err(invalid_input("Workspace path must be absolute"));
+        }
+        fs::create_dir_all(&path)?;
+        self.add(WorkspaceInfo {
+            id: Uuid::new_v4().to_string(),
+            name,
+            path,
+            created_at: Utc::now().to_rfc3339(),
+        })
+    }
+
+    // Registers a folder that already holds a workspace, such as one synced
+    // from another machine. Known folders return their existing entry.
+    pub fn add_existing(&self, path: &Path, name: Option<&str>) -> io::Result<WorkspaceInfo> {
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a folder", path.display()),
+            ));
+        }
+        let path = path.canonicalize()?;
+        if let Some(known) = self
+            .list()
+            .workspaces
+            .into_iter()
+            .find(|w| w.path.canonicalize().is_ok_and(|p| p == path))
+        {
+            return Ok(known);
+        }
+        let name = match name {
+            Some(name) => validate_name(name)?,
+            None => path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "Workspace".to_string()),
+        };
+        self.add(WorkspaceInfo {
+            id: Uuid::new_v4().to_string(),
+            name,
+            path,
+            created_at: Utc::now().to_rfc3339(),
+        })
+    }
+
+    pub fn rename(&self, id: &str, name: &str) -> io::Result<WorkspaceInfo> {
+        let name = validate_name(name)?;
+        self.change(|list| {
+            let workspace = list
+                .workspaces
+                .iter_mut()
+                .find(|w| w.id == id)
+                .ok_or_else(|| not_found(id))?;
+            workspace.name = name;
+            Ok(())
+        })?;
+        self.get(id)
+    }
+
+    // Forgets a workspace. Its folder is left untouched so it can be opened
+    // again later.
+    pub fn remove(&self, id: &str) -> io::Result<()> {
+        self.change(|list| {
+            if list.active == id {
+                return Err(invalid_input(
+                    "Switch to another workspace before closing this one",
+                ));
+            }
+            let before = list.workspaces.len();
+            list.workspaces.retain(|w| w.id != id);
+            if list.workspaces.len() == before {
+                return Err(not_found(id));
+            }
+            Ok(())
+        })
+    }
+
+    fn add(&self, workspace: WorkspaceInfo) -> io::Result<WorkspaceInfo> {
+        self.change(|list| {
+            list.workspaces.push(workspace.clone());
+            Ok(())
+        })?;
+        Ok(workspace)
+    }
+
+    // Applies `f` and saves, leaving the list unchanged if either fails.
+    fn change(&self, f: impl FnOnce(&mut WorkspaceList) -> io::Result<()>) -> io::Result<()> {
+        let mut list = self.list.write().unwrap();
+        let mut updated = list.clone();
+        f(&mut updated)?;
+        self.save(&updated)?;
+        *list = updated;
+        Ok(())
+    }
+
+    fn save(&self, list: &WorkspaceList) -> io::Result<()> {
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, serde_json::to_string_pretty(list)?)?;
+        fs::rename(&partial, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An app config and data folder, removed when dropped.
+    struct AppDirs(PathBuf);
+
+    impl AppDirs {
+        fn new() -> AppDirs {
+            AppDirs(std::env::temp_dir().join(format!("bonsai-workspace-test-{}", Uuid::new_v4())))
+        }
+
+        fn config(&self) -> PathBuf {
+            self.0.join("config")
+        }
+
+        fn data(&self) -> PathBuf {
+            self.0.join("data")
+        }
+    }
+
+    impl Drop for AppDirs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write(path: &Path, text: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn first_run_moves_files_into_the_default_workspace() {
+        let dirs = AppDirs::new();
+        write(&dirs.data().join("config/config.json"), r#"{"theme":"light"}"#);
+        write(&dirs.data().join("config/secrets.json"), "{}");
+        write(&dirs.data().join("uploads/photo.png"), "png");
+        write(&dirs.data().join("sages/sages.json"), "[]");
+        write(&dirs.config().join("settings.json"), "{}");
+        // Files the workspace already has are kept
+        write(&dirs.data().join("bonsai/uploads/photo.png"), "newer");
+
+        let workspaces = Workspaces::load(&dirs.config(), &dirs.data()).unwrap();
+        let root = workspaces.active().path;
+        assert_eq!(root, dirs.data().join("bonsai"));
+        for file in ["config/config.json", "config/secrets.json", "config/settings.json", "sages/sages.json"] {
+            assert!(root.join(file).exists(), "{} was not moved", file);
+        }
+        assert_eq!(fs::read_to_string(root.join("uploads/photo.png")).unwrap(), "newer");
+        assert!(!dirs.data().join("config").exists());
+        assert!(!dirs.data().join("sages").exists());
+        assert!(!dirs.config().join("settings.json").exists());
+    }
+
+    #[test]
+    fn later_runs_leave_the_data_folder_alone() {
+        let dirs = AppDirs::new();
+        Workspaces::load(&dirs.config(), &dirs.data()).unwrap();
+        write(&dirs.data().join("uploads/photo.png"), "png");
+
+        Workspaces::load(&dirs.config(), &dirs.data()).unwrap();
+        assert!(dirs.data().join("uploads/photo.png").exists());
+    }
+
+    #[test]
+    fn broken_lists_are_set_aside() {
+        let dirs = AppDirs::new();
+        write(&dirs.config().join("workspaces.json"), "{ not json");
+        assert!(Workspaces::load(&dirs.config(), &dirs.data()).is_err());
+
+        let (workspaces, error) = Workspaces::load_or_reset(&dirs.config(), &dirs.data()).unwrap();
+        assert!(error.is_some());
+        assert_eq!(workspaces.active().id, DEFAULT_ID);
+        let aside: Vec<String> = fs::read_dir(dirs.config())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with("workspaces.json.broken-"))
+            .collect();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(fs::read_to_string(dirs.config().join(&aside[0])).unwrap(), "{ not json");
+
+        let (_, error) = Workspaces::load_or_reset(&dirs.config(), &dirs.data()).unwrap();
+        assert!(error.is_none());
+    }
+}
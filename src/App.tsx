@@ -4,11 +4,13 @@ import { HomePage } from './pages/HomePage';
 import { LeafPage } from './pages/LeafPage';
 import { PreferencesPage } from './pages/PreferencesPage';
 import { useDarkmode } from './hooks/useDarkMode';
+import { useStartupErrors } from './hooks/useStartupErrors';
 import { ConfigProvider } from './providers/ConfigProvider';
 import { SagePage } from './pages/SagePage';
 
 const AppContent = () => {
   useDarkmode();
+  useStartupErrors();
   return (
     <main className="w-full h-full">
       <Routes>
@@ -1,5 +1,5 @@
 import { Extension, JSONContent } from '@tiptap/core';
-import { WorkerAIMessagePayload, WorkerAIResponse } from '@/workers/reflect';
+import { WorkerAIMessagePayload, WorkerAIResponse, reflect } from '@/lib/reflect';
 import { ChatMessageRole } from '@/hooks/ai/useChat';
 import { WorkerAIBlock } from '../Reflect';
 export const AIWorkerExtensionName = 'aiWorker';
@@ -10,12 +10,9 @@ export interface WorkerAIExtensions {
   interval: number;
 }
 
-interface AIWorkerExtensionOptions {
-  openAIAPIKey?: string;
-}
+interface AIWorkerExtensionOptions {}
 
 interface AIWorkerExtensionStorage {
-  worker: Worker | null;
   workerExtensions: WorkerAIExtensions[];
   debouncedUpdate: (() => void) | undefined;
   previousBlocks: WorkerAIBlock[];
@@ -75,15 +72,8 @@ declare module '@tiptap/core' {
 export const AIWorkerExtension = Extension.create<AIWorkerExtensionOptions>({
   name: AIWorkerExtensionName,
 
-  addOptions() {
-    return {
-      openAIAPIKey: '',
-    };
-  },
-
   addStorage() {
     return {
-      worker: null,
       workerExtensions: [] as WorkerAIExtensions[],
       debouncedUpdate: undefined,
       previousBlocks: [],
@@ -91,15 +81,10 @@ export const AIWorkerExtension = Extension.create<AIWorkerExtensionOptions>({
   },
 
   onCreate() {
-    const worker = new Worker(
-      new URL('@/workers/reflect.ts', import.meta.url),
-      {
-        type: 'module',
+    const onResponse = (data: WorkerAIResponse) => {
+      if (this.editor.isDestroyed) {
+        return;
       }
-    );
-
-    worker.onmessage = (event: MessageEvent) => {
-      const data = event.data as WorkerAIResponse;
       if (data.response && Array.isArray(data.response)) {
         data.response.forEach((block) => {
           const { blockId, text } = block;
@@ -113,11 +98,8 @@ export const AIWorkerExtension = Extension.create<AIWorkerExtensionOptions>({
         });
       }
     };
-    this.storage.worker = worker;
 
     this.storage.debouncedUpdate = debounce(() => {
-      const worker = this.storage.worker;
-
       const currentBlocks = collectReflectBlocks(this.editor.getJSON());
       const hasChanged = (block: WorkerAIBlock) => {
         const prevBlock = this.storage.previousBlocks.find(
@@ -140,9 +122,8 @@ export const AIWorkerExtension = Extension.create<AIWorkerExtensionOptions>({
               name: name,
               prompt: prompt,
               blocks: changedBlocks,
-              openaiApiKey: this.options.openAIAPIKey ?? '',
             };
-            worker?.postMessage(workerAIMessagePayload);
+            reflect(workerAIMessagePayload).then(onResponse);
           }
         );
       }
@@ -168,8 +149,5 @@ export const AIWorkerExtension = Extension.create<AIWorkerExtensionOptions>({
     this.storage.workerExtensions.forEach((workExt: WorkerAIExtensions) => {
       clearInterval(workExt.interval);
     });
-    if (this.storage.worker) {
-      this.storage.worker.terminate();
-    }
   },
 });
@@ -1,10 +1,11 @@
 import { ChatMessage } from '@/hooks/ai/useChat';
 import { Extension, JSONContent } from '@tiptap/core';
+import { reflect } from '@/lib/reflect';
 
 interface ReflectExtensionOptions {
   shortcut: string;
   onShortcut: () => void;
-  openAIAPIKey?: string;
+  prompt: string;
 }
 
 export interface WorkerAIBlock {
@@ -50,7 +51,7 @@ export const Reflect = Extension.create<ReflectExtensionOptions>({
     return {
       shortcut: 'Mod-j',
       onShortcut: () => {},
-      openAIAPIKey: undefined,
+      prompt: 'Keep the meaning and the tone of the original',
     };
   },
 
@@ -63,20 +64,12 @@ export const Reflect = Extension.create<ReflectExtensionOptions>({
           editor.commands.insertInlineChatAfterBlock(block.blockId);
         }
 
-        const worker = new Worker(
-          new URL('@/workers/reflect.ts', import.meta.url),
-          {
-            type: 'module',
-          }
-        );
-
-        worker.onmessage = (event: MessageEvent) => {
-          console.log('Received message from worker:', event.data);
-        };
-
-        worker.postMessage({
-          key: this.options.openAIAPIKey ?? '',
+        reflect({
+          name: this.name,
+          prompt: this.options.prompt,
           blocks: reflectBlocks,
+        }).then((result) => {
+          console.log('Received reflection:', result);
         });
 
         return true;
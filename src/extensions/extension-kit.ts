@@ -50,14 +50,13 @@ interface ExtensionKitProps {
   userId?: string;
   userName?: string;
   userColor?: string;
-  openAIAPIKey?: string;
 }
 
 const DocumentWithTitle = Document.extend({
   // content: 'heading block*',
 });
 
-export const ExtensionKit = ({ openAIAPIKey }: ExtensionKitProps) => [
+export const ExtensionKit = (_props: ExtensionKitProps = {}) => [
   DocumentWithTitle,
   Columns,
   TaskList,
@@ -128,9 +127,7 @@ export const ExtensionKit = ({ openAIAPIKey }: ExtensionKitProps) => [
   InlineChat,
   BlockID,
   CustomHighlight,
-  AIWorkerExtension.configure({
-    openAIAPIKey,
-  }),
+  AIWorkerExtension,
   AILinter.configure({
     plugins: [BadWords],
   }),
@@ -40,9 +40,7 @@ export const useBlockEditor = ({
     {
       autofocus: true,
       content: initialContent || '',
-      extensions: ExtensionKit({
-        openAIAPIKey: config.openaiApiKey,
-      }),
+      extensions: ExtensionKit({}),
       onUpdate: ({ editor }) => {
         onDebouncedEditorUpdate(editor);
       },
@@ -0,0 +1,16 @@
+import { invoke } from '@tauri-apps/api/core';
+import { useEffect } from 'react';
+
+// Shows problems the backend found while starting, e.g. settings that could
+// not be read and were replaced with defaults. Each is only handed out once.
+export const useStartupErrors = () => {
+  useEffect(() => {
+    (invoke('take_startup_errors') as Promise<string[]>)
+      .then((errors) => {
+        if (errors.length > 0) {
+          window.alert(errors.join('\n\n'));
+        }
+      })
+      .catch((error) => console.error('Error getting startup errors:', error));
+  }, []);
+};
@@ -0,0 +1,50 @@
+import { WorkerAIBlock, WorkerAIResponseBlock } from '@/extensions/Reflect';
+import { invoke } from '@tauri-apps/api/core';
+
+export interface WorkerAIMessagePayload {
+  name: string;
+  prompt: string;
+  blocks: WorkerAIBlock[];
+}
+
+export interface WorkerAIResponse {
+  name: string;
+  response: WorkerAIResponseBlock[];
+}
+
+interface AIChatMessage {
+  role: 'system' | 'user' | 'assistant';
+  content: string;
+}
+
+// The backend makes the model call so the provider key never reaches the page
+const enhanceBlock = async (
+  prompt: string,
+  block: WorkerAIBlock
+): Promise<WorkerAIResponseBlock> => {
+  const { blockId, text } = block;
+
+  const messages: AIChatMessage[] = [
+    { role: 'system', content: `You are a helpful assistant that enhances content. Here are the guidelines: ${prompt}. Only respond with the enhanced content and nothing else.` },
+    { role: 'user', content: `Here is the content to enhance: ${text}` },
+  ];
+
+  try {
+    const reply = await invoke<string>('ai_chat', { messages });
+    return { blockId, text: reply };
+  } catch (e) {
+    console.error('Failed to invoke model:', e);
+    return { blockId, text: '' };
+  }
+};
+
+export const reflect = async ({
+  name,
+  prompt,
+  blocks,
+}: WorkerAIMessagePayload): Promise<WorkerAIResponse> => {
+  const response = await Promise.all(
+    blocks.map((block) => enhanceBlock(prompt, block))
+  );
+  return { name, response };
+};